@@ -53,4 +53,18 @@ fn main() {
     }
     println!("cargo:rustc-env=STD_ENV_ARCH={}", env::var("CARGO_CFG_TARGET_ARCH").unwrap());
     println!("cargo:rustc-cfg=backtrace_in_libstd");
+
+    // The oldest version of Windows the build has to run on, if it's newer than what the target
+    // supports. Every version up to it is set, so `windows_min_version = "8"` holds for builds
+    // that require Windows 8 or any later version.
+    println!("cargo:rerun-if-env-changed=STD_WINDOWS_MIN_VERSION");
+    if let Ok(min_version) = env::var("STD_WINDOWS_MIN_VERSION") {
+        const VERSIONS: [&str; 5] = ["xp", "vista", "7", "8", "10"];
+        let Some(last) = VERSIONS.iter().position(|&version| version == min_version) else {
+            panic!("STD_WINDOWS_MIN_VERSION must be one of {VERSIONS:?}, not {min_version:?}");
+        };
+        for version in &VERSIONS[..=last] {
+            println!("cargo:rustc-cfg=windows_min_version=\"{version}\"");
+        }
+    }
 }
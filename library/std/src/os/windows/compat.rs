@@ -0,0 +1,125 @@
+//! Diagnostics for the runtime lookup of Windows API functions.
+//!
+//! std looks up many Windows API functions at runtime, as not all of them exist on every version
+//! of Windows. Depending on the system, a function may come from its own module or from unicows,
+//! or be replaced by a fallback implementation. [`capabilities`] summarizes which of the
+//! downlevel paths std took as a result.
+
+#![unstable(feature = "windows_compat", issue = "none")]
+
+/// Lists how each Windows API function looked up at runtime so far was resolved, one per line.
+///
+/// Each line names the function, followed by the module it was found in, `unicows`, or whether a
+/// fallback implementation took its place, or it is missing entirely:
+///
+/// ```text
+/// GetFinalPathNameByHandleW: fallback, not in kernel32
+/// CreateFileW: unicows
+/// ```
+///
+/// Functions are only looked up the first time they are needed, so this only lists those that
+/// were used before. Returns `None` unless the `RUST_COMPAT_TRACE` environment variable was set
+/// when the program started. The format is not guaranteed to stay the same.
+#[unstable(feature = "windows_compat_trace", issue = "none")]
+pub fn resolved_functions() -> Option<String> {
+    crate::sys::compat::trace::report()
+}
+
+/// Describes which of the optional features and backends std uses on this system, one per line.
+///
+/// This covers what std picked at startup, such as the source of randomness, whether overlapped
+/// I/O is used, how threads are parked and whether stack overflows are reported:
+///
+/// ```text
+/// windows nt: true
+/// async i/o: true
+/// rng: ProcessPrng
+/// ```
+///
+/// Meant to be included in bug reports about old systems. The format is not guaranteed to stay
+/// the same.
+#[unstable(feature = "windows_compat_report", issue = "none")]
+pub fn report() -> String {
+    crate::sys::compat::report()
+}
+
+/// The implementation `std::sync::Mutex` and `RwLock` use, which depends on the Windows version.
+#[unstable(feature = "windows_compat_capabilities", issue = "none")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MutexKind {
+    /// Slim reader/writer locks, on Windows 7 and later.
+    SrwLock,
+    /// Critical sections, on NT 4 and later. Readers of an `RwLock` exclude each other.
+    CriticalSection,
+    /// Mutex objects created by `CreateMutex`, on all other versions.
+    Legacy,
+}
+
+/// Where a Windows API function that std looks up at runtime came from.
+#[unstable(feature = "windows_compat_capabilities", issue = "none")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FunctionSource {
+    /// The function wasn't needed yet, so it wasn't looked up either.
+    NotLoadedYet,
+    /// Found in the module that normally exports it.
+    Native,
+    /// Found in unicows. Some of its functions are stubs that behave differently from the real
+    /// ones.
+    Unicows,
+    /// Not found, so std uses its own implementation instead.
+    Fallback,
+    /// Not found, and std has no implementation of its own.
+    Missing,
+}
+
+/// Which of the optional Windows features std uses were found on this system.
+#[unstable(feature = "windows_compat_capabilities", issue = "none")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Slim reader/writer locks were loaded, and are used for `Mutex` and `RwLock`.
+    pub srw_locks: bool,
+    /// The `TryAcquire*` functions of slim reader/writer locks were loaded. Slim reader/writer
+    /// locks aren't used without them, as on Vista, since `try_lock` and friends couldn't work.
+    pub srw_try_locks: bool,
+    /// `WaitOnAddress` and `WakeByAddressSingle` were loaded.
+    pub wait_on_address: bool,
+    /// `TryEnterCriticalSection` was loaded.
+    pub try_enter_critical_section: bool,
+    /// Overlapped I/O is used, which needs NT and `CancelIo`.
+    pub async_io: bool,
+    /// `CancelIo` is present.
+    pub cancel_io: bool,
+    /// The lock implementation that was picked.
+    pub mutex: MutexKind,
+    /// unicows is loaded, and so provides the wide functions that 9x/ME lack.
+    pub unicows: bool,
+    /// Where `GetUserProfileDirectoryW` came from.
+    pub get_user_profile_directory: FunctionSource,
+    /// Where `CompareStringOrdinal` came from.
+    pub compare_string_ordinal: FunctionSource,
+}
+
+/// Returns which of the optional Windows features std uses were found on this system.
+///
+/// The locking primitives are picked before `main` runs, so those fields never change. Whether
+/// unicows is loaded is checked on every call, and other functions are only looked up the first
+/// time std needs them, so their fields may change until then.
+#[unstable(feature = "windows_compat_capabilities", issue = "none")]
+pub fn capabilities() -> Capabilities {
+    crate::sys::compat::capabilities()
+}
+
+/// Stops std from loading Windows API functions from unicows.
+///
+/// On 9x/ME, unicows provides the wide functions that the system lacks, and it is used whenever
+/// it is loaded. Where that isn't wanted, for example because some other `unicows.dll` ended up
+/// loaded, call this before std needs any of them. Functions that were already loaded stay as they
+/// are. Setting the `RUST_COMPAT_NO_UNICOWS` environment variable before the program starts does
+/// the same.
+#[unstable(feature = "windows_compat_unicows", issue = "none")]
+pub fn disable_unicows() {
+    crate::sys::compat::disable_unicows()
+}
@@ -468,7 +468,8 @@ pub trait MetadataExt {
     ///
     /// This will return `None` if the `Metadata` instance was created from a
     /// call to `DirEntry::metadata`. If this `Metadata` was created by using
-    /// `fs::metadata` or `File::metadata`, then this will return `Some`.
+    /// `fs::metadata` or `File::metadata`, then this will return `Some`,
+    /// except on Windows 9x/ME, where file indices aren't stable.
     #[unstable(feature = "windows_by_handle", issue = "63010")]
     fn file_index(&self) -> Option<u64>;
 }
@@ -512,6 +513,17 @@ pub trait FileTypeExt: Sealed {
     /// Returns `true` if this file type is a symbolic link that is also a file.
     #[stable(feature = "windows_file_type_ext", since = "1.64.0")]
     fn is_symlink_file(&self) -> bool;
+    /// Returns `true` if this file type is a directory junction (a mount point).
+    ///
+    /// Junctions are reported as symbolic links to directories as well, as
+    /// [`fs::read_link`] and [`fs::symlink_metadata`] treat them alike, but
+    /// they always point to an absolute path on a local volume. Before
+    /// Windows Vista, they are the only kind of link to a directory there is.
+    ///
+    /// [`fs::read_link`]: crate::fs::read_link
+    /// [`fs::symlink_metadata`]: crate::fs::symlink_metadata
+    #[unstable(feature = "windows_file_type_junction", issue = "none")]
+    fn is_junction(&self) -> bool;
 }
 
 #[stable(feature = "windows_file_type_ext", since = "1.64.0")]
@@ -525,6 +537,9 @@ fn is_symlink_dir(&self) -> bool {
     fn is_symlink_file(&self) -> bool {
         self.as_inner().is_symlink_file()
     }
+    fn is_junction(&self) -> bool {
+        self.as_inner().is_junction()
+    }
 }
 
 /// Windows-specific extensions to [`fs::FileTimes`].
@@ -0,0 +1,70 @@
+//! Windows console capabilities.
+
+#![unstable(feature = "windows_console_vt", issue = "none")]
+
+use super::{AsHandle, BorrowedHandle, OwnedHandle};
+use crate::fs::File;
+use crate::io::{Stderr, StderrLock, Stdout, StdoutLock};
+use crate::sealed::Sealed;
+
+/// Whether a handle supports virtual terminal sequences (ANSI escape codes).
+///
+/// Returned by [`ConsoleExt::enable_virtual_terminal`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VtSupport {
+    /// The handle is a console with virtual terminal processing enabled.
+    Enabled,
+    /// The handle is a console that doesn't support virtual terminal sequences. This is the case
+    /// for all versions of Windows before Windows 10 1511.
+    Unsupported,
+    /// The handle is not a console, e.g. a pipe or a file.
+    ///
+    /// Terminals that communicate through pipes, like mintty, may still support virtual terminal
+    /// sequences.
+    NotConsole,
+}
+
+/// Windows-specific extensions for console output.
+pub trait ConsoleExt: Sealed {
+    /// Enables virtual terminal processing (`ENABLE_VIRTUAL_TERMINAL_PROCESSING`) for the
+    /// console this writes to, if possible.
+    ///
+    /// If the console doesn't support it, its mode is left unchanged. The result is cached for
+    /// the standard output and error handles.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// #![feature(windows_console_vt)]
+    /// use std::io;
+    /// use std::os::windows::io::{ConsoleExt, VtSupport};
+    ///
+    /// if io::stdout().enable_virtual_terminal() == VtSupport::Enabled {
+    ///     println!("\x1b[32mgreen\x1b[0m");
+    /// } else {
+    ///     println!("green");
+    /// }
+    /// ```
+    fn enable_virtual_terminal(&self) -> VtSupport;
+}
+
+macro_rules! impl_console_ext {
+    ($($t:ty),*$(,)?) => {$(
+        impl ConsoleExt for $t {
+            #[inline]
+            fn enable_virtual_terminal(&self) -> VtSupport {
+                crate::sys::io::console_vt_support(self.as_handle())
+            }
+        }
+    )*}
+}
+
+impl_console_ext!(
+    BorrowedHandle<'_>,
+    OwnedHandle,
+    File,
+    Stdout,
+    StdoutLock<'_>,
+    Stderr,
+    StderrLock<'_>
+);
@@ -54,10 +54,13 @@
 
 #![stable(feature = "rust1", since = "1.0.0")]
 
+mod console;
 mod handle;
 mod raw;
 mod socket;
 
+#[unstable(feature = "windows_console_vt", issue = "none")]
+pub use console::*;
 #[stable(feature = "io_safety", since = "1.63.0")]
 pub use handle::*;
 #[stable(feature = "rust1", since = "1.0.0")]
@@ -11,6 +11,7 @@
 use crate::sys;
 #[cfg(not(target_vendor = "uwp"))]
 use crate::sys::cvt;
+use crate::sys_common::{FromInner, IntoInner};
 
 /// A borrowed socket.
 ///
@@ -126,6 +127,10 @@ impl BorrowedSocket<'_> {
     /// object as the existing `BorrowedSocket` instance.
     #[stable(feature = "io_safety", since = "1.63.0")]
     pub fn try_clone_to_owned(&self) -> io::Result<OwnedSocket> {
+        if sys::net::needs_handle_duplication() {
+            return sys::net::duplicate_handle(*self);
+        }
+
         let mut info = unsafe { mem::zeroed::<sys::c::WSAPROTOCOL_INFOA>() };
         let result = unsafe {
             sys::c::WSADuplicateSocketA(
@@ -134,7 +139,14 @@ pub fn try_clone_to_owned(&self) -> io::Result<OwnedSocket> {
                 &mut info,
             )
         };
-        sys::net::cvt(result)?;
+        if let Err(e) = sys::net::cvt(result) {
+            // Some Winsock providers don't support duplication, but the socket's handle can still
+            // be duplicated within this process.
+            if e.raw_os_error() == Some(sys::c::WSAEOPNOTSUPP) {
+                return sys::net::duplicate_handle(*self);
+            }
+            return Err(e);
+        }
         let socket = unsafe {
             sys::c::WSASocketA(
                 info.iAddressFamily,
@@ -206,6 +218,7 @@ impl IntoRawSocket for OwnedSocket {
     fn into_raw_socket(self) -> RawSocket {
         let socket = self.socket;
         forget(self);
+        sys::net::release_socket(socket as sys::c::SOCKET);
         socket
     }
 }
@@ -224,7 +237,7 @@ impl Drop for OwnedSocket {
     #[inline]
     fn drop(&mut self) {
         unsafe {
-            let _ = sys::c::closesocket(self.socket as sys::c::SOCKET);
+            sys::net::close_socket(self.socket as sys::c::SOCKET);
         }
     }
 }
@@ -334,7 +347,7 @@ fn as_socket(&self) -> BorrowedSocket<'_> {
 impl From<crate::net::TcpStream> for OwnedSocket {
     #[inline]
     fn from(tcp_stream: crate::net::TcpStream) -> OwnedSocket {
-        unsafe { OwnedSocket::from_raw_socket(tcp_stream.into_raw_socket()) }
+        tcp_stream.into_inner().into_socket().into_inner()
     }
 }
 
@@ -342,7 +355,7 @@ fn from(tcp_stream: crate::net::TcpStream) -> OwnedSocket {
 impl From<OwnedSocket> for crate::net::TcpStream {
     #[inline]
     fn from(owned: OwnedSocket) -> Self {
-        unsafe { Self::from_raw_socket(owned.into_raw_socket()) }
+        Self::from_inner(FromInner::from_inner(FromInner::from_inner(owned)))
     }
 }
 
@@ -358,7 +371,7 @@ fn as_socket(&self) -> BorrowedSocket<'_> {
 impl From<crate::net::TcpListener> for OwnedSocket {
     #[inline]
     fn from(tcp_listener: crate::net::TcpListener) -> OwnedSocket {
-        unsafe { OwnedSocket::from_raw_socket(tcp_listener.into_raw_socket()) }
+        tcp_listener.into_inner().into_socket().into_inner()
     }
 }
 
@@ -366,7 +379,7 @@ fn from(tcp_listener: crate::net::TcpListener) -> OwnedSocket {
 impl From<OwnedSocket> for crate::net::TcpListener {
     #[inline]
     fn from(owned: OwnedSocket) -> Self {
-        unsafe { Self::from_raw_socket(owned.into_raw_socket()) }
+        Self::from_inner(FromInner::from_inner(FromInner::from_inner(owned)))
     }
 }
 
@@ -382,7 +395,7 @@ fn as_socket(&self) -> BorrowedSocket<'_> {
 impl From<crate::net::UdpSocket> for OwnedSocket {
     #[inline]
     fn from(udp_socket: crate::net::UdpSocket) -> OwnedSocket {
-        unsafe { OwnedSocket::from_raw_socket(udp_socket.into_raw_socket()) }
+        udp_socket.into_inner().into_socket().into_inner()
     }
 }
 
@@ -390,6 +403,6 @@ fn from(udp_socket: crate::net::UdpSocket) -> OwnedSocket {
 impl From<OwnedSocket> for crate::net::UdpSocket {
     #[inline]
     fn from(owned: OwnedSocket) -> Self {
-        unsafe { Self::from_raw_socket(owned.into_raw_socket()) }
+        Self::from_inner(FromInner::from_inner(FromInner::from_inner(owned)))
     }
 }
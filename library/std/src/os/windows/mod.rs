@@ -25,6 +25,7 @@
 #![stable(feature = "rust1", since = "1.0.0")]
 #![doc(cfg(windows))]
 
+pub mod compat;
 pub mod ffi;
 pub mod fs;
 pub mod io;
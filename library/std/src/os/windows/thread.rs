@@ -23,3 +23,42 @@ fn into_raw_handle(self) -> RawHandle {
         self.into_inner().into_handle().into_raw_handle() as *mut _
     }
 }
+
+/// Returns the name of the primitive [`thread::park`] blocks on, for diagnostics.
+///
+/// Which one is used depends on the Windows version. This is one of `"WaitOnAddress"`,
+/// `"keyed events"`, `"event"` and `"mutex and condvar"`, or `"unresolved"` if no thread handle
+/// was created yet. The names are not guaranteed to stay the same.
+#[unstable(feature = "windows_thread_parking_backend", issue = "none")]
+pub fn parking_backend() -> &'static str {
+    crate::sys::thread_parking::backend_name()
+}
+
+/// What std does when it detects that a thread overflowed its stack.
+#[unstable(feature = "windows_stack_overflow_mode", issue = "none")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[non_exhaustive]
+pub enum StackOverflowMode {
+    /// Print a message and leave the exception to the handlers registered after std's, which
+    /// usually means that the process exits with `STATUS_STACK_OVERFLOW`. This is the default.
+    #[default]
+    ReportAndContinueSearch,
+    /// Print a message and terminate the process with `STATUS_STACK_OVERFLOW` right away, so that
+    /// crash reporters see the state of the overflow rather than of whatever ran after it.
+    ReportAndAbort,
+    /// Don't do anything, for hosts that handle stack overflows themselves.
+    Silent,
+}
+
+/// Sets what std does when it detects a stack overflow, in any thread from then on.
+///
+/// Has no effect on UWP, where stack overflows aren't handled by std.
+#[unstable(feature = "windows_stack_overflow_mode", issue = "none")]
+pub fn set_stack_overflow_mode(mode: StackOverflowMode) {
+    use crate::sys::stack_overflow::{set_mode, OverflowMode};
+    set_mode(match mode {
+        StackOverflowMode::ReportAndContinueSearch => OverflowMode::ReportAndContinueSearch,
+        StackOverflowMode::ReportAndAbort => OverflowMode::ReportAndAbort,
+        StackOverflowMode::Silent => OverflowMode::Silent,
+    });
+}
@@ -33,6 +33,7 @@
 use core::ptr::addr_of;
 
 use super::c;
+use super::net::IsMinusOne;
 
 /// Helper method for getting the size of `T` as a u32.
 /// Errors at compile time if the size would overflow.
@@ -143,15 +144,45 @@ unsafe fn set_info(
 /// avoid the risk of another function overwriting it.
 pub fn get_last_error() -> WinError {
     // SAFETY: This just returns a thread-local u32 and has no other effects.
-    unsafe { WinError { code: c::GetLastError() } }
+    let code = unsafe { c::GetLastError() };
+    WinError { code, domain: ErrorDomain::Win32 }
 }
 
-/// An error code as returned by [`get_last_error`].
+/// An error code as returned by [`get_last_error`] or [`wsa_get_last_error`].
 ///
 /// This is usually a 16-bit Win32 error code but may be a 32-bit HRESULT or NTSTATUS.
 /// Check the documentation of the Windows API function being called for expected errors.
-#[derive(Clone, Copy, PartialEq, Eq)]
-#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct WinError {
     pub code: u32,
+    /// Which of the thread's last errors the code was read from.
+    pub domain: ErrorDomain,
+}
+
+/// Where a [`WinError`] was read from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorDomain {
+    /// `GetLastError`, which Win32 functions set.
+    Win32,
+    /// `WSAGetLastError`, which Windows Sockets functions set.
+    Winsock,
+}
+
+/// Gets the error from the last Windows Sockets function.
+/// Like [`get_last_error`], this must be called immediately after the function
+/// that sets the error.
+///
+/// Socket functions must use this rather than [`get_last_error`]: on 9x/ME,
+/// Winsock keeps an error of its own, which `GetLastError` doesn't return.
+pub fn wsa_get_last_error() -> WinError {
+    // SAFETY: This just returns a thread-local i32 and has no other effects.
+    let code = unsafe { c::WSAGetLastError() };
+    // Winsock's codes are never negative, but reinterpret them like any other `u32` code.
+    WinError { code: u32::from_ne_bytes(code.to_ne_bytes()), domain: ErrorDomain::Winsock }
+}
+
+/// Checks the result of a Windows Sockets function, which is -1 (`SOCKET_ERROR`) if it failed,
+/// and then gets its error right away with [`wsa_get_last_error`].
+pub fn cvt_wsa<T: IsMinusOne>(result: T) -> Result<T, WinError> {
+    if result.is_minus_one() { Err(wsa_get_last_error()) } else { Ok(result) }
 }
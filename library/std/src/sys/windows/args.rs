@@ -35,7 +35,8 @@ const fn non_zero_u16(n: u16) -> NonZeroU16 {
 
 pub fn args() -> Args {
     // SAFETY: `GetCommandLineW` returns a pointer to a null terminated UTF-16
-    // string so it's safe for `WStrUnits` to use.
+    // string so it's safe for `WStrUnits` to use. On 9x/ME without unicows,
+    // that is the ANSI command line converted from the active code page.
     unsafe {
         let lp_cmd_line = c::GetCommandLineW();
         let parsed_args_list = parse_lp_cmd_line(WStrUnits::new(lp_cmd_line), || {
@@ -263,22 +264,44 @@ pub(crate) fn append_arg(cmd: &mut Vec<u16>, arg: &Arg, force_quotes: bool) -> i
     Ok(())
 }
 
+/// The command interpreter that runs batch files.
+///
+/// `CreateProcess` can't start a batch file by itself, so we start the interpreter and build its
+/// command line ourselves. The two interpreters parse that line very differently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BatInterpreter {
+    /// `cmd.exe`, on NT. Runs both `.bat` and `.cmd` files.
+    Cmd,
+    /// `command.com`, on 9x/ME. Only knows `.bat` files, has no escape character, and is limited
+    /// to the 127 byte command tail of DOS programs.
+    CommandCom,
+}
+
+impl BatInterpreter {
+    pub(crate) fn current() -> Self {
+        if compat::is_windows_nt() { Self::Cmd } else { Self::CommandCom }
+    }
+
+    /// Whether `program` (null terminated) is a batch file this interpreter runs.
+    pub(crate) fn runs(self, program: &[u16]) -> bool {
+        // Case insensitive "ends_with" of UTF-16 encoded ".bat" or ".cmd"
+        let end = program.len().checked_sub(5).and_then(|i| program.get(i..));
+        match self {
+            Self::Cmd => matches!(
+                end,
+                Some([46, 98 | 66, 97 | 65, 116 | 84, 0] | [46, 99 | 67, 109 | 77, 100 | 68, 0])
+            ),
+            Self::CommandCom => matches!(end, Some([46, 98 | 66, 97 | 65, 116 | 84, 0])),
+        }
+    }
+}
+
 pub(crate) fn make_bat_command_line(
+    interpreter: BatInterpreter,
     script: &[u16],
     args: &[Arg],
     force_quotes: bool,
 ) -> io::Result<Vec<u16>> {
-    // Set the start of the command line to `cmd.exe /c "`
-    // It is necessary to surround the command in an extra pair of quotes,
-    // hence the trailing quote here. It will be closed after all arguments
-    // have been added.
-    let mut cmd: Vec<u16> =
-        if compat::is_windows_nt() { "cmd.exe /d /c \"" } else { "command.com /c \"" }
-            .encode_utf16()
-            .collect();
-
-    // Push the script name surrounded by its quote pair.
-    cmd.push(b'"' as u16);
     // Windows file names cannot contain a `"` character or end with `\\`.
     // If the script name does then return an error.
     if script.contains(&(b'"' as u16)) || script.last() == Some(&(b'\\' as u16)) {
@@ -287,7 +310,23 @@ pub(crate) fn make_bat_command_line(
             "Windows file names may not contain `\"` or end with `\\`"
         ));
     }
-    cmd.extend_from_slice(script.strip_suffix(&[0]).unwrap_or(script));
+    let script = script.strip_suffix(&[0]).unwrap_or(script);
+    match interpreter {
+        BatInterpreter::Cmd => make_cmd_command_line(script, args, force_quotes),
+        BatInterpreter::CommandCom => make_command_com_command_line(script, args, force_quotes),
+    }
+}
+
+fn make_cmd_command_line(script: &[u16], args: &[Arg], force_quotes: bool) -> io::Result<Vec<u16>> {
+    // Set the start of the command line to `cmd.exe /c "`
+    // It is necessary to surround the command in an extra pair of quotes,
+    // hence the trailing quote here. It will be closed after all arguments
+    // have been added.
+    let mut cmd: Vec<u16> = "cmd.exe /d /c \"".encode_utf16().collect();
+
+    // Push the script name surrounded by its quote pair.
+    cmd.push(b'"' as u16);
+    cmd.extend_from_slice(script);
     cmd.push(b'"' as u16);
 
     // Append the arguments.
@@ -315,6 +354,83 @@ pub(crate) fn make_bat_command_line(
     Ok(cmd)
 }
 
+fn make_command_com_command_line(
+    script: &[u16],
+    args: &[Arg],
+    force_quotes: bool,
+) -> io::Result<Vec<u16>> {
+    const PROGRAM: &str = "command.com";
+    // The command tail of a DOS program is at most 127 bytes, including the terminating carriage
+    // return. command.com silently cuts off anything longer.
+    const MAX_TAIL: usize = 126;
+    // Separate arguments, unless quoted.
+    const SEPARATORS: &[u8] = b"\t ,;=";
+    // Interpreted even inside quotes, and there is no way to escape them.
+    const UNSAFE: &[u8] = b"\"\r\n%|<>";
+
+    // A `%` in the script name is expanded just like in the arguments. The other unsafe characters
+    // can't be part of a file name anyway.
+    if script.iter().any(|&c| c == b'%' as u16) {
+        return Err(io::const_io_error!(
+            io::ErrorKind::InvalidInput,
+            "batch file names may not contain `%` on command.com"
+        ));
+    }
+
+    // Unlike cmd.exe, command.com doesn't strip an extra pair of quotes around the command, so
+    // quotes are only ever added around the script name and arguments that need them.
+    let mut cmd: Vec<u16> = PROGRAM.encode_utf16().chain(" /c ".encode_utf16()).collect();
+    let quote = force_quotes || script.iter().any(|&c| c < 0x80 && SEPARATORS.contains(&(c as u8)));
+    if quote {
+        cmd.push(b'"' as u16);
+    }
+    cmd.extend_from_slice(script);
+    if quote {
+        cmd.push(b'"' as u16);
+    }
+
+    for arg in args {
+        cmd.push(' ' as u16);
+        match arg {
+            Arg::Regular(arg) => {
+                ensure_no_nuls(arg)?;
+                let bytes = arg.as_encoded_bytes();
+                if bytes.iter().any(|c| UNSAFE.contains(c)) {
+                    return Err(io::const_io_error!(
+                        io::ErrorKind::InvalidInput,
+                        "batch file arguments may not contain `\"`, `%`, `|`, `<`, `>` or \
+                         newlines on command.com"
+                    ));
+                }
+                let quote = force_quotes
+                    || bytes.is_empty()
+                    || bytes.iter().any(|c| SEPARATORS.contains(c));
+                if quote {
+                    cmd.push(b'"' as u16);
+                }
+                cmd.extend(arg.encode_wide());
+                if quote {
+                    cmd.push(b'"' as u16);
+                }
+            }
+            Arg::Raw(arg) => {
+                ensure_no_nuls(arg)?;
+                cmd.extend(arg.encode_wide());
+            }
+        }
+    }
+
+    // This counts UTF-16 code units, so characters that take two bytes in a double-byte ANSI code
+    // page still slip through.
+    if cmd.len() - PROGRAM.len() > MAX_TAIL {
+        return Err(io::const_io_error!(
+            io::ErrorKind::InvalidInput,
+            "batch file command line is too long for command.com"
+        ));
+    }
+    Ok(cmd)
+}
+
 /// Takes a path and tries to return a non-verbatim path.
 ///
 /// This is necessary because cmd.exe does not support verbatim paths.
@@ -1,4 +1,5 @@
 use crate::ffi::OsString;
+use crate::sys::c;
 use crate::sys::windows::args::*;
 
 fn chk(string: &str, parts: &[&str]) {
@@ -10,6 +11,15 @@ fn chk(string: &str, parts: &[&str]) {
     assert_eq!(parsed.as_slice(), expected.as_slice(), "{:?}", string);
 }
 
+/// Like `chk`, but for an ANSI command line as 9x/ME without unicows have it.
+fn chk_ansi(ansi: &[u8], parts: &[&str]) {
+    let wide = c::ansi::command_line_to_wide(ansi);
+    let parsed =
+        unsafe { parse_lp_cmd_line(WStrUnits::new(wide.as_ptr()), || OsString::from("TEST.EXE")) };
+    let expected: Vec<OsString> = parts.iter().map(|k| OsString::from(k)).collect();
+    assert_eq!(parsed.as_slice(), expected.as_slice(), "{:?}", ansi.escape_ascii().to_string());
+}
+
 #[test]
 fn empty() {
     chk("", &["TEST.EXE"]);
@@ -89,3 +99,97 @@ fn post_2008() {
     chk(r#"EXE """Call Me Ishmael""""#, &["EXE", r#""Call Me Ishmael""#]);
     chk(r#"EXE """"Call Me Ishmael"" b c"#, &["EXE", r#""Call"#, "Me", "Ishmael", "b", "c"]);
 }
+
+#[test]
+fn ansi_command_lines() {
+    // An empty command line still yields the executable name.
+    chk_ansi(b"", &["TEST.EXE"]);
+    chk_ansi(b"EXE", &["EXE"]);
+    chk_ansi(br#""C:\Program Files\EXE.EXE" a b"#, &[r"C:\Program Files\EXE.EXE", "a", "b"]);
+    chk_ansi(br#"EXE "embedded spaces" here"#, &["EXE", "embedded spaces", "here"]);
+    chk_ansi(br#"EXE "C:\TEST A\\" b"#, &["EXE", r"C:\TEST A\", "b"]);
+    chk_ansi(br#"EXE trailing\"#, &["EXE", r"trailing\"]);
+    chk_ansi(br#"EXE a\\"b c" d"#, &["EXE", r"a\b c", "d"]);
+}
+
+#[test]
+fn ansi_command_line_code_page() {
+    // Whatever the code page, a character it can represent survives the round trip.
+    for s in ["\u{e9}", "\u{e4}\u{f6}\u{fc}", "\u{3042}", "\u{20ac}"] {
+        let wide: Vec<u16> = s.encode_utf16().collect();
+        let Ok(ansi) = c::ansi::wide_to_ansi(&wide) else { continue };
+        let mut line = b"EXE \"".to_vec();
+        line.extend_from_slice(&ansi);
+        line.extend_from_slice(b" x\" y");
+        chk_ansi(&line, &["EXE", format!("{s} x").as_str(), "y"]);
+    }
+}
+
+#[test]
+fn ansi_command_line_matches_wide() {
+    let wide = unsafe { WStrUnits::new(c::GetCommandLineW()) };
+    let wide: Vec<u16> = wide.map_or(Vec::new(), |units| units.map(|w| w.get()).collect());
+    // Only an ASCII command line converts to the same thing in every code page.
+    if !wide.iter().all(|&w| w < 0x80) {
+        return;
+    }
+    let line = unsafe { WStrUnits::new(c::ansi::GetCommandLineW()) };
+    let line: Vec<u16> = line.map_or(Vec::new(), |units| units.map(|w| w.get()).collect());
+    assert_eq!(line, wide);
+    // The converted command line is kept, not converted again.
+    assert_eq!(unsafe { c::ansi::GetCommandLineW() }, unsafe { c::ansi::GetCommandLineW() });
+}
+
+fn bat_line(interpreter: BatInterpreter, args: &[&str]) -> crate::io::Result<String> {
+    let script: Vec<u16> = r"C:\x y\run.bat".encode_utf16().chain([0]).collect();
+    let args: Vec<Arg> = args.iter().map(|a| Arg::Regular(a.into())).collect();
+    let line = make_bat_command_line(interpreter, &script, &args, false)?;
+    Ok(String::from_utf16(&line).unwrap())
+}
+
+#[test]
+fn bat_command_lines() {
+    assert_eq!(
+        bat_line(BatInterpreter::Cmd, &["a", "b c", "%TMP%", ""]).unwrap(),
+        r#"cmd.exe /d /c ""C:\x y\run.bat" a "b c" "%TMP%" """"#
+    );
+    // No extra pair of quotes, and only separators need quoting.
+    assert_eq!(
+        bat_line(BatInterpreter::CommandCom, &["a", "b c", "d,e", "f&g", ""]).unwrap(),
+        r#"command.com /c "C:\x y\run.bat" a "b c" "d,e" f&g """#
+    );
+
+    let script: Vec<u16> = r"C:\RUN.BAT".encode_utf16().chain([0]).collect();
+    let line = make_bat_command_line(BatInterpreter::CommandCom, &script, &[], false).unwrap();
+    assert_eq!(String::from_utf16(&line).unwrap(), r"command.com /c C:\RUN.BAT");
+
+    assert!(BatInterpreter::Cmd.runs(&"A.CMD\0".encode_utf16().collect::<Vec<_>>()));
+    assert!(!BatInterpreter::CommandCom.runs(&"A.CMD\0".encode_utf16().collect::<Vec<_>>()));
+    assert!(BatInterpreter::CommandCom.runs(&"a.Bat\0".encode_utf16().collect::<Vec<_>>()));
+}
+
+#[test]
+fn bat_args_command_com_cannot_represent() {
+    use crate::io::ErrorKind::InvalidInput;
+
+    for arg in ["a\"b", "a\nb", "a\rb", "%TMP%", "a|b", "a<b", "a>b"] {
+        let err = bat_line(BatInterpreter::CommandCom, &[arg]).unwrap_err();
+        assert_eq!(err.kind(), InvalidInput, "{arg:?}");
+        // cmd.exe can quote all of these.
+        if !arg.contains(['"', '\n', '\r']) {
+            bat_line(BatInterpreter::Cmd, &[arg]).unwrap();
+        }
+    }
+
+    // The same goes for a `%` in the script name.
+    let script: Vec<u16> = r"C:\100%\run.bat".encode_utf16().chain([0]).collect();
+    let err = make_bat_command_line(BatInterpreter::CommandCom, &script, &[], false).unwrap_err();
+    assert_eq!(err.kind(), InvalidInput);
+
+    // The command tail of `command.com /c C:\x y\run.bat ...` is limited to 126 characters.
+    let fits = "a".repeat(126 - r#" /c "C:\x y\run.bat" "#.len());
+    bat_line(BatInterpreter::CommandCom, &[&fits]).unwrap();
+    let err = bat_line(BatInterpreter::CommandCom, &[&format!("{fits}a")]).unwrap_err();
+    assert_eq!(err.kind(), InvalidInput);
+    bat_line(BatInterpreter::Cmd, &[&format!("{fits}a")]).unwrap();
+}
@@ -10,8 +10,14 @@
 use crate::os::raw::{c_char, c_long, c_longlong, c_uint, c_ulong, c_ushort, c_void};
 use crate::os::windows::io::{AsRawHandle, BorrowedHandle};
 use crate::ptr;
+use crate::sync::atomic::{AtomicU64, Ordering};
 use core::ffi::NonZero_c_ulong;
 
+#[cfg(test)]
+mod tests;
+
+pub mod ansi;
+mod inet;
 mod windows_sys;
 mod wspiapi;
 pub use windows_sys::*;
@@ -47,6 +53,7 @@
 pub use TIMEVAL as timeval;
 
 pub const INVALID_HANDLE_VALUE: HANDLE = ::core::ptr::invalid_mut(-1i32 as _);
+pub const BCRYPT_RNG_ALG_HANDLE: BCRYPT_ALG_HANDLE = ::core::ptr::invalid_mut(0x81);
 
 // https://learn.microsoft.com/en-us/cpp/c-runtime-library/exit-success-exit-failure?view=msvc-170
 pub const EXIT_SUCCESS: u32 = 0;
@@ -56,6 +63,7 @@
 pub const SRWLOCK_INIT: SRWLOCK = SRWLOCK { Ptr: ptr::null_mut() };
 
 // Some windows_sys types have different signs than the types we use.
+pub const OBJ_CASE_INSENSITIVE: u32 = windows_sys::OBJ_CASE_INSENSITIVE as u32;
 pub const OBJ_DONT_REPARSE: u32 = windows_sys::OBJ_DONT_REPARSE as u32;
 pub const FRS_ERR_SYSVOL_POPULATE_TIMEOUT: u32 =
     windows_sys::FRS_ERR_SYSVOL_POPULATE_TIMEOUT as u32;
@@ -80,6 +88,104 @@ pub fn nt_success(status: NTSTATUS) -> bool {
     status >= 0
 }
 
+/// Extends a `GetTickCount` value to 64 bits, given the last extended value in `last`.
+///
+/// `GetTickCount` wraps around after 49.7 days. We detect that by the tick count going backwards,
+/// so this has to be called at least once every 24.8 days to not miss a wraparound. A `now` that
+/// is slightly older than `last` (because another thread got in between reading the tick count
+/// and updating `last`) is extended relative to `last`, without going back in time for `last`.
+///
+/// A `last` of zero means that there is no previous value yet.
+pub fn extend_tick_count(last: &AtomicU64, now: u32) -> u64 {
+    let mut old = last.load(Ordering::Relaxed);
+    loop {
+        let delta = now.wrapping_sub(old as u32) as i32;
+        if old != 0 && delta <= 0 {
+            return old.wrapping_sub(delta.unsigned_abs() as u64);
+        }
+        let new = if old == 0 { now as u64 } else { old + delta as u64 };
+        match last.compare_exchange_weak(old, new, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return new,
+            Err(current) => old = current,
+        }
+    }
+}
+
+/// Compares two UTF-16 strings like `CompareStringOrdinal`, for systems that don't have it.
+///
+/// A length of -1 means that the string is null-terminated.
+unsafe fn compare_string_ordinal(
+    string1: PCWSTR,
+    len1: i32,
+    string2: PCWSTR,
+    len2: i32,
+    ignore_case: BOOL,
+) -> COMPARESTRING_RESULT {
+    unsafe fn wide_slice<'a>(string: PCWSTR, len: i32) -> Option<&'a [u16]> {
+        let len = match len {
+            -1 => (0..).take_while(|&i| *string.add(i) != 0).count(),
+            len => usize::try_from(len).ok()?,
+        };
+        Some(crate::slice::from_raw_parts(string, len))
+    }
+
+    let (Some(string1), Some(string2)) = (wide_slice(string1, len1), wide_slice(string2, len2))
+    else {
+        SetLastError(ERROR_INVALID_PARAMETER);
+        return 0;
+    };
+    let fold = |&unit: &u16| if ignore_case != FALSE { ordinal_uppercase(unit) } else { unit };
+    match string1.iter().map(fold).cmp(string2.iter().map(fold)) {
+        crate::cmp::Ordering::Less => CSTR_LESS_THAN,
+        crate::cmp::Ordering::Equal => CSTR_EQUAL,
+        crate::cmp::Ordering::Greater => CSTR_GREATER_THAN,
+    }
+}
+
+/// Uppercases a UTF-16 code unit the way ordinal comparisons ignore case: one unit at a time,
+/// leaving surrogates alone.
+///
+/// Windows' own mapping only pairs up characters that map to each other both ways, so e.g. the
+/// dotless i and the long s aren't the same as `I` and `S`, even though Unicode uppercases them
+/// to those.
+fn ordinal_uppercase(unit: u16) -> u16 {
+    let Some(c) = char::from_u32(unit as u32) else { return unit };
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) if u == c || u.to_lowercase().eq([c]) => {
+            u16::try_from(u as u32).unwrap_or(unit)
+        }
+        _ => unit,
+    }
+}
+
+/// The `NTSTATUS` values we can run into, mapped onto the Win32 errors `RtlNtStatusToDosError`
+/// returns for them.
+const NT_STATUS_TO_DOS_ERROR: &[(NTSTATUS, u32)] = &[
+    (STATUS_SUCCESS, ERROR_SUCCESS),
+    (STATUS_PENDING, ERROR_IO_PENDING),
+    (STATUS_END_OF_FILE, ERROR_HANDLE_EOF),
+    (STATUS_OBJECT_NAME_NOT_FOUND, ERROR_FILE_NOT_FOUND),
+    (STATUS_OBJECT_PATH_NOT_FOUND, ERROR_PATH_NOT_FOUND),
+    (STATUS_OBJECT_NAME_COLLISION, ERROR_ALREADY_EXISTS),
+    (STATUS_ACCESS_DENIED, ERROR_ACCESS_DENIED),
+    (STATUS_CANNOT_DELETE, ERROR_ACCESS_DENIED),
+    (STATUS_SHARING_VIOLATION, ERROR_SHARING_VIOLATION),
+    (STATUS_NOT_IMPLEMENTED, ERROR_INVALID_FUNCTION),
+    (STATUS_INVALID_PARAMETER, ERROR_INVALID_PARAMETER),
+    (STATUS_NO_MEMORY, ERROR_NOT_ENOUGH_MEMORY),
+];
+
+/// Converts an `NTSTATUS` to a Win32 error code without `ntdll`.
+///
+/// Unknown values map to `ERROR_MR_MID_NOT_FOUND`, just like with the real function.
+pub fn nt_status_to_dos_error(status: NTSTATUS) -> u32 {
+    NT_STATUS_TO_DOS_ERROR
+        .iter()
+        .find(|&&(s, _)| s == status)
+        .map_or(ERROR_MR_MID_NOT_FOUND, |&(_, error)| error)
+}
+
 impl UNICODE_STRING {
     pub fn from_ref(slice: &[u16]) -> Self {
         let len = mem::size_of_val(slice);
@@ -197,6 +303,7 @@ pub struct in6_addr {
 cfg_if::cfg_if! {
 if #[cfg(not(target_vendor = "uwp"))] {
     pub const EXCEPTION_CONTINUE_SEARCH: i32 = 0;
+    pub const EXCEPTION_CONTINUE_EXECUTION: i32 = -1;
 }
 }
 
@@ -322,7 +429,7 @@ pub unsafe fn NtWriteFile(
 // Functions that aren't available on every version of Windows that we support,
 // but we still use them and just provide some form of a fallback implementation.
 compat_fn_with_fallback! {
-    pub static KERNEL32: &CStr = c"kernel32" => { load: false, unicows: false };
+    pub static KERNEL32: &CStr = c"kernel32" => { loaded: false, unicows: false };
 
     // >= Win10 1607
     // https://docs.microsoft.com/en-us/windows/win32/api/processthreadsapi/nf-processthreadsapi-setthreaddescription
@@ -362,10 +469,12 @@ pub fn SetFilePointerEx(
         let lDistanceToMove = lidistancetomove as i32;
         let mut distance_to_move_high = (lidistancetomove >> 32) as i32;
 
-        let newPos_low = SetFilePointer(hfile, lDistanceToMove, &mut distance_to_move_high, dwmovemethod);
-
         // since (-1 as u32) could be a valid value for the lower 32 bits of the new file pointer
-        // position, a call to GetLastError is needed to actually see if it failed
+        // position, a call to GetLastError is needed to actually see if it failed. SetFilePointer
+        // doesn't clear the last error on success, so an earlier error mustn't be mistaken for
+        // one.
+        SetLastError(NO_ERROR);
+        let newPos_low = SetFilePointer(hfile, lDistanceToMove, &mut distance_to_move_high, dwmovemethod);
         if newPos_low == INVALID_SET_FILE_POINTER && GetLastError() != NO_ERROR {
             return FALSE;
         }
@@ -394,7 +503,11 @@ pub fn CreateHardLinkW(
         lpfilename: PCWSTR,
         lpexistingfilename: PCWSTR,
         lpsecurityattributes: *const SECURITY_ATTRIBUTES,
-    ) -> BOOL {
+    ) -> BOOL => probe(|create_hard_link| {
+        // Linking "" to "" only fails with "not implemented" if that's all it does.
+        let empty: &[u16] = &[0];
+        unsafe { create_hard_link(empty.as_ptr(), empty.as_ptr(), ptr::null()) != FALSE }
+    }) {
         SetLastError(ERROR_CALL_NOT_IMPLEMENTED);
         FALSE
     }
@@ -424,6 +537,17 @@ pub fn FreeEnvironmentStringsW(penv: PCWSTR) -> BOOL {
         TRUE
     }
 
+    // >= NT 4, 98
+    // https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-createwaitabletimerw
+    pub fn CreateWaitableTimerW(
+        lptimerattributes: *const SECURITY_ATTRIBUTES,
+        bmanualreset: BOOL,
+        lptimername: PCWSTR,
+    ) -> HANDLE {
+        SetLastError(ERROR_CALL_NOT_IMPLEMENTED);
+        ptr::null_mut()
+    }
+
     // >= Vista / Server 2008
     // https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-createwaitabletimerexw
     pub fn CreateWaitableTimerExW(
@@ -434,10 +558,202 @@ pub fn CreateWaitableTimerExW(
     ) -> HANDLE {
         ptr::null_mut()
     }
+
+    // >= Vista / Server 2008
+    // https://docs.microsoft.com/en-us/windows/win32/api/stringapiset/nf-stringapiset-comparestringordinal
+    pub fn CompareStringOrdinal(
+        lpstring1: PCWSTR,
+        cchcount1: i32,
+        lpstring2: PCWSTR,
+        cchcount2: i32,
+        bignorecase: BOOL,
+    ) -> COMPARESTRING_RESULT {
+        compare_string_ordinal(lpstring1, cchcount1, lpstring2, cchcount2, bignorecase)
+    }
+
+    // >= Vista / Server 2008
+    // https://learn.microsoft.com/en-us/windows/win32/api/sysinfoapi/nf-sysinfoapi-gettickcount64
+    pub fn GetTickCount64() -> u64 {
+        static LAST_TICK_COUNT: AtomicU64 = AtomicU64::new(0);
+        extend_tick_count(&LAST_TICK_COUNT, GetTickCount())
+    }
+}
+
+/// What the wide file functions are probed with: an empty path, which the real functions reject
+/// with a different error than "not implemented".
+const PROBE_PATH: &[u16] = &[0];
+
+// Wide functions that 9x/ME only provide through unicows. Without it, we fall back to converting
+// the arguments to the active ANSI code page and calling the ANSI version, see `ansi`.
+//
+// 9x/ME kernel32 exports the file functions as stubs that fail with ERROR_CALL_NOT_IMPLEMENTED,
+// so those are probed.
+compat_fn_with_fallback! {
+    pub static KERNEL32_WIDE: &CStr = c"kernel32" => { loaded: false, unicows: true };
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-deletefilew
+    pub fn DeleteFileW(lpfilename: PCWSTR) -> BOOL => probe(|delete_file| {
+        unsafe { delete_file(PROBE_PATH.as_ptr()) != FALSE }
+    }) {
+        ansi::DeleteFileW(lpfilename)
+    }
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-createdirectoryw
+    pub fn CreateDirectoryW(
+        lppathname: PCWSTR,
+        lpsecurityattributes: *const SECURITY_ATTRIBUTES,
+    ) -> BOOL => probe(|create_directory| {
+        unsafe { create_directory(PROBE_PATH.as_ptr(), ptr::null()) != FALSE }
+    }) {
+        ansi::CreateDirectoryW(lppathname, lpsecurityattributes)
+    }
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-removedirectoryw
+    pub fn RemoveDirectoryW(lppathname: PCWSTR) -> BOOL => probe(|remove_directory| {
+        unsafe { remove_directory(PROBE_PATH.as_ptr()) != FALSE }
+    }) {
+        ansi::RemoveDirectoryW(lppathname)
+    }
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-getfileattributesw
+    pub fn GetFileAttributesW(lpfilename: PCWSTR) -> u32 => probe(|get_file_attributes| {
+        unsafe { get_file_attributes(PROBE_PATH.as_ptr()) != INVALID_FILE_ATTRIBUTES }
+    }) {
+        ansi::GetFileAttributesW(lpfilename)
+    }
+
+    // >= NT 4, 98+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-getfileattributesexw
+    pub fn GetFileAttributesExW(
+        lpfilename: PCWSTR,
+        finfolevelid: GET_FILEEX_INFO_LEVELS,
+        lpfileinformation: *mut ::core::ffi::c_void,
+    ) -> BOOL => probe(|get_file_attributes_ex| {
+        let mut data: WIN32_FILE_ATTRIBUTE_DATA = unsafe { mem::zeroed() };
+        let data = (&mut data as *mut WIN32_FILE_ATTRIBUTE_DATA).cast();
+        unsafe { get_file_attributes_ex(PROBE_PATH.as_ptr(), GetFileExInfoStandard, data) != FALSE }
+    }) {
+        ansi::GetFileAttributesExW(lpfilename, finfolevelid, lpfileinformation)
+    }
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-setfileattributesw
+    pub fn SetFileAttributesW(
+        lpfilename: PCWSTR,
+        dwfileattributes: FILE_FLAGS_AND_ATTRIBUTES,
+    ) -> BOOL => probe(|set_file_attributes| {
+        unsafe { set_file_attributes(PROBE_PATH.as_ptr(), 0) != FALSE }
+    }) {
+        ansi::SetFileAttributesW(lpfilename, dwfileattributes)
+    }
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-findfirstfilew
+    pub fn FindFirstFileW(
+        lpfilename: PCWSTR,
+        lpfindfiledata: *mut WIN32_FIND_DATAW,
+    ) -> HANDLE => probe(|find_first_file| {
+        let mut data: WIN32_FIND_DATAW = unsafe { mem::zeroed() };
+        let handle = unsafe { find_first_file(PROBE_PATH.as_ptr(), &mut data) };
+        if handle == INVALID_HANDLE_VALUE {
+            return false;
+        }
+        unsafe { FindClose(handle) };
+        true
+    }) {
+        ansi::FindFirstFileW(lpfilename, lpfindfiledata)
+    }
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-findnextfilew
+    pub fn FindNextFileW(
+        hfindfile: HANDLE,
+        lpfindfiledata: *mut WIN32_FIND_DATAW,
+    ) -> BOOL => probe(|find_next_file| {
+        let mut data: WIN32_FIND_DATAW = unsafe { mem::zeroed() };
+        unsafe { find_next_file(INVALID_HANDLE_VALUE, &mut data) != FALSE }
+    }) {
+        ansi::FindNextFileW(hfindfile, lpfindfiledata)
+    }
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/libloaderapi/nf-libloaderapi-getmodulefilenamew
+    pub fn GetModuleFileNameW(hmodule: HMODULE, lpfilename: PWSTR, nsize: u32) -> u32 {
+        ansi::GetModuleFileNameW(hmodule, lpfilename, nsize)
+    }
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-formatmessagew
+    pub fn FormatMessageW(
+        dwflags: FORMAT_MESSAGE_OPTIONS,
+        lpsource: *const ::core::ffi::c_void,
+        dwmessageid: u32,
+        dwlanguageid: u32,
+        lpbuffer: PWSTR,
+        nsize: u32,
+        arguments: *const *const i8,
+    ) -> u32 {
+        ansi::FormatMessageW(
+            dwflags,
+            lpsource,
+            dwmessageid,
+            dwlanguageid,
+            lpbuffer,
+            nsize,
+            arguments,
+        )
+    }
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/processenv/nf-processenv-getenvironmentvariablew
+    pub fn GetEnvironmentVariableW(lpname: PCWSTR, lpbuffer: PWSTR, nsize: u32) -> u32 {
+        ansi::GetEnvironmentVariableW(lpname, lpbuffer, nsize)
+    }
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/processenv/nf-processenv-setenvironmentvariablew
+    pub fn SetEnvironmentVariableW(lpname: PCWSTR, lpvalue: PCWSTR) -> BOOL {
+        ansi::SetEnvironmentVariableW(lpname, lpvalue)
+    }
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/processenv/nf-processenv-getenvironmentstringsw
+    pub fn GetEnvironmentStringsW() -> PWSTR {
+        ansi::GetEnvironmentStringsW()
+    }
+
+    // >= NT 3.1, 95+ with unicows
+    // https://learn.microsoft.com/en-us/windows/win32/api/processenv/nf-processenv-getcommandlinew
+    pub fn GetCommandLineW() -> PCWSTR {
+        ansi::GetCommandLineW()
+    }
+}
+
+// Functions that every version exports in both variants, but whose wide variant is a stub on
+// 9x/ME. These pick the variant at runtime, see `compat_fn_aw`.
+compat_fn_aw! {
+    #[link(name = "kernel32")]
+    // https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-createeventw
+    pub fn CreateEvent = CreateEventA / CreateEventW(
+        lpeventattributes: *const SECURITY_ATTRIBUTES,
+        bmanualreset: BOOL,
+        binitialstate: BOOL,
+        [ansi] lpname: PCWSTR,
+    ) -> HANDLE => fail(ptr::null_mut());
+    // https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-createmutexw
+    pub fn CreateMutex = CreateMutexA / CreateMutexW(
+        lpmutexattributes: *const SECURITY_ATTRIBUTES,
+        binitialowner: BOOL,
+        [ansi] lpname: PCWSTR,
+    ) -> HANDLE => fail(ptr::null_mut());
 }
 
 compat_fn_lazy! {
-    pub static KERNEL32: &CStr = c"kernel32" => { load: false, unicows: false };
+    pub static KERNEL32_LAZY: &CStr = c"kernel32" => { loaded: false, unicows: false };
     // >= Vista / Server 2008 (XP / Server 2003 when linking a supported FileExtd.lib)
     // https://docs.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-setfileinformationbyhandle
     pub fn SetFileInformationByHandle(
@@ -473,17 +789,11 @@ pub fn CopyFileExW(
         lpdata: *const ::core::ffi::c_void,
         pbcancel: *mut BOOL,
         dwcopyflags: u32,
-    ) -> BOOL;
-
-    // >= Vista / Server 2008
-    // https://docs.microsoft.com/en-us/windows/win32/api/stringapiset/nf-stringapiset-comparestringordinal
-    pub fn CompareStringOrdinal(
-        lpstring1: PCWSTR,
-        cchcount1: i32,
-        lpstring2: PCWSTR,
-        cchcount2: i32,
-        bignorecase: BOOL,
-    ) -> COMPARESTRING_RESULT;
+    ) -> BOOL => probe(|copy_file_ex| {
+        // Copying "" to "" only fails with "not implemented" if that's all it does.
+        let empty: &[u16] = &[0];
+        unsafe { copy_file_ex(empty.as_ptr(), empty.as_ptr(), None, ptr::null(), ptr::null_mut(), 0) != FALSE }
+    });
 
     // >= NT4+, 98+
     // https://learn.microsoft.com/en-us/windows/win32/fileio/cancelio
@@ -513,8 +823,110 @@ pub fn UpdateProcThreadAttribute(
     // >= Vista / Server 2008
     // https://learn.microsoft.com/en-us/windows/win32/api/processthreadsapi/nf-processthreadsapi-deleteprocthreadattributelist
     pub fn DeleteProcThreadAttributeList(lpattributelist: LPPROC_THREAD_ATTRIBUTE_LIST) -> ();
+
+    // >= Win10 1607
+    // https://learn.microsoft.com/en-us/windows/win32/api/processthreadsapi/nf-processthreadsapi-getthreaddescription
+    pub fn GetThreadDescription(hthread: HANDLE, ppszthreaddescription: *mut PWSTR) -> HRESULT;
+
+    // >= XP SP1 / Server 2003
+    // https://learn.microsoft.com/en-us/windows/win32/api/processthreadsapi/nf-processthreadsapi-getprocesshandlecount
+    pub fn GetProcessHandleCount(hprocess: HANDLE, pdwhandlecount: *mut u32) -> BOOL;
+
+    // >= NT 3.1; 9x/ME only have a stub
+    // https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-getprocessaffinitymask
+    pub fn GetProcessAffinityMask(
+        hprocess: HANDLE,
+        lpprocessaffinitymask: *mut usize,
+        lpsystemaffinitymask: *mut usize,
+    ) -> BOOL;
+
+    // >= XP / Server 2003
+    // https://learn.microsoft.com/en-us/windows/win32/api/sysinfoapi/nf-sysinfoapi-getnativesysteminfo
+    pub fn GetNativeSystemInfo(lpsysteminfo: *mut SYSTEM_INFO) -> ();
+
+    // >= NT 4, 98
+    // https://learn.microsoft.com/en-us/windows/win32/api/debugapi/nf-debugapi-isdebuggerpresent
+    pub fn IsDebuggerPresent() -> BOOL;
+
+    // >= NT 3.5; exported but not implemented on 9x/ME
+    // https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-lockfileex
+    pub fn LockFileEx(
+        hfile: HANDLE,
+        dwflags: LOCK_FILE_FLAGS,
+        dwreserved: u32,
+        nnumberofbytestolocklow: u32,
+        nnumberofbytestolockhigh: u32,
+        lpoverlapped: *mut OVERLAPPED,
+    ) -> BOOL;
 }
 
+// No system has this, so tests can see how a missing function behaves.
+#[cfg(test)]
+compat_fn_lazy! {
+    pub static TEST_LAZY: &CStr = c"kernel32" => { loaded: false, unicows: false };
+    pub fn RustCompatMissingFunction(value: u32) -> u32;
+}
+
+// Winsock has kept its ordinals since 1.1, where 111 is `WSAGetLastError`.
+#[cfg(test)]
+compat_fn_lazy! {
+    pub static WS2_32: &CStr = c"ws2_32" => { loaded: false, unicows: false };
+    pub fn RustCompatByOrdinal() -> i32 => ordinal 111;
+}
+
+// Only used by one test, so that it's still unloaded when that test starts.
+#[cfg(test)]
+compat_fn_with_fallback! {
+    pub static TEST_OEMCP: &CStr = c"kernel32" => { loaded: false, unicows: true };
+    pub fn GetOEMCP() -> u32 {
+        0
+    }
+}
+
+// Only used by one test, so that it's still unloaded when that test starts.
+#[cfg(test)]
+compat_fn_with_fallback! {
+    pub static TEST_PROCESSOR_NUMBER: &CStr = c"kernel32" => { loaded: false, unicows: false };
+    pub fn GetCurrentProcessorNumber() -> u32 {
+        0
+    }
+}
+
+// Only looked up through a fake resolver, by one test.
+#[cfg(test)]
+compat_fn_with_fallback! {
+    pub static TEST_STUB: &CStr = c"kernel32" => { loaded: false, unicows: false };
+    pub fn RustCompatStub(value: u32) -> BOOL => probe(|stub| unsafe { stub(0) } != FALSE) {
+        value as BOOL
+    }
+}
+
+// A module that never exists, so the fallback is always used.
+#[cfg(test)]
+compat_fn_with_fallback! {
+    pub static MISSING: &CStr = c"rust-compat-missing" => { loaded: true, unicows: false };
+    pub fn RustCompatNoModule() -> u32 {
+        7
+    }
+}
+
+// Never exported by anything, and only loaded by one test, which calls the loader itself.
+#[cfg(test)]
+compat_fn_optional! {
+    ();
+    pub fn RustCompatOptional() -> u32;
+}
+
+// Not used by anything but one test, so it's only loaded when that test calls it.
+#[cfg(all(test, feature = "windows_minimal_preload"))]
+compat_fn_optional! {
+    crate::sys::compat::load_logical_drives_function();
+    // >= NT 3.1, 95
+    // https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-getlogicaldrives
+    pub fn GetLogicalDrives() -> u32;
+}
+
+#[cfg(not(windows_min_version = "8"))]
 compat_fn_optional! {
     crate::sys::compat::load_synch_functions();
     pub fn WaitOnAddress(
@@ -526,6 +938,19 @@ pub fn WaitOnAddress(
     pub fn WakeByAddressSingle(address: *const ::core::ffi::c_void);
 }
 
+// Builds for Windows 8 and later import these like any other function.
+#[cfg(windows_min_version = "8")]
+#[link(name = "synchronization")]
+extern "system" {
+    pub fn WaitOnAddress(
+        address: *const ::core::ffi::c_void,
+        compareaddress: *const ::core::ffi::c_void,
+        addresssize: usize,
+        dwmilliseconds: u32,
+    ) -> BOOL;
+    pub fn WakeByAddressSingle(address: *const ::core::ffi::c_void);
+}
+
 compat_fn_optional! {
     crate::sys::compat::load_try_enter_critical_section_function();
     // >= NT 4
@@ -556,7 +981,7 @@ pub fn SleepConditionVariableSRW(
 }
 
 compat_fn_lazy! {
-    pub static USERENV: &CStr = c"userenv" => { load: true, unicows: false };
+    pub static USERENV: &CStr = c"userenv" => { loaded: true, unicows: false };
 
     // >= NT4
     // https://learn.microsoft.com/en-us/windows/win32/api/userenv/nf-userenv-getuserprofiledirectoryw
@@ -568,7 +993,7 @@ pub fn GetUserProfileDirectoryW(
 }
 
 compat_fn_with_fallback! {
-    pub static BCRYPT: &CStr = c"bcrypt" => { load: true, unicows: false };
+    pub static BCRYPT: &CStr = c"bcrypt" => { loaded: true, unicows: false };
 
     // >= Vista / Server 2008
     // https://learn.microsoft.com/en-us/windows/win32/api/bcrypt/nf-bcrypt-bcryptgenrandom
@@ -587,18 +1012,40 @@ pub fn BCryptGenRandom(
 }
 
 compat_fn_lazy! {
-    pub static ADVAPI32: &CStr = c"advapi32" => { load: true, unicows: false };
+    pub static BCRYPT_LAZY: &CStr = c"bcrypt" => { loaded: true, unicows: false };
 
-    // NT only
+    // >= Vista / Server 2008
+    // https://learn.microsoft.com/en-us/windows/win32/api/bcrypt/nf-bcrypt-bcryptopenalgorithmprovider
+    pub fn BCryptOpenAlgorithmProvider(
+        phalgorithm: *mut BCRYPT_ALG_HANDLE,
+        pszalgid: PCWSTR,
+        pszimplementation: PCWSTR,
+        dwflags: BCRYPT_OPEN_ALGORITHM_PROVIDER_FLAGS,
+    ) -> NTSTATUS;
+}
+
+compat_fn_lazy! {
+    pub static BCRYPTPRIMITIVES: &CStr = c"bcryptprimitives" => { loaded: false, unicows: false };
+
+    // >= 8 / Server 2012, also allowed on UWP
+    // https://learn.microsoft.com/en-us/windows/win32/seccng/processprng
+    pub fn ProcessPrng(pbdata: *mut u8, cbdata: usize) -> BOOL;
+}
+
+compat_fn_with_fallback! {
+    pub static ADVAPI32: &CStr = c"advapi32" => { loaded: true, unicows: false };
+
+    // NT only. Callers should check `available()` and take a token-less path instead, the
+    // fallback only makes sure that stray calls fail instead of jumping through a null pointer.
     // https://learn.microsoft.com/en-us/windows/win32/api/processthreadsapi/nf-processthreadsapi-openprocesstoken
     pub fn OpenProcessToken(
         processhandle: HANDLE,
         desiredaccess: TOKEN_ACCESS_MASK,
         tokenhandle: *mut HANDLE,
-    ) -> BOOL;
-}
-compat_fn_with_fallback! {
-    pub static ADVAPI32: &CStr = c"advapi32" => { load: true, unicows: false };
+    ) -> BOOL {
+        SetLastError(ERROR_CALL_NOT_IMPLEMENTED);
+        FALSE
+    }
 
     // >= XP / Server 2003
     // https://learn.microsoft.com/en-us/windows/win32/api/ntsecapi/nf-ntsecapi-rtlgenrandom
@@ -606,6 +1053,33 @@ pub fn SystemFunction036(randombuffer: *mut ::core::ffi::c_void, randombufferlen
     -> BOOLEAN {
         0
     }
+
+    // >= NT 4, 95 OSR2 (95 with IE 3.02)
+    // https://learn.microsoft.com/en-us/windows/win32/api/wincrypt/nf-wincrypt-cryptacquirecontexta
+    pub fn CryptAcquireContextA(
+        phprov: *mut usize,
+        szcontainer: PCSTR,
+        szprovider: PCSTR,
+        dwprovtype: u32,
+        dwflags: u32,
+    ) -> BOOL {
+        SetLastError(ERROR_CALL_NOT_IMPLEMENTED);
+        FALSE
+    }
+
+    // >= NT 4, 95 OSR2 (95 with IE 3.02)
+    // https://learn.microsoft.com/en-us/windows/win32/api/wincrypt/nf-wincrypt-cryptgenrandom
+    pub fn CryptGenRandom(hprov: usize, dwlen: u32, pbbuffer: *mut u8) -> BOOL {
+        SetLastError(ERROR_CALL_NOT_IMPLEMENTED);
+        FALSE
+    }
+
+    // >= NT 4, 95 OSR2 (95 with IE 3.02)
+    // https://learn.microsoft.com/en-us/windows/win32/api/wincrypt/nf-wincrypt-cryptreleasecontext
+    pub fn CryptReleaseContext(hprov: usize, dwflags: u32) -> BOOL {
+        SetLastError(ERROR_CALL_NOT_IMPLEMENTED);
+        FALSE
+    }
 }
 pub const RtlGenRandom: unsafe fn(
     randombuffer: *mut ::core::ffi::c_void,
@@ -613,7 +1087,7 @@ pub fn SystemFunction036(randombuffer: *mut ::core::ffi::c_void, randombufferlen
 ) -> BOOLEAN = SystemFunction036;
 
 compat_fn_lazy! {
-    pub static NTDLL: &CStr = c"ntdll" => { load: true, unicows: false };
+    pub static NTDLL_LAZY: &CStr = c"ntdll" => { loaded: true, unicows: false };
 
     // NT only
     pub fn NtCreateFile(
@@ -629,12 +1103,23 @@ pub fn NtCreateFile(
         eabuffer: *const ::core::ffi::c_void,
         ealength: u32,
     ) -> NTSTATUS;
+    // NT only
+    pub fn NtSetInformationFile(
+        filehandle: HANDLE,
+        iostatusblock: *mut IO_STATUS_BLOCK,
+        fileinformation: *const ::core::ffi::c_void,
+        length: u32,
+        fileinformationclass: FILE_INFORMATION_CLASS,
+    ) -> NTSTATUS;
+    // >= 2000
+    // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-rtlgetversion
+    pub fn RtlGetVersion(lpversioninformation: *mut OSVERSIONINFOW) -> NTSTATUS;
 }
 
 pub mod ntdll {
     use super::*;
     compat_fn_lazy! {
-        pub static NTDLL: &CStr = c"ntdll" => { load: true, unicows: false };
+        pub static NTDLL: &CStr = c"ntdll" => { loaded: true, unicows: false };
 
         // NT only
         pub fn NtReadFile(
@@ -662,7 +1147,7 @@ pub fn NtWriteFile(
     }
 }
 compat_fn_with_fallback! {
-    pub static NTDLL: &CStr = c"ntdll" => { load: true, unicows: false };
+    pub static NTDLL: &CStr = c"ntdll" => { loaded: true, unicows: false };
 
     pub fn NtCreateKeyedEvent(
         KeyedEventHandle: LPHANDLE,
@@ -689,7 +1174,7 @@ pub fn NtWaitForKeyedEvent(
         panic!("keyed events not available")
     }
     pub fn RtlNtStatusToDosError(Status: NTSTATUS) -> u32 {
-        Status as u32
+        nt_status_to_dos_error(Status)
     }
 }
 
@@ -713,6 +1198,9 @@ pub fn AddVectoredExceptionHandler(
             first: u32,
             handler: PVECTORED_EXCEPTION_HANDLER,
         ) -> *mut c_void;
+        // >= XP
+        // https://learn.microsoft.com/en-us/windows/win32/api/errhandlingapi/nf-errhandlingapi-removevectoredexceptionhandler
+        pub fn RemoveVectoredExceptionHandler(handle: *const c_void) -> u32;
     }
 
     pub type PVECTORED_EXCEPTION_HANDLER = Option<
@@ -746,7 +1234,7 @@ pub struct WSADATA {
 mod ws2_32 {
     use super::*;
     compat_fn_with_fallback! {
-        pub static WS2_32: &CStr = c"ws2_32" => { load: true, unicows: false };
+        pub static WS2_32: &CStr = c"ws2_32" => { loaded: true, unicows: false };
 
         // >= NT4/2000 with IPv6 Tech Preview
         pub fn getaddrinfo(
@@ -761,16 +1249,32 @@ pub fn getaddrinfo(
         pub fn freeaddrinfo(paddrinfo: *const ADDRINFOA) -> () {
             wship6::freeaddrinfo(paddrinfo)
         }
+        // >= Vista
+        pub fn inet_ntop(
+            family: i32,
+            paddr: *const c_void,
+            pstringbuf: PSTR,
+            stringbufsize: usize,
+        ) -> PCSTR {
+            inet::inet_ntop(family, paddr, pstringbuf, stringbufsize)
+        }
+        // >= Vista
+        pub fn inet_pton(family: i32, pszaddrstring: PCSTR, paddrbuf: *mut c_void) -> i32 {
+            inet::inet_pton(family, pszaddrstring, paddrbuf)
+        }
     }
 }
 pub use ws2_32::freeaddrinfo;
+// std formats and parses addresses itself, so these are only here for whoever needs them.
+#[allow(unused_imports)]
+pub use ws2_32::{inet_ntop, inet_pton};
 
 mod wship6 {
     use super::wspiapi::{wspiapi_freeaddrinfo, wspiapi_getaddrinfo};
     use super::{ADDRINFOA, PCSTR};
 
     compat_fn_with_fallback! {
-        pub static WSHIP6: &CStr = c"wship6" => { load: true, unicows: false };
+        pub static WSHIP6: &CStr = c"wship6" => { loaded: true, unicows: false };
 
         // >= 2000 with IPv6 Tech Preview
         pub fn getaddrinfo(
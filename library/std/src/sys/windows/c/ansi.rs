@@ -0,0 +1,665 @@
+//! Emulation of wide-character APIs on top of their ANSI counterparts.
+//!
+//! 9x/ME only implement the `...A` variants of most functions, the `...W` exports are stubs that
+//! fail with `ERROR_CALL_NOT_IMPLEMENTED`. The functions in here convert their arguments to the
+//! active ANSI code page and call the ANSI API instead, keeping the signature of the wide API so
+//! they can be used as drop-in fallbacks.
+
+use super::{
+    CreateDirectoryA, CreateFileA, DeleteFileA, FindClose, FindFirstFileA, FindNextFileA,
+    FormatMessageA, FreeEnvironmentStringsA, GetCommandLineA, GetEnvironmentStrings,
+    GetEnvironmentVariableA, GetFileAttributesA, GetFileExInfoStandard, GetLastError,
+    GetModuleFileNameA, LocalAlloc, LocalFree, MoveFileA, MultiByteToWideChar, RemoveDirectoryA,
+    SetEnvironmentVariableA, SetFileAttributesA, SetLastError, WideCharToMultiByte, BOOL, CP_ACP,
+    ERROR_ACCESS_DENIED, ERROR_ALREADY_EXISTS, ERROR_CALL_NOT_IMPLEMENTED, ERROR_ENVVAR_NOT_FOUND,
+    ERROR_FILENAME_EXCED_RANGE, ERROR_FILE_EXISTS, ERROR_FILE_NOT_FOUND, ERROR_INSUFFICIENT_BUFFER,
+    ERROR_INVALID_NAME, ERROR_INVALID_PARAMETER, ERROR_NO_MORE_FILES, ERROR_NO_UNICODE_TRANSLATION,
+    FALSE, FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_READONLY, FILE_CREATION_DISPOSITION,
+    FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_MODE, FORMAT_MESSAGE_ALLOCATE_BUFFER,
+    FORMAT_MESSAGE_OPTIONS, GET_FILEEX_INFO_LEVELS, HANDLE, HMODULE, INVALID_FILE_ATTRIBUTES,
+    INVALID_HANDLE_VALUE, LMEM_FIXED, MAX_PATH, MOVEFILE_DELAY_UNTIL_REBOOT,
+    MOVEFILE_REPLACE_EXISTING, MOVE_FILE_FLAGS, PCSTR, PCWSTR, PSTR, PWSTR, SECURITY_ATTRIBUTES,
+    TRUE, WIN32_FILE_ATTRIBUTE_DATA, WIN32_FIND_DATAA, WIN32_FIND_DATAW,
+};
+use crate::cmp;
+use crate::ffi::{c_void, CStr};
+use crate::mem;
+use crate::ptr;
+use crate::slice;
+use crate::sync::atomic::{
+    AtomicPtr,
+    Ordering::{AcqRel, Acquire},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// A path converted to the active ANSI code page, null terminated.
+pub struct AnsiPath([u8; MAX_PATH as usize]);
+
+impl AnsiPath {
+    /// Converts a null terminated wide path.
+    ///
+    /// The ANSI APIs can't handle paths longer than `MAX_PATH`, so longer paths fail with
+    /// `ERROR_FILENAME_EXCED_RANGE` instead of being truncated. Characters that have no
+    /// representation in the code page fail with `ERROR_NO_UNICODE_TRANSLATION`, as the default
+    /// character (`?`) would silently refer to a different file or act as a wildcard.
+    pub unsafe fn new(path: PCWSTR) -> Result<Self, u32> {
+        let mut buf = [0; MAX_PATH as usize];
+        let mut used_default_char = FALSE;
+        let len = WideCharToMultiByte(
+            CP_ACP,
+            0,
+            path,
+            -1,
+            buf.as_mut_ptr(),
+            buf.len() as i32,
+            ptr::null(),
+            &mut used_default_char,
+        );
+        if len == 0 {
+            return Err(match GetLastError() {
+                ERROR_INSUFFICIENT_BUFFER => ERROR_FILENAME_EXCED_RANGE,
+                err => err,
+            });
+        }
+        if used_default_char != FALSE {
+            return Err(ERROR_NO_UNICODE_TRANSLATION);
+        }
+        Ok(Self(buf))
+    }
+
+    pub fn as_ptr(&self) -> PCSTR {
+        self.0.as_ptr()
+    }
+}
+
+/// An optional null terminated string, converted to the active ANSI code page for the `...A`
+/// variant of a function (see `compat_fn_aw!`).
+pub struct AnsiString(Option<Vec<u8>>);
+
+impl AnsiString {
+    /// Converts the null terminated wide string `s`, which may be null.
+    ///
+    /// Fails with `ERROR_NO_UNICODE_TRANSLATION` if a character has no representation in the code
+    /// page, see `wide_to_ansi`.
+    pub unsafe fn new(s: PCWSTR) -> Result<Self, u32> {
+        if s.is_null() {
+            return Ok(Self(None));
+        }
+        wide_to_ansi_str(s).map(|ansi| Self(Some(ansi)))
+    }
+
+    /// The converted string, or null if the wide one was null.
+    pub fn as_ptr(&self) -> PCSTR {
+        self.0.as_ref().map_or(ptr::null(), |ansi| ansi.as_ptr())
+    }
+}
+
+/// Converts UTF-16 to the active ANSI code page, keeping any embedded nulls.
+///
+/// Like with paths, characters that have no representation in the code page fail with
+/// `ERROR_NO_UNICODE_TRANSLATION` instead of being replaced by the default character.
+pub fn wide_to_ansi(wide: &[u16]) -> Result<Vec<u8>, u32> {
+    if wide.is_empty() {
+        return Ok(Vec::new());
+    }
+    unsafe {
+        let mut used_default_char = FALSE;
+        let len = WideCharToMultiByte(
+            CP_ACP,
+            0,
+            wide.as_ptr(),
+            wide.len() as i32,
+            ptr::null_mut(),
+            0,
+            ptr::null(),
+            &mut used_default_char,
+        );
+        if len == 0 {
+            return Err(GetLastError());
+        }
+        if used_default_char != FALSE {
+            return Err(ERROR_NO_UNICODE_TRANSLATION);
+        }
+        let mut ansi = Vec::with_capacity(len as usize);
+        let len = WideCharToMultiByte(
+            CP_ACP,
+            0,
+            wide.as_ptr(),
+            wide.len() as i32,
+            ansi.as_mut_ptr(),
+            len,
+            ptr::null(),
+            ptr::null_mut(),
+        );
+        if len == 0 {
+            return Err(GetLastError());
+        }
+        ansi.set_len(len as usize);
+        Ok(ansi)
+    }
+}
+
+/// Sets the last error and returns `ret`, for use in the error paths of the shims.
+unsafe fn fail<T>(code: u32, ret: T) -> T {
+    SetLastError(code);
+    ret
+}
+
+/// Converts a wide path argument, returning `$ret` with the last error set on failure.
+macro_rules! ansi_path {
+    ($path:expr, $ret:expr) => {
+        match AnsiPath::new($path) {
+            Ok(path) => path,
+            Err(code) => return fail(code, $ret),
+        }
+    };
+}
+
+/// `MoveFileExW` on top of `MoveFileA`.
+///
+/// `MOVEFILE_REPLACE_EXISTING` is emulated by deleting the destination first and then moving the
+/// file, which means that the replacement is *not* atomic: another process may observe the
+/// destination as missing, or create a new file in its place in between the two steps. If the
+/// destination can't be deleted (because it is a directory or still open, for example), the
+/// original `MoveFileA` error is returned and the destination is left untouched. Read-only
+/// destinations are replaced as well; if that fails, the read-only attribute is restored.
+///
+/// `MOVEFILE_DELAY_UNTIL_REBOOT` is not supported and fails with `ERROR_CALL_NOT_IMPLEMENTED`.
+/// All other flags are ignored.
+pub unsafe fn MoveFileExW(
+    lpexistingfilename: PCWSTR,
+    lpnewfilename: PCWSTR,
+    dwflags: MOVE_FILE_FLAGS,
+) -> BOOL {
+    if dwflags & MOVEFILE_DELAY_UNTIL_REBOOT != 0 {
+        return fail(ERROR_CALL_NOT_IMPLEMENTED, FALSE);
+    }
+
+    let existing = ansi_path!(lpexistingfilename, FALSE);
+    let new = ansi_path!(lpnewfilename, FALSE);
+
+    if MoveFileA(existing.as_ptr(), new.as_ptr()) != FALSE {
+        return TRUE;
+    }
+
+    let move_err = GetLastError();
+    if dwflags & MOVEFILE_REPLACE_EXISTING == 0
+        || !matches!(move_err, ERROR_ALREADY_EXISTS | ERROR_FILE_EXISTS)
+    {
+        return FALSE;
+    }
+
+    // Only files can be replaced, just like with the real `MoveFileExW`.
+    let attrs = GetFileAttributesA(new.as_ptr());
+    if attrs == INVALID_FILE_ATTRIBUTES || attrs & FILE_ATTRIBUTE_DIRECTORY != 0 {
+        return fail(move_err, FALSE);
+    }
+
+    if DeleteFileA(new.as_ptr()) == FALSE {
+        if attrs & FILE_ATTRIBUTE_READONLY == 0 || GetLastError() != ERROR_ACCESS_DENIED {
+            return fail(move_err, FALSE);
+        }
+
+        // Read-only files can't be deleted, so clear the attribute and try again.
+        if SetFileAttributesA(new.as_ptr(), attrs & !FILE_ATTRIBUTE_READONLY) == FALSE {
+            return fail(move_err, FALSE);
+        }
+        if DeleteFileA(new.as_ptr()) == FALSE {
+            SetFileAttributesA(new.as_ptr(), attrs);
+            return fail(move_err, FALSE);
+        }
+    }
+
+    // The destination is gone at this point, so there's nothing to restore if this fails.
+    MoveFileA(existing.as_ptr(), new.as_ptr())
+}
+
+/// Converts a null terminated string in the active ANSI code page to UTF-16, see
+/// `ansi_to_wide_vec`.
+///
+/// The result is always null terminated and truncated to fit into `dst`.
+unsafe fn ansi_to_wide(src: &[u8], dst: &mut [u16]) {
+    let wide = ansi_to_wide_vec(&src[..src.iter().position(|&c| c == 0).unwrap_or(src.len())]);
+    let len = cmp::min(wide.len(), dst.len() - 1);
+    dst[..len].copy_from_slice(&wide[..len]);
+    dst[len] = 0;
+}
+
+/// Converts `WIN32_FIND_DATAA` to `WIN32_FIND_DATAW`, including the 8.3 alternate name.
+unsafe fn find_data_to_wide(data: &WIN32_FIND_DATAA, wide: &mut WIN32_FIND_DATAW) {
+    wide.dwFileAttributes = data.dwFileAttributes;
+    wide.ftCreationTime = data.ftCreationTime;
+    wide.ftLastAccessTime = data.ftLastAccessTime;
+    wide.ftLastWriteTime = data.ftLastWriteTime;
+    wide.nFileSizeHigh = data.nFileSizeHigh;
+    wide.nFileSizeLow = data.nFileSizeLow;
+    wide.dwReserved0 = data.dwReserved0;
+    wide.dwReserved1 = data.dwReserved1;
+    ansi_to_wide(&data.cFileName, &mut wide.cFileName);
+    ansi_to_wide(&data.cAlternateFileName, &mut wide.cAlternateFileName);
+}
+
+/// `DeleteFileW` on top of `DeleteFileA`.
+pub unsafe fn DeleteFileW(lpfilename: PCWSTR) -> BOOL {
+    let path = ansi_path!(lpfilename, FALSE);
+    DeleteFileA(path.as_ptr())
+}
+
+/// `CreateFileW` on top of `CreateFileA`.
+pub unsafe fn CreateFileW(
+    lpfilename: PCWSTR,
+    dwdesiredaccess: u32,
+    dwsharemode: FILE_SHARE_MODE,
+    lpsecurityattributes: *const SECURITY_ATTRIBUTES,
+    dwcreationdisposition: FILE_CREATION_DISPOSITION,
+    dwflagsandattributes: FILE_FLAGS_AND_ATTRIBUTES,
+    htemplatefile: HANDLE,
+) -> HANDLE {
+    let path = ansi_path!(lpfilename, INVALID_HANDLE_VALUE);
+    CreateFileA(
+        path.as_ptr(),
+        dwdesiredaccess,
+        dwsharemode,
+        lpsecurityattributes,
+        dwcreationdisposition,
+        dwflagsandattributes,
+        htemplatefile,
+    )
+}
+
+/// `CreateDirectoryW` on top of `CreateDirectoryA`.
+pub unsafe fn CreateDirectoryW(
+    lppathname: PCWSTR,
+    lpsecurityattributes: *const SECURITY_ATTRIBUTES,
+) -> BOOL {
+    let path = ansi_path!(lppathname, FALSE);
+    CreateDirectoryA(path.as_ptr(), lpsecurityattributes)
+}
+
+/// `RemoveDirectoryW` on top of `RemoveDirectoryA`.
+pub unsafe fn RemoveDirectoryW(lppathname: PCWSTR) -> BOOL {
+    let path = ansi_path!(lppathname, FALSE);
+    RemoveDirectoryA(path.as_ptr())
+}
+
+/// `GetFileAttributesW` on top of `GetFileAttributesA`.
+pub unsafe fn GetFileAttributesW(lpfilename: PCWSTR) -> u32 {
+    let path = ansi_path!(lpfilename, INVALID_FILE_ATTRIBUTES);
+    GetFileAttributesA(path.as_ptr())
+}
+
+/// `SetFileAttributesW` on top of `SetFileAttributesA`.
+pub unsafe fn SetFileAttributesW(
+    lpfilename: PCWSTR,
+    dwfileattributes: FILE_FLAGS_AND_ATTRIBUTES,
+) -> BOOL {
+    let path = ansi_path!(lpfilename, FALSE);
+    SetFileAttributesA(path.as_ptr(), dwfileattributes)
+}
+
+/// Returns the null terminated wide string `s` without its terminator.
+unsafe fn wide_str<'a>(s: PCWSTR) -> &'a [u16] {
+    let len = (0..).take_while(|&i| *s.add(i) != 0).count();
+    slice::from_raw_parts(s, len)
+}
+
+fn is_sep(c: &u16) -> bool {
+    *c == b'\\' as u16 || *c == b'/' as u16
+}
+
+/// Returns the root directory of `path` (with a trailing backslash), or an empty slice if `path`
+/// is relative and thus on the current drive.
+fn root_of(path: &[u16]) -> &[u16] {
+    match path {
+        // `C:\...`
+        [_, colon, sep, ..] if *colon == b':' as u16 && is_sep(sep) => &path[..3],
+        // `\\server\share\...`: the root ends after the share name.
+        [a, b, rest @ ..] if is_sep(a) && is_sep(b) => {
+            let mut seps = rest.iter().enumerate().filter(|(_, c)| is_sep(c));
+            match seps.nth(1) {
+                Some((i, _)) => &path[..i + 3],
+                None => path,
+            }
+        }
+        _ => &[],
+    }
+}
+
+/// Whether `FindFirstFileA` can describe `path` itself. It can't for roots (`C:\`,
+/// `\\server\share`), bare drives (`C:`), paths ending in a separator and paths ending in a `.` or
+/// `..` component, as those don't have a directory entry of their own to return.
+fn is_findable(path: &[u16]) -> bool {
+    const DOT: u16 = b'.' as u16;
+    let is_root = !path.is_empty() && root_of(path).len() == path.len();
+    let is_drive = matches!(path, [_, colon] if *colon == b':' as u16);
+    let is_dots = matches!(path.rsplit(is_sep).next(), Some([DOT] | [DOT, DOT]));
+    !(is_root || is_drive || is_dots || path.last().is_some_and(is_sep))
+}
+
+/// `GetFileAttributesExW` on top of `FindFirstFileA`, as 95 doesn't have `GetFileAttributesExA`.
+///
+/// The find data of the path is returned, after closing the find handle right away. Where the
+/// find API can't describe the path (see `is_findable`), only the attributes are available
+/// through `GetFileAttributesA`, and the times and size are zero. Like the real API, wildcards
+/// fail with `ERROR_INVALID_NAME`, and a missing file with `ERROR_FILE_NOT_FOUND` or
+/// `ERROR_PATH_NOT_FOUND` if its directory is missing as well.
+pub unsafe fn GetFileAttributesExW(
+    lpfilename: PCWSTR,
+    finfolevelid: GET_FILEEX_INFO_LEVELS,
+    lpfileinformation: *mut c_void,
+) -> BOOL {
+    if finfolevelid != GetFileExInfoStandard {
+        return fail(ERROR_INVALID_PARAMETER, FALSE);
+    }
+    let wide = wide_str(lpfilename);
+    if wide.iter().any(|&c| c == b'*' as u16 || c == b'?' as u16) {
+        return fail(ERROR_INVALID_NAME, FALSE);
+    }
+    let path = ansi_path!(lpfilename, FALSE);
+    let info = &mut *lpfileinformation.cast::<WIN32_FILE_ATTRIBUTE_DATA>();
+
+    if !is_findable(wide) {
+        let attrs = GetFileAttributesA(path.as_ptr());
+        if attrs == INVALID_FILE_ATTRIBUTES {
+            return FALSE;
+        }
+        *info = WIN32_FILE_ATTRIBUTE_DATA { dwFileAttributes: attrs, ..mem::zeroed() };
+        return TRUE;
+    }
+
+    let mut data: WIN32_FIND_DATAA = mem::zeroed();
+    let handle = FindFirstFileA(path.as_ptr(), &mut data);
+    if handle == INVALID_HANDLE_VALUE {
+        // 9x/ME report a missing file in an existing directory as the end of the search.
+        return match GetLastError() {
+            ERROR_NO_MORE_FILES => fail(ERROR_FILE_NOT_FOUND, FALSE),
+            _ => FALSE,
+        };
+    }
+    FindClose(handle);
+    *info = WIN32_FILE_ATTRIBUTE_DATA {
+        dwFileAttributes: data.dwFileAttributes,
+        ftCreationTime: data.ftCreationTime,
+        ftLastAccessTime: data.ftLastAccessTime,
+        ftLastWriteTime: data.ftLastWriteTime,
+        nFileSizeHigh: data.nFileSizeHigh,
+        nFileSizeLow: data.nFileSizeLow,
+    };
+    TRUE
+}
+
+/// `FindFirstFileW` on top of `FindFirstFileA`.
+///
+/// File names are converted from the active ANSI code page, so the returned handle must only be
+/// used with the [`FindNextFileW`] shim below.
+pub unsafe fn FindFirstFileW(lpfilename: PCWSTR, lpfindfiledata: *mut WIN32_FIND_DATAW) -> HANDLE {
+    let path = ansi_path!(lpfilename, INVALID_HANDLE_VALUE);
+    let mut data: WIN32_FIND_DATAA = mem::zeroed();
+    let handle = FindFirstFileA(path.as_ptr(), &mut data);
+    if handle != INVALID_HANDLE_VALUE {
+        find_data_to_wide(&data, &mut *lpfindfiledata);
+    }
+    handle
+}
+
+/// `FindNextFileW` on top of `FindNextFileA`.
+pub unsafe fn FindNextFileW(hfindfile: HANDLE, lpfindfiledata: *mut WIN32_FIND_DATAW) -> BOOL {
+    let mut data: WIN32_FIND_DATAA = mem::zeroed();
+    let result = FindNextFileA(hfindfile, &mut data);
+    if result != FALSE {
+        find_data_to_wide(&data, &mut *lpfindfiledata);
+    }
+    result
+}
+
+/// `GetModuleFileNameW` on top of `GetModuleFileNameA`.
+///
+/// Keeps the truncation semantics of the real API: if the buffer is too small, the path is
+/// truncated to `nsize` characters without a null terminator and `nsize` is returned, with the
+/// last error set to `ERROR_INSUFFICIENT_BUFFER`. Bytes that don't map to a character in the
+/// active code page are converted to replacement characters.
+pub unsafe fn GetModuleFileNameW(hmodule: HMODULE, lpfilename: PWSTR, nsize: u32) -> u32 {
+    let mut ansi = [0u8; MAX_PATH as usize];
+    let len = GetModuleFileNameA(hmodule, ansi.as_mut_ptr(), ansi.len() as u32) as usize;
+    if len == 0 {
+        return 0;
+    }
+    module_file_name_to_wide(&ansi[..cmp::min(len, ansi.len())], lpfilename, nsize)
+}
+
+/// Stores the path `GetModuleFileNameA` returned into the buffer of [`GetModuleFileNameW`].
+pub unsafe fn module_file_name_to_wide(ansi: &[u8], lpfilename: PWSTR, nsize: u32) -> u32 {
+    let wide = ansi_to_wide_vec(ansi);
+    let dst = slice::from_raw_parts_mut(lpfilename, nsize as usize);
+    if wide.len() < dst.len() {
+        dst[..wide.len()].copy_from_slice(&wide);
+        dst[wide.len()] = 0;
+        SetLastError(0);
+        wide.len() as u32
+    } else {
+        dst.copy_from_slice(&wide[..dst.len()]);
+        fail(ERROR_INSUFFICIENT_BUFFER, nsize)
+    }
+}
+
+/// Converts a string in the active ANSI code page to UTF-16, without a null terminator.
+///
+/// Embedded nulls are kept. The conversion only fails on invalid sequences in code pages without a
+/// default character, in which case ASCII is passed through and everything else is replaced.
+unsafe fn ansi_to_wide_vec(src: &[u8]) -> Vec<u16> {
+    if src.is_empty() {
+        return Vec::new();
+    }
+    let len = MultiByteToWideChar(CP_ACP, 0, src.as_ptr(), src.len() as i32, ptr::null_mut(), 0);
+    if len == 0 {
+        return src
+            .iter()
+            .map(|&a| if a.is_ascii() { a as u16 } else { char::REPLACEMENT_CHARACTER as u16 })
+            .collect();
+    }
+    let mut wide = Vec::with_capacity(len as usize);
+    let len = MultiByteToWideChar(
+        CP_ACP,
+        0,
+        src.as_ptr(),
+        src.len() as i32,
+        wide.as_mut_ptr(),
+        wide.capacity() as i32,
+    );
+    wide.set_len(len as usize);
+    wide
+}
+
+/// `FormatMessageW` on top of `FormatMessageA`.
+///
+/// The ANSI message is always formatted into a buffer allocated by `FormatMessageA` and freed
+/// again after the conversion. With `FORMAT_MESSAGE_ALLOCATE_BUFFER`, the wide message is
+/// allocated with `LocalAlloc`, so the caller has to `LocalFree` it just like with the real API.
+/// Trailing line breaks are removed from the message. If `FormatMessageA` fails as well, a
+/// generic message is returned instead, as an empty message is of no use to anyone.
+pub unsafe fn FormatMessageW(
+    dwflags: FORMAT_MESSAGE_OPTIONS,
+    lpsource: *const c_void,
+    dwmessageid: u32,
+    dwlanguageid: u32,
+    lpbuffer: PWSTR,
+    nsize: u32,
+    arguments: *const *const i8,
+) -> u32 {
+    const UNKNOWN_ERROR: &[u8] = b"Unknown error";
+
+    let mut ansi: PSTR = ptr::null_mut();
+    let len = FormatMessageA(
+        dwflags | FORMAT_MESSAGE_ALLOCATE_BUFFER,
+        lpsource,
+        dwmessageid,
+        dwlanguageid,
+        ptr::addr_of_mut!(ansi).cast(),
+        0,
+        arguments,
+    );
+    let message = if len == 0 || ansi.is_null() {
+        UNKNOWN_ERROR
+    } else {
+        slice::from_raw_parts(ansi, len as usize)
+    };
+    let trimmed =
+        message.len() - message.iter().rev().take_while(|&&c| c == b'\r' || c == b'\n').count();
+    let mut wide = ansi_to_wide_vec(&message[..trimmed]);
+    wide.push(0);
+    if !ansi.is_null() {
+        LocalFree(ansi.cast());
+    }
+
+    let dst = if dwflags & FORMAT_MESSAGE_ALLOCATE_BUFFER != 0 {
+        let dst = LocalAlloc(LMEM_FIXED, wide.len() * mem::size_of::<u16>()).cast::<u16>();
+        if dst.is_null() {
+            return 0;
+        }
+        *lpbuffer.cast::<PWSTR>() = dst;
+        dst
+    } else if wide.len() > nsize as usize {
+        return fail(ERROR_INSUFFICIENT_BUFFER, 0);
+    } else {
+        lpbuffer
+    };
+    ptr::copy_nonoverlapping(wide.as_ptr(), dst, wide.len());
+    wide.len() as u32 - 1
+}
+
+/// Converts the null terminated wide string `s` to the active ANSI code page, null terminated.
+unsafe fn wide_to_ansi_str(s: PCWSTR) -> Result<Vec<u8>, u32> {
+    wide_to_ansi(slice::from_raw_parts(s, wide_str(s).len() + 1))
+}
+
+/// `GetEnvironmentVariableW` on top of `GetEnvironmentVariableA`.
+///
+/// The value is converted from the active code page, see `ansi_to_wide_vec`. As the converted
+/// value can be shorter or longer than the ANSI one, the size returned when the buffer is too
+/// small is the one of the converted value (including the null terminator), so that a retry with
+/// a buffer that large succeeds. Names that can't be represented in the code page can't have been
+/// set, so they fail with `ERROR_ENVVAR_NOT_FOUND`.
+pub unsafe fn GetEnvironmentVariableW(lpname: PCWSTR, lpbuffer: PWSTR, nsize: u32) -> u32 {
+    let Ok(name) = wide_to_ansi_str(lpname) else {
+        return fail(ERROR_ENVVAR_NOT_FOUND, 0);
+    };
+
+    // Like the wide API, the ANSI one returns the size it needs if the buffer is too small.
+    let mut value: Vec<u8> = Vec::new();
+    loop {
+        SetLastError(0);
+        let len = GetEnvironmentVariableA(
+            name.as_ptr(),
+            value.as_mut_ptr(),
+            value.capacity().try_into().unwrap_or(u32::MAX),
+        ) as usize;
+        if len == 0 {
+            // Either the variable doesn't exist, or it is empty.
+            if GetLastError() != 0 {
+                return 0;
+            }
+            break;
+        }
+        if len < value.capacity() {
+            value.set_len(len);
+            break;
+        }
+        value.reserve(len);
+    }
+
+    let wide = ansi_to_wide_vec(&value);
+    if wide.len() >= nsize as usize {
+        return wide.len() as u32 + 1;
+    }
+    ptr::copy_nonoverlapping(wide.as_ptr(), lpbuffer, wide.len());
+    *lpbuffer.add(wide.len()) = 0;
+    wide.len() as u32
+}
+
+/// `SetEnvironmentVariableW` on top of `SetEnvironmentVariableA`.
+///
+/// Names and values with characters that can't be represented in the active code page fail with
+/// `ERROR_NO_UNICODE_TRANSLATION`, instead of setting a different variable or value.
+pub unsafe fn SetEnvironmentVariableW(lpname: PCWSTR, lpvalue: PCWSTR) -> BOOL {
+    let name = match wide_to_ansi_str(lpname) {
+        Ok(name) => name,
+        Err(code) => return fail(code, FALSE),
+    };
+    let value = match (!lpvalue.is_null()).then(|| wide_to_ansi_str(lpvalue)).transpose() {
+        Ok(value) => value,
+        Err(code) => return fail(code, FALSE),
+    };
+    SetEnvironmentVariableA(name.as_ptr(), value.as_ref().map_or(ptr::null(), |v| v.as_ptr()))
+}
+
+/// `GetEnvironmentStringsW` on top of `GetEnvironmentStrings`.
+///
+/// The variables are converted just like by the [`GetEnvironmentVariableW`] shim, so both always
+/// agree on the values. The block is allocated with `LocalAlloc`, so it must be freed with the
+/// [`FreeEnvironmentStringsW`] shim below, not with the real function.
+pub unsafe fn GetEnvironmentStringsW() -> PWSTR {
+    let ansi = GetEnvironmentStrings();
+    if ansi.is_null() {
+        return ptr::null_mut();
+    }
+    // The block is a sequence of null terminated strings, ended by an empty one.
+    let mut len = 0;
+    loop {
+        let var = CStr::from_ptr(ansi.add(len).cast());
+        if var.is_empty() {
+            break;
+        }
+        len += var.to_bytes_with_nul().len();
+    }
+    let mut wide = ansi_to_wide_vec(slice::from_raw_parts(ansi, len));
+    FreeEnvironmentStringsA(ansi);
+    // The empty string that ends the block.
+    wide.push(0);
+
+    let block = LocalAlloc(LMEM_FIXED, wide.len() * mem::size_of::<u16>()).cast::<u16>();
+    if !block.is_null() {
+        ptr::copy_nonoverlapping(wide.as_ptr(), block, wide.len());
+    }
+    block
+}
+
+/// Frees a block returned by the [`GetEnvironmentStringsW`] shim.
+pub unsafe fn FreeEnvironmentStringsW(penv: PCWSTR) -> BOOL {
+    LocalFree(penv as *mut c_void);
+    TRUE
+}
+
+/// Converts an ANSI command line to the null terminated UTF-16 one that `GetCommandLineW` would
+/// return for it, see `ansi_to_wide_vec`.
+pub fn command_line_to_wide(ansi: &[u8]) -> Vec<u16> {
+    let mut wide = unsafe { ansi_to_wide_vec(ansi) };
+    wide.push(0);
+    wide
+}
+
+/// `GetCommandLineW` on top of `GetCommandLineA`.
+///
+/// Like the real function, this returns the same string for the lifetime of the process: the
+/// command line is converted on the first call, and never freed.
+pub unsafe fn GetCommandLineW() -> PCWSTR {
+    static COMMAND_LINE: AtomicPtr<u16> = AtomicPtr::new(ptr::null_mut());
+
+    let line = COMMAND_LINE.load(Acquire);
+    if !line.is_null() {
+        return line;
+    }
+    let ansi = GetCommandLineA();
+    let ansi = if ansi.is_null() { &[][..] } else { CStr::from_ptr(ansi.cast()).to_bytes() };
+    let wide = Box::leak(command_line_to_wide(ansi).into_boxed_slice());
+    let (new, len) = (wide.as_mut_ptr(), wide.len());
+    match COMMAND_LINE.compare_exchange(ptr::null_mut(), new, AcqRel, Acquire) {
+        Ok(_) => new,
+        Err(existing) => {
+            // Another thread got there first, and its string may already be in use.
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(new, len)));
+            existing
+        }
+    }
+}
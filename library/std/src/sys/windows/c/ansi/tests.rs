@@ -0,0 +1,444 @@
+use super::MoveFileExW;
+use crate::env;
+use crate::fs;
+use crate::io::{Error, ErrorKind};
+use crate::os::windows::fs::OpenOptionsExt;
+use crate::path::Path;
+use crate::sys::c;
+use crate::sys::to_u16s;
+use crate::sys_common::io::test::tmpdir;
+
+fn move_file_ex(from: &Path, to: &Path, flags: u32) -> Result<(), Error> {
+    let from = to_u16s(from).unwrap();
+    let to = to_u16s(to).unwrap();
+    if unsafe { MoveFileExW(from.as_ptr(), to.as_ptr(), flags) } == c::FALSE {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn move_file_replace_existing() {
+    let tmp = tmpdir();
+    let from = tmp.join("from");
+    let to = tmp.join("to");
+    fs::write(&from, b"new").unwrap();
+    fs::write(&to, b"old").unwrap();
+
+    // Without the flag, the destination must not be touched.
+    assert!(move_file_ex(&from, &to, 0).is_err());
+    assert_eq!(fs::read(&to).unwrap(), b"old");
+
+    move_file_ex(&from, &to, c::MOVEFILE_REPLACE_EXISTING).unwrap();
+    assert!(!from.exists());
+    assert_eq!(fs::read(&to).unwrap(), b"new");
+}
+
+#[test]
+fn move_file_replace_readonly() {
+    let tmp = tmpdir();
+    let from = tmp.join("from");
+    let to = tmp.join("to");
+    fs::write(&from, b"new").unwrap();
+    fs::write(&to, b"old").unwrap();
+    let mut perms = fs::metadata(&to).unwrap().permissions();
+    perms.set_readonly(true);
+    fs::set_permissions(&to, perms).unwrap();
+
+    move_file_ex(&from, &to, c::MOVEFILE_REPLACE_EXISTING).unwrap();
+    assert!(!from.exists());
+    assert_eq!(fs::read(&to).unwrap(), b"new");
+}
+
+#[test]
+fn move_file_replace_open_destination() {
+    let tmp = tmpdir();
+    let from = tmp.join("from");
+    let to = tmp.join("to");
+    fs::write(&from, b"new").unwrap();
+    fs::write(&to, b"old").unwrap();
+
+    // Open the destination without `FILE_SHARE_DELETE`, so it can't be deleted.
+    let _open =
+        fs::OpenOptions::new().read(true).share_mode(c::FILE_SHARE_READ).open(&to).unwrap();
+
+    let err = move_file_ex(&from, &to, c::MOVEFILE_REPLACE_EXISTING).unwrap_err();
+    assert!(matches!(
+        err.raw_os_error().map(|e| e as u32),
+        Some(c::ERROR_ALREADY_EXISTS | c::ERROR_FILE_EXISTS)
+    ));
+    assert_eq!(fs::read(&from).unwrap(), b"new");
+    assert_eq!(fs::read(&to).unwrap(), b"old");
+}
+
+#[test]
+fn move_file_delay_until_reboot() {
+    let tmp = tmpdir();
+    let from = tmp.join("from");
+    fs::write(&from, b"new").unwrap();
+
+    let err = move_file_ex(&from, &tmp.join("to"), c::MOVEFILE_DELAY_UNTIL_REBOOT).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(c::ERROR_CALL_NOT_IMPLEMENTED as i32));
+    assert!(from.exists());
+}
+
+/// Returns a non-ASCII file name that is representable in the active code page, if any.
+fn ansi_file_name() -> Option<&'static str> {
+    ["naïve-ß", "ĳ-ő", "ж-я", "ひらがな", "한글", "中文"].into_iter().find(|name| {
+        let wide = to_u16s(name).unwrap();
+        unsafe { super::AnsiPath::new(wide.as_ptr()) }.is_ok()
+    })
+}
+
+#[test]
+fn ansi_path_too_long() {
+    let long = to_u16s("a".repeat(c::MAX_PATH as usize)).unwrap();
+    let err = unsafe { super::AnsiPath::new(long.as_ptr()) }.err();
+    assert_eq!(err, Some(c::ERROR_FILENAME_EXCED_RANGE));
+}
+
+#[test]
+fn file_shims() {
+    let Some(name) = ansi_file_name() else { return };
+    let tmp = tmpdir();
+    let file = tmp.join(name);
+    fs::write(&file, b"").unwrap();
+    let path = to_u16s(&file).unwrap();
+
+    unsafe {
+        let attrs = super::GetFileAttributesW(path.as_ptr());
+        assert_ne!(attrs, c::INVALID_FILE_ATTRIBUTES);
+        assert_eq!(attrs & c::FILE_ATTRIBUTE_DIRECTORY, 0);
+
+        assert_ne!(
+            super::SetFileAttributesW(path.as_ptr(), attrs | c::FILE_ATTRIBUTE_READONLY),
+            c::FALSE
+        );
+        assert!(fs::metadata(&file).unwrap().permissions().readonly());
+        assert_ne!(super::SetFileAttributesW(path.as_ptr(), attrs), c::FALSE);
+
+        assert_ne!(super::DeleteFileW(path.as_ptr()), c::FALSE);
+        assert!(!file.exists());
+        assert_eq!(super::GetFileAttributesW(path.as_ptr()), c::INVALID_FILE_ATTRIBUTES);
+    }
+}
+
+#[test]
+fn directory_shims() {
+    let Some(name) = ansi_file_name() else { return };
+    let tmp = tmpdir();
+    let dir = tmp.join(name);
+    let path = to_u16s(&dir).unwrap();
+
+    unsafe {
+        assert_ne!(super::CreateDirectoryW(path.as_ptr(), crate::ptr::null()), c::FALSE);
+        assert!(dir.is_dir());
+        assert_ne!(super::RemoveDirectoryW(path.as_ptr()), c::FALSE);
+        assert!(!dir.exists());
+    }
+}
+
+#[test]
+fn find_shims() {
+    let Some(name) = ansi_file_name() else { return };
+    let tmp = tmpdir();
+    fs::write(tmp.join(name), b"hello").unwrap();
+    fs::create_dir(tmp.join("dir")).unwrap();
+    let pattern = to_u16s(tmp.join("*")).unwrap();
+
+    let mut names = Vec::new();
+    unsafe {
+        let mut data: c::WIN32_FIND_DATAW = crate::mem::zeroed();
+        let handle = super::FindFirstFileW(pattern.as_ptr(), &mut data);
+        assert_ne!(handle, c::INVALID_HANDLE_VALUE);
+        loop {
+            let name = crate::sys::truncate_utf16_at_nul(&data.cFileName);
+            names.push((String::from_utf16(name).unwrap(), data.nFileSizeLow));
+            if super::FindNextFileW(handle, &mut data) == c::FALSE {
+                assert_eq!(c::GetLastError(), c::ERROR_NO_MORE_FILES);
+                break;
+            }
+        }
+        c::FindClose(handle);
+    }
+    assert!(names.contains(&(name.to_owned(), 5)));
+    assert!(names.iter().any(|(name, _)| name == "dir"));
+}
+
+#[test]
+fn module_file_name_grows() {
+    let exe = crate::env::current_exe().unwrap();
+    let mut buf = vec![0u16; 1];
+    let path = loop {
+        let len = unsafe {
+            super::GetModuleFileNameW(crate::ptr::null_mut(), buf.as_mut_ptr(), buf.len() as u32)
+        };
+        assert_ne!(len, 0);
+        if (len as usize) < buf.len() {
+            break String::from_utf16(&buf[..len as usize]).unwrap();
+        }
+        assert_eq!(unsafe { c::GetLastError() }, c::ERROR_INSUFFICIENT_BUFFER);
+        buf.resize(buf.len() * 2, 0);
+    };
+    assert_eq!(Path::new(&path), exe);
+}
+
+fn format_message(code: u32, flags: u32, buf: &mut [u16]) -> u32 {
+    unsafe {
+        super::FormatMessageW(
+            flags | c::FORMAT_MESSAGE_FROM_SYSTEM | c::FORMAT_MESSAGE_IGNORE_INSERTS,
+            crate::ptr::null(),
+            code,
+            0,
+            buf.as_mut_ptr(),
+            buf.len() as u32,
+            crate::ptr::null(),
+        )
+    }
+}
+
+#[test]
+fn format_message_into_buffer() {
+    let mut buf = [0u16; 1024];
+    let len = format_message(c::ERROR_FILE_NOT_FOUND, 0, &mut buf) as usize;
+    assert_ne!(len, 0);
+    assert_eq!(buf[len], 0);
+    let msg = String::from_utf16(&buf[..len]).unwrap();
+    assert!(!msg.is_empty());
+    assert!(!msg.ends_with(['\r', '\n']));
+
+    let mut small = [0u16; 2];
+    assert_eq!(format_message(c::ERROR_FILE_NOT_FOUND, 0, &mut small), 0);
+    assert_eq!(unsafe { c::GetLastError() }, c::ERROR_INSUFFICIENT_BUFFER);
+}
+
+#[test]
+fn format_message_allocate_buffer() {
+    unsafe {
+        let mut ptr: c::PWSTR = crate::ptr::null_mut();
+        let len = super::FormatMessageW(
+            c::FORMAT_MESSAGE_ALLOCATE_BUFFER
+                | c::FORMAT_MESSAGE_FROM_SYSTEM
+                | c::FORMAT_MESSAGE_IGNORE_INSERTS,
+            crate::ptr::null(),
+            c::ERROR_ACCESS_DENIED,
+            0,
+            crate::ptr::addr_of_mut!(ptr).cast(),
+            0,
+            crate::ptr::null(),
+        ) as usize;
+        assert_ne!(len, 0);
+        assert!(!ptr.is_null());
+        assert_eq!(*ptr.add(len), 0);
+        let msg = String::from_utf16(crate::slice::from_raw_parts(ptr, len)).unwrap();
+        c::LocalFree(ptr.cast());
+        assert!(!msg.is_empty());
+        assert!(!msg.ends_with(['\r', '\n']));
+    }
+}
+
+#[test]
+fn root_of_paths() {
+    let root = |path: &str| {
+        let path: Vec<u16> = path.encode_utf16().collect();
+        String::from_utf16(super::root_of(&path)).unwrap()
+    };
+    assert_eq!(root(r"C:\Windows\System"), r"C:\");
+    assert_eq!(root("C:/"), "C:/");
+    assert_eq!(root(r"\\server\share\dir\file"), r"\\server\share\");
+    assert_eq!(root(r"\\server\share"), r"\\server\share");
+    assert_eq!(root(r"dir\file"), "");
+    assert_eq!(root(r"C:file"), "");
+}
+
+/// Calls `GetFileAttributesExW`, or the fallback instead of the real API.
+fn attributes_ex(path: &Path, fallback: bool) -> Result<c::WIN32_FILE_ATTRIBUTE_DATA, Error> {
+    let path = to_u16s(path).unwrap();
+    let get = if fallback { super::GetFileAttributesExW } else { c::GetFileAttributesExW };
+    unsafe {
+        let mut data: c::WIN32_FILE_ATTRIBUTE_DATA = crate::mem::zeroed();
+        let info = crate::ptr::addr_of_mut!(data).cast();
+        if get(path.as_ptr(), c::GetFileExInfoStandard, info) == c::FALSE {
+            Err(Error::last_os_error())
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+/// The attributes, size, and creation and last write times. Last access times are left out, as
+/// looking at the file may update them.
+fn comparable(data: &c::WIN32_FILE_ATTRIBUTE_DATA) -> [u32; 7] {
+    [
+        data.dwFileAttributes,
+        data.nFileSizeHigh,
+        data.nFileSizeLow,
+        data.ftCreationTime.dwLowDateTime,
+        data.ftCreationTime.dwHighDateTime,
+        data.ftLastWriteTime.dwLowDateTime,
+        data.ftLastWriteTime.dwHighDateTime,
+    ]
+}
+
+#[test]
+fn file_attributes_ex_matches_real_api() {
+    let tmp = tmpdir();
+    let file = tmp.join("file");
+    let readonly = tmp.join("readonly");
+    let dir = tmp.join("dir");
+    fs::write(&file, b"some contents").unwrap();
+    fs::write(&readonly, b"").unwrap();
+    let mut perms = fs::metadata(&readonly).unwrap().permissions();
+    perms.set_readonly(true);
+    fs::set_permissions(&readonly, perms).unwrap();
+    fs::create_dir(&dir).unwrap();
+
+    for path in [&file, &readonly, &dir] {
+        let real = attributes_ex(path, false).unwrap();
+        let fallback = attributes_ex(path, true).unwrap();
+        assert_eq!(comparable(&fallback), comparable(&real), "{}", path.display());
+    }
+}
+
+#[test]
+fn file_attributes_ex_of_roots() {
+    let drive = crate::env::var("SystemDrive").unwrap_or_else(|_| "C:".to_owned());
+    let tmp = tmpdir();
+    let trailing = format!(r"{}\", tmp.path().display());
+    let dot = tmp.join(".");
+
+    for path in [format!(r"{drive}\"), trailing, dot.display().to_string()] {
+        let path = Path::new(&path);
+        let real = attributes_ex(path, false).unwrap();
+        let fallback = attributes_ex(path, true).unwrap();
+        // Only the attributes are available, the rest is zeroed.
+        assert_eq!(fallback.dwFileAttributes, real.dwFileAttributes, "{}", path.display());
+        assert_eq!(comparable(&fallback)[1..], [0; 6], "{}", path.display());
+    }
+}
+
+#[test]
+fn file_attributes_ex_errors() {
+    let tmp = tmpdir();
+    fs::write(tmp.join("file"), b"").unwrap();
+    for (path, code) in [
+        (tmp.join("missing"), c::ERROR_FILE_NOT_FOUND),
+        (tmp.join(r"missing\file"), c::ERROR_PATH_NOT_FOUND),
+        (tmp.join("fil?"), c::ERROR_INVALID_NAME),
+        (tmp.join("*"), c::ERROR_INVALID_NAME),
+    ] {
+        // The data has no `Debug`, which `unwrap_err` would need.
+        let real = attributes_ex(&path, false).map(drop).unwrap_err();
+        let fallback = attributes_ex(&path, true).map(drop).unwrap_err();
+        assert_eq!(real.raw_os_error(), Some(code as i32), "{}", path.display());
+        assert_eq!(fallback.raw_os_error(), Some(code as i32), "{}", path.display());
+    }
+}
+
+/// Reads a variable through the shim, growing the buffer to the size it asks for.
+fn ansi_getenv(name: &str) -> Option<String> {
+    let name = to_u16s(name).unwrap();
+    let mut buf = Vec::new();
+    loop {
+        let len = unsafe {
+            super::GetEnvironmentVariableW(name.as_ptr(), buf.as_mut_ptr(), buf.len() as u32)
+        } as usize;
+        if len == 0 {
+            assert_eq!(unsafe { c::GetLastError() }, c::ERROR_ENVVAR_NOT_FOUND);
+            return None;
+        }
+        if len < buf.len() {
+            return Some(String::from_utf16(&buf[..len]).unwrap());
+        }
+        buf.resize(len, 0);
+    }
+}
+
+fn ansi_setenv(name: &str, value: Option<&str>) -> Result<(), Error> {
+    let name = to_u16s(name).unwrap();
+    let value = value.map(|value| to_u16s(value).unwrap());
+    let value = value.as_ref().map_or(crate::ptr::null(), |value| value.as_ptr());
+    if unsafe { super::SetEnvironmentVariableW(name.as_ptr(), value) } == c::FALSE {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the variables listed by the `GetEnvironmentStringsW` shim.
+fn ansi_vars() -> Vec<String> {
+    let mut vars = Vec::new();
+    unsafe {
+        let block = super::GetEnvironmentStringsW();
+        assert!(!block.is_null());
+        let mut var = block;
+        while *var != 0 {
+            let wide = super::wide_str(var);
+            vars.push(String::from_utf16(wide).unwrap());
+            var = var.add(wide.len() + 1);
+        }
+        super::FreeEnvironmentStringsW(block);
+    }
+    vars
+}
+
+#[test]
+fn environment_shims_round_trip() {
+    let Some(value) = ansi_file_name() else { return };
+    let name = "RUST_TEST_ANSI_ENV_ROUND_TRIP";
+    ansi_setenv(name, Some(value)).unwrap();
+
+    // In multi-byte code pages, the ANSI value is longer than the wide one. The size asked for
+    // has to be the one of the wide value.
+    let wide_name = to_u16s(name).unwrap();
+    let needed =
+        unsafe { super::GetEnvironmentVariableW(wide_name.as_ptr(), crate::ptr::null_mut(), 0) };
+    assert_eq!(needed as usize, value.encode_utf16().count() + 1);
+
+    assert_eq!(ansi_getenv(name).as_deref(), Some(value));
+    assert_eq!(env::var(name).as_deref(), Ok(value));
+    assert!(ansi_vars().contains(&format!("{name}={value}")));
+
+    ansi_setenv(name, None).unwrap();
+    assert_eq!(ansi_getenv(name), None);
+    assert!(!ansi_vars().iter().any(|var| var.starts_with(&format!("{name}="))));
+}
+
+#[test]
+fn environment_shims_reject_unrepresentable() {
+    // With UTF-8 as the ANSI code page, everything can be represented.
+    if unsafe { c::GetACP() } == c::CP_UTF8 {
+        return;
+    }
+    let name = "RUST_TEST_ANSI_ENV_UNREPRESENTABLE";
+    let err = ansi_setenv(name, Some("crab \u{1F980}")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert_eq!(ansi_getenv(name), None);
+
+    // A name like that can't have been set, so it is missing.
+    assert_eq!(ansi_getenv("RUST_TEST_\u{1F980}"), None);
+}
+
+#[test]
+fn ansi_string_round_trip() {
+    let null = unsafe { super::AnsiString::new(crate::ptr::null()) }.unwrap();
+    assert!(null.as_ptr().is_null());
+
+    let Some(text) = ansi_file_name() else { return };
+    let wide = to_u16s(text).unwrap();
+    let ansi = unsafe { super::AnsiString::new(wide.as_ptr()) }.unwrap();
+    let ansi = unsafe { crate::ffi::CStr::from_ptr(ansi.as_ptr().cast()) };
+    let back = unsafe { super::ansi_to_wide_vec(ansi.to_bytes()) };
+    assert_eq!(String::from_utf16(&back).unwrap(), text);
+}
+
+#[test]
+fn ansi_string_rejects_unrepresentable() {
+    if unsafe { c::GetACP() } == c::CP_UTF8 {
+        return;
+    }
+    let wide = to_u16s("crab \u{1F980}").unwrap();
+    let err = unsafe { super::AnsiString::new(wide.as_ptr()) }.err();
+    assert_eq!(err, Some(c::ERROR_NO_UNICODE_TRANSLATION));
+}
@@ -0,0 +1,91 @@
+//! `inet_ntop` and `inet_pton` converted to rust, as ws2_32 only has them since Vista.
+//!
+//! IPv6 addresses are formatted as recommended by RFC 5952: the longest run of two or more zero
+//! groups is compressed to `::`, and IPv4-mapped addresses end in dotted decimal. Like the
+//! originals, these don't know about scope IDs, so a `%zone` suffix is rejected when parsing.
+
+use super::{
+    in6_addr, in_addr, WSASetLastError, AF_INET, AF_INET6, ERROR_INVALID_PARAMETER, PCSTR, PSTR,
+    WSAEAFNOSUPPORT, WSAEFAULT,
+};
+use crate::ffi::{c_void, CStr};
+use crate::fmt::{self, Write};
+use crate::net::{Ipv4Addr, Ipv6Addr};
+use crate::ptr;
+use crate::str;
+
+#[cfg(test)]
+mod tests;
+
+/// Long enough for the longest IPv6 address text and its nul terminator.
+const INET6_ADDRSTRLEN: usize = 46;
+
+/// The text of an address, before it is copied to the caller's buffer.
+struct AddrText {
+    buf: [u8; INET6_ADDRSTRLEN],
+    len: usize,
+}
+
+impl fmt::Write for AddrText {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf.get_mut(self.len..end).ok_or(fmt::Error)?.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+pub unsafe fn inet_ntop(
+    family: i32,
+    paddr: *const c_void,
+    pstringbuf: PSTR,
+    stringbufsize: usize,
+) -> PCSTR {
+    let mut text = AddrText { buf: [0; INET6_ADDRSTRLEN], len: 0 };
+    let result = if paddr.is_null() {
+        Err(fmt::Error)
+    } else if family == AF_INET as i32 {
+        let addr = paddr.cast::<in_addr>().read_unaligned();
+        write!(text, "{}", Ipv4Addr::from(addr.s_addr.to_ne_bytes()))
+    } else if family == AF_INET6 as i32 {
+        let addr = paddr.cast::<in6_addr>().read_unaligned();
+        write!(text, "{}", Ipv6Addr::from(addr.s6_addr))
+    } else {
+        WSASetLastError(WSAEAFNOSUPPORT);
+        return ptr::null();
+    };
+
+    // The buffer has to fit the text and its nul terminator.
+    if result.is_err() || pstringbuf.is_null() || stringbufsize <= text.len {
+        WSASetLastError(ERROR_INVALID_PARAMETER as i32);
+        return ptr::null();
+    }
+    ptr::copy_nonoverlapping(text.buf.as_ptr(), pstringbuf, text.len);
+    *pstringbuf.add(text.len) = 0;
+    pstringbuf
+}
+
+pub unsafe fn inet_pton(family: i32, pszaddrstring: PCSTR, paddrbuf: *mut c_void) -> i32 {
+    if family != AF_INET as i32 && family != AF_INET6 as i32 {
+        WSASetLastError(WSAEAFNOSUPPORT);
+        return -1;
+    }
+    if pszaddrstring.is_null() || paddrbuf.is_null() {
+        WSASetLastError(WSAEFAULT);
+        return -1;
+    }
+
+    // Invalid text isn't an error, it's reported by returning 0.
+    let Ok(text) = str::from_utf8(CStr::from_ptr(pszaddrstring.cast()).to_bytes()) else {
+        return 0;
+    };
+    if family == AF_INET as i32 {
+        let Ok(addr) = text.parse::<Ipv4Addr>() else { return 0 };
+        let addr = in_addr { s_addr: u32::from_ne_bytes(addr.octets()) };
+        paddrbuf.cast::<in_addr>().write_unaligned(addr);
+    } else {
+        let Ok(addr) = text.parse::<Ipv6Addr>() else { return 0 };
+        paddrbuf.cast::<in6_addr>().write_unaligned(in6_addr { s6_addr: addr.octets() });
+    }
+    1
+}
@@ -0,0 +1,179 @@
+use super::{inet_ntop, inet_pton, INET6_ADDRSTRLEN};
+use crate::net::{Ipv4Addr, Ipv6Addr};
+use crate::ptr;
+use crate::sys::c;
+
+fn ntop(family: i32, addr: &[u8], buf_len: usize) -> Result<String, i32> {
+    let mut buf = vec![0xffu8; buf_len];
+    unsafe {
+        let result = inet_ntop(family, addr.as_ptr().cast(), buf.as_mut_ptr(), buf.len());
+        if result.is_null() {
+            return Err(c::WSAGetLastError());
+        }
+        assert_eq!(result, buf.as_ptr());
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap();
+    Ok(String::from_utf8(buf[..len].to_vec()).unwrap())
+}
+
+fn pton<const N: usize>(family: i32, text: &str) -> Option<[u8; N]> {
+    let text = crate::ffi::CString::new(text).unwrap();
+    let mut buf = [0u8; N];
+    let result = unsafe { inet_pton(family, text.as_ptr().cast(), buf.as_mut_ptr().cast()) };
+    assert!(result == 0 || result == 1, "unexpected result {result}");
+    (result == 1).then_some(buf)
+}
+
+const V4: &[([u8; 4], &str)] = &[
+    ([0, 0, 0, 0], "0.0.0.0"),
+    ([127, 0, 0, 1], "127.0.0.1"),
+    ([192, 168, 1, 10], "192.168.1.10"),
+    ([255, 255, 255, 255], "255.255.255.255"),
+];
+
+const V6: &[([u16; 8], &str)] = &[
+    ([0, 0, 0, 0, 0, 0, 0, 0], "::"),
+    ([0, 0, 0, 0, 0, 0, 0, 1], "::1"),
+    ([1, 0, 0, 0, 0, 0, 0, 0], "1::"),
+    ([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1], "2001:db8::1"),
+    // The first of two equally long runs of zeros is compressed.
+    ([0x2001, 0xdb8, 0, 0, 1, 0, 0, 1], "2001:db8::1:0:0:1"),
+    // The longest run of zeros is compressed.
+    ([0x2001, 0, 0, 1, 0, 0, 0, 1], "2001:0:0:1::1"),
+    // A single zero group isn't compressed.
+    ([0x2001, 0xdb8, 0, 1, 1, 1, 1, 1], "2001:db8:0:1:1:1:1:1"),
+    // Scoped addresses, which are only written without their zone.
+    ([0xfe80, 0, 0, 0, 0, 0, 0, 1], "fe80::1"),
+    ([0xfe80, 0, 0, 0, 0xabcd, 0xef01, 0x2345, 0x6789], "fe80::abcd:ef01:2345:6789"),
+    ([0xff02, 0, 0, 0, 0, 1, 0xff00, 1], "ff02::1:ff00:1"),
+    ([0xfec0, 0, 0, 0xffff, 0, 0, 0, 1], "fec0:0:0:ffff::1"),
+    // Only IPv4-mapped addresses embed an IPv4 address.
+    ([0, 0, 0, 0, 0, 0xffff, 0xc000, 0x201], "::ffff:192.0.2.1"),
+    ([0, 0, 0, 0, 0, 0, 0xc000, 0x201], "::c000:201"),
+    ([0x64, 0xff9b, 0, 0, 0, 0, 0xc000, 0x201], "64:ff9b::c000:201"),
+    (
+        [0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff],
+        "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff",
+    ),
+];
+
+fn v6_bytes(segments: [u16; 8]) -> [u8; 16] {
+    Ipv6Addr::from(segments).octets()
+}
+
+#[test]
+fn ntop_v4() {
+    for (addr, text) in V4 {
+        assert_eq!(ntop(c::AF_INET, addr, 16).as_deref(), Ok(*text));
+    }
+}
+
+#[test]
+fn ntop_v6() {
+    for (segments, text) in V6 {
+        let addr = v6_bytes(*segments);
+        assert_eq!(ntop(c::AF_INET6, &addr, INET6_ADDRSTRLEN).as_deref(), Ok(*text));
+    }
+}
+
+#[test]
+fn ntop_buffer_size() {
+    let invalid = Err(c::ERROR_INVALID_PARAMETER as i32);
+    for (addr, text) in V4 {
+        assert_eq!(ntop(c::AF_INET, addr, text.len() + 1).as_deref(), Ok(*text));
+        assert_eq!(ntop(c::AF_INET, addr, text.len()), invalid);
+    }
+    for (segments, text) in V6 {
+        let addr = v6_bytes(*segments);
+        assert_eq!(ntop(c::AF_INET6, &addr, text.len() + 1).as_deref(), Ok(*text));
+        assert_eq!(ntop(c::AF_INET6, &addr, text.len()), invalid);
+    }
+    assert_eq!(ntop(c::AF_INET, &[1, 2, 3, 4], 0), invalid);
+
+    let addr = [1u8, 2, 3, 4];
+    let result = unsafe { inet_ntop(c::AF_INET, addr.as_ptr().cast(), ptr::null_mut(), 16) };
+    assert!(result.is_null());
+    assert_eq!(unsafe { c::WSAGetLastError() }, c::ERROR_INVALID_PARAMETER as i32);
+}
+
+#[test]
+fn ntop_unknown_family() {
+    assert_eq!(ntop(c::AF_UNSPEC.into(), &[0; 16], 64), Err(c::WSAEAFNOSUPPORT));
+}
+
+#[test]
+fn pton_v4() {
+    for (addr, text) in V4 {
+        assert_eq!(pton::<4>(c::AF_INET, text), Some(*addr));
+    }
+    for text in [
+        "",
+        "1.2.3",
+        "1.2.3.4.5",
+        "256.0.0.1",
+        "1.2.3.-4",
+        " 1.2.3.4",
+        "1.2.3.4 ",
+        "0x1.2.3.4",
+        "1.2.3.4%1",
+        "::1",
+    ] {
+        assert_eq!(pton::<4>(c::AF_INET, text), None, "{text:?}");
+    }
+}
+
+#[test]
+fn pton_v6() {
+    for (segments, text) in V6 {
+        assert_eq!(pton::<16>(c::AF_INET6, text), Some(v6_bytes(*segments)), "{text:?}");
+    }
+    let other_forms: &[(&str, [u16; 8])] = &[
+        ("2001:DB8::1", [0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]),
+        ("2001:db8:0:0:0:0:0:1", [0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]),
+        ("2001:0db8::0001", [0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]),
+        ("::192.0.2.1", [0, 0, 0, 0, 0, 0, 0xc000, 0x201]),
+        ("1:2:3:4:5:6:1.2.3.4", [1, 2, 3, 4, 5, 6, 0x102, 0x304]),
+        ("1:2:3:4:5:6:7:8", [1, 2, 3, 4, 5, 6, 7, 8]),
+    ];
+    for (text, segments) in other_forms {
+        assert_eq!(pton::<16>(c::AF_INET6, text), Some(v6_bytes(*segments)), "{text:?}");
+    }
+    for text in [
+        "",
+        ":",
+        ":::",
+        "1::2::3",
+        "12345::",
+        "1:2:3:4:5:6:7",
+        "1:2:3:4:5:6:7:8:9",
+        "::1.2.3",
+        "[::1]",
+        "fe80::1%4",
+        "fe80::1%eth0",
+        "1.2.3.4",
+    ] {
+        assert_eq!(pton::<16>(c::AF_INET6, text), None, "{text:?}");
+    }
+}
+
+#[test]
+fn pton_roundtrip() {
+    for addr in [Ipv4Addr::LOCALHOST, Ipv4Addr::new(10, 0, 200, 3)] {
+        let text = ntop(c::AF_INET, &addr.octets(), 16).unwrap();
+        assert_eq!(pton::<4>(c::AF_INET, &text), Some(addr.octets()));
+    }
+}
+
+#[test]
+fn pton_errors() {
+    let text = b"::1\0";
+    let mut buf = [0u8; 16];
+    unsafe {
+        assert_eq!(inet_pton(c::AF_UNSPEC as i32, text.as_ptr(), buf.as_mut_ptr().cast()), -1);
+        assert_eq!(c::WSAGetLastError(), c::WSAEAFNOSUPPORT);
+        assert_eq!(inet_pton(c::AF_INET6, ptr::null(), buf.as_mut_ptr().cast()), -1);
+        assert_eq!(c::WSAGetLastError(), c::WSAEFAULT);
+        assert_eq!(inet_pton(c::AF_INET6, text.as_ptr(), ptr::null_mut()), -1);
+        assert_eq!(c::WSAGetLastError(), c::WSAEFAULT);
+    }
+}
@@ -0,0 +1,125 @@
+use super::*;
+
+#[test]
+fn nt_status_to_dos_error_matches_ntdll() {
+    if !RtlNtStatusToDosError::available() {
+        return;
+    }
+
+    for &(status, _) in NT_STATUS_TO_DOS_ERROR {
+        let expected = unsafe { RtlNtStatusToDosError(status) };
+        assert_eq!(nt_status_to_dos_error(status), expected, "{status:#x}");
+    }
+
+    const STATUS_UNKNOWN: NTSTATUS = 0xE123_4567u32 as i32;
+    let expected = unsafe { RtlNtStatusToDosError(STATUS_UNKNOWN) };
+    assert_eq!(nt_status_to_dos_error(STATUS_UNKNOWN), expected);
+}
+
+#[test]
+fn nt_status_to_dos_error_unknown() {
+    assert_eq!(nt_status_to_dos_error(0xC0001234u32 as i32), ERROR_MR_MID_NOT_FOUND);
+}
+
+#[test]
+fn extend_tick_count_wraps() {
+    let last = AtomicU64::new(0);
+    assert_eq!(extend_tick_count(&last, 1000), 1000);
+    assert_eq!(extend_tick_count(&last, u32::MAX - 10), u32::MAX as u64 - 10);
+    assert_eq!(extend_tick_count(&last, 5), (1 << 32) + 5);
+    assert_eq!(extend_tick_count(&last, 100), (1 << 32) + 100);
+    assert_eq!(extend_tick_count(&last, u32::MAX), (2 << 32) - 1);
+    assert_eq!(extend_tick_count(&last, 0), 2 << 32);
+    assert_eq!(last.load(Ordering::Relaxed), 2 << 32);
+}
+
+#[test]
+fn extend_tick_count_first_value() {
+    // The first value may already be past the point where the tick count looks negative.
+    let last = AtomicU64::new(0);
+    assert_eq!(extend_tick_count(&last, u32::MAX - 1), u32::MAX as u64 - 1);
+    assert_eq!(extend_tick_count(&last, 1), (1 << 32) + 1);
+}
+
+#[test]
+fn extend_tick_count_stale() {
+    // A reader that observed the tick count before another one updated `last` doesn't go back in
+    // time for everyone else, not even across a wraparound.
+    let last = AtomicU64::new(0);
+    extend_tick_count(&last, 10);
+    assert_eq!(extend_tick_count(&last, u32::MAX - 5), u32::MAX as u64 - 5);
+    assert_eq!(extend_tick_count(&last, 3), (1 << 32) + 3);
+    assert_eq!(extend_tick_count(&last, u32::MAX - 1), u32::MAX as u64 - 1);
+    assert_eq!(extend_tick_count(&last, 2), (1 << 32) + 2);
+    assert_eq!(last.load(Ordering::Relaxed), (1 << 32) + 3);
+}
+
+#[test]
+fn extend_tick_count_interleaved() {
+    // Callers extend the tick count in another order than they read it in, as threads do when one
+    // is preempted in between. The tick count advances a step each round, through several
+    // wraparounds, and then a caller that read it a round earlier comes in late. Both get the
+    // true 64-bit count.
+    const STEP: u64 = 1 << 28;
+    let last = AtomicU64::new(0);
+    let mut late = 0;
+    for round in 1..64 {
+        let now = round * STEP;
+        assert_eq!(extend_tick_count(&last, now as u32), now);
+        assert_eq!(extend_tick_count(&last, late as u32), late);
+        late = now;
+    }
+    assert_eq!(last.load(Ordering::Relaxed), 63 * STEP);
+}
+
+#[test]
+fn tick_count_64_matches_tick_count() {
+    let (before, ticks, after) = unsafe { (GetTickCount(), GetTickCount64(), GetTickCount()) };
+    assert!((ticks as u32).wrapping_sub(before) <= after.wrapping_sub(before));
+}
+
+#[test]
+fn try_call_missing_function() {
+    for _ in 0..2 {
+        assert!(RustCompatMissingFunction::option().is_none());
+        unsafe { SetLastError(0) };
+        assert_eq!(unsafe { RustCompatMissingFunction::try_call(1) }, None);
+        let err = crate::io::Error::last_os_error();
+        assert_eq!(err.raw_os_error(), Some(ERROR_CALL_NOT_IMPLEMENTED as i32));
+        assert_eq!(err.kind(), crate::io::ErrorKind::Unsupported);
+    }
+
+    // Functions that do exist are called.
+    let handle = INVALID_HANDLE_VALUE;
+    let len = unsafe { GetFinalPathNameByHandleW::try_call(handle, crate::ptr::null_mut(), 0, 0) };
+    if GetFinalPathNameByHandleW::option().is_some() {
+        assert_eq!(len, Some(0));
+        assert_eq!(unsafe { GetLastError() }, ERROR_INVALID_HANDLE);
+    }
+}
+
+#[test]
+fn load_by_ordinal() {
+    use crate::sys::compat::Module;
+
+    let ws2_32 = unsafe { Module::load(c"ws2_32") }.unwrap();
+    let by_name = ws2_32.proc_address(c"WSAGetLastError").unwrap();
+    assert_eq!(ws2_32.proc_address_by_ordinal(111), Some(by_name));
+    // The name doesn't exist, so the ordinal is used.
+    let by_ordinal = RustCompatByOrdinal::option().unwrap();
+    assert_eq!(by_ordinal as *mut crate::ffi::c_void, by_name.as_ptr());
+}
+
+#[test]
+fn stdcall_decorated_names() {
+    assert_eq!(ansi_str!(stdcall GetTickCount()), c"_GetTickCount@0");
+    assert_eq!(ansi_str!(stdcall Sleep(u32)), c"_Sleep@4");
+    // Smaller arguments still take 4 bytes, and 8-byte ones take 8.
+    assert_eq!(ansi_str!(stdcall Small(u8, u16, bool)), c"_Small@12");
+    assert_eq!(ansi_str!(stdcall Seek(u32, i64, i64, u32)), c"_Seek@24");
+    assert_eq!(ansi_str!(stdcall Precise(f64, u64)), c"_Precise@16");
+    assert_eq!(
+        ansi_str!(stdcall Many(u64, u64, u64, u64, u64, u64, u64, u64, u64, u64, u64, u64, u64)),
+        c"_Many@104"
+    );
+}
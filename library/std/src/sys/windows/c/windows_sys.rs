@@ -8,6 +8,24 @@
 
 #![allow(non_snake_case, non_upper_case_globals, non_camel_case_types, dead_code, clippy::all)]
 #[link(name = "advapi32")]
+extern "system" {
+    pub fn CryptAcquireContextA(
+        phprov: *mut usize,
+        szcontainer: PCSTR,
+        szprovider: PCSTR,
+        dwprovtype: u32,
+        dwflags: u32,
+    ) -> BOOL;
+}
+#[link(name = "advapi32")]
+extern "system" {
+    pub fn CryptGenRandom(hprov: usize, dwlen: u32, pbbuffer: *mut u8) -> BOOL;
+}
+#[link(name = "advapi32")]
+extern "system" {
+    pub fn CryptReleaseContext(hprov: usize, dwflags: u32) -> BOOL;
+}
+#[link(name = "advapi32")]
 extern "system" {
     pub fn OpenProcessToken(
         processhandle: HANDLE,
@@ -30,6 +48,15 @@ pub fn BCryptGenRandom(
         dwflags: BCRYPTGENRANDOM_FLAGS,
     ) -> NTSTATUS;
 }
+#[link(name = "bcrypt")]
+extern "system" {
+    pub fn BCryptOpenAlgorithmProvider(
+        phalgorithm: *mut BCRYPT_ALG_HANDLE,
+        pszalgid: PCWSTR,
+        pszimplementation: PCWSTR,
+        dwflags: BCRYPT_OPEN_ALGORITHM_PROVIDER_FLAGS,
+    ) -> NTSTATUS;
+}
 #[link(name = "kernel32")]
 extern "system" {
     pub fn AcquireSRWLockExclusive(srwlock: *mut SRWLOCK) -> ();
@@ -76,6 +103,13 @@ pub fn CopyFileW(
     ) -> BOOL;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn CreateDirectoryA(
+        lppathname: PCSTR,
+        lpsecurityattributes: *const SECURITY_ATTRIBUTES,
+    ) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn CreateDirectoryW(
         lppathname: PCWSTR,
@@ -101,6 +135,18 @@ pub fn CreateEventW(
     ) -> HANDLE;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn CreateFileA(
+        lpfilename: PCSTR,
+        dwdesiredaccess: u32,
+        dwsharemode: FILE_SHARE_MODE,
+        lpsecurityattributes: *const SECURITY_ATTRIBUTES,
+        dwcreationdisposition: FILE_CREATION_DISPOSITION,
+        dwflagsandattributes: FILE_FLAGS_AND_ATTRIBUTES,
+        htemplatefile: HANDLE,
+    ) -> HANDLE;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn CreateFileW(
         lpfilename: PCWSTR,
@@ -151,6 +197,21 @@ pub fn CreatePipe(
     ) -> BOOL;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn CreateProcessA(
+        lpapplicationname: PCSTR,
+        lpcommandline: PSTR,
+        lpprocessattributes: *const SECURITY_ATTRIBUTES,
+        lpthreadattributes: *const SECURITY_ATTRIBUTES,
+        binherithandles: BOOL,
+        dwcreationflags: PROCESS_CREATION_FLAGS,
+        lpenvironment: *const ::core::ffi::c_void,
+        lpcurrentdirectory: PCSTR,
+        lpstartupinfo: *const STARTUPINFOA,
+        lpprocessinformation: *mut PROCESS_INFORMATION,
+    ) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn CreateProcessW(
         lpapplicationname: PCWSTR,
@@ -194,10 +255,22 @@ pub fn CreateWaitableTimerExW(
     ) -> HANDLE;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn CreateWaitableTimerW(
+        lptimerattributes: *const SECURITY_ATTRIBUTES,
+        bmanualreset: BOOL,
+        lptimername: PCWSTR,
+    ) -> HANDLE;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn DeleteCriticalSection(lpcriticalsection: *mut CRITICAL_SECTION) -> ();
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn DeleteFileA(lpfilename: PCSTR) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn DeleteFileW(lpfilename: PCWSTR) -> BOOL;
 }
@@ -243,10 +316,18 @@ pub fn DuplicateHandle(
     pub fn FindClose(hfindfile: HANDLE) -> BOOL;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn FindFirstFileA(lpfilename: PCSTR, lpfindfiledata: *mut WIN32_FIND_DATAA) -> HANDLE;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn FindFirstFileW(lpfilename: PCWSTR, lpfindfiledata: *mut WIN32_FIND_DATAW) -> HANDLE;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn FindNextFileA(hfindfile: HANDLE, lpfindfiledata: *mut WIN32_FIND_DATAA) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn FindNextFileW(hfindfile: HANDLE, lpfindfiledata: *mut WIN32_FIND_DATAW) -> BOOL;
 }
@@ -255,6 +336,18 @@ pub fn DuplicateHandle(
     pub fn FlushFileBuffers(hfile: HANDLE) -> BOOL;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn FormatMessageA(
+        dwflags: FORMAT_MESSAGE_OPTIONS,
+        lpsource: *const ::core::ffi::c_void,
+        dwmessageid: u32,
+        dwlanguageid: u32,
+        lpbuffer: PSTR,
+        nsize: u32,
+        arguments: *const *const i8,
+    ) -> u32;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn FormatMessageW(
         dwflags: FORMAT_MESSAGE_OPTIONS,
@@ -267,14 +360,30 @@ pub fn FormatMessageW(
     ) -> u32;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn FreeEnvironmentStringsA(penv: PCSTR) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn FreeEnvironmentStringsW(penv: PCWSTR) -> BOOL;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn FreeLibrary(hlibmodule: HMODULE) -> BOOL;
+}
+#[link(name = "kernel32")]
+extern "system" {
+    pub fn GetACP() -> u32;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn GetActiveProcessorCount(groupnumber: u16) -> u32;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn GetCommandLineA() -> PCSTR;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn GetCommandLineW() -> PCWSTR;
 }
@@ -303,10 +412,18 @@ pub fn FormatMessageW(
     pub fn GetCurrentThreadId() -> u32;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn GetEnvironmentStrings() -> PSTR;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn GetEnvironmentStringsW() -> PWSTR;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn GetEnvironmentVariableA(lpname: PCSTR, lpbuffer: PSTR, nsize: u32) -> u32;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn GetEnvironmentVariableW(lpname: PCWSTR, lpbuffer: PWSTR, nsize: u32) -> u32;
 }
@@ -315,6 +432,10 @@ pub fn FormatMessageW(
     pub fn GetExitCodeProcess(hprocess: HANDLE, lpexitcode: *mut u32) -> BOOL;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn GetFileAttributesA(lpfilename: PCSTR) -> u32;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn GetFileAttributesW(lpfilename: PCWSTR) -> u32;
 }
@@ -339,6 +460,15 @@ pub fn GetFileInformationByHandleEx(
     pub fn GetFileSize(hfile: HANDLE, lpfilesizehigh: *mut u32) -> u32;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn GetFileTime(
+        hfile: HANDLE,
+        lpcreationtime: *mut FILETIME,
+        lplastaccesstime: *mut FILETIME,
+        lplastwritetime: *mut FILETIME,
+    ) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn GetFileType(hfile: HANDLE) -> FILE_TYPE;
 }
@@ -365,6 +495,10 @@ pub fn GetFullPathNameW(
     pub fn GetLastError() -> WIN32_ERROR;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn GetModuleFileNameA(hmodule: HMODULE, lpfilename: PSTR, nsize: u32) -> u32;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn GetModuleFileNameW(hmodule: HMODULE, lpfilename: PWSTR, nsize: u32) -> u32;
 }
@@ -394,6 +528,10 @@ pub fn GetOverlappedResult(
     pub fn GetProcessId(process: HANDLE) -> u32;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn GetShortPathNameW(lpszlongpath: PCWSTR, lpszshortpath: PWSTR, cchbuffer: u32) -> u32;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn GetStdHandle(nstdhandle: STD_HANDLE) -> HANDLE;
 }
@@ -418,6 +556,16 @@ pub fn GetOverlappedResult(
     pub fn GetTempPathW(nbufferlength: u32, lpbuffer: PWSTR) -> u32;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn GetThreadTimes(
+        hthread: HANDLE,
+        lpcreationtime: *mut FILETIME,
+        lpexittime: *mut FILETIME,
+        lpkerneltime: *mut FILETIME,
+        lpusertime: *mut FILETIME,
+    ) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn GetTickCount() -> u32;
 }
@@ -468,6 +616,39 @@ pub fn InitializeProcThreadAttributeList(
     pub fn LoadLibraryA(lplibfilename: PCSTR) -> HMODULE;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn LocalAlloc(uflags: LOCAL_ALLOC_FLAGS, ubytes: usize) -> HLOCAL;
+}
+#[link(name = "kernel32")]
+extern "system" {
+    pub fn LocalFree(hmem: HLOCAL) -> HLOCAL;
+}
+#[link(name = "kernel32")]
+extern "system" {
+    pub fn LockFile(
+        hfile: HANDLE,
+        dwfileoffsetlow: u32,
+        dwfileoffsethigh: u32,
+        nnumberofbytestolocklow: u32,
+        nnumberofbytestolockhigh: u32,
+    ) -> BOOL;
+}
+#[link(name = "kernel32")]
+extern "system" {
+    pub fn LockFileEx(
+        hfile: HANDLE,
+        dwflags: LOCK_FILE_FLAGS,
+        dwreserved: u32,
+        nnumberofbytestolocklow: u32,
+        nnumberofbytestolockhigh: u32,
+        lpoverlapped: *mut OVERLAPPED,
+    ) -> BOOL;
+}
+#[link(name = "kernel32")]
+extern "system" {
+    pub fn MoveFileA(lpexistingfilename: PCSTR, lpnewfilename: PCSTR) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn MoveFileExW(
         lpexistingfilename: PCWSTR,
@@ -487,6 +668,17 @@ pub fn MultiByteToWideChar(
     ) -> i32;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn PeekNamedPipe(
+        hnamedpipe: HANDLE,
+        lpbuffer: *mut ::core::ffi::c_void,
+        nbuffersize: u32,
+        lpbytesread: *mut u32,
+        lptotalbytesavail: *mut u32,
+        lpbytesleftthismessage: *mut u32,
+    ) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn PulseEvent(hevent: HANDLE) -> BOOL;
 }
@@ -499,6 +691,19 @@ pub fn MultiByteToWideChar(
     pub fn QueryPerformanceFrequency(lpfrequency: *mut i64) -> BOOL;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn QueueUserAPC(pfnapc: PAPCFUNC, hthread: HANDLE, dwdata: usize) -> u32;
+}
+#[link(name = "kernel32")]
+extern "system" {
+    pub fn RaiseException(
+        dwexceptioncode: u32,
+        dwexceptionflags: u32,
+        nnumberofarguments: u32,
+        lparguments: *const usize,
+    ) -> ();
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn ReadConsoleW(
         hconsoleinput: HANDLE,
@@ -541,10 +746,18 @@ pub fn ReadFileEx(
     pub fn ReleaseSRWLockShared(srwlock: *mut SRWLOCK) -> ();
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn RemoveDirectoryA(lppathname: PCSTR) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn RemoveDirectoryW(lppathname: PCWSTR) -> BOOL;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn SetConsoleMode(hconsolehandle: HANDLE, dwmode: CONSOLE_MODE) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn SetCurrentDirectoryW(lppathname: PCWSTR) -> BOOL;
 }
@@ -553,10 +766,25 @@ pub fn ReadFileEx(
     pub fn SetEndOfFile(hfile: HANDLE) -> BOOL;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn SetEnvironmentVariableA(lpname: PCSTR, lpvalue: PCSTR) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn SetEnvironmentVariableW(lpname: PCWSTR, lpvalue: PCWSTR) -> BOOL;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn SetEvent(hevent: HANDLE) -> BOOL;
+}
+#[link(name = "kernel32")]
+extern "system" {
+    pub fn SetFileAttributesA(
+        lpfilename: PCSTR,
+        dwfileattributes: FILE_FLAGS_AND_ATTRIBUTES,
+    ) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn SetFileAttributesW(
         lpfilename: PCWSTR,
@@ -677,6 +905,16 @@ pub fn SystemTimeToFileTime(lpsystemtime: *const SYSTEMTIME, lpfiletime: *mut FI
     pub fn TryAcquireSRWLockShared(srwlock: *mut SRWLOCK) -> BOOLEAN;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn UnlockFile(
+        hfile: HANDLE,
+        dwfileoffsetlow: u32,
+        dwfileoffsethigh: u32,
+        nnumberofbytestounlocklow: u32,
+        nnumberofbytestounlockhigh: u32,
+    ) -> BOOL;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn UpdateProcThreadAttribute(
         lpattributelist: LPPROC_THREAD_ATTRIBUTE_LIST,
@@ -689,6 +927,23 @@ pub fn UpdateProcThreadAttribute(
     ) -> BOOL;
 }
 #[link(name = "kernel32")]
+extern "system" {
+    pub fn VirtualProtect(
+        lpaddress: *const ::core::ffi::c_void,
+        dwsize: usize,
+        flnewprotect: PAGE_PROTECTION_FLAGS,
+        lpfloldprotect: *mut PAGE_PROTECTION_FLAGS,
+    ) -> BOOL;
+}
+#[link(name = "kernel32")]
+extern "system" {
+    pub fn VirtualQuery(
+        lpaddress: *const ::core::ffi::c_void,
+        lpbuffer: *mut MEMORY_BASIC_INFORMATION,
+        dwlength: usize,
+    ) -> usize;
+}
+#[link(name = "kernel32")]
 extern "system" {
     pub fn WaitForMultipleObjects(
         ncount: u32,
@@ -849,6 +1104,10 @@ pub fn WSASend(
     ) -> i32;
 }
 #[link(name = "ws2_32")]
+extern "system" {
+    pub fn WSASetLastError(ierror: i32) -> ();
+}
+#[link(name = "ws2_32")]
 extern "system" {
     pub fn WSASocketA(
         af: i32,
@@ -1007,6 +1266,7 @@ fn clone(&self) -> Self {
 }
 pub type BCRYPTGENRANDOM_FLAGS = u32;
 pub type BCRYPT_ALG_HANDLE = *mut ::core::ffi::c_void;
+pub type BCRYPT_OPEN_ALGORITHM_PROVIDER_FLAGS = u32;
 pub const BCRYPT_USE_SYSTEM_PREFERRED_RNG: BCRYPTGENRANDOM_FLAGS = 2u32;
 pub const BELOW_NORMAL_PRIORITY_CLASS: PROCESS_CREATION_FLAGS = 16384u32;
 pub type BOOL = i32;
@@ -1278,6 +1538,7 @@ fn clone(&self) -> Self {
     }
 }
 pub type CONTEXT_FLAGS = u32;
+pub const CP_ACP: u32 = 0u32;
 pub const CP_UTF8: u32 = 65001u32;
 pub const CREATE_ALWAYS: FILE_CREATION_DISPOSITION = 2u32;
 pub const CREATE_BREAKAWAY_FROM_JOB: PROCESS_CREATION_FLAGS = 16777216u32;
@@ -1330,6 +1591,7 @@ fn clone(&self) -> Self {
         *self
     }
 }
+pub const CRYPT_VERIFYCONTEXT: u32 = 4026531840u32;
 pub const CSTR_EQUAL: COMPARESTRING_RESULT = 2i32;
 pub const CSTR_GREATER_THAN: COMPARESTRING_RESULT = 3i32;
 pub const CSTR_LESS_THAN: COMPARESTRING_RESULT = 1i32;
@@ -3422,6 +3684,7 @@ fn clone(&self) -> Self {
         *self
     }
 }
+pub type FILE_INFORMATION_CLASS = i32;
 pub type FILE_INFO_BY_HANDLE_CLASS = i32;
 #[repr(C)]
 pub struct FILE_IO_PRIORITY_HINT_INFO {
@@ -3551,6 +3814,7 @@ fn clone(&self) -> Self {
 pub const FileCompressionInfo: FILE_INFO_BY_HANDLE_CLASS = 8i32;
 pub const FileDispositionInfo: FILE_INFO_BY_HANDLE_CLASS = 4i32;
 pub const FileDispositionInfoEx: FILE_INFO_BY_HANDLE_CLASS = 21i32;
+pub const FileDispositionInformation: FILE_INFORMATION_CLASS = 13i32;
 pub const FileEndOfFileInfo: FILE_INFO_BY_HANDLE_CLASS = 6i32;
 pub const FileFullDirectoryInfo: FILE_INFO_BY_HANDLE_CLASS = 14i32;
 pub const FileFullDirectoryRestartInfo: FILE_INFO_BY_HANDLE_CLASS = 15i32;
@@ -3574,6 +3838,7 @@ fn clone(&self) -> Self {
 pub const GENERIC_READ: GENERIC_ACCESS_RIGHTS = 2147483648u32;
 pub const GENERIC_WRITE: GENERIC_ACCESS_RIGHTS = 1073741824u32;
 pub type GETFINALPATHNAMEBYHANDLE_FLAGS = u32;
+pub type GET_FILEEX_INFO_LEVELS = i32;
 #[repr(C)]
 pub struct GUID {
     pub data1: u32,
@@ -3597,11 +3862,13 @@ pub const fn from_u128(uuid: u128) -> Self {
         }
     }
 }
+pub const GetFileExInfoStandard: GET_FILEEX_INFO_LEVELS = 0i32;
 pub type HANDLE = *mut ::core::ffi::c_void;
 pub type HANDLE_FLAGS = u32;
 pub const HANDLE_FLAG_INHERIT: HANDLE_FLAGS = 1u32;
 pub const HANDLE_FLAG_PROTECT_FROM_CLOSE: HANDLE_FLAGS = 2u32;
 pub const HIGH_PRIORITY_CLASS: PROCESS_CREATION_FLAGS = 128u32;
+pub type HLOCAL = *mut ::core::ffi::c_void;
 pub type HMODULE = *mut ::core::ffi::c_void;
 pub type HRESULT = i32;
 pub const IDLE_PRIORITY_CLASS: PROCESS_CREATION_FLAGS = 64u32;
@@ -3805,6 +4072,11 @@ fn clone(&self) -> Self {
         *self
     }
 }
+pub const LMEM_FIXED: LOCAL_ALLOC_FLAGS = 0u32;
+pub type LOCAL_ALLOC_FLAGS = u32;
+pub const LOCKFILE_EXCLUSIVE_LOCK: LOCK_FILE_FLAGS = 2u32;
+pub const LOCKFILE_FAIL_IMMEDIATELY: LOCK_FILE_FLAGS = 1u32;
+pub type LOCK_FILE_FLAGS = u32;
 pub type LPOVERLAPPED_COMPLETION_ROUTINE = ::core::option::Option<
     unsafe extern "system" fn(
         dwerrorcode: u32,
@@ -3855,6 +4127,23 @@ fn clone(&self) -> Self {
 pub const MB_ERR_INVALID_CHARS: MULTI_BYTE_TO_WIDE_CHAR_FLAGS = 8u32;
 pub const MB_PRECOMPOSED: MULTI_BYTE_TO_WIDE_CHAR_FLAGS = 1u32;
 pub const MB_USEGLYPHCHARS: MULTI_BYTE_TO_WIDE_CHAR_FLAGS = 4u32;
+#[repr(C)]
+pub struct MEMORY_BASIC_INFORMATION {
+    pub BaseAddress: *mut ::core::ffi::c_void,
+    pub AllocationBase: *mut ::core::ffi::c_void,
+    pub AllocationProtect: PAGE_PROTECTION_FLAGS,
+    pub RegionSize: usize,
+    pub State: VIRTUAL_ALLOCATION_TYPE,
+    pub Protect: PAGE_PROTECTION_FLAGS,
+    pub Type: PAGE_TYPE,
+}
+impl ::core::marker::Copy for MEMORY_BASIC_INFORMATION {}
+impl ::core::clone::Clone for MEMORY_BASIC_INFORMATION {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+pub const MEM_RESERVE: VIRTUAL_ALLOCATION_TYPE = 8192u32;
 pub const MOVEFILE_COPY_ALLOWED: MOVE_FILE_FLAGS = 2u32;
 pub const MOVEFILE_CREATE_HARDLINK: MOVE_FILE_FLAGS = 16u32;
 pub const MOVEFILE_DELAY_UNTIL_REBOOT: MOVE_FILE_FLAGS = 4u32;
@@ -3890,10 +4179,26 @@ fn clone(&self) -> Self {
         *self
     }
 }
+pub const OBJ_CASE_INSENSITIVE: i32 = 64i32;
 pub const OBJ_DONT_REPARSE: i32 = 4096i32;
 pub const OPEN_ALWAYS: FILE_CREATION_DISPOSITION = 4u32;
 pub const OPEN_EXISTING: FILE_CREATION_DISPOSITION = 3u32;
 #[repr(C)]
+pub struct OSVERSIONINFOW {
+    pub dwOSVersionInfoSize: u32,
+    pub dwMajorVersion: u32,
+    pub dwMinorVersion: u32,
+    pub dwBuildNumber: u32,
+    pub dwPlatformId: u32,
+    pub szCSDVersion: [u16; 128],
+}
+impl ::core::marker::Copy for OSVERSIONINFOW {}
+impl ::core::clone::Clone for OSVERSIONINFOW {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+#[repr(C)]
 pub struct OVERLAPPED {
     pub Internal: usize,
     pub InternalHigh: usize,
@@ -3928,6 +4233,11 @@ fn clone(&self) -> Self {
         *self
     }
 }
+pub const PAGE_GUARD: PAGE_PROTECTION_FLAGS = 256u32;
+pub type PAGE_PROTECTION_FLAGS = u32;
+pub const PAGE_READWRITE: PAGE_PROTECTION_FLAGS = 4u32;
+pub type PAGE_TYPE = u32;
+pub type PAPCFUNC = ::core::option::Option<unsafe extern "system" fn(parameter: usize) -> ()>;
 pub type PCSTR = *const u8;
 pub type PCWSTR = *const u16;
 pub type PIO_APC_ROUTINE = ::core::option::Option<
@@ -3972,6 +4282,7 @@ fn clone(&self) -> Self {
 pub const PROFILE_SERVER: PROCESS_CREATION_FLAGS = 1073741824u32;
 pub const PROFILE_USER: PROCESS_CREATION_FLAGS = 268435456u32;
 pub const PROGRESS_CONTINUE: u32 = 0u32;
+pub const PROV_RSA_FULL: u32 = 1u32;
 pub type PSTR = *mut u8;
 pub type PTIMERAPCROUTINE = ::core::option::Option<
     unsafe extern "system" fn(
@@ -4075,6 +4386,33 @@ fn clone(&self) -> Self {
 pub const STARTF_USESIZE: STARTUPINFOW_FLAGS = 2u32;
 pub const STARTF_USESTDHANDLES: STARTUPINFOW_FLAGS = 256u32;
 #[repr(C)]
+pub struct STARTUPINFOA {
+    pub cb: u32,
+    pub lpReserved: PSTR,
+    pub lpDesktop: PSTR,
+    pub lpTitle: PSTR,
+    pub dwX: u32,
+    pub dwY: u32,
+    pub dwXSize: u32,
+    pub dwYSize: u32,
+    pub dwXCountChars: u32,
+    pub dwYCountChars: u32,
+    pub dwFillAttribute: u32,
+    pub dwFlags: STARTUPINFOW_FLAGS,
+    pub wShowWindow: u16,
+    pub cbReserved2: u16,
+    pub lpReserved2: *mut u8,
+    pub hStdInput: HANDLE,
+    pub hStdOutput: HANDLE,
+    pub hStdError: HANDLE,
+}
+impl ::core::marker::Copy for STARTUPINFOA {}
+impl ::core::clone::Clone for STARTUPINFOA {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+#[repr(C)]
 pub struct STARTUPINFOEXW {
     pub StartupInfo: STARTUPINFOW,
     pub lpAttributeList: LPPROC_THREAD_ATTRIBUTE_LIST,
@@ -4113,12 +4451,22 @@ fn clone(&self) -> Self {
     }
 }
 pub type STARTUPINFOW_FLAGS = u32;
+pub const STATUS_ACCESS_DENIED: NTSTATUS = -1073741790i32;
+pub const STATUS_ALERTED: NTSTATUS = 257i32;
+pub const STATUS_CANNOT_DELETE: NTSTATUS = -1073741535i32;
 pub const STATUS_DELETE_PENDING: NTSTATUS = -1073741738i32;
 pub const STATUS_END_OF_FILE: NTSTATUS = -1073741807i32;
 pub const STATUS_INVALID_PARAMETER: NTSTATUS = -1073741811i32;
 pub const STATUS_NOT_IMPLEMENTED: NTSTATUS = -1073741822i32;
+pub const STATUS_NO_MEMORY: NTSTATUS = -1073741801i32;
+pub const STATUS_OBJECT_NAME_COLLISION: NTSTATUS = -1073741771i32;
+pub const STATUS_OBJECT_NAME_NOT_FOUND: NTSTATUS = -1073741772i32;
+pub const STATUS_OBJECT_PATH_NOT_FOUND: NTSTATUS = -1073741766i32;
 pub const STATUS_PENDING: NTSTATUS = 259i32;
+pub const STATUS_SHARING_VIOLATION: NTSTATUS = -1073741757i32;
 pub const STATUS_SUCCESS: NTSTATUS = 0i32;
+pub const STATUS_UNSUCCESSFUL: NTSTATUS = -1073741823i32;
+pub const STATUS_USER_APC: NTSTATUS = 192i32;
 pub const STD_ERROR_HANDLE: STD_HANDLE = 4294967284u32;
 pub type STD_HANDLE = u32;
 pub const STD_INPUT_HANDLE: STD_HANDLE = 4294967286u32;
@@ -4241,6 +4589,7 @@ fn clone(&self) -> Self {
         *self
     }
 }
+pub type VIRTUAL_ALLOCATION_TYPE = u32;
 pub const VOLUME_NAME_DOS: GETFINALPATHNAMEBYHANDLE_FLAGS = 0u32;
 pub const VOLUME_NAME_GUID: GETFINALPATHNAMEBYHANDLE_FLAGS = 1u32;
 pub const VOLUME_NAME_NONE: GETFINALPATHNAMEBYHANDLE_FLAGS = 4u32;
@@ -4254,6 +4603,40 @@ fn clone(&self) -> Self {
 pub const WC_ERR_INVALID_CHARS: u32 = 128u32;
 pub type WIN32_ERROR = u32;
 #[repr(C)]
+pub struct WIN32_FILE_ATTRIBUTE_DATA {
+    pub dwFileAttributes: u32,
+    pub ftCreationTime: FILETIME,
+    pub ftLastAccessTime: FILETIME,
+    pub ftLastWriteTime: FILETIME,
+    pub nFileSizeHigh: u32,
+    pub nFileSizeLow: u32,
+}
+impl ::core::marker::Copy for WIN32_FILE_ATTRIBUTE_DATA {}
+impl ::core::clone::Clone for WIN32_FILE_ATTRIBUTE_DATA {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+#[repr(C)]
+pub struct WIN32_FIND_DATAA {
+    pub dwFileAttributes: u32,
+    pub ftCreationTime: FILETIME,
+    pub ftLastAccessTime: FILETIME,
+    pub ftLastWriteTime: FILETIME,
+    pub nFileSizeHigh: u32,
+    pub nFileSizeLow: u32,
+    pub dwReserved0: u32,
+    pub dwReserved1: u32,
+    pub cFileName: [u8; 260],
+    pub cAlternateFileName: [u8; 14],
+}
+impl ::core::marker::Copy for WIN32_FIND_DATAA {}
+impl ::core::clone::Clone for WIN32_FIND_DATAA {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+#[repr(C)]
 pub struct WIN32_FIND_DATAW {
     pub dwFileAttributes: u32,
     pub ftCreationTime: FILETIME,
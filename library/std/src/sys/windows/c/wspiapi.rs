@@ -495,7 +495,7 @@ pub struct hostent {
 
 compat_fn_lazy! {
     // load is not needed, we already need ws2_32 to get here
-    pub static WS2_32: &CStr = c"ws2_32" => { load: false, unicows: false };
+    pub static WS2_32: &CStr = c"ws2_32" => { loaded: false, unicows: false };
 
     /// The pointer that is returned points to the SERVENT structure allocated by the
     /// Windows Sockets library. The application must never attempt to modify this
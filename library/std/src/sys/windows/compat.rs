@@ -17,15 +17,99 @@
 //! place. While this is low cost for the happy path (where the function is
 //! already loaded) it does mean there's some overhead the first time the
 //! function is called. In the worst case, multiple threads may all end up
-//! importing the same function unnecessarily.
+//! importing the same function unnecessarily, and storing the same pointer.
+//!
+//! Pointers are always stored with `Release` and loaded with `Acquire` ordering,
+//! so that a thread calling through a pointer also sees everything the thread
+//! that stored it did before. This costs nothing on x86, but matters on ARM.
 
 use crate::ffi::{c_void, CStr};
-use crate::ptr::NonNull;
-use crate::sync::atomic::Ordering;
+use crate::fmt;
+use crate::ptr::{self, NonNull};
+#[cfg(test)]
+use crate::sync::atomic::AtomicUsize;
+use crate::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU8, Ordering};
 use crate::sys::c;
 
+mod system;
+pub mod trace;
 mod version;
-pub use version::{is_windows_nt, supports_async_io};
+pub use system::{windows_capabilities, WindowsCapabilities};
+pub use version::{is_windows_nt, nt_build_number, supports_async_io};
+#[cfg(test)]
+pub use version::with_sync_io;
+
+#[cfg(test)]
+mod tests;
+
+/// Describes which of the optional features and backends are used on this system, one per line.
+///
+/// Meant for diagnosing problems on old systems, where it isn't obvious which fallback was taken.
+/// Returned by `std::os::windows::compat::report`.
+pub fn report() -> String {
+    let bcrypt_failure = match crate::sys::rand::bcrypt_failure() {
+        Some(status) => format!("{status:#x}"),
+        None => "none".into(),
+    };
+    let loaded = |error: Option<LoadError>| match error {
+        Some(error) => error.to_string(),
+        None => "loaded".into(),
+    };
+    #[cfg(not(target_vendor = "uwp"))]
+    let stack_overflow_handler = loaded(c::AddVectoredExceptionHandler::load_error());
+    #[cfg(target_vendor = "uwp")]
+    let stack_overflow_handler = "unsupported".to_string();
+    format!(
+        "windows nt: {}\nnt build: {}\nasync i/o: {}\nrng: {}\nbcrypt failure: {}\n\
+         stack guarantee missing: {}\nstack overflow handler: {}\nthread parking: {}\n\
+         srw locks: {}\n",
+        is_windows_nt(),
+        nt_build_number(),
+        supports_async_io(),
+        crate::sys::rand::backend_name(),
+        bcrypt_failure,
+        crate::sys::stack_overflow::guarantee_missing(),
+        stack_overflow_handler,
+        crate::sys::thread_parking::backend_name(),
+        loaded(c::AcquireSRWLockExclusive::load_error()),
+    )
+}
+
+/// Collects which of the optional features std uses were found, for `std::os::windows::compat`.
+pub fn capabilities() -> crate::os::windows::compat::Capabilities {
+    use super::locks::compat::MutexKind as Kind;
+    use crate::os::windows::compat::{Capabilities, FunctionSource, MutexKind};
+
+    let function_source = |source| match source {
+        Source::NotLoadedYet => FunctionSource::NotLoadedYet,
+        Source::Native => FunctionSource::Native,
+        Source::Unicows => FunctionSource::Unicows,
+        Source::Fallback => FunctionSource::Fallback,
+        Source::Missing => FunctionSource::Missing,
+    };
+    let mutex = match windows_capabilities().mutex_kind {
+        Kind::SrwLock => MutexKind::SrwLock,
+        Kind::CriticalSection => MutexKind::CriticalSection,
+        Kind::Legacy => MutexKind::Legacy,
+    };
+    Capabilities {
+        srw_locks: mutex == MutexKind::SrwLock,
+        srw_try_locks: c::TryAcquireSRWLockExclusive::option().is_some()
+            && c::TryAcquireSRWLockShared::option().is_some(),
+        #[cfg(not(windows_min_version = "8"))]
+        wait_on_address: c::WaitOnAddress::option().is_some(),
+        // Imported, so it's always there.
+        #[cfg(windows_min_version = "8")]
+        wait_on_address: true,
+        try_enter_critical_section: c::TryEnterCriticalSection::option().is_some(),
+        async_io: supports_async_io(),
+        cancel_io: c::CancelIo::option().is_some(),
+        mutex,
+        unicows: unsafe { Module::new(UNICOWS) }.is_some(),
+        get_user_profile_directory: function_source(c::GetUserProfileDirectoryW::source()),
+        compare_string_ordinal: function_source(c::CompareStringOrdinal::source()),
+    }
+}
 
 // This uses a static initializer to preload some imported functions.
 // The CRT (C runtime) executes static initializers before `main`
@@ -45,6 +129,67 @@
 #[link_section = ".CRT$XCT"]
 static INIT_TABLE_ENTRY: unsafe extern "C" fn() = init;
 
+unsafe extern "C" fn init() {
+    ensure_init();
+}
+
+/// Runs `preload` if that hasn't happened yet.
+///
+/// Normally the CRT already did that before `main`, but not for binaries with custom entry points
+/// or that skip CRT initialization in some other way. So everything `preload` sets up checks this
+/// first: the `compat_fn_optional` functions and the `WindowsCapabilities`.
+#[inline]
+pub fn ensure_init() {
+    PRELOAD.ensure(|| unsafe { preload() });
+}
+
+static PRELOAD: Preload = Preload::new();
+
+const UNINIT: u8 = 0;
+const RUNNING: u8 = 1;
+const DONE: u8 = 2;
+
+/// A `Once` for `preload`, which can't use `Once` as that needs the locks `preload` picks.
+struct Preload {
+    state: AtomicU8,
+    /// The thread that runs `preload`, so that its own calls to `ensure` while doing that don't
+    /// wait for themselves.
+    thread: AtomicU32,
+}
+
+impl Preload {
+    const fn new() -> Self {
+        Self { state: AtomicU8::new(UNINIT), thread: AtomicU32::new(0) }
+    }
+
+    #[inline]
+    fn ensure(&self, preload: impl FnOnce()) {
+        if self.state.load(Ordering::Acquire) != DONE {
+            self.ensure_slow(preload);
+        }
+    }
+
+    #[cold]
+    fn ensure_slow(&self, preload: impl FnOnce()) {
+        let me = unsafe { c::GetCurrentThreadId() };
+        match self.state.compare_exchange(UNINIT, RUNNING, Ordering::Acquire, Ordering::Acquire) {
+            Ok(_) => {
+                self.thread.store(me, Ordering::Relaxed);
+                preload();
+                self.state.store(DONE, Ordering::Release);
+            }
+            Err(DONE) => {}
+            // Whatever `preload` looks at while it runs sees the defaults.
+            Err(_) if self.thread.load(Ordering::Relaxed) == me => {}
+            Err(_) => {
+                while self.state.load(Ordering::Acquire) != DONE {
+                    unsafe { c::Sleep(0) };
+                }
+            }
+        }
+    }
+}
+
 /// Preload some imported functions.
 ///
 /// Note that any functions included here will be unconditionally loaded in
@@ -55,8 +200,11 @@
 /// negative performance impact in practical situations.
 ///
 /// Currently we only preload `WaitOnAddress` and `WakeByAddressSingle`.
-unsafe extern "C" fn init() {
-    // In an exe this code is executed before main() so is single threaded.
+///
+/// Builds with the `windows_minimal_preload` feature only check the Windows version here, for
+/// binaries where startup work matters more than the first use of a lock or thread.
+unsafe fn preload() {
+    // In an exe this code is normally executed before main() so is single threaded.
     // In a DLL the system's loader lock will be held thereby synchronizing
     // access. So the same best practices apply here as they do to running in DllMain:
     // https://docs.microsoft.com/en-us/windows/win32/dlls/dynamic-link-library-best-practices
@@ -66,23 +214,52 @@
     // because this function runs during global initialization. For example, DO NOT
     // do any dynamic allocation, don't call LoadLibrary, etc.
 
-    version::init_windows_version_check();
+    // Before anything gets loaded, so that everything is traced, and nothing comes from unicows.
+    trace::init();
+    if env_var_set(c"RUST_COMPAT_NO_UNICOWS") {
+        disable_unicows();
+    }
+    let mut capabilities = WindowsCapabilities::DEFAULT;
+    version::check_windows_version(&mut capabilities);
+    system::publish(capabilities, false);
 
-    // check all the different synchronization primitives ...
-    load_try_enter_critical_section_function();
-    load_srw_functions();
-    // ... and init mutex downlevel compat based on it
-    super::locks::compat::init();
+    // With minimal preload, everything else is loaded the first time it's needed, see
+    // `compat_fn_optional` and `windows_capabilities`. Locks used before this picks the mutex
+    // kind pick it themselves, just like there.
+    #[cfg(not(feature = "windows_minimal_preload"))]
+    {
+        capabilities.mutex_kind = pick_mutex_kind();
+        system::publish(capabilities, true);
 
-    // Attempt to preload the synch functions.
-    load_synch_functions();
-    #[cfg(not(target_vendor = "uwp"))]
-    load_stack_overflow_functions();
+        // Attempt to preload the synch functions, unless they are imported.
+        #[cfg(not(windows_min_version = "8"))]
+        load_synch_functions();
+        #[cfg(not(target_vendor = "uwp"))]
+        load_stack_overflow_functions();
+    }
+}
+
+/// Checks all the different synchronization primitives, and picks the mutex downlevel compat
+/// based on them.
+fn pick_mutex_kind() -> super::locks::compat::MutexKind {
+    load_try_enter_critical_section_function();
+    super::locks::compat::choose(load_srw_functions())
 }
 
 /// Helper macro for creating CStrs from literals and symbol names.
+///
+/// `stdcall name(types...)` creates the x86 stdcall decoration of the name of a function with
+/// arguments of those types, `_name@N`, where `N` is the number of bytes they take on the stack.
 macro_rules! ansi_str {
     (sym $ident:ident) => {{ crate::sys::compat::const_cstr_from_bytes(concat!(stringify!($ident), "\0").as_bytes()) }};
+    (stdcall $ident:ident($($argtype:ty),* $(,)?)) => {{
+        const NAME: &str = stringify!($ident);
+        const SIZE: usize =
+            0 $(+ crate::sys::compat::stdcall_arg_size(crate::mem::size_of::<$argtype>()))*;
+        // Room for `_`, `@`, up to five digits and the null.
+        const BYTES: [u8; NAME.len() + 8] = crate::sys::compat::decorate_stdcall(NAME, SIZE);
+        crate::sys::compat::const_cstr_until_nul(&BYTES)
+    }};
     ($lit:literal) => {{ crate::sys::compat::const_cstr_from_bytes(concat!($lit, "\0").as_bytes()) }};
 }
 
@@ -109,6 +286,60 @@ pub(crate) const fn const_cstr_from_bytes(bytes: &'static [u8]) -> &'static CStr
     unsafe { crate::ffi::CStr::from_bytes_with_nul_unchecked(bytes) }
 }
 
+/// Like `const_cstr_from_bytes`, but ends the string at the first null, ignoring what follows.
+///
+/// # Panics
+///
+/// Panics if the slice contains no null.
+pub(crate) const fn const_cstr_until_nul(bytes: &'static [u8]) -> &'static CStr {
+    match CStr::from_bytes_until_nul(bytes) {
+        Ok(cstr) => cstr,
+        Err(_) => panic!("A CStr must be null terminated"),
+    }
+}
+
+/// How many bytes an argument of `size` bytes takes on the stack in a stdcall call, as counted by
+/// the decoration of stdcall names. Every argument takes a multiple of 4 bytes.
+pub(crate) const fn stdcall_arg_size(size: usize) -> usize {
+    (size + 3) & !3
+}
+
+/// Writes the stdcall decoration of `name`, `_name@size`, and a null to an array of `N` bytes. The
+/// remainder of the array is left null too.
+///
+/// # Panics
+///
+/// Panics if the decorated name doesn't fit.
+pub(crate) const fn decorate_stdcall<const N: usize>(name: &str, size: usize) -> [u8; N] {
+    let name = name.as_bytes();
+    let mut out = [0; N];
+    let mut len = 0;
+    out[len] = b'_';
+    len += 1;
+    let mut i = 0;
+    while i < name.len() {
+        out[len] = name[i];
+        len += 1;
+        i += 1;
+    }
+    out[len] = b'@';
+    len += 1;
+    // The digits of `size`, most significant first.
+    let mut unit = 1;
+    while size / unit >= 10 {
+        unit *= 10;
+    }
+    while unit > 0 {
+        out[len] = b'0' + (size / unit % 10) as u8;
+        len += 1;
+        unit /= 10;
+    }
+    if len >= N {
+        panic!("The decorated name doesn't fit");
+    }
+    out
+}
+
 /// Represents a loaded module.
 ///
 /// Note that the modules std depends on must not be unloaded.
@@ -128,7 +359,6 @@ pub unsafe fn new(name: &CStr) -> Option<Self> {
         NonNull::new(module).map(Self)
     }
 
-    #[allow(dead_code)]
     pub unsafe fn load(name: &CStr) -> Option<Self> {
         // SAFETY: A CStr is always null terminated.
         let module = c::LoadLibraryA(name.as_ptr().cast::<u8>());
@@ -137,6 +367,10 @@ pub unsafe fn load(name: &CStr) -> Option<Self> {
 
     // Try to get the address of a function.
     pub fn proc_address(self, name: &CStr) -> Option<NonNull<c_void>> {
+        #[cfg(test)]
+        if blocked(name) {
+            return None;
+        }
         unsafe {
             // SAFETY:
             // `self.0` will always be a valid module.
@@ -146,74 +380,531 @@ pub fn proc_address(self, name: &CStr) -> Option<NonNull<c_void>> {
             proc.map(|p| NonNull::new_unchecked(p as *mut c_void))
         }
     }
+
+    /// Like `proc_address`, but says why the function wasn't found.
+    pub fn try_proc_address(self, name: &CStr) -> Result<NonNull<c_void>, LoadError> {
+        self.proc_address(name).ok_or_else(LoadError::symbol_not_found)
+    }
+
+    /// Try to get the address of a function by its ordinal.
+    ///
+    /// Ordinals aren't checked against names, so this finds whatever the module exports under
+    /// `ordinal`. Only use it for ordinals that never changed.
+    pub fn proc_address_by_ordinal(self, ordinal: u16) -> Option<NonNull<c_void>> {
+        unsafe {
+            // SAFETY:
+            // `self.0` will always be a valid module.
+            // `GetProcAddress` takes an ordinal in the low word of the name, with a zero high word.
+            let proc = c::GetProcAddress(self.0.as_ptr(), ptr::invalid(ordinal as usize));
+            // SAFETY: `GetProcAddress` returns None on null.
+            proc.map(|p| NonNull::new_unchecked(p as *mut c_void))
+        }
+    }
 }
 
 pub static UNICOWS: &CStr = c"unicows";
 
+/// Where a function declared with one of the `compat_fn_*` macros came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Source {
+    /// The function wasn't used yet, so it wasn't looked for either.
+    NotLoadedYet = 0,
+    /// Found in the module it is declared for.
+    Native = 1,
+    /// Found in unicows.
+    Unicows = 2,
+    /// Not found, so the fallback implementation was installed.
+    Fallback = 3,
+    /// Not found, and there is no fallback.
+    Missing = 4,
+}
+
+impl Source {
+    pub fn from_u8(source: u8) -> Self {
+        match source {
+            1 => Self::Native,
+            2 => Self::Unicows,
+            3 => Self::Fallback,
+            4 => Self::Missing,
+            _ => Self::NotLoadedYet,
+        }
+    }
+}
+
+/// Why a `compat_fn_optional` function wasn't loaded, with the error `GetLastError` returned at
+/// the time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    /// The module it is loaded from wasn't found.
+    ModuleNotFound { code: u32 },
+    /// The module doesn't export it, or one of the functions it's loaded together with.
+    SymbolNotFound { code: u32 },
+}
+
+impl LoadError {
+    /// Made right after looking up a module failed.
+    pub fn module_not_found() -> Self {
+        Self::ModuleNotFound { code: unsafe { c::GetLastError() } }
+    }
+
+    /// Made right after looking up a function failed.
+    pub fn symbol_not_found() -> Self {
+        Self::SymbolNotFound { code: unsafe { c::GetLastError() } }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::ModuleNotFound { code } => write!(f, "module not found (os error {code})"),
+            Self::SymbolNotFound { code } => write!(f, "function not found (os error {code})"),
+        }
+    }
+}
+
+/// Where a `compat_fn_optional` function keeps its `LoadError`. This can't just be a `Mutex`, as
+/// it's written while the locks are being picked.
+pub struct LoadFailure {
+    /// 0 if nothing was recorded, or else which `LoadError` it was.
+    kind: AtomicU8,
+    code: AtomicU32,
+}
+
+impl LoadFailure {
+    pub const fn new() -> Self {
+        Self { kind: AtomicU8::new(0), code: AtomicU32::new(0) }
+    }
+
+    pub fn record(&self, error: LoadError) {
+        let (kind, code) = match error {
+            LoadError::ModuleNotFound { code } => (1, code),
+            LoadError::SymbolNotFound { code } => (2, code),
+        };
+        self.code.store(code, Ordering::Relaxed);
+        self.kind.store(kind, Ordering::Release);
+    }
+
+    pub fn get(&self) -> Option<LoadError> {
+        let kind = self.kind.load(Ordering::Acquire);
+        let code = self.code.load(Ordering::Relaxed);
+        match kind {
+            1 => Some(LoadError::ModuleNotFound { code }),
+            2 => Some(LoadError::SymbolNotFound { code }),
+            _ => None,
+        }
+    }
+}
+
+/// Set if functions must not be loaded from unicows, even if it is loaded.
+static UNICOWS_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Stops functions that weren't loaded yet from being loaded from unicows, so that a `unicows.dll`
+/// that isn't wanted can't take the place of the system's functions.
+///
+/// This is also done if `RUST_COMPAT_NO_UNICOWS` is set in the environment at startup.
+pub fn disable_unicows() {
+    UNICOWS_DISABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn unicows_enabled() -> bool {
+    !UNICOWS_DISABLED.load(Ordering::Relaxed)
+}
+
+/// Whether the environment variable `name` is set, even if it's empty.
+///
+/// Only asks for the size of the value, so this doesn't allocate and can be used by `preload`.
+fn env_var_set(name: &CStr) -> bool {
+    unsafe { c::GetEnvironmentVariableA(name.as_ptr().cast(), ptr::null_mut(), 0) != 0 }
+}
+
+/// A module that many functions are loaded from, and its handle once the loader found it.
+struct CachedModule {
+    name: &'static CStr,
+    handle: AtomicPtr<c_void>,
+    /// How often the loader was asked for the module.
+    #[cfg(test)]
+    lookups: AtomicUsize,
+}
+
+impl CachedModule {
+    const fn new(name: &'static CStr) -> Self {
+        Self {
+            name,
+            handle: AtomicPtr::new(ptr::null_mut()),
+            #[cfg(test)]
+            lookups: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the module, looking it up with `find` until that succeeds once. `find` says why the
+    /// module wasn't found, as it has to capture the last error right after the lookup.
+    ///
+    /// Threads that race on the first lookup all call `find`, as waiting for another thread that
+    /// is inside `LoadLibraryA` could deadlock on the loader lock if we hold it (in `DllMain`). The
+    /// first to finish publishes its module, and the others `release` theirs, so that the module
+    /// ends up with a single reference.
+    fn get(
+        &self,
+        find: impl FnOnce(&CStr) -> Result<Module, LoadError>,
+        release: impl FnOnce(Module),
+    ) -> Result<Module, LoadError> {
+        if let Some(handle) = NonNull::new(self.handle.load(Ordering::Acquire)) {
+            return Ok(Module(handle));
+        }
+        #[cfg(test)]
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        let module = find(self.name)?;
+        match self.handle.compare_exchange(
+            ptr::null_mut(),
+            module.0.as_ptr(),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => Ok(module),
+            Err(existing) => {
+                release(module);
+                // SAFETY: Only non-null handles are ever stored.
+                Ok(Module(unsafe { NonNull::new_unchecked(existing) }))
+            }
+        }
+    }
+}
+
+/// How `Module::cached` looks up a module.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(in crate::sys) enum Lookup {
+    /// With `Module::new`, which only finds modules that are already loaded.
+    Loaded,
+    /// With `Module::load`, which loads the module if needed, and adds a reference to it.
+    Load,
+}
+
+/// The modules used by more than a few `compat_fn_*` functions. This is a fixed array so that it
+/// can be used from `init`, where nothing may allocate.
+static MODULE_CACHE: [CachedModule; 8] = [
+    CachedModule::new(c"kernel32"),
+    CachedModule::new(c"ntdll"),
+    CachedModule::new(c"advapi32"),
+    CachedModule::new(c"userenv"),
+    CachedModule::new(c"bcrypt"),
+    CachedModule::new(c"ws2_32"),
+    CachedModule::new(c"wship6"),
+    CachedModule::new(c"unicows"),
+];
+
+impl Module {
+    /// Looks up a module with `Module::new` or `Module::load`, as `lookup` says, but only until
+    /// that succeeds once if it's one of the cached modules.
+    ///
+    /// The reference that `LoadLibraryA` adds is never released, so a cached module keeps exactly
+    /// one, even if threads race to load it. Failures aren't cached, as a module looked up with
+    /// `Module::new` may still be loaded later. The error is taken right after the lookup that
+    /// failed, so nothing that runs after it can overwrite the code.
+    ///
+    /// # SAFETY
+    ///
+    /// The same as for `Module::new`. A module that was found with `Module::new` is also returned
+    /// to callers that would have loaded it themselves, and so must not be unloaded either.
+    pub unsafe fn cached(name: &CStr, lookup: Lookup) -> Result<Self, LoadError> {
+        let find = |name: &CStr| {
+            let module = match lookup {
+                Lookup::Loaded => Self::new(name),
+                Lookup::Load => Self::load(name),
+            };
+            module.ok_or_else(LoadError::module_not_found)
+        };
+        let Some(entry) = MODULE_CACHE.iter().find(|entry| entry.name == name) else {
+            return find(name);
+        };
+        entry.get(find, |module| {
+            if lookup == Lookup::Load {
+                c::FreeLibrary(module.0.as_ptr());
+            }
+        })
+    }
+}
+
+/// Runs `probe`, which calls a function that was just loaded, and returns `false` only if that
+/// call failed with `ERROR_CALL_NOT_IMPLEMENTED`. The last error is left as it was.
+pub fn passes_probe(probe: impl FnOnce() -> bool) -> bool {
+    unsafe {
+        let last_error = c::GetLastError();
+        let passed = probe() || c::GetLastError() != c::ERROR_CALL_NOT_IMPLEMENTED;
+        c::SetLastError(last_error);
+        passed
+    }
+}
+
+/// The functions that one `compat_fn_with_fallback!` or `compat_fn_lazy!` declares, which are all
+/// loaded from the same module.
+///
+/// Each function only gets its typed entry points. Everything else lives here, indexed by the
+/// order the functions were declared in, so that the code looking them up exists only once.
+pub(in crate::sys) struct SymbolTable<const N: usize> {
+    module: &'static CStr,
+    lookup: Lookup,
+    unicows: bool,
+    names: [&'static CStr; N],
+    /// The stdcall decorations of the names, only on x86, where some modules export those instead.
+    decorated: [Option<&'static CStr>; N],
+    ordinals: [Option<u16>; N],
+    /// The function each entry point calls. It starts out as the entry point's `load` function,
+    /// which replaces it with the function that was found, or the fallback.
+    pub ptrs: [AtomicPtr<c_void>; N],
+    /// Where the function in `ptrs` came from, as a `Source`.
+    sources: [AtomicU8; N],
+}
+
+const NOT_LOADED_YET: AtomicU8 = AtomicU8::new(Source::NotLoadedYet as u8);
+
+impl<const N: usize> SymbolTable<N> {
+    pub const fn new(
+        module: &'static CStr,
+        lookup: Lookup,
+        unicows: bool,
+        names: [&'static CStr; N],
+        decorated: [Option<&'static CStr>; N],
+        ordinals: [Option<u16>; N],
+        ptrs: [AtomicPtr<c_void>; N],
+    ) -> Self {
+        let sources = [NOT_LOADED_YET; N];
+        Self { module, lookup, unicows, names, decorated, ordinals, ptrs, sources }
+    }
+
+    /// Where function `i` came from, without loading it.
+    pub fn source(&self, i: usize) -> Source {
+        Source::from_u8(self.sources[i].load(Ordering::Acquire))
+    }
+
+    /// Looks up function `i`, and returns it along with whether it came from unicows.
+    pub unsafe fn find(&self, i: usize) -> (Option<NonNull<c_void>>, bool) {
+        let symbol = (self.names[i], self.decorated[i], self.ordinals[i]);
+        find_symbol(self.module, self.lookup, self.unicows, symbol)
+    }
+
+    /// Stores the function that `find` returned for function `i`, or `fallback` if there is
+    /// none, and returns the one it stored. A null `fallback` means the function is missing.
+    pub fn store(
+        &self,
+        i: usize,
+        f: Option<NonNull<c_void>>,
+        in_unicows: bool,
+        fallback: *mut c_void,
+    ) -> *mut c_void {
+        let source = match (in_unicows, f) {
+            (true, Some(_)) => Source::Unicows,
+            (false, Some(_)) => Source::Native,
+            (_, None) if fallback.is_null() => Source::Missing,
+            (_, None) => Source::Fallback,
+        };
+        trace::record(self.names[i], self.module, source);
+        // Before the function, so that it's up to date once the function is.
+        self.sources[i].store(source as u8, Ordering::Release);
+        let f = f.map_or(fallback, NonNull::as_ptr);
+        self.ptrs[i].store(f, Ordering::Release);
+        f
+    }
+
+    /// Replaces function `i` with `fallback`, if it's still `f`, for a function that was loaded
+    /// but turned out not to work. Returns whether it did, which only one caller does.
+    pub fn demote(&self, i: usize, f: *mut c_void, fallback: *mut c_void) -> bool {
+        if f == fallback {
+            return false;
+        }
+        let demoted =
+            self.ptrs[i].compare_exchange(f, fallback, Ordering::AcqRel, Ordering::Acquire).is_ok();
+        // Unlike in `store`, the source follows the function, as only the thread that swapped
+        // it out may record that.
+        if demoted {
+            let source = if fallback.is_null() { Source::Missing } else { Source::Fallback };
+            trace::record(self.names[i], self.module, source);
+            self.sources[i].store(source as u8, Ordering::Release);
+        }
+        demoted
+    }
+}
+
+/// Looks up a symbol, given as its name, decorated name and ordinal, in unicows if it may come
+/// from there, and in `module` otherwise.
+///
+/// The decorated name and the ordinal are only tried if the name isn't found, in that order.
+unsafe fn find_symbol(
+    module: &CStr,
+    lookup: Lookup,
+    unicows: bool,
+    (name, decorated, ordinal): (&CStr, Option<&CStr>, Option<u16>),
+) -> (Option<NonNull<c_void>>, bool) {
+    let by_name =
+        |module: Module| module.proc_address(name).or_else(|| module.proc_address(decorated?));
+    let in_unicows = if unicows && unicows_enabled() {
+        Module::cached(UNICOWS, Lookup::Loaded).ok().and_then(by_name)
+    } else {
+        None
+    };
+    let f = in_unicows.or_else(|| {
+        let module = Module::cached(module, lookup).ok()?;
+        by_name(module).or_else(|| module.proc_address_by_ordinal(ordinal?))
+    });
+    #[cfg(test)]
+    if let Some(resolve) = test_resolver() {
+        return (resolve(name), false);
+    }
+    (f, in_unicows.is_some())
+}
+
+/// Lists the functions that tests want treated as missing, separated by commas. Tests set it for
+/// the child processes they start, so that all of those run as if on a system without them, from
+/// `preload` on.
+#[cfg(test)]
+const BLOCK_VAR: &CStr = c"RUST_TEST_COMPAT_BLOCK";
+
+/// Whether `name` is listed in `BLOCK_VAR`. The variable is read into a buffer on the stack each
+/// time, as `preload` must not allocate. The last error is left as it was.
+#[cfg(test)]
+fn blocked(name: &CStr) -> bool {
+    let mut buf = [0u8; 512];
+    let len = unsafe {
+        let last_error = c::GetLastError();
+        let len = c::GetEnvironmentVariableA(
+            BLOCK_VAR.as_ptr().cast(),
+            buf.as_mut_ptr(),
+            buf.len() as u32,
+        );
+        c::SetLastError(last_error);
+        len as usize
+    };
+    // Too long for the buffer if it's larger, in which case nothing is blocked.
+    len < buf.len() && buf[..len].split(|&b| b == b',').any(|blocked| blocked == name.to_bytes())
+}
+
+/// Looks up functions in place of their modules, for tests.
+#[cfg(test)]
+type Resolver = fn(&CStr) -> Option<NonNull<c_void>>;
+
+#[cfg(test)]
+thread_local! {
+    static RESOLVER: crate::cell::Cell<Option<Resolver>> = crate::cell::Cell::new(None);
+}
+
+/// The resolver that `with_resolver` set on the current thread.
+#[cfg(test)]
+pub fn test_resolver() -> Option<Resolver> {
+    RESOLVER.with(crate::cell::Cell::get)
+}
+
+/// Runs `f` with the functions that are loaded on the current thread looked up by `resolver`,
+/// instead of in their modules.
+#[cfg(test)]
+fn with_resolver<R>(resolver: Resolver, f: impl FnOnce() -> R) -> R {
+    let previous = RESOLVER.with(|current| current.replace(Some(resolver)));
+    let result = f();
+    RESOLVER.with(|current| current.set(previous));
+    result
+}
+
+/// How often the loader was asked for one of the cached modules.
+#[cfg(test)]
+fn module_lookups(name: &CStr) -> usize {
+    MODULE_CACHE.iter().find(|entry| entry.name == name).unwrap().lookups.load(Ordering::Relaxed)
+}
+
 /// Load a function or use a fallback implementation if that fails.
+///
+/// The functions of one invocation are all loaded from the module `$name`, and share a
+/// `SymbolTable`, named `$module`, which must be unique in its scope. With `loaded: true`, the
+/// module is only found if it's already loaded, like kernel32 and ntdll always are. With
+/// `loaded: false`, it's loaded if needed.
+///
+/// On x86, a function whose name isn't found is looked up by its stdcall decoration, `_name@N`,
+/// too, which some modules (like replacements for unicows) export instead.
+///
+/// A function that some versions of a module only export by ordinal can be given its ordinal with
+/// `=> ordinal N` after the signature. It's looked up by ordinal if the name isn't found.
+///
+/// Where a module may export a stub that fails every call with `ERROR_CALL_NOT_IMPLEMENTED`, a
+/// cheap call to check for that can be given with `=> probe(|f| ...)`, after the ordinal if there
+/// is one. It is called with the function that was found, and returns whether the call succeeded.
+/// If it didn't, and the last error is `ERROR_CALL_NOT_IMPLEMENTED`, the function is treated as
+/// not found.
 macro_rules! compat_fn_with_fallback {
     {
-        pub static $module:ident: &CStr = $name:expr => { load: $load:expr, unicows: $unicows:expr };
+        pub static $module:ident: &CStr = $name:expr =>
+            { loaded: $loaded:expr, unicows: $unicows:expr };
         $(
             $(#[$meta:meta])*
-            $vis:vis fn $symbol:ident($($argname:ident: $argtype:ty),* $(,)?) $(-> $rettype:ty)? $fallback_body:block
+            $vis:vis fn $symbol:ident($($argname:ident: $argtype:ty),* $(,)?) $(-> $rettype:ty)?
+                $(=> ordinal $ordinal:literal)? $(=> probe($probe:expr))? $fallback_body:block
         )+
     } => {
-    $(
+        symbol_table!(
+            $module, $name, $loaded, $unicows, [$($symbol($($argtype),*) $(=> $ordinal)?),+]
+        );
+        compat_fn_with_fallback!(@symbols $module, 0, $({
+            $(#[$meta])*
+            $vis fn $symbol($($argname: $argtype),*) $(-> $rettype)?
+                $(=> probe($probe))? $fallback_body
+        })+);
+    };
+    (@symbols $module:ident, $index:expr,) => {};
+    (@symbols $module:ident, $index:expr, {
+        $(#[$meta:meta])*
+        $vis:vis fn $symbol:ident($($argname:ident: $argtype:ty),*) $(-> $rettype:ty)?
+            $(=> probe($probe:expr))? $fallback_body:block
+    } $($rest:tt)*) => {
         $(#[$meta])*
         pub mod $symbol {
             #[allow(unused_imports)]
             use super::*;
             use crate::mem;
-            use crate::ffi::{CStr, c_void};
-            use crate::sync::atomic::{AtomicPtr, Ordering};
-            use crate::sys::compat::{Module, UNICOWS};
+            use crate::sync::atomic::Ordering;
+            use crate::sys::compat::Source;
 
             type F = unsafe extern "system" fn($($argtype),*) $(-> $rettype)?;
 
-            /// `PTR` contains a function pointer to one of three functions.
-            /// It starts with the `load` function.
+            /// The function's index in its `SymbolTable`, whose entry starts out with `load`.
             /// When that is called it attempts to load the requested symbol.
-            /// If it succeeds, `PTR` is set to the address of that symbol.
-            /// If it fails, then `PTR` is set to `fallback`.
-            static PTR: AtomicPtr<c_void> = AtomicPtr::new(load as *mut _);
+            /// If it succeeds, the entry is set to the address of that symbol.
+            /// If it fails, then the entry is set to `fallback`.
+            const INDEX: usize = $index;
+
+            /// Where the function came from, without loading it.
+            #[allow(dead_code)]
+            pub fn source() -> Source {
+                super::$module.source(INDEX)
+            }
 
-            unsafe extern "system" fn load($($argname: $argtype),*) $(-> $rettype)? {
+            /// The function that calls currently go to.
+            #[cfg(test)]
+            #[allow(dead_code)]
+            pub(in crate::sys) fn ptr() -> *mut crate::ffi::c_void {
+                super::$module.ptrs[INDEX].load(Ordering::Acquire)
+            }
+
+            pub(super) unsafe extern "system" fn load($($argname: $argtype),*) $(-> $rettype)? {
                 let func = load_from_module();
                 func($($argname),*)
             }
 
             fn load_from_module() -> F {
-                unsafe {
-                    static SYMBOL_NAME: &CStr = ansi_str!(sym $symbol);
-
-                    let in_unicows = if $unicows {
-                        Module::new(UNICOWS).and_then(|m| m.proc_address(SYMBOL_NAME))
-                    } else {
-                        None
-                    };
-
-                    let f = in_unicows.or_else(|| {
-                        if $load {
-                            Module::new($name)
-                        } else {
-                            Module::load($name)
-                        }.and_then(|m| m.proc_address(SYMBOL_NAME))
+                let (f, in_unicows) = unsafe { super::$module.find(INDEX) };
+                // A stub rejected by the probe counts as not found, even if it was in unicows.
+                $(
+                    // Typed up front, so the probe's closure knows what it's given.
+                    let probe: fn(F) -> bool = $probe;
+                    let f = f.filter(|&f| {
+                        let f = unsafe { mem::transmute::<_, F>(f) };
+                        crate::sys::compat::passes_probe(|| probe(f))
                     });
-
-                    if let Some(f) = f {
-                        PTR.store(f.as_ptr(), Ordering::Relaxed);
-                        mem::transmute(f)
-                    } else {
-                        PTR.store(fallback as *mut _, Ordering::Relaxed);
-                        fallback
-                    }
+                )?
+                unsafe {
+                    mem::transmute(super::$module.store(INDEX, f, in_unicows, fallback as *mut _))
                 }
             }
 
             #[allow(dead_code)]
             pub fn available() -> bool {
-                let mut ptr = PTR.load(Ordering::Relaxed);
+                let mut ptr = super::$module.ptrs[INDEX].load(Ordering::Acquire);
                 if ptr == load as *mut _ {
                     ptr = load_from_module() as *mut _;
                 }
@@ -221,26 +912,63 @@ pub fn available() -> bool {
                 ptr != fallback as *mut _
             }
 
-            #[allow(unused_variables)]
-            unsafe extern "system" fn fallback($($argname: $argtype),*) $(-> $rettype)? {
+            /// The fallback implementation, which tests may call directly even on systems that
+            /// have the function.
+            #[allow(unused_variables, dead_code)]
+            pub unsafe extern "system" fn fallback($($argname: $argtype),*) $(-> $rettype)? {
                 $fallback_body
             }
 
             #[inline(always)]
+            #[allow(dead_code)]
             pub unsafe fn call($($argname: $argtype),*) $(-> $rettype)? {
-                let func: F = mem::transmute(PTR.load(Ordering::Relaxed));
+                let func: F = mem::transmute(super::$module.ptrs[INDEX].load(Ordering::Acquire));
                 func($($argname),*)
             }
         }
         $(#[$meta])*
+        #[allow(unused_imports)]
         $vis use $symbol::call as $symbol;
-    )*
-    }
+
+        compat_fn_with_fallback!(@symbols $module, $index + 1, $($rest)*);
+    };
+}
+
+/// Declares the `SymbolTable` named `$module` for the functions of one `compat_fn_with_fallback!`
+/// or `compat_fn_lazy!`, in the order they were declared in.
+macro_rules! symbol_table {
+    ($module:ident, $name:expr, $loaded:expr, $unicows:expr, [
+        $($symbol:ident($($argtype:ty),*) $(=> $ordinal:literal)?),+
+    ]) => {
+        static $module: crate::sys::compat::SymbolTable<{ [$(stringify!($symbol)),+].len() }> =
+            crate::sys::compat::SymbolTable::new(
+                $name,
+                if $loaded {
+                    crate::sys::compat::Lookup::Loaded
+                } else {
+                    crate::sys::compat::Lookup::Load
+                },
+                $unicows,
+                [$(ansi_str!(sym $symbol)),+],
+                [$(
+                    if cfg!(target_arch = "x86") {
+                        Some(ansi_str!(stdcall $symbol($($argtype),*)))
+                    } else {
+                        None
+                    }
+                ),+],
+                [$(symbol_table!(@ordinal $($ordinal)?)),+],
+                [$(crate::sync::atomic::AtomicPtr::new($symbol::load as *mut _)),+],
+            );
+    };
+    (@ordinal $ordinal:literal) => { Some($ordinal) };
+    (@ordinal) => { None };
 }
 
 /// Optionally loaded functions.
 ///
-/// Actual loading of the function defers to $load_functions.
+/// Actual loading of the function defers to $load_functions. `preload` runs it, or the first use
+/// of each function with the `windows_minimal_preload` feature.
 macro_rules! compat_fn_optional {
     ($load_functions:expr;
     $(
@@ -254,25 +982,60 @@ pub mod $symbol {
                 use crate::ffi::c_void;
                 use crate::mem;
                 use crate::ptr::{self, NonNull};
+                #[cfg(all(not(miri), feature = "windows_minimal_preload"))]
+                use crate::sync::atomic::AtomicBool;
                 use crate::sync::atomic::{AtomicPtr, Ordering};
+                use crate::sys::compat::LoadFailure;
 
                 pub(in crate::sys) static PTR: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+                /// Why the function couldn't be loaded, if it was tried.
+                pub(in crate::sys) static FAILURE: LoadFailure = LoadFailure::new();
 
                 type F = unsafe extern "system" fn($($argtype),*) $(-> $rettype)?;
 
+                /// Whether `$load_functions` ran for this function, with minimal preload.
+                #[cfg(all(not(miri), feature = "windows_minimal_preload"))]
+                static LOADED: AtomicBool = AtomicBool::new(false);
+
+                /// Makes sure `PTR` was loaded, if the function exists.
                 #[inline(always)]
-                #[allow(dead_code)]
-                pub fn option() -> Option<F> {
+                fn ensure_loaded() {
                     // Miri does not understand the way we do preloading
                     // therefore load the function here instead.
                     #[cfg(miri)] $load_functions;
-                    NonNull::new(PTR.load(Ordering::Relaxed)).map(|f| unsafe { mem::transmute(f) })
+                    crate::sys::compat::ensure_init();
+                    // `preload` left loading it to the first use.
+                    #[cfg(all(not(miri), feature = "windows_minimal_preload"))]
+                    if !LOADED.load(Ordering::Acquire) {
+                        $load_functions;
+                        LOADED.store(true, Ordering::Release);
+                    }
+                }
+
+                #[inline(always)]
+                #[allow(dead_code)]
+                pub fn option() -> Option<F> {
+                    ensure_loaded();
+                    NonNull::new(PTR.load(Ordering::Acquire)).map(|f| unsafe { mem::transmute(f) })
+                }
+
+                #[allow(dead_code)]
+                pub fn available() -> bool {
+                    option().is_some()
+                }
+
+                /// Why the function wasn't loaded, or `None` if it was.
+                #[allow(dead_code)]
+                pub fn load_error() -> Option<crate::sys::compat::LoadError> {
+                    // A failed attempt may have been followed by one that worked.
+                    if available() { None } else { FAILURE.get() }
                 }
 
                 #[inline(always)]
                 #[allow(dead_code)]
                 pub unsafe fn call($($argname: $argtype),*) $(-> $rettype)? {
-                    (mem::transmute::<_, F>(PTR.load(Ordering::Relaxed)))($($argname),*)
+                    ensure_loaded();
+                    (mem::transmute::<_, F>(PTR.load(Ordering::Acquire)))($($argname),*)
                 }
             }
 
@@ -283,178 +1046,396 @@ pub unsafe fn call($($argname: $argtype),*) $(-> $rettype)? {
     )
 }
 
+/// Load a function the first time it's used, leaving it missing if that fails.
+///
+/// Symbol tables, ordinals and probes work like for `compat_fn_with_fallback`.
 macro_rules! compat_fn_lazy {
     {
-        pub static $module:ident: &CStr = $name:expr => { load: $load:expr, unicows: $unicows:expr };
+        pub static $module:ident: &CStr = $name:expr =>
+            { loaded: $loaded:expr, unicows: $unicows:expr };
         $(
             $(#[$meta:meta])*
-            $vis:vis fn $symbol:ident($($argname:ident: $argtype:ty),* $(,)?) $(-> $rettype:ty)?;
+            $vis:vis fn $symbol:ident($($argname:ident: $argtype:ty),* $(,)?) $(-> $rettype:ty)?
+                $(=> ordinal $ordinal:literal)? $(=> probe($probe:expr))?;
         )+
     } => {
-    $(
+        symbol_table!(
+            $module, $name, $loaded, $unicows, [$($symbol($($argtype),*) $(=> $ordinal)?),+]
+        );
+        compat_fn_lazy!(@symbols $module, 0, $({
+            $(#[$meta])*
+            $vis fn $symbol($($argname: $argtype),*) $(-> $rettype)? $(=> probe($probe))?;
+        })+);
+    };
+    (@symbols $module:ident, $index:expr,) => {};
+    (@symbols $module:ident, $index:expr, {
+        $(#[$meta:meta])*
+        $vis:vis fn $symbol:ident($($argname:ident: $argtype:ty),*) $(-> $rettype:ty)?
+            $(=> probe($probe:expr))?;
+    } $($rest:tt)*) => {
         $(#[$meta])*
         pub mod $symbol {
             #[allow(unused_imports)]
             use super::*;
             use crate::mem;
-            use crate::ffi::{CStr, c_void};
-            use crate::sync::atomic::{AtomicPtr, Ordering};
-            use crate::sys::compat::{Module, UNICOWS};
+            use crate::ptr::{self, NonNull};
+            use crate::sync::atomic::Ordering;
+            use crate::sys::compat::Source;
 
             type F = unsafe extern "system" fn($($argtype),*) $(-> $rettype)?;
 
-            /// `PTR` contains a function pointer to one of three functions.
-            /// It starts with the `load` function.
+            /// The function's index in its `SymbolTable`, whose entry starts out with `load`.
             /// When that is called it attempts to load the requested symbol.
-            /// If it succeeds, `PTR` is set to the address of that symbol.
-            /// If it fails, then `PTR` is set to `fallback`.
-            static PTR: AtomicPtr<c_void> = AtomicPtr::new(load as *mut _);
+            /// If it succeeds, the entry is set to the address of that symbol.
+            /// If it fails, then the entry is set to null.
+            const INDEX: usize = $index;
 
-            unsafe extern "system" fn load($($argname: $argtype),*) $(-> $rettype)? {
+            /// Where the function came from, without loading it.
+            #[allow(dead_code)]
+            pub fn source() -> Source {
+                super::$module.source(INDEX)
+            }
+
+            pub(super) unsafe extern "system" fn load($($argname: $argtype),*) $(-> $rettype)? {
                 let func = load_from_module();
                 (func.unwrap())($($argname),*)
             }
 
             fn load_from_module() -> Option<F> {
-                unsafe {
-                    static SYMBOL_NAME: &CStr = ansi_str!(sym $symbol);
-
-                    let in_unicows = if $unicows {
-                        Module::new(UNICOWS).and_then(|m| m.proc_address(SYMBOL_NAME))
-                    } else {
-                        None
-                    };
-
-                    let f = in_unicows.or_else(|| {
-                        if $load {
-                            Module::new($name)
-                        } else {
-                            Module::load($name)
-                        }.and_then(|m| m.proc_address(SYMBOL_NAME))
+                let (f, in_unicows) = unsafe { super::$module.find(INDEX) };
+                // A stub rejected by the probe counts as not found, even if it was in unicows.
+                $(
+                    // Typed up front, so the probe's closure knows what it's given.
+                    let probe: fn(F) -> bool = $probe;
+                    let f = f.filter(|&f| {
+                        let f = unsafe { mem::transmute::<_, F>(f) };
+                        crate::sys::compat::passes_probe(|| probe(f))
                     });
-
-                    if let Some(f) = f {
-                        PTR.store(f.as_ptr(), Ordering::Relaxed);
-                        Some(mem::transmute(f))
-                    } else {
-                        PTR.store(crate::ptr::null_mut(), Ordering::Relaxed);
-                        None
-                    }
-                }
+                )?
+                let f = super::$module.store(INDEX, f, in_unicows, ptr::null_mut());
+                NonNull::new(f).map(|f| unsafe { mem::transmute(f) })
             }
 
             #[allow(dead_code)]
             pub fn option() -> Option<F> {
                 unsafe {
-                    let ptr = PTR.load(Ordering::Relaxed);
+                    let ptr = super::$module.ptrs[INDEX].load(Ordering::Acquire);
                     if ptr == load as *mut _ {
                         load_from_module()
                     } else {
-                        Some(mem::transmute(ptr))
+                        // Null if the symbol couldn't be loaded.
+                        NonNull::new(ptr).map(|f| mem::transmute(f))
+                    }
+                }
+            }
+
+            /// Only calls the function if it could be loaded. Otherwise, returns `None` with the
+            /// last error set to `ERROR_CALL_NOT_IMPLEMENTED`, which `io::Error::last_os_error`
+            /// turns into an `Unsupported` error.
+            #[allow(dead_code, unused_parens)]
+            pub unsafe fn try_call($($argname: $argtype),*) -> Option<($($rettype)?)> {
+                match option() {
+                    Some(func) => Some(func($($argname),*)),
+                    None => {
+                        crate::sys::c::SetLastError(crate::sys::c::ERROR_CALL_NOT_IMPLEMENTED);
+                        None
                     }
                 }
             }
 
+            /// Stops using the function, for one that was loaded but turned out to be a stub
+            /// anyway. From then on, it counts as missing. Returns whether this call demoted it.
+            #[allow(dead_code)]
+            pub fn demote() -> bool {
+                let ptr = super::$module.ptrs[INDEX].load(Ordering::Acquire);
+                ptr != load as *mut _ && super::$module.demote(INDEX, ptr, ptr::null_mut())
+            }
+
+            /// Calls the function, which must have been loaded (see `option` and `try_call`).
             #[inline(always)]
+            #[allow(dead_code)]
             pub unsafe fn call($($argname: $argtype),*) $(-> $rettype)? {
-                let func: F = mem::transmute(PTR.load(Ordering::Relaxed));
+                let func: F = mem::transmute(super::$module.ptrs[INDEX].load(Ordering::Acquire));
                 func($($argname),*)
             }
         }
         $(#[$meta])*
+        #[allow(unused_imports)]
+        $vis use $symbol::call as $symbol;
+
+        compat_fn_lazy!(@symbols $module, $index + 1, $($rest)*);
+    };
+}
+
+/// Declares a function that exists as both an ANSI (`...A`) and a wide (`...W`) variant, which
+/// every version of Windows exports, and a dispatcher with the signature of the wide one.
+///
+/// On NT, the dispatcher calls the wide variant. 9x/ME only implement the ANSI variant, so there
+/// the arguments marked with `[ansi]` (null terminated wide strings, or null) are converted to the
+/// active code page first. If that fails, the last error is set and the dispatcher returns the
+/// value given with `=> fail(...)`.
+macro_rules! compat_fn_aw {
+    {
+        #[link(name = $link:literal)]
+        $(
+            $(#[$meta:meta])*
+            $vis:vis fn $symbol:ident = $ansi:ident / $wide:ident(
+                $($([$conv:ident])? $argname:ident: $argtype:ty),* $(,)?
+            ) -> $rettype:ty => fail($failure:expr);
+        )+
+    } => {
+    $(
+        $(#[$meta])*
+        pub mod $symbol {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[link(name = $link)]
+            extern "system" {
+                fn $ansi($($argname: compat_fn_aw!(@ansi_type $($conv)? $argtype)),*) -> $rettype;
+                fn $wide($($argname: $argtype),*) -> $rettype;
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn call($($argname: $argtype),*) -> $rettype {
+                if crate::sys::compat::is_windows_nt() {
+                    return $wide($($argname),*);
+                }
+                $(
+                    compat_fn_aw!(@to_ansi $($conv)? $argname, $failure);
+                )*
+                $ansi($($argname),*)
+            }
+        }
+        $(#[$meta])*
         $vis use $symbol::call as $symbol;
     )*
-    }
+    };
+    (@ansi_type ansi $argtype:ty) => { crate::sys::c::PCSTR };
+    (@ansi_type $argtype:ty) => { $argtype };
+    (@to_ansi ansi $argname:ident, $failure:expr) => {
+        // Kept alive until the call, as `$argname` is shadowed by the pointer.
+        let $argname = match crate::sys::c::ansi::AnsiString::new($argname) {
+            Ok(ansi) => ansi,
+            Err(code) => {
+                crate::sys::c::SetLastError(code);
+                return $failure;
+            }
+        };
+        let $argname = $argname.as_ptr();
+    };
+    (@to_ansi $argname:ident, $failure:expr) => {};
 }
 
+/// Loads all of the functions from `$library` (a `Result<Module, LoadError>`), or none of them.
+///
+/// If any is missing, each function records why in its `FAILURE`, and this evaluates to `None`.
 macro_rules! static_load {
     (
         $library:expr,
         [$($symbol:ident),* $(,)?]
+    ) => {{
+        let library: Result<Module, LoadError> = $library;
+        let found = [$(
+            library.and_then(|library| {
+                const $symbol: &CStr = ansi_str!(sym $symbol);
+                library.try_proc_address($symbol)
+            })
+        ),*];
+        match found.iter().find_map(|f| f.err()) {
+            None => {
+                let ptrs = [$(&c::$symbol::PTR),*];
+                for (ptr, f) in ptrs.into_iter().zip(found) {
+                    if let Ok(f) = f {
+                        ptr.store(f.as_ptr(), Ordering::Release);
+                    }
+                }
+                Some(())
+            }
+            Some(error) => {
+                $(
+                    c::$symbol::FAILURE.record(error);
+                )*
+                None
+            }
+        }
+    }}
+}
+
+/// Like `static_load!`, but stores each function that `$lookup` finds, even if others are
+/// missing, and those that it doesn't find record why. Returns an array with whether each
+/// function in the list was found.
+macro_rules! static_load_partial {
+    (
+        $lookup:expr,
+        [$($symbol:ident),* $(,)?]
     ) => {
-        $(
-            let $symbol = {
+        [$(
+            {
                 const $symbol: &CStr = ansi_str!(sym $symbol);
-                $library.proc_address($symbol)?
-            };
-        )*
-        $(
-            c::$symbol::PTR.store($symbol.as_ptr(), Ordering::Relaxed);
-        )*
+                match $lookup($symbol) {
+                    Ok(f) => {
+                        c::$symbol::PTR.store(f.as_ptr(), Ordering::Release);
+                        true
+                    }
+                    Err(error) => {
+                        c::$symbol::FAILURE.record(error);
+                        false
+                    }
+                }
+            }
+        ),*]
     }
 }
 
-/// Load all needed functions from "api-ms-win-core-synch-l1-2-0".
-pub(super) fn load_synch_functions() {
-    fn try_load() -> Option<()> {
-        const MODULE_NAME: &CStr = c"api-ms-win-core-synch-l1-2-0";
+/// The modules that host the functions of API set contracts, in the order they are tried when the
+/// contract itself can't be found.
+///
+/// Contracts like "api-ms-win-core-synch-l1-2-0" aren't registered on some embedded builds of
+/// Windows, nor on Wine, even where the functions behind them are exported from kernelbase (or
+/// kernel32).
+#[cfg(not(windows_min_version = "8"))]
+const API_SET_HOSTS: [&CStr; 2] = [c"kernelbase", c"kernel32"];
 
-        // Try loading the library and all the required functions.
-        // If any step fails, then they all fail.
-        let library = unsafe { Module::new(MODULE_NAME) }?;
-        static_load!(library, [WaitOnAddress, WakeByAddressSingle]);
+/// Loads functions through `load` from the API set `contract`, or else from the first of its
+/// hosts that has all of them. Returns the name of the module they were loaded from.
+#[cfg(not(windows_min_version = "8"))]
+fn load_from_api_set(
+    contract: &'static CStr,
+    load: fn(Result<Module, LoadError>) -> Option<()>,
+) -> Option<&'static CStr> {
+    #[cfg(test)]
+    let skip_contract = IGNORE_API_SETS.with(crate::cell::Cell::get);
+    #[cfg(not(test))]
+    let skip_contract = false;
 
-        Some(())
-    }
+    let contract = if skip_contract { None } else { Some(contract) };
+    // Each of these is always loaded if it exists.
+    contract
+        .into_iter()
+        .chain(API_SET_HOSTS)
+        .find(|&name| load(unsafe { Module::cached(name, Lookup::Loaded) }).is_some())
+}
+
+#[cfg(all(test, not(windows_min_version = "8")))]
+thread_local! {
+    static IGNORE_API_SETS: crate::cell::Cell<bool> = crate::cell::Cell::new(false);
+}
 
-    try_load();
+/// Runs `f` as if no API set contract could be found on the current thread, like on systems that
+/// don't register them.
+#[cfg(all(test, not(windows_min_version = "8")))]
+fn with_api_sets_ignored<R>(f: impl FnOnce() -> R) -> R {
+    let ignored = IGNORE_API_SETS.with(|ignore| ignore.replace(true));
+    let result = f();
+    IGNORE_API_SETS.with(|ignore| ignore.set(ignored));
+    result
+}
+
+#[cfg(not(windows_min_version = "8"))]
+const SYNCH_API_SET: &CStr = c"api-ms-win-core-synch-l1-2-0";
+
+#[cfg(not(windows_min_version = "8"))]
+fn load_synch_functions_from(library: Result<Module, LoadError>) -> Option<()> {
+    static_load!(library, [WaitOnAddress, WakeByAddressSingle])
+}
+
+/// Load all needed functions from "api-ms-win-core-synch-l1-2-0", or its hosts.
+#[cfg(not(windows_min_version = "8"))]
+pub(super) fn load_synch_functions() {
+    load_from_api_set(SYNCH_API_SET, load_synch_functions_from);
 }
 
 #[cfg(not(target_vendor = "uwp"))]
 pub(super) fn load_stack_overflow_functions() {
-    fn try_load() -> Option<()> {
-        const MODULE_NAME: &CStr = c"kernel32";
-
-        // Try loading the library and all the required functions.
-        // If any step fails, then they all fail.
-        let library = unsafe { Module::new(MODULE_NAME) }?;
+    const MODULE_NAME: &CStr = c"kernel32";
 
-        static_load!(library, [SetThreadStackGuarantee, AddVectoredExceptionHandler]);
+    static_load!(
+        unsafe { Module::cached(MODULE_NAME, Lookup::Loaded) },
+        [SetThreadStackGuarantee, AddVectoredExceptionHandler, RemoveVectoredExceptionHandler]
+    );
+}
 
-        Some(())
-    }
+/// Loads the function that tests use to look at load failures.
+#[cfg(test)]
+pub(super) fn load_optional_test_function(library: Result<Module, LoadError>) -> Option<()> {
+    static_load!(library, [RustCompatOptional])
+}
 
-    try_load();
+/// Loads the function that only `optional_called_first` calls.
+#[cfg(all(test, feature = "windows_minimal_preload"))]
+pub(super) fn load_logical_drives_function() {
+    static_load!(unsafe { Module::cached(c"kernel32", Lookup::Loaded) }, [GetLogicalDrives]);
 }
 
 pub(super) fn load_try_enter_critical_section_function() {
-    fn try_load() -> Option<()> {
-        const MODULE_NAME: &CStr = c"kernel32";
-
-        let library = unsafe { Module::new(MODULE_NAME) }?;
-        static_load!(library, [TryEnterCriticalSection]);
-        Some(())
-    }
-
-    try_load();
-}
-
-pub(super) fn load_srw_functions() {
-    fn try_load() -> Option<()> {
-        const MODULE_NAME: &CStr = c"kernel32";
-
-        // Try loading the library and all the required functions.
-        // If any step fails, then they all fail.
-        let library = unsafe { Module::new(MODULE_NAME) }?;
-
-        static_load!(
-            library,
-            [
-                // check the try_ functions first, as they have higher system requirements
-                TryAcquireSRWLockExclusive,
-                TryAcquireSRWLockShared,
-                AcquireSRWLockExclusive,
-                AcquireSRWLockShared,
-                ReleaseSRWLockExclusive,
-                ReleaseSRWLockShared,
-                SleepConditionVariableSRW,
-                WakeAllConditionVariable,
-                WakeConditionVariable
-            ]
-        );
+    const MODULE_NAME: &CStr = c"kernel32";
 
-        Some(())
-    }
+    static_load!(unsafe { Module::cached(MODULE_NAME, Lookup::Loaded) }, [TryEnterCriticalSection]);
+}
 
-    try_load();
+/// Which of the SRW lock functions `load_srw_functions` found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SrwFunctions {
+    /// Everything but the `TryAcquire*` functions, which is enough for locks and condition
+    /// variables. Vista and later.
+    pub locks: bool,
+    /// Both `TryAcquire*` functions. Windows 7 and later.
+    pub try_locks: bool,
+}
+
+pub(super) fn load_srw_functions() -> SrwFunctions {
+    const MODULE_NAME: &CStr = c"kernel32";
+
+    let library = unsafe { Module::cached(MODULE_NAME, Lookup::Loaded) };
+    load_srw_functions_from(|name| library?.try_proc_address(name))
+}
+
+/// Stores each of the SRW lock functions that `lookup` finds, so that Vista, which doesn't have
+/// the `TryAcquire*` functions, still gets to use the others.
+fn load_srw_functions_from(
+    lookup: impl Fn(&CStr) -> Result<NonNull<c_void>, LoadError>,
+) -> SrwFunctions {
+    let [try_exclusive, try_shared, locks @ ..] = static_load_partial!(
+        lookup,
+        [
+            TryAcquireSRWLockExclusive,
+            TryAcquireSRWLockShared,
+            AcquireSRWLockExclusive,
+            AcquireSRWLockShared,
+            ReleaseSRWLockExclusive,
+            ReleaseSRWLockShared,
+            SleepConditionVariableSRW,
+            WakeAllConditionVariable,
+            WakeConditionVariable
+        ]
+    );
+    SrwFunctions { locks: locks.iter().all(|&found| found), try_locks: try_exclusive && try_shared }
+}
+
+/// Puts the mutex kind and the SRW lock functions back to how a binary without CRT initialization
+/// starts out, so that the next lock picks the kind again.
+///
+/// # Safety
+///
+/// No other thread may be using a lock until one of them picked the kind again.
+#[cfg(test)]
+pub(super) unsafe fn unload_mutex_kind() {
+    macro_rules! unload {
+        ($($symbol:ident),+) => {
+            $(c::$symbol::PTR.store(ptr::null_mut(), Ordering::Relaxed);)+
+        };
+    }
+    unload!(
+        TryAcquireSRWLockExclusive,
+        TryAcquireSRWLockShared,
+        AcquireSRWLockExclusive,
+        AcquireSRWLockShared,
+        ReleaseSRWLockExclusive,
+        ReleaseSRWLockShared,
+        SleepConditionVariableSRW,
+        WakeAllConditionVariable,
+        WakeConditionVariable
+    );
+    system::forget_mutex_kind();
 }
@@ -0,0 +1,103 @@
+//! What `preload` found out about the system, which is checked all over std.
+//!
+//! It's all packed into one atomic byte, so that any thread can read it without `unsafe`, even
+//! while `preload` is still filling it in.
+
+use crate::sync::atomic::{AtomicU8, Ordering};
+use crate::sys::locks::compat::MutexKind;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindowsCapabilities {
+    /// Whether this is an NT-based system, rather than 9x/ME.
+    pub is_nt: bool,
+    /// Whether overlapped I/O can be used, which also needs `CancelIo`.
+    pub async_io: bool,
+    /// The lock implementation picked for this system.
+    pub mutex_kind: MutexKind,
+}
+
+impl WindowsCapabilities {
+    /// What is assumed until `preload` found out: a modern NT system.
+    pub const DEFAULT: Self = Self { is_nt: true, async_io: true, mutex_kind: MutexKind::SrwLock };
+
+    const fn encode(self) -> u8 {
+        let mutex_kind = match self.mutex_kind {
+            MutexKind::SrwLock => 0,
+            MutexKind::CriticalSection => 1,
+            MutexKind::Legacy => 2,
+        };
+        (self.is_nt as u8) << 1 | (self.async_io as u8) << 2 | mutex_kind << 3
+    }
+
+    fn decode(bits: u8) -> Self {
+        let mutex_kind = match bits >> 3 {
+            0 => MutexKind::SrwLock,
+            1 => MutexKind::CriticalSection,
+            _ => MutexKind::Legacy,
+        };
+        Self { is_nt: bits & 1 << 1 != 0, async_io: bits & 1 << 2 != 0, mutex_kind }
+    }
+}
+
+/// Set once the mutex kind was picked. Until then, whoever asks for it first picks it: the thread
+/// running `preload` before it got there, or anyone with the `windows_minimal_preload` feature, as
+/// `preload` then only publishes the Windows version.
+const COMPLETE: u8 = 1;
+
+static CAPABILITIES: AtomicU8 = AtomicU8::new(WindowsCapabilities::DEFAULT.encode());
+
+/// Publishes what `preload` found out so far. It does that as it goes, so that the rest of it
+/// already sees the Windows version, and sets `complete` the last time.
+pub(super) fn publish(capabilities: WindowsCapabilities, complete: bool) {
+    let complete = if complete { COMPLETE } else { 0 };
+    CAPABILITIES.store(capabilities.encode() | complete, Ordering::Release);
+}
+
+/// The capabilities of this system, running `preload` first if that hasn't happened yet.
+///
+/// The mutex kind is never the default `SrwLock` before the SRW lock functions are loaded: it's
+/// published with a release store after loading them, and reading it acquires them.
+#[inline]
+pub fn windows_capabilities() -> WindowsCapabilities {
+    super::ensure_init();
+    let mut bits = CAPABILITIES.load(Ordering::Acquire);
+    if bits & COMPLETE == 0 {
+        bits = complete(bits);
+    }
+    WindowsCapabilities::decode(bits)
+}
+
+/// What `preload` published, which always includes the Windows version. Unlike
+/// `windows_capabilities`, this never has to pick the mutex kind.
+#[inline]
+pub(super) fn published() -> WindowsCapabilities {
+    super::ensure_init();
+    WindowsCapabilities::decode(CAPABILITIES.load(Ordering::Acquire))
+}
+
+/// Picks the mutex kind that `preload` didn't publish yet, and returns the capabilities with it.
+#[cold]
+fn complete(bits: u8) -> u8 {
+    let capabilities = WindowsCapabilities {
+        mutex_kind: super::pick_mutex_kind(),
+        ..WindowsCapabilities::decode(bits)
+    };
+    let complete = capabilities.encode() | COMPLETE;
+    // Threads racing to get here all pick the same kind, so whichever publishes it first wins.
+    match CAPABILITIES.compare_exchange(bits, complete, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => complete,
+        Err(current) => current,
+    }
+}
+
+/// Whether `preload` published everything, without running it.
+#[cfg(test)]
+pub fn is_complete() -> bool {
+    CAPABILITIES.load(Ordering::Acquire) & COMPLETE != 0
+}
+
+/// Forgets the mutex kind, so that it's picked again by whoever asks for it next.
+#[cfg(test)]
+pub(super) fn forget_mutex_kind() {
+    CAPABILITIES.fetch_and(!COMPLETE, Ordering::Release);
+}
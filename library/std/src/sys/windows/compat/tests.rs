@@ -0,0 +1,512 @@
+use super::{module_lookups, report, trace, CachedModule, Source};
+use crate::sys::c;
+use crate::sys::test::run_alone;
+
+#[test]
+fn report_lists_everything() {
+    crate::sys::rand::hashmap_random_keys();
+    let _ = crate::thread::current();
+    let report = report();
+    let keys: Vec<_> = report.lines().map(|l| l.split(':').next().unwrap()).collect();
+    let expected = [
+        "windows nt",
+        "nt build",
+        "async i/o",
+        "rng",
+        "bcrypt failure",
+        "stack guarantee missing",
+        "stack overflow handler",
+        "thread parking",
+        "srw locks",
+    ];
+    assert_eq!(keys, expected);
+    assert!(!report.contains("rng: unresolved"), "{report}");
+    assert!(!report.contains("thread parking: unresolved"), "{report}");
+}
+
+#[test]
+fn trace_lists_fallbacks() {
+    trace::enable();
+    trace::record(c"RustTraceFallback", c"kernel32", Source::Fallback);
+    trace::record(c"RustTraceMissing", c"ntdll", Source::Missing);
+    trace::record(c"RustTraceUnicows", c"kernel32", Source::Unicows);
+    trace::record(c"RustTraceFound", c"kernel32", Source::Native);
+    // As if a second thread had raced to load it.
+    trace::record(c"RustTraceFound", c"kernel32", Source::Native);
+
+    let report = trace::report().unwrap();
+    let lines: Vec<_> = report.lines().filter(|l| l.starts_with("RustTrace")).collect();
+    assert_eq!(
+        lines,
+        [
+            "RustTraceFallback: fallback, not in kernel32",
+            "RustTraceMissing: missing, not in ntdll",
+            "RustTraceUnicows: unicows",
+            "RustTraceFound: kernel32",
+        ]
+    );
+}
+
+#[test]
+fn modules_looked_up_once() {
+    // Both from kernel32, which is loaded with `LoadLibraryA`.
+    c::GetFinalPathNameByHandleW::option();
+    c::CopyFileExW::option();
+    assert_eq!(module_lookups(c"kernel32"), 1);
+}
+
+#[test]
+fn capabilities_match_probes() {
+    use crate::os::windows::compat::MutexKind;
+
+    let caps = crate::os::windows::compat::capabilities();
+    assert_eq!(
+        caps.srw_locks,
+        c::AcquireSRWLockExclusive::option().is_some() && caps.srw_try_locks
+    );
+    #[cfg(not(windows_min_version = "8"))]
+    assert_eq!(caps.wait_on_address, c::WakeByAddressSingle::option().is_some());
+    #[cfg(windows_min_version = "8")]
+    assert!(caps.wait_on_address);
+    assert_eq!(caps.async_io, super::supports_async_io());
+    let expected = if caps.srw_locks {
+        MutexKind::SrwLock
+    } else if caps.try_enter_critical_section {
+        MutexKind::CriticalSection
+    } else {
+        MutexKind::Legacy
+    };
+    assert_eq!(caps.mutex, expected);
+    // Async I/O is only used where `CancelIo` can stop it.
+    assert!(!caps.async_io || caps.cancel_io);
+    assert_eq!(caps.unicows, unsafe { super::Module::new(c"unicows") }.is_some());
+}
+
+#[test]
+fn source_without_module() {
+    assert_eq!(c::RustCompatNoModule::source(), Source::NotLoadedYet);
+    assert_eq!(unsafe { c::RustCompatNoModule::call() }, 7);
+    assert_eq!(c::RustCompatNoModule::source(), Source::Fallback);
+}
+
+#[test]
+fn concurrent_first_calls() {
+    use crate::sync::{Arc, Barrier};
+    use crate::thread;
+
+    const THREADS: usize = 16;
+    let expected = unsafe { super::Module::new(c"kernel32") }
+        .unwrap()
+        .proc_address(c"GetCurrentProcessorNumber")
+        .is_some();
+
+    // All threads race to be the first to use the function, half of them by calling it.
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let threads: Vec<_> = (0..THREADS)
+        .map(|i| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                if i % 2 == 0 {
+                    unsafe { c::GetCurrentProcessorNumber() };
+                }
+                c::GetCurrentProcessorNumber::available()
+            })
+        })
+        .collect();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), expected);
+    }
+}
+
+#[test]
+fn preload_runs_once_on_demand() {
+    use crate::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+    use crate::sync::{Arc, Barrier};
+    use crate::thread;
+    use crate::time::Duration;
+
+    // A fresh one, as if the CRT never ran `init`.
+    static PRELOAD: super::Preload = super::Preload::new();
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    static LOADED: AtomicBool = AtomicBool::new(false);
+    fn preload() {
+        RUNS.fetch_add(1, Relaxed);
+        // What `preload` itself uses must not wait for it to finish.
+        PRELOAD.ensure(|| unreachable!());
+        thread::sleep(Duration::from_millis(50));
+        LOADED.store(true, Relaxed);
+    }
+
+    const THREADS: usize = 8;
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                PRELOAD.ensure(preload);
+                // Nobody gets past `ensure` while the first caller is still loading.
+                LOADED.load(Relaxed)
+            })
+        })
+        .collect();
+    for thread in threads {
+        assert!(thread.join().unwrap());
+    }
+    assert_eq!(RUNS.load(Relaxed), 1);
+}
+
+#[test]
+fn unicows_disabled() {
+    // Disabling unicows can't be undone, so keep it from affecting other tests.
+    run_alone("compat::tests::unicows_disabled", || {
+        super::disable_unicows();
+        let lookups = module_lookups(c"unicows");
+
+        let kernel32 = unsafe { super::Module::new(c"kernel32") }.unwrap();
+        let expected = kernel32.proc_address(c"GetOEMCP").unwrap();
+        assert!(c::GetOEMCP::available());
+        assert_eq!(c::GetOEMCP::ptr(), expected.as_ptr());
+        // Not even looked for.
+        assert_eq!(module_lookups(c"unicows"), lookups);
+    });
+}
+
+// The x86 rust9x targets use the generic parker, whatever the minimum version.
+#[cfg(all(windows_min_version = "8", not(all(target_arch = "x86", target_vendor = "rust9x"))))]
+#[test]
+fn parker_imports_wait_on_address() {
+    crate::thread::current();
+    // Nothing is selected at runtime, and nothing needs to be loaded.
+    assert_eq!(crate::sys::thread_parking::backend_name(), "WaitOnAddress");
+    assert!(report().contains("thread parking: WaitOnAddress\n"));
+    assert!(crate::os::windows::compat::capabilities().wait_on_address);
+}
+
+#[cfg(not(windows_min_version = "8"))]
+#[test]
+fn synch_functions_without_api_sets() {
+    use super::{
+        load_from_api_set, load_synch_functions_from, with_api_sets_ignored, SYNCH_API_SET,
+    };
+
+    // Only Windows 8 and later have them at all.
+    if c::WaitOnAddress::option().is_none() {
+        return;
+    }
+    let found =
+        with_api_sets_ignored(|| load_from_api_set(SYNCH_API_SET, load_synch_functions_from));
+    assert_eq!(found, Some(c"kernelbase"));
+    assert!(c::WaitOnAddress::option().is_some());
+    assert!(c::WakeByAddressSingle::option().is_some());
+}
+
+#[test]
+fn stub_replaced_by_fallback() {
+    use super::with_resolver;
+    use crate::ffi::{c_void, CStr};
+    use crate::ptr::NonNull;
+
+    unsafe extern "system" fn stub(_: u32) -> c::BOOL {
+        c::SetLastError(c::ERROR_CALL_NOT_IMPLEMENTED);
+        c::FALSE
+    }
+    fn resolve(_: &CStr) -> Option<NonNull<c_void>> {
+        NonNull::new(stub as *mut c_void)
+    }
+
+    unsafe { c::SetLastError(c::ERROR_ACCESS_DENIED) };
+    assert!(!with_resolver(resolve, c::RustCompatStub::available));
+    // Probing doesn't touch the last error.
+    assert_eq!(unsafe { c::GetLastError() }, c::ERROR_ACCESS_DENIED);
+    assert_eq!(c::RustCompatStub::source(), Source::Fallback);
+    assert_eq!(unsafe { c::RustCompatStub(1) }, 1);
+}
+
+/// Run by `wide_file_stubs_fall_back`, and does nothing otherwise.
+#[test]
+fn wide_file_stubs_child() {
+    use super::with_resolver;
+    use crate::ffi::{c_void, CStr};
+    use crate::ptr::NonNull;
+
+    if crate::env::var_os("RUST_TEST_WIDE_FILE_STUBS").is_none() {
+        return;
+    }
+
+    // Stubs like the ones 9x/ME kernel32 exports, with the signatures of the real functions.
+    unsafe fn not_implemented<T>(result: T) -> T {
+        c::SetLastError(c::ERROR_CALL_NOT_IMPLEMENTED);
+        result
+    }
+    unsafe extern "system" fn path(_: c::PCWSTR) -> c::BOOL {
+        not_implemented(c::FALSE)
+    }
+    unsafe extern "system" fn path_and(_: c::PCWSTR, _: usize) -> c::BOOL {
+        not_implemented(c::FALSE)
+    }
+    unsafe extern "system" fn attributes(_: c::PCWSTR) -> u32 {
+        not_implemented(c::INVALID_FILE_ATTRIBUTES)
+    }
+    unsafe extern "system" fn attributes_ex(_: c::PCWSTR, _: i32, _: *mut c_void) -> c::BOOL {
+        not_implemented(c::FALSE)
+    }
+    unsafe extern "system" fn find_first(_: c::PCWSTR, _: *mut c_void) -> c::HANDLE {
+        not_implemented(c::INVALID_HANDLE_VALUE)
+    }
+    unsafe extern "system" fn find_next(_: c::HANDLE, _: *mut c_void) -> c::BOOL {
+        not_implemented(c::FALSE)
+    }
+    fn resolve(name: &CStr) -> Option<NonNull<c_void>> {
+        let f = match name.to_bytes() {
+            b"DeleteFileW" | b"RemoveDirectoryW" => path as *mut c_void,
+            b"CreateDirectoryW" | b"SetFileAttributesW" => path_and as *mut c_void,
+            b"GetFileAttributesW" => attributes as *mut c_void,
+            b"GetFileAttributesExW" => attributes_ex as *mut c_void,
+            b"FindFirstFileW" => find_first as *mut c_void,
+            b"FindNextFileW" => find_next as *mut c_void,
+            _ => return None,
+        };
+        NonNull::new(f)
+    }
+
+    with_resolver(resolve, || {
+        assert!(!c::DeleteFileW::available());
+        assert!(!c::CreateDirectoryW::available());
+        assert!(!c::RemoveDirectoryW::available());
+        assert!(!c::GetFileAttributesW::available());
+        assert!(!c::GetFileAttributesExW::available());
+        assert!(!c::SetFileAttributesW::available());
+        assert!(!c::FindFirstFileW::available());
+        assert!(!c::FindNextFileW::available());
+    });
+    assert_eq!(c::DeleteFileW::source(), Source::Fallback);
+    assert_eq!(c::FindNextFileW::source(), Source::Fallback);
+
+    // std::fs now goes through the ANSI versions.
+    let dir = crate::env::temp_dir().join(format!("rust-wide-stubs-{}", crate::process::id()));
+    crate::fs::create_dir(&dir).unwrap();
+    crate::fs::write(dir.join("file"), b"").unwrap();
+    assert!(crate::fs::metadata(&dir).unwrap().is_dir());
+    assert_eq!(crate::fs::read_dir(&dir).unwrap().count(), 1);
+    crate::fs::remove_file(dir.join("file")).unwrap();
+    crate::fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn wide_file_stubs_fall_back() {
+    use crate::process::Command;
+
+    let output = Command::new(crate::env::current_exe().unwrap())
+        .args(["compat::tests::wide_file_stubs_child", "--test-threads=1"])
+        .env("RUST_TEST_WIDE_FILE_STUBS", "1")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains(" 1 passed"), "{stdout}");
+}
+
+/// Run by `copy_file_ex_stub_demoted`, and does nothing otherwise.
+#[test]
+fn copy_file_ex_stub_child() {
+    use super::with_resolver;
+    use crate::ffi::{c_void, CStr};
+    use crate::ptr::NonNull;
+
+    if crate::env::var_os("RUST_TEST_COPY_FILE_EX_STUB").is_none() {
+        return;
+    }
+
+    // Gets past the probe, which copies "" to "", but can't copy anything.
+    unsafe extern "system" fn copy_file_ex(
+        from: c::PCWSTR,
+        _: c::PCWSTR,
+        _: c::LPPROGRESS_ROUTINE,
+        _: *const c_void,
+        _: *mut c::BOOL,
+        _: u32,
+    ) -> c::BOOL {
+        let code = if *from == 0 { c::ERROR_PATH_NOT_FOUND } else { c::ERROR_CALL_NOT_IMPLEMENTED };
+        c::SetLastError(code);
+        c::FALSE
+    }
+    fn resolve(name: &CStr) -> Option<NonNull<c_void>> {
+        match name.to_bytes() {
+            b"CopyFileExW" => NonNull::new(copy_file_ex as *mut c_void),
+            _ => None,
+        }
+    }
+
+    assert!(with_resolver(resolve, || c::CopyFileExW::option().is_some()));
+    assert_eq!(c::CopyFileExW::source(), Source::Native);
+
+    // The first copy already succeeds, and the stub is out of the way after it.
+    let dir = crate::env::temp_dir().join(format!("rust-copy-stub-{}", crate::process::id()));
+    crate::fs::create_dir(&dir).unwrap();
+    crate::fs::write(dir.join("from"), b"hello").unwrap();
+    assert_eq!(crate::fs::copy(dir.join("from"), dir.join("to")).unwrap(), 5);
+    assert_eq!(crate::fs::read(dir.join("to")).unwrap(), b"hello");
+    assert_eq!(c::CopyFileExW::source(), Source::Missing);
+    assert!(c::CopyFileExW::option().is_none());
+    // Only once.
+    assert!(!c::CopyFileExW::demote());
+    crate::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn copy_file_ex_stub_demoted() {
+    use crate::process::Command;
+
+    let output = Command::new(crate::env::current_exe().unwrap())
+        .args(["compat::tests::copy_file_ex_stub_child", "--test-threads=1"])
+        .env("RUST_TEST_COPY_FILE_EX_STUB", "1")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains(" 1 passed"), "{stdout}");
+}
+
+#[test]
+fn srw_functions_on_vista() {
+    use super::{load_srw_functions_from, LoadError, Module, SrwFunctions};
+
+    // Vista has everything but the `TryAcquire*` functions. Those that are found are the same as
+    // the ones `init` stored, and those that are missing are left alone.
+    let kernel32 = unsafe { Module::new(c"kernel32") }.unwrap();
+    let srw = load_srw_functions_from(|name| {
+        if name.to_bytes().starts_with(b"TryAcquire") {
+            Err(LoadError::SymbolNotFound { code: c::ERROR_PROC_NOT_FOUND })
+        } else {
+            kernel32.try_proc_address(name)
+        }
+    });
+    let has_srw_locks = c::AcquireSRWLockExclusive::option().is_some();
+    assert_eq!(srw, SrwFunctions { locks: has_srw_locks, try_locks: false });
+}
+
+#[test]
+fn optional_load_errors() {
+    use super::{load_optional_test_function, LoadError, Lookup, Module};
+
+    assert_eq!(c::RustCompatOptional::load_error(), None);
+
+    let library = unsafe { Module::cached(c"rust-compat-missing", Lookup::Loaded) };
+    // The code was taken by the lookup, so it doesn't matter what fails after it.
+    unsafe { c::SetLastError(c::ERROR_ACCESS_DENIED) };
+    assert_eq!(load_optional_test_function(library), None);
+    let expected = LoadError::ModuleNotFound { code: c::ERROR_MOD_NOT_FOUND };
+    assert_eq!(c::RustCompatOptional::load_error(), Some(expected));
+
+    let library = unsafe { Module::cached(c"kernel32", Lookup::Loaded) };
+    assert_eq!(load_optional_test_function(library), None);
+    let expected = LoadError::SymbolNotFound { code: c::ERROR_PROC_NOT_FOUND };
+    assert_eq!(c::RustCompatOptional::load_error(), Some(expected));
+    assert!(!c::RustCompatOptional::available());
+}
+
+#[cfg(feature = "windows_minimal_preload")]
+#[test]
+fn optional_called_first() {
+    // Nothing else loads it, so this call has to.
+    assert_ne!(unsafe { c::GetLogicalDrives() }, 0);
+    assert!(c::GetLogicalDrives::available());
+}
+
+#[test]
+fn racing_loads_keep_one_reference() {
+    use crate::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+    use crate::thread;
+
+    static ENTRY: CachedModule = CachedModule::new(c"kernel32");
+    static LOADS: AtomicUsize = AtomicUsize::new(0);
+    static RELEASES: AtomicUsize = AtomicUsize::new(0);
+
+    let threads: Vec<_> = (0..16)
+        .map(|_| {
+            thread::spawn(|| {
+                let module = ENTRY.get(
+                    |name| {
+                        LOADS.fetch_add(1, Relaxed);
+                        unsafe { super::Module::load(name) }
+                            .ok_or_else(super::LoadError::module_not_found)
+                    },
+                    |module| {
+                        RELEASES.fetch_add(1, Relaxed);
+                        unsafe { c::FreeLibrary(module.0.as_ptr()) };
+                    },
+                );
+                module.unwrap().0.as_ptr() as usize
+            })
+        })
+        .collect();
+    let handles: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+    assert!(handles.iter().all(|&handle| handle == handles[0]));
+    assert_eq!(LOADS.load(Relaxed) - RELEASES.load(Relaxed), 1);
+    assert_eq!(ENTRY.lookups.load(Relaxed), LOADS.load(Relaxed));
+    // Once it's cached, nothing is loaded anymore.
+    ENTRY.get(|_| panic!("loaded again"), |_| panic!("released again")).unwrap();
+}
+
+#[test]
+fn table_entries_match_their_functions() {
+    // Two functions from the same table, which every supported system has.
+    let kernel32 = unsafe { super::Module::new(c"kernel32") }.unwrap();
+    assert!(c::GetSystemTimeAsFileTime::available());
+    assert!(c::SetFilePointerEx::available());
+    let expected = kernel32.proc_address(c"GetSystemTimeAsFileTime").unwrap();
+    assert_eq!(c::GetSystemTimeAsFileTime::ptr(), expected.as_ptr());
+    let expected = kernel32.proc_address(c"SetFilePointerEx").unwrap();
+    assert_eq!(c::SetFilePointerEx::ptr(), expected.as_ptr());
+}
+
+#[test]
+fn windows_capabilities_complete() {
+    // The CRT ran `preload` before the test harness's `main`, so it doesn't have to run now. With
+    // minimal preload, the mutex kind is only picked when it's first asked for.
+    if !cfg!(feature = "windows_minimal_preload") {
+        assert!(super::system::is_complete());
+    }
+    let capabilities = super::windows_capabilities();
+    assert!(super::system::is_complete());
+    assert_eq!(capabilities.is_nt, unsafe { c::GetVersion() } < 0x8000_0000);
+    assert_eq!(capabilities.mutex_kind, crate::sys::locks::compat::mutex_kind());
+}
+
+/// Run by `blocked_functions_fall_back` with the time and temp path functions blocked, and does
+/// nothing otherwise.
+#[test]
+fn blocked_child() {
+    if crate::env::var_os("RUST_TEST_COMPAT_BLOCK").is_none() {
+        return;
+    }
+    // The precise time falls back to `GetSystemTimeAsFileTime`, which falls back to
+    // `GetSystemTime` in turn.
+    let now = crate::time::SystemTime::now();
+    assert!(now > crate::time::UNIX_EPOCH + crate::time::Duration::from_secs(1_000_000_000));
+    assert_eq!(c::GetSystemTimePreciseAsFileTime::source(), Source::Fallback);
+    assert_eq!(c::GetSystemTimeAsFileTime::source(), Source::Fallback);
+
+    assert!(crate::env::temp_dir().is_dir());
+    assert_eq!(c::GetTempPath2W::source(), Source::Fallback);
+}
+
+#[test]
+fn blocked_functions_fall_back() {
+    use crate::process::Command;
+
+    let output = Command::new(crate::env::current_exe().unwrap())
+        .args(["compat::tests::blocked_child"])
+        .env(
+            "RUST_TEST_COMPAT_BLOCK",
+            "GetSystemTimePreciseAsFileTime,GetSystemTimeAsFileTime,GetTempPath2W",
+        )
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains(" 1 passed"), "{stdout}");
+}
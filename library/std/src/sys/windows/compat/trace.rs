@@ -0,0 +1,101 @@
+//! Optional tracing of how the functions loaded by `compat_fn_with_fallback!` and
+//! `compat_fn_lazy!` got resolved.
+//!
+//! Tracing is enabled by setting `RUST_COMPAT_TRACE` in the environment of the program. This is
+//! checked once in `init`, which runs during CRT initialization and so must not allocate: symbols
+//! are recorded into a fixed-size table, and only formatted when the trace is asked for.
+
+use crate::ffi::{c_char, CStr};
+use crate::fmt::Write;
+use crate::ptr;
+use crate::sync::atomic::{
+    AtomicBool, AtomicPtr, AtomicU8, AtomicUsize,
+    Ordering::{Acquire, Relaxed, Release},
+};
+
+use super::Source;
+
+const VAR: &CStr = c"RUST_COMPAT_TRACE";
+
+/// Symbols beyond this many are only counted. std loads far fewer than this.
+const CAPACITY: usize = 128;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+struct Entry {
+    symbol: AtomicPtr<c_char>,
+    module: AtomicPtr<c_char>,
+    /// Zero until the entry is completely written.
+    source: AtomicU8,
+}
+
+const EMPTY: Entry = Entry {
+    symbol: AtomicPtr::new(ptr::null_mut()),
+    module: AtomicPtr::new(ptr::null_mut()),
+    source: AtomicU8::new(0),
+};
+
+static ENTRIES: [Entry; CAPACITY] = [EMPTY; CAPACITY];
+/// The number of symbols recorded, including those that didn't fit.
+static RECORDED: AtomicUsize = AtomicUsize::new(0);
+
+/// Enables tracing if the environment asks for it. Must run before any symbol is loaded.
+pub fn init() {
+    ENABLED.store(super::env_var_set(VAR), Relaxed);
+}
+
+#[cfg(test)]
+pub fn enable() {
+    ENABLED.store(true, Relaxed);
+}
+
+/// Records how `symbol`, declared for `module`, was resolved.
+pub fn record(symbol: &'static CStr, module: &'static CStr, source: Source) {
+    if !ENABLED.load(Relaxed) {
+        return;
+    }
+    let Some(entry) = ENTRIES.get(RECORDED.fetch_add(1, Relaxed)) else { return };
+    entry.symbol.store(symbol.as_ptr().cast_mut(), Relaxed);
+    entry.module.store(module.as_ptr().cast_mut(), Relaxed);
+    entry.source.store(source as u8, Release);
+}
+
+/// Lists the recorded symbols as `symbol: source`, one per line, or `None` if tracing is off.
+///
+/// A symbol that several threads resolved at the same time is only listed once.
+pub fn report() -> Option<String> {
+    if !ENABLED.load(Relaxed) {
+        return None;
+    }
+    let recorded = RECORDED.load(Relaxed);
+    let mut seen = Vec::new();
+    let mut out = String::new();
+    for entry in &ENTRIES[..recorded.min(CAPACITY)] {
+        let source = Source::from_u8(entry.source.load(Acquire));
+        // Still being written.
+        if source == Source::NotLoadedYet {
+            continue;
+        }
+        let symbol = entry.symbol.load(Relaxed);
+        if seen.contains(&symbol) {
+            continue;
+        }
+        seen.push(symbol);
+        // SAFETY: Both were stored from a `&'static CStr`.
+        let (symbol, module) =
+            unsafe { (CStr::from_ptr(symbol), CStr::from_ptr(entry.module.load(Relaxed))) };
+        let (symbol, module) = (symbol.to_string_lossy(), module.to_string_lossy());
+        let _ = match source {
+            Source::Unicows => writeln!(out, "{symbol}: unicows"),
+            Source::Native => writeln!(out, "{symbol}: {module}"),
+            Source::Fallback => writeln!(out, "{symbol}: fallback, not in {module}"),
+            Source::Missing | Source::NotLoadedYet => {
+                writeln!(out, "{symbol}: missing, not in {module}")
+            }
+        };
+    }
+    if let Some(dropped) = recorded.checked_sub(CAPACITY).filter(|&n| n > 0) {
+        let _ = writeln!(out, "({dropped} more not recorded)");
+    }
+    Some(out)
+}
@@ -1,24 +1,77 @@
+#[cfg(test)]
+use crate::cell::Cell;
+use crate::mem;
+use crate::sync::atomic::{AtomicU32, Ordering};
 use crate::sys::c;
 
-static mut IS_NT: bool = true;
-static mut SUPPORTS_ASYNC_IO: bool = true;
+use super::WindowsCapabilities;
 
-pub fn init_windows_version_check() {
+/// Fills in what the Windows version tells about the system.
+pub(super) fn check_windows_version(capabilities: &mut WindowsCapabilities) {
     // according to old MSDN info, the high-order bit is set only on 95/98/ME.
-    unsafe {
-        IS_NT = c::GetVersion() < 0x8000_0000;
-        SUPPORTS_ASYNC_IO = IS_NT && c::CancelIo::option().is_some();
-    };
+    capabilities.is_nt = unsafe { c::GetVersion() } < 0x8000_0000;
+    capabilities.async_io = capabilities.is_nt && c::CancelIo::option().is_some();
 }
 
 /// Returns true if we are running on a Windows NT-based system. Only use this for APIs where the
 /// same API differs in behavior or capability on 9x/ME compared to NT.
 #[inline(always)]
 pub fn is_windows_nt() -> bool {
-    unsafe { IS_NT }
+    super::system::published().is_nt
 }
 
 #[inline(always)]
 pub fn supports_async_io() -> bool {
-    unsafe { SUPPORTS_ASYNC_IO }
+    #[cfg(test)]
+    if FORCE_SYNC_IO.with(Cell::get) {
+        return false;
+    }
+    super::system::published().async_io
+}
+
+#[cfg(test)]
+thread_local! {
+    static FORCE_SYNC_IO: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f` with `supports_async_io` returning false on the current thread, so that the 9x/ME
+/// I/O paths can be tested on NT.
+#[cfg(test)]
+pub fn with_sync_io<R>(f: impl FnOnce() -> R) -> R {
+    let forced = FORCE_SYNC_IO.with(|force| force.replace(true));
+    let result = f();
+    FORCE_SYNC_IO.with(|force| force.set(forced));
+    result
+}
+
+/// Returns the build number of Windows NT, or 0 on 9x/ME.
+///
+/// Unlike `GetVersion`, `RtlGetVersion` isn't affected by the compatibility shims that make newer
+/// systems report the version the application's manifest declares support for, which is Windows 8
+/// at most without a manifest. The build number is determined once and then cached.
+pub fn nt_build_number() -> u32 {
+    // `u32::MAX` until the build number has been determined.
+    static BUILD_NUMBER: AtomicU32 = AtomicU32::new(u32::MAX);
+
+    let build = BUILD_NUMBER.load(Ordering::Relaxed);
+    if build != u32::MAX {
+        return build;
+    }
+
+    let build = if !is_windows_nt() {
+        0
+    } else if let Some(rtl_get_version) = c::RtlGetVersion::option() {
+        unsafe {
+            let mut info: c::OSVERSIONINFOW = mem::zeroed();
+            info.dwOSVersionInfoSize = mem::size_of::<c::OSVERSIONINFOW>() as u32;
+            // `RtlGetVersion` always succeeds.
+            rtl_get_version(&mut info);
+            info.dwBuildNumber
+        }
+    } else {
+        // NT 3.x/4.0 put the build number in the high-order word.
+        (unsafe { c::GetVersion() } >> 16) & 0x7fff
+    };
+    BUILD_NUMBER.store(build, Ordering::Relaxed);
+    build
 }
@@ -1,6 +1,8 @@
 use crate::os::windows::prelude::*;
 
 use crate::borrow::Cow;
+#[cfg(test)]
+use crate::cell::Cell;
 use crate::ffi::{c_void, OsString};
 use crate::fmt;
 use crate::io::{self, BorrowedCursor, Error, IoSlice, IoSliceMut, SeekFrom};
@@ -9,16 +11,21 @@
 use crate::path::{Path, PathBuf};
 use crate::ptr;
 use crate::slice;
+use crate::sync::atomic::{AtomicBool, Ordering};
 use crate::sync::Arc;
 use crate::sys::handle::Handle;
 use crate::sys::time::SystemTime;
 use crate::sys::{c, cvt, Align8};
 use crate::sys_common::{AsInner, FromInner, IntoInner};
 use crate::thread;
+use crate::time::Duration;
 
 use super::path::maybe_verbatim;
 use super::{api, compat, to_u16s, IoResult};
 
+#[cfg(test)]
+mod tests;
+
 pub struct File {
     handle: Handle,
 }
@@ -43,12 +50,18 @@ pub struct FileType {
 }
 
 pub struct ReadDir {
-    handle: FindNextFileHandle,
+    /// `None` if the directory was empty to begin with.
+    handle: Option<FindNextFileHandle>,
     root: Arc<PathBuf>,
     first: Option<c::WIN32_FIND_DATAW>,
 }
 
-struct FindNextFileHandle(c::HANDLE);
+/// A search started by `FindFirstFileW`, or by its ANSI shim if `ansi`.
+struct FindNextFileHandle {
+    handle: c::HANDLE,
+    /// Whether the search has to be continued through the ANSI shim, see `find_with_ansi_api`.
+    ansi: bool,
+}
 
 unsafe impl Send for FindNextFileHandle {}
 unsafe impl Sync for FindNextFileHandle {}
@@ -117,10 +130,16 @@ fn next(&mut self) -> Option<io::Result<DirEntry>> {
                 return Some(Ok(e));
             }
         }
+        let handle = self.handle.as_ref()?;
         unsafe {
             let mut wfd = mem::zeroed();
             loop {
-                if c::FindNextFileW(self.handle.0, &mut wfd) == 0 {
+                let found = if handle.ansi {
+                    c::ansi::FindNextFileW(handle.handle, &mut wfd)
+                } else {
+                    c::FindNextFileW(handle.handle, &mut wfd)
+                };
+                if found == 0 {
                     if api::get_last_error().code == c::ERROR_NO_MORE_FILES {
                         return None;
                     } else {
@@ -137,7 +156,7 @@ fn next(&mut self) -> Option<io::Result<DirEntry>> {
 
 impl Drop for FindNextFileHandle {
     fn drop(&mut self) {
-        let r = unsafe { c::FindClose(self.0) };
+        let r = unsafe { c::FindClose(self.handle) };
         debug_assert!(r != 0);
     }
 }
@@ -307,16 +326,34 @@ impl File {
     pub fn open(path: &Path, opts: &OpenOptions) -> io::Result<File> {
         let path = maybe_verbatim(path)?;
         let creation = opts.get_creation_mode()?;
+        let access = opts.get_access_mode()?;
+        let flags = opts.get_flags_and_attributes();
         let handle = unsafe {
-            c::CreateFileW(
+            let handle = c::CreateFileW(
                 path.as_ptr(),
-                opts.get_access_mode()?,
+                access,
                 opts.share_mode,
                 opts.security_attributes,
                 creation,
-                opts.get_flags_and_attributes(),
+                flags,
                 ptr::null_mut(),
-            )
+            );
+            // Without unicows, 9x/ME only have a stub for `CreateFileW`.
+            if handle == c::INVALID_HANDLE_VALUE
+                && c::GetLastError() == c::ERROR_CALL_NOT_IMPLEMENTED
+            {
+                c::ansi::CreateFileW(
+                    path.as_ptr(),
+                    access,
+                    opts.share_mode,
+                    opts.security_attributes,
+                    creation,
+                    flags,
+                    ptr::null_mut(),
+                )
+            } else {
+                handle
+            }
         };
         let handle = unsafe { HandleOrInvalid::from_raw_handle(handle) };
         if let Ok(handle) = OwnedHandle::try_from(handle) {
@@ -352,6 +389,117 @@ pub fn datasync(&self) -> io::Result<()> {
         self.fsync()
     }
 
+    pub fn lock(&self) -> io::Result<()> {
+        self.lock_with(LockTier::first_available(), true, true).map(drop)
+    }
+
+    pub fn lock_shared(&self) -> io::Result<()> {
+        self.lock_with(LockTier::first_available(), false, true).map(drop)
+    }
+
+    pub fn try_lock(&self) -> io::Result<bool> {
+        self.lock_with(LockTier::first_available(), true, false)
+    }
+
+    pub fn try_lock_shared(&self) -> io::Result<bool> {
+        self.lock_with(LockTier::first_available(), false, false)
+    }
+
+    pub fn unlock(&self) -> io::Result<()> {
+        self.unlock_with(LockTier::first_available())
+    }
+
+    /// Locks the whole file, waiting for other locks to go away if `wait`. Returns whether the
+    /// lock was taken, which it always is if `wait`.
+    fn lock_with(&self, tier: LockTier, exclusive: bool, wait: bool) -> io::Result<bool> {
+        let handle = self.handle.as_raw_handle();
+        let range = tier.whole_file();
+        let result = match tier {
+            LockTier::LockFileEx => {
+                let mut flags = 0;
+                if exclusive {
+                    flags |= c::LOCKFILE_EXCLUSIVE_LOCK;
+                }
+                if !wait {
+                    flags |= c::LOCKFILE_FAIL_IMMEDIATELY;
+                }
+                unsafe {
+                    // The range starts at the offset in the `OVERLAPPED`.
+                    let mut overlapped: c::OVERLAPPED = mem::zeroed();
+                    overlapped.Anonymous.Anonymous.Offset = range.offset_low;
+                    overlapped.Anonymous.Anonymous.OffsetHigh = range.offset_high;
+                    let ok = c::LockFileEx(
+                        handle,
+                        flags,
+                        0,
+                        range.len_low,
+                        range.len_high,
+                        &mut overlapped,
+                    );
+                    // Handles opened for asynchronous I/O lock asynchronously too, and the
+                    // `OVERLAPPED` must live until the lock request is done.
+                    if ok == c::FALSE && c::GetLastError() == c::ERROR_IO_PENDING {
+                        let mut transferred = 0;
+                        cvt(c::GetOverlappedResult(handle, &overlapped, &mut transferred, c::TRUE))
+                    } else {
+                        cvt(ok)
+                    }
+                }
+            }
+            // `LockFile` can't take shared locks, so all locks are exclusive. It never waits
+            // either, so waiting is done by trying again until the lock is free.
+            LockTier::LockFile => loop {
+                let result = cvt(unsafe {
+                    c::LockFile(
+                        handle,
+                        range.offset_low,
+                        range.offset_high,
+                        range.len_low,
+                        range.len_high,
+                    )
+                });
+                match result {
+                    Err(ref err) if wait && is_lock_violation(err) => unsafe { c::Sleep(10) },
+                    result => break result,
+                }
+            },
+        };
+        match result {
+            Ok(_) => Ok(true),
+            Err(ref err) if !wait && is_lock_violation(err) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Releases the locks taken through `tier`, which must be the same tier that took them, so
+    /// that the ranges match.
+    fn unlock_with(&self, tier: LockTier) -> io::Result<()> {
+        let handle = self.handle.as_raw_handle();
+        let range = tier.whole_file();
+        let unlock = || {
+            cvt(unsafe {
+                c::UnlockFile(
+                    handle,
+                    range.offset_low,
+                    range.offset_high,
+                    range.len_low,
+                    range.len_high,
+                )
+            })
+        };
+        unlock()?;
+        if tier == LockTier::LockFile {
+            return Ok(());
+        }
+        // `LockFileEx` lets a handle hold both an exclusive and a shared lock on the same range,
+        // which take one unlock each. The second one fails if there was only one lock.
+        match unlock() {
+            Ok(_) => Ok(()),
+            Err(err) if err.raw_os_error() == Some(c::ERROR_NOT_LOCKED as i32) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
     pub fn truncate(&self, size: u64) -> io::Result<()> {
         Self::truncate_inner(self.handle.as_raw_handle(), size)
     }
@@ -361,42 +509,58 @@ pub fn truncate_inner(handle: RawHandle, size: u64) -> io::Result<()> {
             let info = c::FILE_END_OF_FILE_INFO { EndOfFile: size as i64 };
             api::set_file_information_by_handle(handle, &info).io_result()
         } else {
-            let mut saved_pos = 0i64;
-            unsafe {
-                // get current file pointer position
-                cvt(c::SetFilePointerEx(handle, 0, &mut saved_pos, c::FILE_CURRENT))?;
-
-                // seek to new end position
-                cvt(c::SetFilePointerEx(handle, size as i64, ptr::null_mut(), c::FILE_BEGIN))?;
-
-                // set current position as end of file
-                cvt(c::SetEndOfFile(handle))?;
-
-                // go back to saved position
-                cvt(c::SetFilePointerEx(handle, saved_pos, ptr::null_mut(), c::FILE_BEGIN))?;
-            }
-            Ok(())
+            Self::truncate_by_seeking(handle, size, |handle| {
+                cvt(unsafe { c::SetEndOfFile(handle) }).map(drop)
+            })
         }
     }
 
+    /// Sets the length of the file by calling `set_end_of_file` with the file pointer moved to
+    /// `size`, for systems without `SetFileInformationByHandle`.
+    ///
+    /// The file pointer is moved back to where it was even if that fails, in which case the error
+    /// from setting the length is returned rather than any from moving the pointer back. Sizes
+    /// that don't fit into an `i64` fail with `ERROR_NEGATIVE_SEEK`, like they would on newer
+    /// systems.
+    fn truncate_by_seeking(
+        handle: RawHandle,
+        size: u64,
+        set_end_of_file: impl FnOnce(RawHandle) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let seek = |pos| unsafe {
+            cvt(c::SetFilePointerEx(handle, pos, ptr::null_mut(), c::FILE_BEGIN)).map(drop)
+        };
+        let mut saved_pos = 0i64;
+        cvt(unsafe { c::SetFilePointerEx(handle, 0, &mut saved_pos, c::FILE_CURRENT) })?;
+        let result = seek(size as i64).and_then(|_| set_end_of_file(handle));
+        let restored = seek(saved_pos);
+        result.and(restored)
+    }
+
     #[cfg(not(target_vendor = "uwp"))]
     pub fn file_attr(&self) -> io::Result<FileAttr> {
         unsafe {
             let mut info: c::BY_HANDLE_FILE_INFORMATION = mem::zeroed();
             cvt(c::GetFileInformationByHandle(self.handle.as_raw_handle(), &mut info))?;
             let mut reparse_tag = 0;
-            if info.dwFileAttributes & c::FILE_ATTRIBUTE_REPARSE_POINT != 0
-                && c::GetFileInformationByHandleEx::option().is_some()
-            {
-                let mut attr_tag: c::FILE_ATTRIBUTE_TAG_INFO = mem::zeroed();
-                cvt(c::GetFileInformationByHandleEx(
-                    self.handle.as_raw_handle(),
-                    c::FileAttributeTagInfo,
-                    ptr::addr_of_mut!(attr_tag).cast(),
-                    mem::size_of::<c::FILE_ATTRIBUTE_TAG_INFO>().try_into().unwrap(),
-                ))?;
-                if attr_tag.FileAttributes & c::FILE_ATTRIBUTE_REPARSE_POINT != 0 {
-                    reparse_tag = attr_tag.ReparseTag;
+            if info.dwFileAttributes & c::FILE_ATTRIBUTE_REPARSE_POINT != 0 {
+                if c::GetFileInformationByHandleEx::option().is_some() {
+                    let mut attr_tag: c::FILE_ATTRIBUTE_TAG_INFO = mem::zeroed();
+                    cvt(c::GetFileInformationByHandleEx(
+                        self.handle.as_raw_handle(),
+                        c::FileAttributeTagInfo,
+                        ptr::addr_of_mut!(attr_tag).cast(),
+                        mem::size_of::<c::FILE_ATTRIBUTE_TAG_INFO>().try_into().unwrap(),
+                    ))?;
+                    if attr_tag.FileAttributes & c::FILE_ATTRIBUTE_REPARSE_POINT != 0 {
+                        reparse_tag = attr_tag.ReparseTag;
+                    }
+                } else {
+                    // Before Vista, the tag can only be read along with the whole reparse point.
+                    // Without it, junctions (the only links there are) would look like plain
+                    // directories to `symlink_metadata`. If it can't be read, that's still better
+                    // than failing to get the metadata at all.
+                    reparse_tag = self.reparse_tag().unwrap_or(0);
                 }
             }
             Ok(FileAttr {
@@ -408,9 +572,10 @@ pub fn file_attr(&self) -> io::Result<FileAttr> {
                 reparse_tag,
                 volume_serial_number: Some(info.dwVolumeSerialNumber),
                 number_of_links: Some(info.nNumberOfLinks),
-                file_index: Some(
-                    (info.nFileIndexLow as u64) | ((info.nFileIndexHigh as u64) << 32),
-                ),
+                // 9x/ME fill the index in, but it isn't stable, so two handles to the same file
+                // may not agree on it, and different files may share one.
+                file_index: compat::is_windows_nt()
+                    .then(|| (info.nFileIndexLow as u64) | ((info.nFileIndexHigh as u64) << 32)),
             })
         }
     }
@@ -561,6 +726,14 @@ fn reparse_point(
         }
     }
 
+    /// Reads the tag of the reparse point the file was opened as.
+    fn reparse_tag(&self) -> io::Result<c::DWORD> {
+        let mut space =
+            Align8([MaybeUninit::<u8>::uninit(); c::MAXIMUM_REPARSE_DATA_BUFFER_SIZE as usize]);
+        let (_bytes, buf) = self.reparse_point(&mut space)?;
+        Ok(unsafe { (*buf).ReparseTag })
+    }
+
     fn readlink(&self) -> io::Result<PathBuf> {
         let mut space =
             Align8([MaybeUninit::<u8>::uninit(); c::MAXIMUM_REPARSE_DATA_BUFFER_SIZE as usize]);
@@ -599,7 +772,8 @@ fn readlink(&self) -> io::Result<PathBuf> {
             let subst_ptr = path_buffer.add(subst_off.into());
             let subst = slice::from_raw_parts_mut(subst_ptr, subst_len as usize);
             // Absolute paths start with an NT internal namespace prefix `\??\`
-            // We should not let it leak through.
+            // We should not let it leak through. Mount points are always
+            // absolute, so this is how every junction's target is stored.
             if !relative && subst.starts_with(&[92u16, 63u16, 63u16, 92u16]) {
                 // Turn `\??\` into `\\?\` (a verbatim path).
                 subst[1] = b'\\' as u16;
@@ -656,6 +830,36 @@ pub fn set_times(&self, times: FileTimes) -> io::Result<()> {
                 times.modified.as_ref().map(|a| a as *const c::FILETIME).unwrap_or(ptr::null());
             c::SetFileTime(self.as_raw_handle(), created, accessed, modified)
         })?;
+        // 9x/ME only support FAT, which may silently drop creation and access times, so check
+        // what actually ended up on disk.
+        if !compat::is_windows_nt() {
+            self.verify_fat_times(&times)?;
+        }
+        Ok(())
+    }
+
+    fn verify_fat_times(&self, times: &FileTimes) -> io::Result<()> {
+        let (mut created, mut accessed, mut modified) =
+            unsafe { (mem::zeroed(), mem::zeroed(), mem::zeroed()) };
+        cvt(unsafe {
+            c::GetFileTime(self.as_raw_handle(), &mut created, &mut accessed, &mut modified)
+        })?;
+        let checks = [
+            (times.created, created, FatTime::Created),
+            (times.accessed, accessed, FatTime::Accessed),
+            (times.modified, modified, FatTime::Modified),
+        ];
+        for (requested, stored, kind) in checks {
+            match requested {
+                Some(requested) if !kind.stored(to_u64(&requested), to_u64(&stored)) => {
+                    return Err(io::const_io_error!(
+                        io::ErrorKind::Unsupported,
+                        "the file system cannot store this file time",
+                    ));
+                }
+                _ => {}
+            }
+        }
         Ok(())
     }
 
@@ -698,6 +902,27 @@ fn win32_delete(&self) -> io::Result<()> {
         api::set_file_information_by_handle(self.handle.as_raw_handle(), &info).io_result()
     }
 
+    /// Delete a file using win32 semantics through `NtSetInformationFile`, for systems that lack
+    /// `SetFileInformationByHandle` (NT 4 to XP).
+    fn nt_delete(&self) -> io::Result<()> {
+        let info = c::FILE_DISPOSITION_INFO { DeleteFile: c::TRUE as _ };
+        let mut io_status = c::IO_STATUS_BLOCK::PENDING;
+        let status = unsafe {
+            c::NtSetInformationFile(
+                self.handle.as_raw_handle(),
+                &mut io_status,
+                ptr::addr_of!(info).cast(),
+                mem::size_of_val(&info) as u32,
+                c::FileDispositionInformation,
+            )
+        };
+        if c::nt_success(status) {
+            Ok(())
+        } else {
+            Err(io::Error::from_raw_os_error(unsafe { c::RtlNtStatusToDosError(status) } as _))
+        }
+    }
+
     /// Fill the given buffer with as many directory entries as will fit.
     /// This will remember its position and continue from the last call unless
     /// `restart` is set to `true`.
@@ -840,51 +1065,89 @@ fn open_link_no_reparse(parent: &File, name: &[u16], access: u32) -> io::Result<
         let mut handle = ptr::null_mut();
         let mut io_status = c::IO_STATUS_BLOCK::PENDING;
         let mut name_str = c::UNICODE_STRING::from_ref(name);
-        use crate::sync::atomic::{AtomicU32, Ordering};
-        // The `OBJ_DONT_REPARSE` attribute ensures that we haven't been
-        // tricked into following a symlink. However, it may not be available in
-        // earlier versions of Windows.
-        static ATTRIBUTES: AtomicU32 = AtomicU32::new(c::OBJ_DONT_REPARSE);
-        let object = c::OBJECT_ATTRIBUTES {
-            ObjectName: &mut name_str,
-            RootDirectory: parent.as_raw_handle(),
-            Attributes: ATTRIBUTES.load(Ordering::Relaxed),
-            ..c::OBJECT_ATTRIBUTES::default()
-        };
-        let status = c::NtCreateFile(
-            &mut handle,
-            access,
-            &object,
-            &mut io_status,
-            crate::ptr::null_mut(),
-            0,
-            c::FILE_SHARE_DELETE | c::FILE_SHARE_READ | c::FILE_SHARE_WRITE,
-            c::FILE_OPEN,
-            // If `name` is a symlink then open the link rather than the target.
-            c::FILE_OPEN_REPARSE_POINT,
-            crate::ptr::null_mut(),
-            0,
-        );
-        // Convert an NTSTATUS to the more familiar Win32 error codes (aka "DosError")
-        if c::nt_success(status) {
-            Ok(File::from_raw_handle(handle))
-        } else if status == c::STATUS_DELETE_PENDING {
-            // We make a special exception for `STATUS_DELETE_PENDING` because
-            // otherwise this will be mapped to `ERROR_ACCESS_DENIED` which is
-            // very unhelpful.
-            Err(io::Error::from_raw_os_error(c::ERROR_DELETE_PENDING as _))
-        } else if status == c::STATUS_INVALID_PARAMETER
-            && ATTRIBUTES.load(Ordering::Relaxed) == c::OBJ_DONT_REPARSE
-        {
-            // Try without `OBJ_DONT_REPARSE`. See above.
-            ATTRIBUTES.store(0, Ordering::Relaxed);
-            open_link_no_reparse(parent, name, access)
-        } else {
-            Err(io::Error::from_raw_os_error(c::RtlNtStatusToDosError(status) as _))
+        let mut missing = false;
+        let status =
+            create_no_reparse(compat::nt_build_number(), &DONT_REPARSE_REJECTED, |attributes| {
+                let object = c::OBJECT_ATTRIBUTES {
+                    ObjectName: &mut name_str,
+                    RootDirectory: parent.as_raw_handle(),
+                    Attributes: attributes,
+                    ..c::OBJECT_ATTRIBUTES::default()
+                };
+                c::NtCreateFile::try_call(
+                    &mut handle,
+                    access,
+                    &object,
+                    &mut io_status,
+                    crate::ptr::null_mut(),
+                    0,
+                    c::FILE_SHARE_DELETE | c::FILE_SHARE_READ | c::FILE_SHARE_WRITE,
+                    c::FILE_OPEN,
+                    // If `name` is a symlink then open the link rather than the target.
+                    c::FILE_OPEN_REPARSE_POINT,
+                    crate::ptr::null_mut(),
+                    0,
+                )
+                .unwrap_or_else(|| {
+                    missing = true;
+                    c::STATUS_NOT_IMPLEMENTED
+                })
+            });
+        if missing {
+            return Err(io::Error::from_raw_os_error(c::ERROR_CALL_NOT_IMPLEMENTED as _));
         }
+        nt_create_result(status, handle)
     }
 }
 
+/// Turns what `NtCreateFile` returned into the file it opened, or the error it failed with.
+unsafe fn nt_create_result(status: c::NTSTATUS, handle: c::HANDLE) -> io::Result<File> {
+    // Convert an NTSTATUS to the more familiar Win32 error codes (aka "DosError")
+    if c::nt_success(status) {
+        Ok(File::from_raw_handle(handle))
+    } else if status == c::STATUS_DELETE_PENDING {
+        // We make a special exception for `STATUS_DELETE_PENDING` because
+        // otherwise this will be mapped to `ERROR_ACCESS_DENIED` which is
+        // very unhelpful.
+        Err(io::Error::from_raw_os_error(c::ERROR_DELETE_PENDING as _))
+    } else {
+        Err(io::Error::from_raw_os_error(c::RtlNtStatusToDosError(status) as _))
+    }
+}
+
+/// The first build of Windows 10, the first version that accepts `OBJ_DONT_REPARSE`.
+const DONT_REPARSE_BUILD: u32 = 10240;
+
+/// Set once `NtCreateFile` has rejected `OBJ_DONT_REPARSE` even though the build number says it's
+/// supported.
+static DONT_REPARSE_REJECTED: AtomicBool = AtomicBool::new(false);
+
+/// Calls `create`, which is passed the object attributes for `NtCreateFile`.
+///
+/// The `OBJ_DONT_REPARSE` attribute ensures that we haven't been tricked into following a
+/// symlink, but earlier versions of Windows fail with `STATUS_INVALID_PARAMETER` if it's set. So
+/// it's only passed from Windows 10 on, and if it's rejected anyway, the call is retried without
+/// it. If the retry doesn't fail the same way, the attribute was the problem and `rejected` is set
+/// so that later calls don't pass it anymore.
+fn create_no_reparse(
+    build: u32,
+    rejected: &AtomicBool,
+    mut create: impl FnMut(u32) -> c::NTSTATUS,
+) -> c::NTSTATUS {
+    if build < DONT_REPARSE_BUILD || rejected.load(Ordering::Relaxed) {
+        return create(0);
+    }
+    let status = create(c::OBJ_DONT_REPARSE);
+    if status != c::STATUS_INVALID_PARAMETER {
+        return status;
+    }
+    let status = create(0);
+    if status != c::STATUS_INVALID_PARAMETER {
+        rejected.store(true, Ordering::Relaxed);
+    }
+    status
+}
+
 impl AsInner<Handle> for File {
     #[inline]
     fn as_inner(&self) -> &Handle {
@@ -962,11 +1225,11 @@ pub fn modified(&self) -> io::Result<SystemTime> {
     }
 
     pub fn accessed(&self) -> io::Result<SystemTime> {
-        Ok(SystemTime::from(self.last_access_time))
+        stored_time(self.last_access_time)
     }
 
     pub fn created(&self) -> io::Result<SystemTime> {
-        Ok(SystemTime::from(self.creation_time))
+        stored_time(self.creation_time)
     }
 
     pub fn modified_u64(&self) -> u64 {
@@ -1014,10 +1277,70 @@ fn from(wfd: c::WIN32_FIND_DATAW) -> Self {
     }
 }
 
+impl From<c::WIN32_FILE_ATTRIBUTE_DATA> for FileAttr {
+    fn from(data: c::WIN32_FILE_ATTRIBUTE_DATA) -> Self {
+        FileAttr {
+            attributes: data.dwFileAttributes,
+            creation_time: data.ftCreationTime,
+            last_access_time: data.ftLastAccessTime,
+            last_write_time: data.ftLastWriteTime,
+            file_size: ((data.nFileSizeHigh as u64) << 32) | (data.nFileSizeLow as u64),
+            reparse_tag: 0,
+            volume_serial_number: None,
+            number_of_links: None,
+            file_index: None,
+        }
+    }
+}
+
 fn to_u64(ft: &c::FILETIME) -> u64 {
     (ft.dwLowDateTime as u64) | ((ft.dwHighDateTime as u64) << 32)
 }
 
+/// Converts a creation or access time, which is zero if the file system didn't store it.
+///
+/// FAT only got those two with Windows 95, so files written by DOS or by drivers that don't bother
+/// have them zeroed. The 1601 epoch a zero time stands for isn't a time anybody used the file at.
+fn stored_time(ft: c::FILETIME) -> io::Result<SystemTime> {
+    if to_u64(&ft) == 0 {
+        return Err(io::const_io_error!(
+            io::ErrorKind::Unsupported,
+            "the file system did not store this file time",
+        ));
+    }
+    Ok(SystemTime::from(ft))
+}
+
+/// The file times stored by FAT, the only file system supported by 9x/ME.
+#[derive(Clone, Copy, Debug)]
+pub enum FatTime {
+    /// Stored with a resolution of 10ms, but not supported by all drivers.
+    Created,
+    /// Only the (local) date is stored, and not supported by all drivers.
+    Accessed,
+    /// Stored with a resolution of 2s.
+    Modified,
+}
+
+impl FatTime {
+    /// The resolution of this time on FAT, in 100ns intervals.
+    pub const fn resolution(self) -> u64 {
+        const MILLIS: u64 = 10_000;
+        match self {
+            FatTime::Created => 10 * MILLIS,
+            FatTime::Accessed => 24 * 60 * 60 * 1000 * MILLIS,
+            FatTime::Modified => 2 * 1000 * MILLIS,
+        }
+    }
+
+    /// Whether `stored` is what FAT makes of the `requested` time, both in 100ns intervals.
+    ///
+    /// A stored time of zero means that the file system didn't store the time at all.
+    pub fn stored(self, requested: u64, stored: u64) -> bool {
+        stored != 0 && requested.abs_diff(stored) < self.resolution()
+    }
+}
+
 impl FilePermissions {
     pub fn readonly(&self) -> bool {
         self.attrs & c::FILE_ATTRIBUTE_READONLY != 0
@@ -1065,6 +1388,9 @@ pub fn is_symlink_dir(&self) -> bool {
     pub fn is_symlink_file(&self) -> bool {
         self.is_symlink() && !self.is_directory()
     }
+    pub fn is_junction(&self) -> bool {
+        self.is_reparse_point() && self.reparse_tag == c::IO_REPARSE_TAG_MOUNT_POINT
+    }
     fn is_directory(&self) -> bool {
         self.attributes & c::FILE_ATTRIBUTE_DIRECTORY != 0
     }
@@ -1097,29 +1423,277 @@ pub fn readdir(p: &Path) -> io::Result<ReadDir> {
         // E.g. fs::metadata or File::open.
         return Err(io::Error::from_raw_os_error(c::ERROR_PATH_NOT_FOUND as i32));
     }
-    let root = p.to_path_buf();
+    let root = Arc::new(p.to_path_buf());
     let star = p.join("*");
     let path = maybe_verbatim(&star)?;
+    let ansi = find_with_ansi_api();
 
     unsafe {
         let mut wfd = mem::zeroed();
-        let find_handle = c::FindFirstFileW(path.as_ptr(), &mut wfd);
+        let find_handle = if ansi {
+            c::ansi::FindFirstFileW(path.as_ptr(), &mut wfd)
+        } else {
+            c::FindFirstFileW(path.as_ptr(), &mut wfd)
+        };
         if find_handle != c::INVALID_HANDLE_VALUE {
             Ok(ReadDir {
-                handle: FindNextFileHandle(find_handle),
-                root: Arc::new(root),
+                handle: Some(FindNextFileHandle { handle: find_handle, ansi }),
+                root,
                 first: Some(wfd),
             })
         } else {
-            Err(Error::last_os_error())
+            match api::get_last_error().code {
+                // On 9x/ME, the directory exists, but doesn't even have `.` and `..` entries, like
+                // the root of some drives. That is reported as either of these. On NT,
+                // `ERROR_FILE_NOT_FOUND` means that the directory is missing.
+                c::ERROR_FILE_NOT_FOUND | c::ERROR_NO_MORE_FILES if !compat::is_windows_nt() => {
+                    Ok(ReadDir { handle: None, root, first: None })
+                }
+                _ => Err(Error::last_os_error()),
+            }
+        }
+    }
+}
+
+/// Whether directories are searched through the ANSI API rather than `FindFirstFileW`.
+///
+/// That's always the case on 9x/ME, even if unicows provides `FindFirstFileW`: its conversion
+/// drops the 8.3 names, and replaces characters that aren't in the code page, so that the names it
+/// returns can't even be opened again.
+fn find_with_ansi_api() -> bool {
+    #[cfg(test)]
+    if FORCE_ANSI_FIND.with(Cell::get) {
+        return true;
+    }
+    !compat::is_windows_nt()
+}
+
+#[cfg(test)]
+thread_local! {
+    static FORCE_ANSI_FIND: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f` with directories searched through the ANSI API on the current thread, so that the
+/// 9x/ME path can be tested on NT.
+#[cfg(test)]
+fn with_ansi_find<R>(f: impl FnOnce() -> R) -> R {
+    let forced = FORCE_ANSI_FIND.with(|force| force.replace(true));
+    let result = f();
+    FORCE_ANSI_FIND.with(|force| force.set(forced));
+    result
+}
+
+/// The ways files can be locked.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LockTier {
+    /// `LockFileEx`, which takes shared and exclusive locks, and can wait for them.
+    LockFileEx,
+    /// `LockFile` (9x/ME), which only takes exclusive locks, and never waits.
+    LockFile,
+}
+
+impl LockTier {
+    fn first_available() -> LockTier {
+        // 9x/ME export `LockFileEx`, but it always fails there.
+        if compat::is_windows_nt() && c::LockFileEx::option().is_some() {
+            LockTier::LockFileEx
+        } else {
+            LockTier::LockFile
+        }
+    }
+
+    /// The range that stands for the whole file.
+    ///
+    /// Neither function treats any range as the whole file, but locks may extend past the end of
+    /// the file, so the largest range covers it however much it grows. For `LockFile`, that's the
+    /// first 4 GiB, as files on 9x/ME can't be any larger than that anyway.
+    fn whole_file(self) -> LockRange {
+        match self {
+            LockTier::LockFileEx => LockRange::new(0, u64::MAX),
+            LockTier::LockFile => LockRange::new(0, u32::MAX as u64),
+        }
+    }
+}
+
+/// A range of bytes to lock, split into the halves that the locking functions take.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct LockRange {
+    offset_low: u32,
+    offset_high: u32,
+    len_low: u32,
+    len_high: u32,
+}
+
+impl LockRange {
+    fn new(offset: u64, len: u64) -> LockRange {
+        LockRange {
+            offset_low: offset as u32,
+            offset_high: (offset >> 32) as u32,
+            len_low: len as u32,
+            len_high: (len >> 32) as u32,
         }
     }
 }
 
+/// Whether `err` means that the file is locked by someone else.
+fn is_lock_violation(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(c::ERROR_LOCK_VIOLATION as i32)
+}
+
+/// The ways `unlink` can delete a file, from the most to the least capable.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DeleteTier {
+    /// `File::posix_delete`, which also ignores the read-only attribute.
+    Posix,
+    /// `File::win32_delete` (Vista+).
+    Win32,
+    /// `File::nt_delete` (NT 4 to XP).
+    Nt,
+    /// `DeleteFileW`, without a handle (9x/ME).
+    Path,
+}
+
+impl DeleteTier {
+    fn first_available() -> DeleteTier {
+        if !compat::is_windows_nt() {
+            DeleteTier::Path
+        } else if c::SetFileInformationByHandle::option().is_some() {
+            DeleteTier::Posix
+        } else if c::NtSetInformationFile::option().is_some() {
+            DeleteTier::Nt
+        } else {
+            DeleteTier::Path
+        }
+    }
+
+    /// The tier to step down to if this one isn't supported.
+    fn fallback(self) -> Option<DeleteTier> {
+        match self {
+            DeleteTier::Posix => Some(DeleteTier::Win32),
+            DeleteTier::Win32 | DeleteTier::Nt => Some(DeleteTier::Path),
+            DeleteTier::Path => None,
+        }
+    }
+}
+
+/// Whether `err` means that a way of deleting isn't supported by the OS or the file system,
+/// rather than that deleting this file failed.
+fn is_unsupported_delete(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error().map(|code| code as u32),
+        Some(c::ERROR_NOT_SUPPORTED | c::ERROR_INVALID_FUNCTION | c::ERROR_INVALID_PARAMETER)
+    )
+}
+
+#[cfg(test)]
+thread_local! {
+    /// The tier that the last successful `unlink` on this thread used.
+    static LAST_DELETE_TIER: crate::cell::Cell<Option<DeleteTier>> = crate::cell::Cell::new(None);
+}
+
 pub fn unlink(p: &Path) -> io::Result<()> {
+    // One `DeleteFileW` deletes most files. Only the ones it's denied, like read-only files or
+    // files that are still open, go through the tiers, which may still be able to delete them.
     let p_u16s = maybe_verbatim(p)?;
-    cvt(unsafe { c::DeleteFileW(p_u16s.as_ptr()) })?;
-    Ok(())
+    match cvt(unsafe { c::DeleteFileW(p_u16s.as_ptr()) }) {
+        Err(e) if is_denied_delete(&e) => unlink_from(p, DeleteTier::first_available()),
+        result => {
+            #[cfg(test)]
+            if result.is_ok() {
+                LAST_DELETE_TIER.with(|last| last.set(Some(DeleteTier::Path)));
+            }
+            result.map(drop)
+        }
+    }
+}
+
+/// Whether `err` means that `DeleteFileW` wasn't allowed to delete the file, rather than that it
+/// can't be deleted at all.
+fn is_denied_delete(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error().map(|code| code as u32),
+        Some(c::ERROR_ACCESS_DENIED | c::ERROR_SHARING_VIOLATION)
+    )
+}
+
+/// Deletes `p`, starting with the given tier and stepping down while it isn't supported.
+fn unlink_from(p: &Path, mut tier: DeleteTier) -> io::Result<()> {
+    let p_u16s = maybe_verbatim(p)?;
+
+    let mut file = None;
+    if tier != DeleteTier::Path {
+        // Without `FILE_FLAG_BACKUP_SEMANTICS`, directories can't be opened, so they can't be
+        // deleted by accident.
+        let mut opts = OpenOptions::new();
+        opts.access_mode(c::DELETE);
+        opts.custom_flags(c::FILE_FLAG_OPEN_REPARSE_POINT);
+        match File::open(p, &opts) {
+            Ok(f) => file = Some(f),
+            Err(e) if is_unsupported_delete(&e) => tier = DeleteTier::Path,
+            Err(e) => return Err(e),
+        }
+    }
+
+    loop {
+        let result = match (tier, &file) {
+            (DeleteTier::Posix, Some(file)) => file.posix_delete(),
+            (DeleteTier::Win32, Some(file)) => {
+                delete_clearing_readonly(&p_u16s, || file.win32_delete())
+            }
+            (DeleteTier::Nt, Some(file)) => delete_clearing_readonly(&p_u16s, || file.nt_delete()),
+            _ => delete_clearing_readonly(&p_u16s, || {
+                cvt(unsafe { c::DeleteFileW(p_u16s.as_ptr()) }).map(drop)
+            }),
+        };
+        match result {
+            Err(e) if is_unsupported_delete(&e) && tier.fallback().is_some() => {
+                tier = tier.fallback().unwrap();
+                if tier == DeleteTier::Path {
+                    file = None;
+                }
+            }
+            result => {
+                #[cfg(test)]
+                if result.is_ok() {
+                    LAST_DELETE_TIER.with(|last| last.set(Some(tier)));
+                }
+                return result;
+            }
+        }
+    }
+}
+
+/// Runs `delete`, and if that is denied because the file at `path` is read-only, clears the
+/// attribute and tries again, restoring it if that fails as well.
+///
+/// This way, all tiers can delete read-only files like POSIX deletes do.
+fn delete_clearing_readonly(
+    path: &[u16],
+    mut delete: impl FnMut() -> io::Result<()>,
+) -> io::Result<()> {
+    let err = match delete() {
+        Err(e) if e.raw_os_error() == Some(c::ERROR_ACCESS_DENIED as i32) => e,
+        result => return result,
+    };
+
+    unsafe {
+        let attrs = c::GetFileAttributesW(path.as_ptr());
+        if attrs == c::INVALID_FILE_ATTRIBUTES || attrs & c::FILE_ATTRIBUTE_READONLY == 0 {
+            return Err(err);
+        }
+        let writable = match attrs & !c::FILE_ATTRIBUTE_READONLY {
+            0 => c::FILE_ATTRIBUTE_NORMAL,
+            attrs => attrs,
+        };
+        if c::SetFileAttributesW(path.as_ptr(), writable) == c::FALSE {
+            return Err(err);
+        }
+        let result = delete();
+        if result.is_err() {
+            c::SetFileAttributesW(path.as_ptr(), attrs);
+        }
+        result
+    }
 }
 
 pub fn rename(old: &Path, new: &Path) -> io::Result<()> {
@@ -1130,13 +1704,12 @@ pub fn rename(old: &Path, new: &Path) -> io::Result<()> {
 
     match res {
         Err(e) if e.raw_os_error() == Some(c::ERROR_CALL_NOT_IMPLEMENTED as i32) => {
-            // 9x/ME doesn't support MoveFileEx, so we fall back to copy + delete and hope for the
-            // best
-            unsafe {
-                cvt(c::CopyFileW(old.as_ptr(), new.as_ptr(), c::TRUE))?;
-                cvt(c::DeleteFileW(old.as_ptr()))?;
-                Ok(())
-            }
+            // 9x/ME doesn't support MoveFileEx, so we fall back to an emulation based on
+            // `MoveFileA`. Note that replacing an existing file is not atomic there.
+            cvt(unsafe {
+                c::ansi::MoveFileExW(old.as_ptr(), new.as_ptr(), c::MOVEFILE_REPLACE_EXISTING)
+            })?;
+            Ok(())
         }
         Err(e) => Err(e),
         Ok(_) => Ok(()),
@@ -1149,16 +1722,158 @@ pub fn rmdir(p: &Path) -> io::Result<()> {
     Ok(())
 }
 
-/// Open a file or directory without following symlinks.
-fn open_link(path: &Path, access_mode: u32) -> io::Result<File> {
+/// The ways a directory can be opened by path.
+///
+/// Opening one relative to its parent is always done with `NtCreateFile` (see
+/// `open_link_no_reparse`), which needs no flag for directories. That's only ever needed by
+/// `remove_dir_all` though, which checks for it first.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DirOpenTier {
+    /// `NtCreateFile` with `FILE_OPEN_FOR_BACKUP_INTENT`, given the path's NT form (NT).
+    NtCreateFile,
+    /// `CreateFileW` with `FILE_FLAG_BACKUP_SEMANTICS`, which lets it open directories (NT).
+    BackupSemantics,
+    /// 9x/ME, where `CreateFile` can't open directories at all. Only files are opened, and
+    /// directories fail with `Unsupported`.
+    FilesOnly,
+}
+
+impl DirOpenTier {
+    fn first_available() -> Self {
+        if !compat::is_windows_nt() {
+            Self::FilesOnly
+        } else if c::NtCreateFile::option().is_some() {
+            Self::NtCreateFile
+        } else {
+            Self::BackupSemantics
+        }
+    }
+}
+
+/// Opens a file or directory for operations that need a handle to it, like querying its metadata.
+///
+/// `flags` are added to `FILE_FLAG_BACKUP_SEMANTICS`, and ignored on 9x/ME, which has neither.
+/// With `NtCreateFile`, `FILE_FLAG_OPEN_REPARSE_POINT` is the only one that may be passed.
+fn open_dir(path: &Path, access_mode: u32, flags: u32) -> io::Result<File> {
+    open_dir_with(DirOpenTier::first_available(), path, access_mode, flags)
+}
+
+fn open_dir_with(tier: DirOpenTier, path: &Path, access_mode: u32, flags: u32) -> io::Result<File> {
     let mut opts = OpenOptions::new();
     opts.access_mode(access_mode);
-    // `FILE_FLAG_BACKUP_SEMANTICS` allows opening directories.
-    // `FILE_FLAG_OPEN_REPARSE_POINT` opens a link instead of its target.
-    opts.custom_flags(c::FILE_FLAG_BACKUP_SEMANTICS | c::FILE_FLAG_OPEN_REPARSE_POINT);
+    match tier {
+        DirOpenTier::NtCreateFile => {
+            // Paths without an NT form, and a missing `NtCreateFile`, are left to `CreateFileW`.
+            if let Some(nt_path) = nt_path(path)? {
+                match open_dir_nt(&nt_path, access_mode, flags) {
+                    Err(e) if e.raw_os_error() == Some(c::ERROR_CALL_NOT_IMPLEMENTED as i32) => {}
+                    result => return result,
+                }
+            }
+            opts.custom_flags(c::FILE_FLAG_BACKUP_SEMANTICS | flags);
+        }
+        DirOpenTier::BackupSemantics => {
+            opts.custom_flags(c::FILE_FLAG_BACKUP_SEMANTICS | flags);
+        }
+        DirOpenTier::FilesOnly => {
+            // `CreateFile` fails with some unrelated error for directories here, like
+            // `ERROR_ACCESS_DENIED`, so they are told apart before even trying.
+            let wide = maybe_verbatim(path)?;
+            let attrs = unsafe { c::GetFileAttributesW(wide.as_ptr()) };
+            if attrs != c::INVALID_FILE_ATTRIBUTES && attrs & c::FILE_ATTRIBUTE_DIRECTORY != 0 {
+                return Err(io::const_io_error!(
+                    io::ErrorKind::Unsupported,
+                    "directories can't be opened on this version of Windows",
+                ));
+            }
+        }
+    }
     File::open(path, &opts)
 }
 
+/// Opens `path`, which is an NT path like `nt_path` returns, with `NtCreateFile`, the way
+/// `CreateFileW` would with `FILE_FLAG_BACKUP_SEMANTICS` and `flags`.
+fn open_dir_nt(path: &[u16], access_mode: u32, flags: u32) -> io::Result<File> {
+    let mut options = c::FILE_OPEN_FOR_BACKUP_INTENT | c::FILE_SYNCHRONOUS_IO_NONALERT;
+    if flags & c::FILE_FLAG_OPEN_REPARSE_POINT != 0 {
+        options |= c::FILE_OPEN_REPARSE_POINT;
+    }
+    unsafe {
+        let mut handle = ptr::null_mut();
+        let mut io_status = c::IO_STATUS_BLOCK::PENDING;
+        let mut name = c::UNICODE_STRING::from_ref(path);
+        let object = c::OBJECT_ATTRIBUTES {
+            ObjectName: &mut name,
+            // Win32 paths are case-insensitive.
+            Attributes: c::OBJ_CASE_INSENSITIVE,
+            ..c::OBJECT_ATTRIBUTES::default()
+        };
+        // `CreateFileW` always asks for these as well, and synchronous I/O needs `SYNCHRONIZE`.
+        let status = c::NtCreateFile::try_call(
+            &mut handle,
+            access_mode | c::SYNCHRONIZE | c::FILE_READ_ATTRIBUTES,
+            &object,
+            &mut io_status,
+            ptr::null_mut(),
+            0,
+            c::FILE_SHARE_DELETE | c::FILE_SHARE_READ | c::FILE_SHARE_WRITE,
+            c::FILE_OPEN,
+            options,
+            ptr::null_mut(),
+            0,
+        );
+        match status {
+            Some(status) => nt_create_result(status, handle),
+            None => Err(io::Error::from_raw_os_error(c::ERROR_CALL_NOT_IMPLEMENTED as _)),
+        }
+    }
+}
+
+/// The NT form of `path`, without a terminating null, like `\??\C:\dir` for `C:\dir`, which is
+/// what `NtCreateFile` takes. `None` for anything but drive, UNC and device paths.
+fn nt_path(path: &Path) -> io::Result<Option<Vec<u16>>> {
+    const SEP: u16 = b'\\' as u16;
+    const QUERY: u16 = b'?' as u16;
+    const DOT: u16 = b'.' as u16;
+    const COLON: u16 = b':' as u16;
+    // \??\
+    const NT_PREFIX: &[u16] = &[SEP, QUERY, QUERY, SEP];
+    // \??\UNC\
+    const NT_UNC_PREFIX: &[u16] =
+        &[SEP, QUERY, QUERY, SEP, b'U' as u16, b'N' as u16, b'C' as u16, SEP];
+
+    // `CreateFileW` takes NT paths as they are too. Like other paths, they can't contain nulls.
+    let mut wide = to_u16s(path)?;
+    if wide.starts_with(NT_PREFIX) {
+        wide.pop();
+        return Ok(Some(wide));
+    }
+    // Made absolute the way `CreateFileW` would, which leaves verbatim paths alone.
+    let absolute = super::path::absolute(path)?;
+    let absolute: Vec<u16> = absolute.as_os_str().encode_wide().collect();
+    let (prefix, rest) = match &absolute[..] {
+        // \\?\C:\dir => \??\C:\dir
+        [SEP, SEP, QUERY, SEP, rest @ ..] => (NT_PREFIX, rest),
+        // \\.\COM1 => \??\COM1
+        [SEP, SEP, DOT, SEP, rest @ ..] => (NT_PREFIX, rest),
+        // \\server\share => \??\UNC\server\share
+        [SEP, SEP, rest @ ..] => (NT_UNC_PREFIX, rest),
+        // C:\dir => \??\C:\dir
+        [_, COLON, SEP, ..] => (NT_PREFIX, &absolute[..]),
+        _ => return Ok(None),
+    };
+    let mut nt_path = Vec::with_capacity(prefix.len() + rest.len());
+    nt_path.extend_from_slice(prefix);
+    nt_path.extend_from_slice(rest);
+    Ok(Some(nt_path))
+}
+
+/// Open a file or directory without following symlinks.
+fn open_link(path: &Path, access_mode: u32) -> io::Result<File> {
+    // `FILE_FLAG_OPEN_REPARSE_POINT` opens a link instead of its target.
+    open_dir(path, access_mode, c::FILE_FLAG_OPEN_REPARSE_POINT)
+}
+
 pub fn remove_dir_all(path: &Path) -> io::Result<()> {
     // if the modern file/directory APIs are not available, we'll fall back to the old (unsafe, see
     // https://github.com/rust-lang/rust/pull/93112) directory removal implementation
@@ -1166,16 +1881,19 @@ pub fn remove_dir_all(path: &Path) -> io::Result<()> {
         || c::GetFileInformationByHandleEx::option().is_none()
         || c::SetFileInformationByHandle::option().is_none()
     {
-        let filetype = lstat(path)?.file_type();
-        if filetype.is_symlink() {
-            // On Windows symlinks to files and directories are removed differently.
-            // rmdir only deletes dir symlinks and junctions, not file symlinks.
-            return rmdir(path);
-        } else {
-            return remove_dir_all_recursive_old(path);
-        }
+        return remove_dir_all_by_path(path);
+    }
+
+    match remove_dir_all_by_handle(path) {
+        // Whether the handle-based functions work depends on the volume (network volumes vary
+        // a lot), so this is decided for every call. Whatever was removed until then is gone,
+        // and the rest is removed by path.
+        Err(e) if is_unsupported_delete(&e) => remove_dir_all_by_path(path),
+        result => result,
     }
+}
 
+fn remove_dir_all_by_handle(path: &Path) -> io::Result<()> {
     let file = open_link(path, c::DELETE | c::FILE_LIST_DIRECTORY)?;
 
     // Test if the file is not a directory or a symlink to a directory.
@@ -1275,29 +1993,77 @@ fn copy_handle(f: &File) -> mem::ManuallyDrop<File> {
     Ok(())
 }
 
-fn remove_dir_all_recursive_old(path: &Path) -> io::Result<()> {
+/// Removes a directory and everything in it by path, for systems or volumes that can't do it
+/// through handles (like 9x/ME, and FAT or network volumes on old NT).
+///
+/// Directories are listed with `FindFirstFileW`, so this is open to races with something
+/// replacing a directory by a link while it's being removed. Links are never followed though:
+/// directories that are reparse points are removed themselves, without looking inside.
+fn remove_dir_all_by_path(path: &Path) -> io::Result<()> {
+    let attrs = lstat(path)?.attributes;
+    if attrs & c::FILE_ATTRIBUTE_REPARSE_POINT != 0 {
+        // On Windows symlinks to files and directories are removed differently.
+        // rmdir only deletes dir symlinks and junctions, not file symlinks.
+        return rmdir(path);
+    }
+    remove_dir_all_recursive(path)
+}
+
+fn remove_dir_all_recursive(path: &Path) -> io::Result<()> {
     for child in readdir(path)? {
         let child = child?;
-        let child_type = child.file_type()?;
-        if child_type.is_dir() {
-            remove_dir_all_recursive_old(&child.path())?;
-        } else if child_type.is_symlink_dir() {
-            rmdir(&child.path())?;
+        let child_path = child.path();
+        let attrs = child.metadata()?.attributes;
+        if attrs & c::FILE_ATTRIBUTE_DIRECTORY == 0 {
+            retry_delete(|| unlink(&child_path))?;
+        } else if attrs & c::FILE_ATTRIBUTE_REPARSE_POINT != 0 {
+            retry_delete(|| rmdir_clearing_readonly(&child_path))?;
         } else {
-            unlink(&child.path())?;
+            remove_dir_all_recursive(&child_path)?;
         }
     }
-    rmdir(path)
+    retry_delete(|| rmdir_clearing_readonly(path))
+}
+
+fn rmdir_clearing_readonly(p: &Path) -> io::Result<()> {
+    let p = maybe_verbatim(p)?;
+    delete_clearing_readonly(&p, || cvt(unsafe { c::RemoveDirectoryW(p.as_ptr()) }).map(drop))
+}
+
+/// Runs `delete` until it succeeds, or fails for any reason but something else still using the
+/// file, in which case it gives up after a few tries.
+///
+/// On NT, that's a file that is still open, or a directory with files whose deletion is still
+/// pending. 9x/ME don't let open files be deleted at all, and deny access to them.
+fn retry_delete(mut delete: impl FnMut() -> io::Result<()>) -> io::Result<()> {
+    const MAX_RETRIES: u64 = 10;
+
+    let mut tries = 1;
+    loop {
+        let err = match delete() {
+            Err(e) if tries < MAX_RETRIES => e,
+            result => return result,
+        };
+        let transient = match err.raw_os_error().map(|code| code as u32) {
+            Some(c::ERROR_SHARING_VIOLATION | c::ERROR_DELETE_PENDING | c::ERROR_DIR_NOT_EMPTY) => {
+                true
+            }
+            Some(c::ERROR_ACCESS_DENIED) => !compat::is_windows_nt(),
+            _ => false,
+        };
+        if !transient {
+            return Err(err);
+        }
+        thread::sleep(Duration::from_millis(tries));
+        tries += 1;
+    }
 }
 
 pub fn readlink(path: &Path) -> io::Result<PathBuf> {
     // Open the link with no access mode, instead of generic read.
     // By default FILE_LIST_DIRECTORY is denied for the junction "C:\Documents and Settings", so
     // this is needed for a common case.
-    let mut opts = OpenOptions::new();
-    opts.access_mode(0);
-    opts.custom_flags(c::FILE_FLAG_OPEN_REPARSE_POINT | c::FILE_FLAG_BACKUP_SEMANTICS);
-    let file = File::open(path, &opts)?;
+    let file = open_dir(path, 0, c::FILE_FLAG_OPEN_REPARSE_POINT)?;
     file.readlink()
 }
 
@@ -1309,29 +2075,53 @@ pub fn symlink_inner(original: &Path, link: &Path, dir: bool) -> io::Result<()>
     let original = to_u16s(original)?;
     let link = maybe_verbatim(link)?;
     let flags = if dir { c::SYMBOLIC_LINK_FLAG_DIRECTORY } else { 0 };
-    // Formerly, symlink creation required the SeCreateSymbolicLink privilege. For the Windows 10
-    // Creators Update, Microsoft loosened this to allow unprivileged symlink creation if the
-    // computer is in Developer Mode, but SYMBOLIC_LINK_FLAG_ALLOW_UNPRIVILEGED_CREATE must be
-    // added to dwFlags to opt into this behaviour.
-    let result = cvt(unsafe {
-        c::CreateSymbolicLinkW(
-            link.as_ptr(),
-            original.as_ptr(),
-            flags | c::SYMBOLIC_LINK_FLAG_ALLOW_UNPRIVILEGED_CREATE,
-        ) as c::BOOL
-    });
-    if let Err(err) = result {
-        if err.raw_os_error() == Some(c::ERROR_INVALID_PARAMETER as i32) {
-            // Older Windows objects to SYMBOLIC_LINK_FLAG_ALLOW_UNPRIVILEGED_CREATE,
-            // so if we encounter ERROR_INVALID_PARAMETER, retry without that flag.
-            cvt(unsafe {
-                c::CreateSymbolicLinkW(link.as_ptr(), original.as_ptr(), flags) as c::BOOL
-            })?;
-        } else {
-            return Err(err);
-        }
+    create_symlink(compat::nt_build_number(), &UNPRIVILEGED_SYMLINK_REJECTED, flags, |flags| {
+        cvt(unsafe { c::CreateSymbolicLinkW(link.as_ptr(), original.as_ptr(), flags) as c::BOOL })
+            .map(drop)
+    })
+}
+
+/// The first build that knows `SYMBOLIC_LINK_FLAG_ALLOW_UNPRIVILEGED_CREATE`, Windows 10 1703.
+const UNPRIVILEGED_SYMLINK_BUILD: u32 = 15063;
+
+/// Set once `CreateSymbolicLinkW` has rejected `SYMBOLIC_LINK_FLAG_ALLOW_UNPRIVILEGED_CREATE`
+/// even though the build number says it's supported.
+static UNPRIVILEGED_SYMLINK_REJECTED: AtomicBool = AtomicBool::new(false);
+
+/// Creates a symlink using `create`, which is passed the flags for `CreateSymbolicLinkW`.
+///
+/// Formerly, symlink creation required the SeCreateSymbolicLink privilege. For the Windows 10
+/// Creators Update, Microsoft loosened this to allow unprivileged symlink creation if the
+/// computer is in Developer Mode, but SYMBOLIC_LINK_FLAG_ALLOW_UNPRIVILEGED_CREATE must be
+/// added to dwFlags to opt into this behaviour. Older Windows fails with `ERROR_INVALID_PARAMETER`
+/// if it's passed, so we only do that on `build` 15063 and later.
+///
+/// In case the build number is wrong, a flagged call that fails with `ERROR_INVALID_PARAMETER`
+/// is retried without the flag. If that gets past the parameter check, the flag is to blame, so
+/// `rejected` is set to stop passing it.
+fn create_symlink(
+    build: u32,
+    rejected: &AtomicBool,
+    flags: c::DWORD,
+    mut create: impl FnMut(c::DWORD) -> io::Result<()>,
+) -> io::Result<()> {
+    let is_invalid_parameter = |result: &io::Result<()>| match result {
+        Err(err) => err.raw_os_error() == Some(c::ERROR_INVALID_PARAMETER as i32),
+        Ok(()) => false,
+    };
+
+    if build < UNPRIVILEGED_SYMLINK_BUILD || rejected.load(Ordering::Relaxed) {
+        return create(flags);
     }
-    Ok(())
+    let result = create(flags | c::SYMBOLIC_LINK_FLAG_ALLOW_UNPRIVILEGED_CREATE);
+    if !is_invalid_parameter(&result) {
+        return result;
+    }
+    let result = create(flags);
+    if !is_invalid_parameter(&result) {
+        rejected.store(true, Ordering::Relaxed);
+    }
+    result
 }
 
 #[cfg(not(target_vendor = "uwp"))]
@@ -1381,57 +2171,14 @@ fn as_flag(self) -> u32 {
 }
 
 fn metadata(path: &Path, reparse: ReparsePoint) -> io::Result<FileAttr> {
-    let mut opts = OpenOptions::new();
-    // No read or write permissions are necessary
-    opts.access_mode(0);
-
-    if compat::is_windows_nt() {
-        opts.custom_flags(c::FILE_FLAG_BACKUP_SEMANTICS | reparse.as_flag());
-    }
-
-    // Attempt to open the file normally.
+    // Attempt to open the file normally, with no read or write permissions, as none are needed.
     // If that fails with `ERROR_SHARING_VIOLATION` then retry using `FindFirstFileW`.
     // If the fallback fails for any reason we return the original error.
-    match File::open(path, &opts) {
+    match open_dir(path, 0, reparse.as_flag()) {
         Ok(file) => file.file_attr(),
-        Err(_) if !compat::is_windows_nt() => {
-            let path = maybe_verbatim(path)?;
-            unsafe {
-                // Get at least the attributes in case `FindFirstFile` fails down below
-                let attr = c::GetFileAttributesW(path.as_ptr());
-                if attr == c::INVALID_FILE_ATTRIBUTES {
-                    return Err(crate::io::Error::last_os_error());
-                }
-
-                // Since we don't have access to `FILE_FLAG_BACKUP_SEMANTICS` on 9x/ME, fall back to
-                // `FindFirstFile` for directories
-                let mut find_data: c::WIN32_FIND_DATAW = mem::zeroed();
-                let handle = c::FindFirstFileW(path.as_ptr(), &mut find_data);
-
-                if handle == c::INVALID_HANDLE_VALUE {
-                    // You can't get info about the root directory via FindFirstFile, so in that
-                    // case and any other cases where FindFirstFile fails, fall back to the
-                    // already-retrieved attributes For directories, attributes are the only thing
-                    // we can get on 9x/ME
-                    return Ok(FileAttr {
-                        attributes: attr,
-                        creation_time: mem::zeroed(),
-                        last_access_time: mem::zeroed(),
-                        last_write_time: mem::zeroed(),
-                        file_size: 0,
-                        reparse_tag: 0,
-                        volume_serial_number: None,
-                        number_of_links: None,
-                        file_index: None,
-                    });
-                }
-
-                let attrs = FileAttr::from(find_data);
-                c::FindClose(handle);
-
-                return Ok(attrs);
-            }
-        }
+        // Directories can't be opened on 9x/ME, and files may be locked, so fall back to reading
+        // the metadata by path.
+        Err(_) if !compat::is_windows_nt() => metadata_by_path(path),
         Err(e)
             if [Some(c::ERROR_SHARING_VIOLATION as _), Some(c::ERROR_ACCESS_DENIED as _)]
                 .contains(&e.raw_os_error()) =>
@@ -1476,6 +2223,23 @@ fn metadata(path: &Path, reparse: ReparsePoint) -> io::Result<FileAttr> {
     }
 }
 
+/// Reads the metadata of `path` without opening it, which is all 9x/ME can do for directories.
+fn metadata_by_path(path: &Path) -> io::Result<FileAttr> {
+    let path = maybe_verbatim(path)?;
+    let mut data: c::WIN32_FILE_ATTRIBUTE_DATA = unsafe { mem::zeroed() };
+    let info = ptr::addr_of_mut!(data).cast();
+    unsafe {
+        let result = c::GetFileAttributesExW(path.as_ptr(), c::GetFileExInfoStandard, info);
+        // Without unicows, 98/ME only have a stub for `GetFileAttributesExW`.
+        if result == c::FALSE && c::GetLastError() == c::ERROR_CALL_NOT_IMPLEMENTED {
+            cvt(c::ansi::GetFileAttributesExW(path.as_ptr(), c::GetFileExInfoStandard, info))?;
+        } else {
+            cvt(result)?;
+        }
+    }
+    Ok(FileAttr::from(data))
+}
+
 pub fn set_perm(p: &Path, perm: FilePermissions) -> io::Result<()> {
     let p = maybe_verbatim(p)?;
     unsafe {
@@ -1487,7 +2251,9 @@ pub fn set_perm(p: &Path, perm: FilePermissions) -> io::Result<()> {
 fn get_path(f: &File) -> io::Result<PathBuf> {
     super::fill_utf16_buf(
         |buf, sz| unsafe {
-            c::GetFinalPathNameByHandleW(f.handle.as_raw_handle(), buf, sz, c::VOLUME_NAME_DOS)
+            let handle = f.handle.as_raw_handle();
+            // Fails with `ERROR_CALL_NOT_IMPLEMENTED` if it's missing.
+            c::GetFinalPathNameByHandleW::try_call(handle, buf, sz, c::VOLUME_NAME_DOS).unwrap_or(0)
         },
         |buf| PathBuf::from(OsString::from_wide(buf)),
     )
@@ -1495,22 +2261,111 @@ fn get_path(f: &File) -> io::Result<PathBuf> {
 
 pub fn canonicalize(p: &Path) -> io::Result<PathBuf> {
     if c::GetFinalPathNameByHandleW::option().is_some() {
-        let mut opts = OpenOptions::new();
         // No read or write permissions are necessary
-        opts.access_mode(0);
-        // This flag is so we can open directories too
-        opts.custom_flags(c::FILE_FLAG_BACKUP_SEMANTICS);
-        let f = File::open(p, &opts)?;
+        let f = open_dir(p, 0, 0)?;
         get_path(&f)
     } else {
-        // systems that don't support GetFinalPathNameByHandleW also don't support symlinks, so we
-        // fall back to using GetFullPathName.
-        let path = maybe_verbatim(p)?;
-        let mut file_part = ptr::null_mut();
-        super::fill_utf16_buf(
-            |buf, sz| unsafe { c::GetFullPathNameW(path.as_ptr(), sz, buf, &mut file_part) },
-            |buf| PathBuf::from(OsString::from_wide(buf)),
-        )
+        canonicalize_by_name(p)
+    }
+}
+
+/// Canonicalizes a path without `GetFinalPathNameByHandleW`, which needs Vista.
+///
+/// Systems that don't have it don't support symlinks either, so the path can be resolved textually:
+/// `GetFullPathNameW` makes it absolute, and looking up each component with `FindFirstFileW` gets
+/// its name as stored on disk, in its proper case and not as an 8.3 name. Like on newer systems,
+/// the result is a verbatim path, except on 9x/ME which know nothing about those.
+fn canonicalize_by_name(p: &Path) -> io::Result<PathBuf> {
+    // Fail just like opening the file does on newer systems if it doesn't exist.
+    stat(p)?;
+
+    let path = maybe_verbatim(p)?;
+    let absolute = super::fill_utf16_buf(
+        |buf, sz| unsafe { c::GetFullPathNameW(path.as_ptr(), sz, buf, ptr::null_mut()) },
+        |buf| buf.to_vec(),
+    )?;
+    let Some((mut canonical, rest)) = split_root(&absolute, compat::is_windows_nt()) else {
+        // Some other kind of device path, which has no components to look up.
+        return Ok(PathBuf::from(OsString::from_wide(&absolute)));
+    };
+
+    for component in rest.split(|&w| w == SEP).filter(|component| !component.is_empty()) {
+        if canonical.last() != Some(&SEP) {
+            canonical.push(SEP);
+        }
+        let start = canonical.len();
+        canonical.extend_from_slice(component);
+        // Wildcards would look up some other file. They aren't valid in file names, but are kept
+        // as they are in case a file system allows them after all.
+        if component.iter().any(|&w| w == b'*' as u16 || w == b'?' as u16) {
+            continue;
+        }
+        canonical.push(0);
+        unsafe {
+            let mut wfd: c::WIN32_FIND_DATAW = mem::zeroed();
+            let handle = c::FindFirstFileW(canonical.as_ptr(), &mut wfd);
+            canonical.pop();
+            // Listing the directory may not be allowed, in which case the name is kept as it is.
+            if handle != c::INVALID_HANDLE_VALUE {
+                c::FindClose(handle);
+                let name = &wfd.cFileName;
+                let len = name.iter().position(|&w| w == 0).unwrap_or(name.len());
+                canonical.truncate(start);
+                canonical.extend_from_slice(&name[..len]);
+            }
+        }
+    }
+    Ok(PathBuf::from(OsString::from_wide(&canonical)))
+}
+
+const SEP: u16 = b'\\' as u16;
+
+/// Splits an absolute path into its root, in the form `canonicalize` returns it, and the rest.
+///
+/// The root is either a drive, which is upper-cased like newer systems do, or a UNC share. Returns
+/// `None` for any other kind of path.
+fn split_root(path: &[u16], verbatim: bool) -> Option<(Vec<u16>, &[u16])> {
+    const COLON: u16 = b':' as u16;
+    // \\?\
+    const VERBATIM_PREFIX: &[u16] = &[SEP, SEP, b'?' as u16, SEP];
+    // \\.\
+    const DEVICE_PREFIX: &[u16] = &[SEP, SEP, b'.' as u16, SEP];
+    // \\?\UNC\
+    const UNC_PREFIX: &[u16] =
+        &[SEP, SEP, b'?' as u16, SEP, b'U' as u16, b'N' as u16, b'C' as u16, SEP];
+
+    let (unc, path) = if let Some(path) = path.strip_prefix(UNC_PREFIX) {
+        (true, path)
+    } else if let Some(path) =
+        path.strip_prefix(VERBATIM_PREFIX).or_else(|| path.strip_prefix(DEVICE_PREFIX))
+    {
+        (false, path)
+    } else if let Some(path) = path.strip_prefix(&[SEP, SEP][..]) {
+        (true, path)
+    } else {
+        (false, path)
+    };
+
+    if unc {
+        let mut parts = path.splitn(3, |&w| w == SEP);
+        let server = parts.next().filter(|server| !server.is_empty())?;
+        let share = parts.next().filter(|share| !share.is_empty())?;
+        let mut root = if verbatim { UNC_PREFIX.to_vec() } else { vec![SEP, SEP] };
+        root.extend_from_slice(server);
+        root.push(SEP);
+        root.extend_from_slice(share);
+        root.push(SEP);
+        Some((root, parts.next().unwrap_or(&[])))
+    } else {
+        let (drive, rest) = match path {
+            [drive, COLON] => (drive, &[][..]),
+            [drive, COLON, SEP, rest @ ..] => (drive, rest),
+            _ => return None,
+        };
+        let drive = u8::try_from(*drive).ok().filter(u8::is_ascii_alphabetic)?;
+        let mut root = if verbatim { VERBATIM_PREFIX.to_vec() } else { Vec::new() };
+        root.extend_from_slice(&[drive.to_ascii_uppercase() as u16, COLON, SEP]);
+        Some((root, rest))
     }
 }
 
@@ -1523,83 +2378,123 @@ pub fn copy(from: &Path, to: &Path) -> io::Result<u64> {
     // Unicows implements CopyFileExW similarly to other functions (convert to ANSI, call ...A API).
     // However, 9x/ME don't support CopyFileExA either. This means that we have to check both for
     // the API to exist *and* that we're running on NT.
-    if c::CopyFileExW::option().is_some() && compat::is_windows_nt() {
-        unsafe extern "system" fn callback(
-            _TotalFileSize: c::LARGE_INTEGER,
-            _TotalBytesTransferred: c::LARGE_INTEGER,
-            _StreamSize: c::LARGE_INTEGER,
-            StreamBytesTransferred: c::LARGE_INTEGER,
-            dwStreamNumber: c::DWORD,
-            _dwCallbackReason: c::DWORD,
-            _hSourceFile: c::HANDLE,
-            _hDestinationFile: c::HANDLE,
-            lpData: c::LPCVOID,
-        ) -> c::DWORD {
-            if dwStreamNumber == 1 {
-                *(lpData as *mut i64) = StreamBytesTransferred;
-            }
-            c::PROGRESS_CONTINUE
+    let available = c::CopyFileExW::option().is_some() && compat::is_windows_nt();
+    copy_with(
+        available,
+        || {
+            c::CopyFileExW::demote();
+        },
+        || copy_file_ex(&pfrom, &pto),
+        || copy_file(&pfrom, &pto),
+    )
+}
+
+/// Copies a file with `copy_ex` (`CopyFileExW`) if it's `available`, or with `copy` otherwise.
+///
+/// Even where `CopyFileExW` can be imported, it may only be a stub that fails with
+/// `ERROR_CALL_NOT_IMPLEMENTED`, like the one unicows provides. The file is then copied with
+/// `copy` instead, and `demote` is called so that `CopyFileExW` counts as missing from then on.
+/// Copies that fail with `ERROR_NOT_SUPPORTED` are retried with `copy` as well, but the file
+/// system may be to blame for that, so it doesn't rule out `copy_ex` for other files.
+fn copy_with(
+    available: bool,
+    demote: impl FnOnce(),
+    copy_ex: impl FnOnce() -> io::Result<u64>,
+    copy: impl FnOnce() -> io::Result<u64>,
+) -> io::Result<u64> {
+    if !available {
+        return copy();
+    }
+    match copy_ex() {
+        Err(err) if err.raw_os_error() == Some(c::ERROR_CALL_NOT_IMPLEMENTED as i32) => {
+            demote();
+            copy()
         }
+        Err(err) if err.raw_os_error() == Some(c::ERROR_NOT_SUPPORTED as i32) => copy(),
+        result => result,
+    }
+}
 
-        let mut size = 0i64;
-        cvt(unsafe {
-            c::CopyFileExW(
-                pfrom.as_ptr(),
-                pto.as_ptr(),
-                Some(callback),
-                &mut size as *mut _ as *mut _,
-                ptr::null_mut(),
-                0,
-            )
-        })?;
-        Ok(size as u64)
-    } else {
-        // NT 3.51 and earlier, or 9x/ME
+/// Copies `from` to `to` with `CopyFileExW`, returning the size of the file's data.
+fn copy_file_ex(from: &[u16], to: &[u16]) -> io::Result<u64> {
+    unsafe extern "system" fn callback(
+        _TotalFileSize: c::LARGE_INTEGER,
+        _TotalBytesTransferred: c::LARGE_INTEGER,
+        _StreamSize: c::LARGE_INTEGER,
+        StreamBytesTransferred: c::LARGE_INTEGER,
+        dwStreamNumber: c::DWORD,
+        _dwCallbackReason: c::DWORD,
+        _hSourceFile: c::HANDLE,
+        _hDestinationFile: c::HANDLE,
+        lpData: c::LPCVOID,
+    ) -> c::DWORD {
+        if dwStreamNumber == 1 {
+            *(lpData as *mut i64) = StreamBytesTransferred;
+        }
+        c::PROGRESS_CONTINUE
+    }
 
-        // If `CopyFileExW` is not available, we have to copy the file with the non-Ex API,
-        // then open it with `dwDesiredAccess = 0` (query attributes only),
-        // then use `GetFileSize` to retrieve the size
-        cvt(unsafe {
-            c::CopyFileW(
-                pfrom.as_ptr(),
-                pto.as_ptr(),
-                c::FALSE, // FALSE: allow overwriting
-            )
-        })?;
+    let mut size = 0i64;
+    cvt(unsafe {
+        // A missing `CopyFileExW` fails with `ERROR_CALL_NOT_IMPLEMENTED`, just like a stub.
+        c::CopyFileExW::try_call(
+            from.as_ptr(),
+            to.as_ptr(),
+            Some(callback),
+            &mut size as *mut _ as *mut _,
+            ptr::null_mut(),
+            0,
+        )
+        .unwrap_or(c::FALSE)
+    })?;
+    Ok(size as u64)
+}
 
-        let handle = unsafe {
-            c::CreateFileW(
-                pto.as_ptr(),
-                0,
-                c::FILE_SHARE_READ | c::FILE_SHARE_WRITE,
-                ptr::null_mut(),
-                c::OPEN_EXISTING,
-                0,
-                ptr::null_mut(),
-            )
-        };
+/// Copies `from` to `to` with `CopyFileW`, for NT 3.51 and earlier, or 9x/ME.
+fn copy_file(from: &[u16], to: &[u16]) -> io::Result<u64> {
+    // If `CopyFileExW` is not available, we have to copy the file with the non-Ex API,
+    // then open it with `dwDesiredAccess = 0` (query attributes only),
+    // then use `GetFileSize` to retrieve the size
+    cvt(unsafe {
+        c::CopyFileW(
+            from.as_ptr(),
+            to.as_ptr(),
+            c::FALSE, // FALSE: allow overwriting
+        )
+    })?;
 
-        let handle = if let Ok(handle) =
-            OwnedHandle::try_from(unsafe { HandleOrInvalid::from_raw_handle(handle) })
-        {
-            handle
-        } else {
-            return Err(Error::last_os_error());
-        };
+    let handle = unsafe {
+        c::CreateFileW(
+            to.as_ptr(),
+            0,
+            c::FILE_SHARE_READ | c::FILE_SHARE_WRITE,
+            ptr::null_mut(),
+            c::OPEN_EXISTING,
+            0,
+            ptr::null_mut(),
+        )
+    };
 
-        let mut upper_u32: u32 = 0;
-        let lower_u32 = unsafe { c::GetFileSize(handle.as_raw_handle(), &mut upper_u32) };
+    let handle = if let Ok(handle) =
+        OwnedHandle::try_from(unsafe { HandleOrInvalid::from_raw_handle(handle) })
+    {
+        handle
+    } else {
+        return Err(Error::last_os_error());
+    };
 
-        // 0xFFFFFFFF might be a valid length, so we have to check GetLastError
-        if lower_u32 == c::INVALID_FILE_SIZE {
-            let error = api::get_last_error();
-            if error.code != c::ERROR_SUCCESS {
-                return Err(Error::from_raw_os_error(error.code as i32));
-            }
-        }
+    let mut upper_u32: u32 = 0;
+    let lower_u32 = unsafe { c::GetFileSize(handle.as_raw_handle(), &mut upper_u32) };
 
-        Ok((upper_u32 as u64) << 32 | lower_u32 as u64)
+    // 0xFFFFFFFF might be a valid length, so we have to check GetLastError
+    if lower_u32 == c::INVALID_FILE_SIZE {
+        let error = api::get_last_error();
+        if error.code != c::ERROR_SUCCESS {
+            return Err(Error::from_raw_os_error(error.code as i32));
+        }
     }
+
+    Ok((upper_u32 as u64) << 32 | lower_u32 as u64)
 }
 
 #[allow(dead_code)]
@@ -1620,10 +2515,7 @@ fn symlink_junction_inner(original: &Path, junction: &Path) -> io::Result<()> {
     let d = DirBuilder::new();
     d.mkdir(junction)?;
 
-    let mut opts = OpenOptions::new();
-    opts.write(true);
-    opts.custom_flags(c::FILE_FLAG_OPEN_REPARSE_POINT | c::FILE_FLAG_BACKUP_SEMANTICS);
-    let f = File::open(junction, &opts)?;
+    let f = open_dir(junction, c::GENERIC_WRITE, c::FILE_FLAG_OPEN_REPARSE_POINT)?;
     let h = f.as_inner().as_raw_handle();
     unsafe {
         let mut data =
@@ -1707,27 +2599,10 @@ fn match_kind(e: crate::io::Error) -> io::Result<bool> {
     }
 
     // Open the file to ensure any symlinks are followed to their target.
-    let mut opts = OpenOptions::new();
     // No read, write, etc access rights are needed.
-    opts.access_mode(0);
-
-    if compat::is_windows_nt() {
-        // Backup semantics enables opening directories as well as files.
-        opts.custom_flags(c::FILE_FLAG_BACKUP_SEMANTICS);
-    } else {
-        let path = maybe_verbatim(path)?;
-        let attr = unsafe { c::GetFileAttributesW(path.as_ptr()) };
-        if attr == c::INVALID_FILE_ATTRIBUTES {
-            return match_kind(io::Error::last_os_error());
-        } else {
-            // can't open a directory on 9x/ME anyways
-            if attr & c::FILE_ATTRIBUTE_DIRECTORY != 0 {
-                return Ok(true);
-            }
-        }
-    }
-
-    match File::open(path, &opts) {
+    match open_dir(path, 0, 0) {
+        // Only directories that exist can't be opened on 9x/ME.
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => Ok(true),
         Err(e) => match_kind(e),
         // The file was opened successfully therefore it must exist,
         Ok(_) => Ok(true),
@@ -0,0 +1,953 @@
+use super::{
+    canonicalize, canonicalize_by_name, copy_file, copy_with, create_no_reparse, create_symlink,
+    lstat, metadata_by_path, nt_path, open_dir, open_dir_with, open_link_no_reparse, readlink,
+    remove_dir_all_by_path, retry_delete, split_root, symlink_junction, unlink, unlink_from,
+    with_ansi_find, DeleteTier, DirOpenTier, FatTime, File, FileAttr, FileTimes, LockRange,
+    LockTier, OpenOptions, LAST_DELETE_TIER,
+};
+use crate::cell::Cell;
+use crate::ffi::OsString;
+use crate::fs;
+use crate::io::{Error, ErrorKind, Result, SeekFrom};
+use crate::mem;
+use crate::os::windows::ffi::{OsStrExt, OsStringExt};
+use crate::os::windows::fs::OpenOptionsExt;
+use crate::os::windows::io::AsRawHandle;
+use crate::path::{Path, PathBuf};
+use crate::sync::atomic::{AtomicBool, Ordering};
+use crate::sys::{c, compat, cvt, time, to_u16s};
+use crate::sys_common::io::test::tmpdir;
+use crate::sys_common::{AsInner, IntoInner};
+use crate::thread;
+use crate::time::{Duration, SystemTime};
+
+const SECOND: u64 = 10_000_000;
+
+#[test]
+fn fat_time_resolution() {
+    // Some point in 2000, which FAT can represent.
+    let t = 125_911_584_000_000_000;
+
+    assert!(FatTime::Modified.stored(t + SECOND / 2, t + 2 * SECOND));
+    assert!(FatTime::Modified.stored(t, t));
+    assert!(!FatTime::Modified.stored(t, t + 2 * SECOND));
+    assert!(!FatTime::Modified.stored(t, 0));
+
+    assert!(FatTime::Created.stored(t + 50_000, t));
+    assert!(!FatTime::Created.stored(t + 200_000, t));
+    assert!(!FatTime::Created.stored(t, 0));
+
+    assert!(FatTime::Accessed.stored(t + 23 * 60 * 60 * SECOND, t));
+    assert!(!FatTime::Accessed.stored(t + 25 * 60 * 60 * SECOND, t));
+    assert!(!FatTime::Accessed.stored(t, 0));
+}
+
+fn filetime(t: u64) -> c::FILETIME {
+    c::FILETIME { dwLowDateTime: t as u32, dwHighDateTime: (t >> 32) as u32 }
+}
+
+#[test]
+fn missing_fat_times_are_unsupported() {
+    // Some point in 2000, like above.
+    let t = 125_911_584_000_000_000;
+
+    let mut find_data: c::WIN32_FIND_DATAW = unsafe { mem::zeroed() };
+    find_data.ftLastWriteTime = filetime(t);
+    let mut attribute_data: c::WIN32_FILE_ATTRIBUTE_DATA = unsafe { mem::zeroed() };
+    attribute_data.ftLastWriteTime = filetime(t);
+
+    for attr in [FileAttr::from(find_data), FileAttr::from(attribute_data)] {
+        assert_eq!(attr.created().unwrap_err().kind(), ErrorKind::Unsupported);
+        assert_eq!(attr.accessed().unwrap_err().kind(), ErrorKind::Unsupported);
+        assert_eq!(attr.modified().unwrap(), time::SystemTime::from(filetime(t)));
+    }
+
+    // Stored times are still returned, even ones close to the epoch.
+    attribute_data.ftCreationTime = filetime(1);
+    attribute_data.ftLastAccessTime = filetime(t);
+    let attr = FileAttr::from(attribute_data);
+    assert_eq!(attr.created().unwrap(), time::SystemTime::from(filetime(1)));
+    assert_eq!(attr.accessed().unwrap(), time::SystemTime::from(filetime(t)));
+}
+
+fn times(secs: u64) -> FileTimes {
+    let t = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+    let mut times = FileTimes::default();
+    times.set_created(t.into_inner());
+    times.set_accessed(t.into_inner());
+    times.set_modified(t.into_inner());
+    times
+}
+
+#[test]
+fn verify_fat_times() {
+    let tmp = tmpdir();
+    let mut opts = OpenOptions::new();
+    opts.read(true);
+    opts.write(true);
+    opts.create(true);
+    let file = File::open(&tmp.join("file"), &opts).unwrap();
+
+    // 2000-01-01
+    let stored = times(946_684_800);
+    file.set_times(stored).unwrap();
+    file.verify_fat_times(&stored).unwrap();
+
+    // A time that didn't make it to the disk is reported as unsupported.
+    let err = file.verify_fat_times(&times(946_684_800 + 7 * 24 * 60 * 60)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+
+    // Times that weren't requested aren't checked.
+    file.verify_fat_times(&FileTimes::default()).unwrap();
+}
+
+const ALLOW_UNPRIVILEGED: u32 = c::SYMBOLIC_LINK_FLAG_ALLOW_UNPRIVILEGED_CREATE;
+
+/// Calls `create_symlink` with a stub that fails with `ERROR_INVALID_PARAMETER` if it's passed
+/// the unprivileged flag on a system that doesn't know it, and returns the flags of each call.
+fn symlink_calls(build: u32, knows_flag: bool, rejected: &AtomicBool) -> (Result<()>, Vec<u32>) {
+    let mut calls = Vec::new();
+    let result = create_symlink(build, rejected, c::SYMBOLIC_LINK_FLAG_DIRECTORY, |flags| {
+        calls.push(flags);
+        if flags & ALLOW_UNPRIVILEGED != 0 && !knows_flag {
+            Err(Error::from_raw_os_error(c::ERROR_INVALID_PARAMETER as i32))
+        } else {
+            Ok(())
+        }
+    });
+    (result, calls)
+}
+
+#[test]
+fn symlink_flag_by_build() {
+    let dir = c::SYMBOLIC_LINK_FLAG_DIRECTORY;
+    for build in [0, 1057, 2600, 7601, 14393, 15062] {
+        let rejected = AtomicBool::new(false);
+        let (result, calls) = symlink_calls(build, false, &rejected);
+        assert!(result.is_ok());
+        assert_eq!(calls, [dir], "build {build}");
+        assert!(!rejected.load(Ordering::Relaxed));
+    }
+    for build in [15063, 19045, 22631] {
+        let rejected = AtomicBool::new(false);
+        let (result, calls) = symlink_calls(build, true, &rejected);
+        assert!(result.is_ok());
+        assert_eq!(calls, [dir | ALLOW_UNPRIVILEGED], "build {build}");
+        assert!(!rejected.load(Ordering::Relaxed));
+    }
+}
+
+#[test]
+fn symlink_flag_misreported_build() {
+    // The system claims to be new enough, but doesn't know the flag.
+    let rejected = AtomicBool::new(false);
+    let dir = c::SYMBOLIC_LINK_FLAG_DIRECTORY;
+    let (result, calls) = symlink_calls(15063, false, &rejected);
+    assert!(result.is_ok());
+    assert_eq!(calls, [dir | ALLOW_UNPRIVILEGED, dir]);
+    assert!(rejected.load(Ordering::Relaxed));
+
+    // From then on, the flag isn't passed anymore.
+    let (result, calls) = symlink_calls(15063, false, &rejected);
+    assert!(result.is_ok());
+    assert_eq!(calls, [dir]);
+}
+
+#[test]
+fn symlink_invalid_parameter_without_flag() {
+    // If the call fails with `ERROR_INVALID_PARAMETER` anyway, the flag isn't to blame.
+    let rejected = AtomicBool::new(false);
+    let mut calls = 0;
+    let result = create_symlink(15063, &rejected, 0, |_| {
+        calls += 1;
+        Err(Error::from_raw_os_error(c::ERROR_INVALID_PARAMETER as i32))
+    });
+    assert_eq!(result.unwrap_err().raw_os_error(), Some(c::ERROR_INVALID_PARAMETER as i32));
+    assert_eq!(calls, 2);
+    assert!(!rejected.load(Ordering::Relaxed));
+
+    // Other errors aren't retried.
+    let result = create_symlink(15063, &rejected, 0, |_| {
+        calls += 1;
+        Err(Error::from_raw_os_error(c::ERROR_PRIVILEGE_NOT_HELD as i32))
+    });
+    assert_eq!(result.unwrap_err().raw_os_error(), Some(c::ERROR_PRIVILEGE_NOT_HELD as i32));
+    assert_eq!(calls, 3);
+}
+
+/// Runs `create_no_reparse` with an `NtCreateFile` that succeeds unless it's passed
+/// `OBJ_DONT_REPARSE` without `accepted`, returning the status and the attributes of each call.
+fn no_reparse_calls(build: u32, accepted: bool, rejected: &AtomicBool) -> (c::NTSTATUS, Vec<u32>) {
+    let mut calls = Vec::new();
+    let status = create_no_reparse(build, rejected, |attributes| {
+        calls.push(attributes);
+        if attributes & c::OBJ_DONT_REPARSE != 0 && !accepted {
+            c::STATUS_INVALID_PARAMETER
+        } else {
+            c::STATUS_SUCCESS
+        }
+    });
+    (status, calls)
+}
+
+#[test]
+fn dont_reparse_by_build() {
+    for build in [0, 2600, 7601, 9600] {
+        let rejected = AtomicBool::new(false);
+        let (status, calls) = no_reparse_calls(build, false, &rejected);
+        assert_eq!(status, c::STATUS_SUCCESS);
+        assert_eq!(calls, [0], "build {build}");
+    }
+    for build in [10240, 19045, 22631] {
+        let rejected = AtomicBool::new(false);
+        let (status, calls) = no_reparse_calls(build, true, &rejected);
+        assert_eq!(status, c::STATUS_SUCCESS);
+        assert_eq!(calls, [c::OBJ_DONT_REPARSE], "build {build}");
+        assert!(!rejected.load(Ordering::Relaxed));
+    }
+}
+
+#[test]
+fn dont_reparse_rejected() {
+    let rejected = AtomicBool::new(false);
+    let (status, calls) = no_reparse_calls(10240, false, &rejected);
+    assert_eq!(status, c::STATUS_SUCCESS);
+    assert_eq!(calls, [c::OBJ_DONT_REPARSE, 0]);
+    assert!(rejected.load(Ordering::Relaxed));
+
+    // The downgrade sticks, so there's only one call from then on.
+    let (status, calls) = no_reparse_calls(10240, false, &rejected);
+    assert_eq!(status, c::STATUS_SUCCESS);
+    assert_eq!(calls, [0]);
+}
+
+#[test]
+fn dont_reparse_invalid_parameter_without_attribute() {
+    let rejected = AtomicBool::new(false);
+    let mut calls = 0;
+    let status = create_no_reparse(10240, &rejected, |_| {
+        calls += 1;
+        c::STATUS_INVALID_PARAMETER
+    });
+    assert_eq!(status, c::STATUS_INVALID_PARAMETER);
+    assert_eq!(calls, 2);
+    assert!(!rejected.load(Ordering::Relaxed));
+}
+
+/// The tiers `unlink` can start with on this system.
+fn delete_tiers() -> Vec<DeleteTier> {
+    let mut tiers = vec![DeleteTier::Path];
+    if compat::is_windows_nt() && c::NtSetInformationFile::option().is_some() {
+        tiers.push(DeleteTier::Nt);
+    }
+    if compat::is_windows_nt() && c::SetFileInformationByHandle::option().is_some() {
+        tiers.extend([DeleteTier::Win32, DeleteTier::Posix]);
+    }
+    tiers
+}
+
+/// Deletes `path` starting with `tier`, and returns the tier that deleted it.
+fn unlink_with(path: &Path, tier: DeleteTier) -> Result<DeleteTier> {
+    LAST_DELETE_TIER.with(|last| last.set(None));
+    unlink_from(path, tier)?;
+    let last = LAST_DELETE_TIER.with(|last| last.get()).unwrap();
+    // POSIX deletes aren't supported by all file systems, e.g. FAT.
+    if tier == DeleteTier::Posix && last == DeleteTier::Win32 {
+        return Ok(tier);
+    }
+    Ok(last)
+}
+
+#[test]
+fn unlink_tiers() {
+    let tmp = tmpdir();
+    for tier in delete_tiers() {
+        let path = tmp.join(&format!("{tier:?}"));
+        fs::write(&path, b"").unwrap();
+        assert_eq!(unlink_with(&path, tier).unwrap(), tier);
+        assert!(!path.exists(), "{tier:?}");
+    }
+}
+
+#[test]
+fn unlink_plain_file_by_path() {
+    let tmp = tmpdir();
+    let path = tmp.join("plain");
+    fs::write(&path, b"").unwrap();
+    LAST_DELETE_TIER.with(|last| last.set(None));
+    unlink(&path).unwrap();
+    // Nothing stood in the way of `DeleteFileW`, so no handle was opened.
+    assert_eq!(LAST_DELETE_TIER.with(|last| last.get()), Some(DeleteTier::Path));
+    assert!(!path.exists());
+}
+
+#[test]
+fn unlink_denied_file_through_tiers() {
+    let tmp = tmpdir();
+    let path = tmp.join("readonly");
+    fs::write(&path, b"").unwrap();
+    let mut perms = fs::metadata(&path).unwrap().permissions();
+    perms.set_readonly(true);
+    fs::set_permissions(&path, perms).unwrap();
+    LAST_DELETE_TIER.with(|last| last.set(None));
+    unlink(&path).unwrap();
+    // `DeleteFileW` is denied, so the first available tier deletes it.
+    let first = DeleteTier::first_available();
+    let last = LAST_DELETE_TIER.with(|last| last.get()).unwrap();
+    assert!(last == first || first == DeleteTier::Posix && last == DeleteTier::Win32, "{last:?}");
+    assert!(!path.exists());
+}
+
+#[test]
+fn unlink_readonly() {
+    let tmp = tmpdir();
+    for tier in delete_tiers() {
+        let path = tmp.join(&format!("{tier:?}"));
+        fs::write(&path, b"").unwrap();
+        let mut perms = fs::metadata(&path).unwrap().permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(&path, perms).unwrap();
+
+        assert_eq!(unlink_with(&path, tier).unwrap(), tier);
+        assert!(!path.exists(), "{tier:?}");
+    }
+}
+
+#[test]
+fn unlink_open_file() {
+    let tmp = tmpdir();
+    for tier in delete_tiers() {
+        let path = tmp.join(&format!("{tier:?}"));
+        fs::write(&path, b"").unwrap();
+        let shared = fs::OpenOptions::new()
+            .read(true)
+            .share_mode(c::FILE_SHARE_READ | c::FILE_SHARE_WRITE | c::FILE_SHARE_DELETE)
+            .open(&path)
+            .unwrap();
+        // 9x/ME can't delete files that are open.
+        if !compat::is_windows_nt() {
+            assert!(unlink_with(&path, tier).is_err());
+            continue;
+        }
+        assert_eq!(unlink_with(&path, tier).unwrap(), tier);
+        // Only POSIX deletes remove the name right away, others once the last handle is closed.
+        drop(shared);
+        assert!(!path.exists(), "{tier:?}");
+    }
+}
+
+#[test]
+fn unlink_not_shared_for_delete() {
+    let tmp = tmpdir();
+    for tier in delete_tiers() {
+        let path = tmp.join(&format!("{tier:?}"));
+        fs::write(&path, b"").unwrap();
+        let mut perms = fs::metadata(&path).unwrap().permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(&path, perms).unwrap();
+        let open = fs::OpenOptions::new().read(true).share_mode(c::FILE_SHARE_READ).open(&path);
+
+        // The failure isn't taken as a reason to step down a tier, and the file stays read-only.
+        assert!(unlink_from(&path, tier).is_err());
+        assert!(fs::metadata(&path).unwrap().permissions().readonly(), "{tier:?}");
+        drop(open);
+        assert_eq!(unlink_with(&path, tier).unwrap(), tier);
+    }
+}
+
+#[test]
+fn unlink_directory() {
+    let tmp = tmpdir();
+    for tier in delete_tiers() {
+        let path = tmp.join(&format!("{tier:?}"));
+        fs::create_dir(&path).unwrap();
+        assert!(unlink_from(&path, tier).is_err());
+        assert!(path.is_dir(), "{tier:?}");
+    }
+}
+
+/// Checks that the fallback canonicalizes `path` to the same path as `GetFinalPathNameByHandleW`.
+fn chk_canonicalize(path: &Path) {
+    let by_handle = canonicalize(path).unwrap();
+    let by_name = canonicalize_by_name(path).unwrap();
+    assert_eq!(by_name, by_handle, "{path:?}");
+}
+
+fn short_path(path: &Path) -> Option<PathBuf> {
+    let path = to_u16s(path).unwrap();
+    let mut short = vec![0; 1024];
+    let len =
+        unsafe { c::GetShortPathNameW(path.as_ptr(), short.as_mut_ptr(), short.len() as u32) };
+    if len == 0 || len as usize >= short.len() {
+        return None;
+    }
+    short.truncate(len as usize);
+    Some(PathBuf::from(OsString::from_wide(&short)))
+}
+
+#[test]
+fn canonicalize_by_name_matches_by_handle() {
+    if c::GetFinalPathNameByHandleW::option().is_none() {
+        return;
+    }
+
+    let tmp = tmpdir();
+    let dir = tmp.join("Mixed Case Dir");
+    let file = dir.join("Some Longer File Name.Txt");
+    fs::create_dir(&dir).unwrap();
+    fs::write(&file, b"").unwrap();
+
+    chk_canonicalize(tmp.path());
+    chk_canonicalize(&dir);
+    chk_canonicalize(&file);
+    // Whichever way the path is spelled, it resolves to the names on disk.
+    chk_canonicalize(&tmp.join(r"MIXED CASE DIR\some longer file name.txt"));
+    chk_canonicalize(&tmp.join(r"mixed case dir\..\Mixed Case Dir\.\Some Longer File Name.Txt"));
+    chk_canonicalize(&tmp.join("mixed case dir/some longer file name.txt"));
+    chk_canonicalize(&tmp.join(r"mixed case dir\"));
+    if let Some(short) = short_path(&file).filter(|short| *short != file) {
+        chk_canonicalize(&short);
+    }
+
+    // The current directory of the drive the temporary directory is on, as drive relative path.
+    let drive = tmp.path().as_os_str().encode_wide().take(2).collect::<Vec<_>>();
+    if drive.get(1) == Some(&(b':' as u16)) {
+        chk_canonicalize(Path::new(&OsString::from_wide(&drive)));
+    }
+
+    // Administrative shares may well be turned off, but if not, UNC paths work the same.
+    if let [letter, ..] = drive[..] {
+        let share = format!(r"\\localhost\{}$", char::from_u32(letter as u32).unwrap());
+        // Everything after `C:\`.
+        let unc = Path::new(&share).join(tmp.path().components().skip(2).collect::<PathBuf>());
+        if canonicalize(&unc).is_ok() {
+            chk_canonicalize(&unc);
+        }
+    }
+
+    let err = canonicalize_by_name(&tmp.join("missing")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn canonical_roots() {
+    fn chk(path: &str, verbatim: bool, root: &str, rest: &str) {
+        let path: Vec<u16> = OsString::from(path).encode_wide().collect();
+        let (canonical, remaining) = split_root(&path, verbatim).unwrap();
+        assert_eq!(OsString::from_wide(&canonical), root, "{path:?}");
+        assert_eq!(OsString::from_wide(remaining), rest, "{path:?}");
+    }
+
+    chk(r"c:\dir\file", true, r"\\?\C:\", r"dir\file");
+    chk(r"c:\dir\file", false, r"C:\", r"dir\file");
+    chk(r"D:", true, r"\\?\D:\", "");
+    chk(r"\\?\c:\dir", true, r"\\?\C:\", "dir");
+    chk(r"\\.\c:\dir", true, r"\\?\C:\", "dir");
+    chk(r"\\server\share\dir", true, r"\\?\UNC\server\share\", "dir");
+    chk(r"\\server\share", false, r"\\server\share\", "");
+    chk(r"\\?\UNC\server\share\dir", false, r"\\server\share\", "dir");
+
+    let volume = r"\\?\Volume{00000000-0000-0000-0000-000000000000}\dir";
+    for path in [r"\\server", volume, r"\\.\pipe"] {
+        let path: Vec<u16> = OsString::from(path).encode_wide().collect();
+        assert!(split_root(&path, true).is_none(), "{path:?}");
+    }
+}
+
+/// What `read_dir` found for an entry: its name, 8.3 name, whether it's a directory, and its size.
+fn list_dir(path: &Path) -> Vec<(OsString, OsString, bool, u64)> {
+    let mut entries: Vec<_> = super::readdir(path)
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            let alternate = crate::sys::truncate_utf16_at_nul(&entry.data.cAlternateFileName);
+            let metadata = entry.metadata().unwrap();
+            assert_eq!(entry.file_type().unwrap(), metadata.file_type());
+            let is_dir = metadata.file_type().is_dir();
+            (entry.file_name(), OsString::from_wide(alternate), is_dir, metadata.size())
+        })
+        .collect();
+    entries.sort();
+    entries
+}
+
+#[test]
+fn read_dir_ansi_matches_wide() {
+    let tmp = tmpdir();
+    fs::write(tmp.join("short.txt"), b"short").unwrap();
+    fs::write(tmp.join("A Rather Long File Name.text"), b"a longer file").unwrap();
+    fs::create_dir(tmp.join("Some Directory")).unwrap();
+    fs::write(tmp.join(r"Some Directory\nested"), b"").unwrap();
+
+    let wide = list_dir(tmp.path());
+    let ansi = with_ansi_find(|| list_dir(tmp.path()));
+    assert_eq!(ansi, wide);
+    // `.` and `..` are skipped either way.
+    let names: Vec<_> = wide.iter().map(|(name, ..)| name.to_str().unwrap()).collect();
+    assert_eq!(names, ["A Rather Long File Name.text", "Some Directory", "short.txt"]);
+
+    let nested = tmp.join("Some Directory");
+    assert_eq!(with_ansi_find(|| list_dir(&nested)), list_dir(&nested));
+
+    for path in [tmp.join("missing"), tmp.join(r"short.txt\nested")] {
+        let wide = super::readdir(&path).unwrap_err();
+        let ansi = with_ansi_find(|| super::readdir(&path)).unwrap_err();
+        assert_eq!(ansi.raw_os_error(), wide.raw_os_error(), "{path:?}");
+    }
+}
+
+#[test]
+fn junctions_and_symlinks() {
+    let tmp = tmpdir();
+    let target = tmp.join("target");
+    let junction = tmp.join("junction");
+    fs::create_dir(&target).unwrap();
+    symlink_junction(&target, &junction).unwrap();
+
+    let plain = lstat(&target).unwrap().file_type();
+    assert!(plain.is_dir() && !plain.is_symlink() && !plain.is_junction());
+
+    // Junctions are links to directories like symlinks are, and are read as one.
+    let file_type = lstat(&junction).unwrap().file_type();
+    assert!(file_type.is_junction());
+    assert!(file_type.is_symlink_dir());
+    assert!(!file_type.is_dir());
+    assert_eq!(readlink(&junction).unwrap(), target);
+
+    // Without `GetFileInformationByHandleEx`, the tag comes from the reparse point itself.
+    let mut opts = OpenOptions::new();
+    opts.access_mode(0);
+    opts.custom_flags(c::FILE_FLAG_OPEN_REPARSE_POINT | c::FILE_FLAG_BACKUP_SEMANTICS);
+    let file = File::open(&junction, &opts).unwrap();
+    assert_eq!(file.reparse_tag().unwrap(), c::IO_REPARSE_TAG_MOUNT_POINT);
+
+    let symlink = tmp.join("symlink");
+    match crate::os::windows::fs::symlink_dir(&target, &symlink) {
+        // Creating symlinks may take privileges the test doesn't have.
+        Err(err) if err.raw_os_error() == Some(c::ERROR_PRIVILEGE_NOT_HELD as i32) => return,
+        result => result.unwrap(),
+    }
+    let file_type = lstat(&symlink).unwrap().file_type();
+    assert!(file_type.is_symlink_dir() && !file_type.is_junction());
+    assert_eq!(readlink(&symlink).unwrap(), target);
+    let file = File::open(&symlink, &opts).unwrap();
+    assert_eq!(file.reparse_tag().unwrap(), c::IO_REPARSE_TAG_SYMLINK);
+}
+
+const GIB: u64 = 1 << 30;
+
+/// Opens `path` for reading and writing, with `len` bytes and the file pointer at `pos`.
+fn file_at(path: &Path, len: u64, pos: u64) -> File {
+    let mut opts = OpenOptions::new();
+    opts.read(true);
+    opts.write(true);
+    opts.create(true);
+    let file = File::open(path, &opts).unwrap();
+    file.truncate(len).unwrap();
+    file.seek(SeekFrom::Start(pos)).unwrap();
+    file
+}
+
+fn position(file: &File) -> u64 {
+    file.seek(SeekFrom::Current(0)).unwrap()
+}
+
+#[test]
+fn truncate_by_seeking_restores_position() {
+    let tmp = tmpdir();
+    let file = file_at(&tmp.join("file"), 10, 3);
+    let handle = file.as_raw_handle();
+    let set_end_of_file = |handle| cvt(unsafe { c::SetEndOfFile(handle) }).map(drop);
+
+    File::truncate_by_seeking(handle, 100, set_end_of_file).unwrap();
+    assert_eq!((file.file_attr().unwrap().size(), position(&file)), (100, 3));
+    File::truncate_by_seeking(handle, 1, set_end_of_file).unwrap();
+    assert_eq!((file.file_attr().unwrap().size(), position(&file)), (1, 3));
+
+    // A failure to set the length is reported as such, and leaves the position alone.
+    let err = File::truncate_by_seeking(handle, 100, |_| {
+        Err(Error::from_raw_os_error(c::ERROR_DISK_FULL as i32))
+    })
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    assert_eq!((file.file_attr().unwrap().size(), position(&file)), (1, 3));
+
+    // So does a size too large to seek to.
+    let err = File::truncate_by_seeking(handle, u64::MAX, set_end_of_file).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert_eq!((file.file_attr().unwrap().size(), position(&file)), (1, 3));
+}
+
+#[test]
+fn set_file_pointer_fallback_positions() {
+    let tmp = tmpdir();
+    let file = file_at(&tmp.join("file"), 0, 0);
+    let handle = file.as_raw_handle();
+
+    // The low half of these is `INVALID_SET_FILE_POINTER`, which must not be mistaken for an
+    // error just because one happened earlier.
+    for pos in [0xffff_ffff, 5 * GIB - 1, 4 * GIB, 3] {
+        let mut new_pos = 0;
+        unsafe {
+            c::SetLastError(c::ERROR_ACCESS_DENIED);
+            let ok = c::SetFilePointerEx::fallback(handle, pos as i64, &mut new_pos, c::FILE_BEGIN);
+            assert_ne!(ok, 0, "seeking to {pos}: {}", Error::last_os_error());
+        }
+        assert_eq!(new_pos as u64, pos);
+        assert_eq!(position(&file), pos);
+    }
+
+    let mut new_pos = 0;
+    let ok = unsafe { c::SetFilePointerEx::fallback(handle, -1, &mut new_pos, c::FILE_BEGIN) };
+    assert_eq!(ok, 0);
+    assert_eq!(Error::last_os_error().kind(), ErrorKind::InvalidInput);
+    assert_eq!(position(&file), 3);
+}
+
+#[test]
+fn truncate_across_4gib() {
+    let tmp = tmpdir();
+    let file = file_at(&tmp.join("file"), 0, 5);
+    let handle = file.as_raw_handle();
+    let set_end_of_file = |handle| cvt(unsafe { c::SetEndOfFile(handle) }).map(drop);
+    for len in [4 * GIB + 1, 4 * GIB - 1, 5 * GIB, 7] {
+        match File::truncate_by_seeking(handle, len, set_end_of_file) {
+            // FAT32 can't store files of 4 GiB and up.
+            Err(err) if err.kind() == ErrorKind::FileTooLarge && len >= 4 * GIB => continue,
+            // The file takes up all of its length on disk, which may well not be there.
+            Err(err) if err.kind() == ErrorKind::StorageFull => return,
+            result => result.unwrap(),
+        }
+        assert_eq!(file.file_attr().unwrap().size(), len);
+        assert_eq!(position(&file), 5);
+    }
+}
+
+#[test]
+fn lock_ranges() {
+    let range = LockRange::new(0x1_0000_0002, 0x3_0000_0004);
+    assert_eq!(range, LockRange { offset_low: 2, offset_high: 1, len_low: 4, len_high: 3 });
+
+    let whole = LockRange { offset_low: 0, offset_high: 0, len_low: u32::MAX, len_high: u32::MAX };
+    assert_eq!(LockTier::LockFileEx.whole_file(), whole);
+    // `LockFile` stays below 4 GiB.
+    assert_eq!(LockTier::LockFile.whole_file(), LockRange { len_high: 0, ..whole });
+}
+
+#[test]
+fn shared_and_exclusive_locks() {
+    if LockTier::first_available() != LockTier::LockFileEx {
+        return;
+    }
+    let tmp = tmpdir();
+    let path = tmp.join("file");
+    let (a, b) = (file_at(&path, 0, 0), file_at(&path, 0, 0));
+
+    a.lock().unwrap();
+    assert!(!b.try_lock().unwrap());
+    assert!(!b.try_lock_shared().unwrap());
+    a.unlock().unwrap();
+
+    a.lock_shared().unwrap();
+    assert!(b.try_lock_shared().unwrap());
+    assert!(!b.try_lock().unwrap());
+    a.unlock().unwrap();
+    assert!(!b.try_lock().unwrap());
+    b.unlock().unwrap();
+    assert!(b.try_lock().unwrap());
+    b.unlock().unwrap();
+
+    // Unlocking a file that isn't locked fails.
+    let err = a.unlock().unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(c::ERROR_NOT_LOCKED as i32));
+}
+
+#[test]
+fn lock_file_locks_are_exclusive() {
+    // `LockFile` exists on NT as well, so the 9x/ME tier can be tested everywhere.
+    let tier = LockTier::LockFile;
+    let tmp = tmpdir();
+    let path = tmp.join("file");
+    let (a, b) = (file_at(&path, 0, 0), file_at(&path, 0, 0));
+
+    assert!(a.lock_with(tier, false, false).unwrap());
+    assert!(!b.lock_with(tier, false, false).unwrap());
+    assert!(!b.lock_with(tier, true, false).unwrap());
+    // The unlock has to match the range that was locked, or the file would stay locked.
+    a.unlock_with(tier).unwrap();
+    assert!(b.lock_with(tier, false, false).unwrap());
+    b.unlock_with(tier).unwrap();
+    // There was only one lock to release.
+    let err = b.unlock_with(tier).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(c::ERROR_NOT_LOCKED as i32));
+}
+
+#[test]
+fn locks_wait_for_unlock() {
+    let tmp = tmpdir();
+    let path = tmp.join("file");
+    for tier in [LockTier::first_available(), LockTier::LockFile] {
+        let (a, b) = (file_at(&path, 0, 0), file_at(&path, 0, 0));
+        assert!(a.lock_with(tier, true, false).unwrap());
+        let waiter = thread::spawn(move || {
+            b.lock_with(tier, true, true).unwrap();
+            b
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiter.is_finished(), "{tier:?}: locked a locked file");
+        a.unlock_with(tier).unwrap();
+        let b = waiter.join().unwrap();
+        assert!(!a.lock_with(tier, true, false).unwrap());
+        b.unlock_with(tier).unwrap();
+    }
+}
+
+fn os_error(code: u32) -> Result<u64> {
+    Err(Error::from_raw_os_error(code as i32))
+}
+
+#[test]
+fn copy_file_ex_stub() {
+    let tmp = tmpdir();
+    let (from, to) = (tmp.join("from"), tmp.join("to"));
+    fs::write(&from, b"hello").unwrap();
+    let (pfrom, pto) = (to_u16s(&from).unwrap(), to_u16s(&to).unwrap());
+
+    // The stub makes the copy fall back, and gets demoted.
+    let demotions = Cell::new(0);
+    let demote = || demotions.set(demotions.get() + 1);
+    let stub = || os_error(c::ERROR_CALL_NOT_IMPLEMENTED);
+    assert_eq!(copy_with(true, demote, stub, || copy_file(&pfrom, &pto)).unwrap(), 5);
+    assert_eq!(fs::read(&to).unwrap(), b"hello");
+    assert_eq!(demotions.get(), 1);
+}
+
+#[test]
+fn copy_file_ex_errors() {
+    // Unsupported copies fall back, but only that one.
+    let result = copy_with(true, || unreachable!(), || os_error(c::ERROR_NOT_SUPPORTED), || Ok(1));
+    assert_eq!(result.unwrap(), 1);
+
+    // Any other error is the copy's.
+    let result = copy_with(true, || unreachable!(), || os_error(c::ERROR_ACCESS_DENIED), || Ok(1));
+    assert_eq!(result.unwrap_err().raw_os_error(), Some(c::ERROR_ACCESS_DENIED as i32));
+
+    // Where `CopyFileExW` isn't available, it isn't called at all.
+    let result = copy_with(false, || unreachable!(), || unreachable!(), || Ok(1));
+    assert_eq!(result.unwrap(), 1);
+}
+
+fn set_readonly(path: &Path) {
+    let mut perm = fs::metadata(path).unwrap().permissions();
+    perm.set_readonly(true);
+    fs::set_permissions(path, perm).unwrap();
+}
+
+/// Builds a tree `depth` directories deep, with a few read-only files and directories on every
+/// level.
+fn deep_tree(root: &Path, depth: usize) {
+    let mut dir = root.to_owned();
+    for level in 0..depth {
+        fs::create_dir(&dir).unwrap();
+        for name in ["a", "b"] {
+            fs::write(dir.join(name), format!("{level}")).unwrap();
+        }
+        set_readonly(&dir.join("a"));
+        fs::create_dir(dir.join("empty")).unwrap();
+        set_readonly(&dir.join("empty"));
+        dir.push("sub");
+    }
+}
+
+#[test]
+fn remove_dir_all_by_path_deep() {
+    let tmp = tmpdir();
+    let root = tmp.join("root");
+    deep_tree(&root, 30);
+    remove_dir_all_by_path(&root).unwrap();
+    assert!(!root.exists());
+}
+
+#[test]
+fn remove_dir_all_by_path_keeps_link_targets() {
+    let tmp = tmpdir();
+    let target = tmp.join("target");
+    fs::create_dir(&target).unwrap();
+    fs::write(target.join("file"), b"kept").unwrap();
+
+    let root = tmp.join("root");
+    deep_tree(&root, 3);
+    symlink_junction(&target, &root.join("sub").join("junction")).unwrap();
+    remove_dir_all_by_path(&root).unwrap();
+    assert!(!root.exists());
+    assert_eq!(fs::read(target.join("file")).unwrap(), b"kept");
+
+    // A link passed in directly is removed, rather than what it points to.
+    let junction = tmp.join("junction");
+    symlink_junction(&target, &junction).unwrap();
+    remove_dir_all_by_path(&junction).unwrap();
+    assert!(lstat(&junction).is_err());
+    assert_eq!(fs::read(target.join("file")).unwrap(), b"kept");
+}
+
+#[test]
+fn retry_delete_waits_for_open_files() {
+    let tmp = tmpdir();
+    let file = tmp.join("file");
+    fs::write(&file, b"").unwrap();
+    // Without sharing deletes, the file can't be deleted until it's closed.
+    let open = fs::OpenOptions::new().read(true).share_mode(c::FILE_SHARE_READ).open(&file);
+    let mut open = Some(open.unwrap());
+    let mut attempts = 0;
+    retry_delete(|| {
+        attempts += 1;
+        let result = unlink(&file);
+        // Close it only once deleting it failed, so the retry is what deletes it.
+        if result.is_err() {
+            drop(open.take());
+        }
+        result
+    })
+    .unwrap();
+    assert_eq!(attempts, 2);
+    assert!(!file.exists());
+}
+
+#[test]
+fn open_dir_with_nt_create_file() {
+    if !compat::is_windows_nt() || c::NtCreateFile::option().is_none() {
+        return;
+    }
+    assert_eq!(DirOpenTier::first_available(), DirOpenTier::NtCreateFile);
+    let tmp = tmpdir();
+    let dir = open_dir_with(DirOpenTier::NtCreateFile, tmp.path(), 0, 0).unwrap();
+    assert!(dir.file_attr().unwrap().file_type().is_dir());
+
+    let file = tmp.join("file");
+    fs::write(&file, b"data").unwrap();
+    let opened = open_dir_with(DirOpenTier::NtCreateFile, &file, 0, 0).unwrap();
+    assert_eq!(opened.file_attr().unwrap().size(), 4);
+    let err = open_dir_with(DirOpenTier::NtCreateFile, &tmp.join("missing"), 0, 0).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn nt_paths() {
+    let nt = |path: &str| {
+        let nt_path = nt_path(Path::new(path)).unwrap()?;
+        Some(String::from_utf16(&nt_path).unwrap())
+    };
+    assert_eq!(nt(r"C:\dir\file").as_deref(), Some(r"\??\C:\dir\file"));
+    assert_eq!(nt(r"C:/dir/../file").as_deref(), Some(r"\??\C:\file"));
+    assert_eq!(nt(r"\\?\C:\dir").as_deref(), Some(r"\??\C:\dir"));
+    assert_eq!(nt(r"\??\C:\dir").as_deref(), Some(r"\??\C:\dir"));
+    assert_eq!(nt(r"\\server\share\dir").as_deref(), Some(r"\??\UNC\server\share\dir"));
+    assert_eq!(nt(r"\\?\UNC\server\share").as_deref(), Some(r"\??\UNC\server\share"));
+    assert_eq!(nt(r"\\.\COM1").as_deref(), Some(r"\??\COM1"));
+    // Relative paths are made absolute first.
+    assert!(nt("dir").unwrap().starts_with(r"\??\"));
+}
+
+/// Run by `open_dir_without_nt_create_file`, and does nothing otherwise.
+#[test]
+fn open_dir_blocked_child() {
+    if crate::env::var_os("RUST_TEST_COMPAT_BLOCK").is_none() {
+        return;
+    }
+    assert_eq!(DirOpenTier::first_available(), DirOpenTier::BackupSemantics);
+    // `NtCreateFile` can't be used at all, even when asked for.
+    let tmp = tmpdir();
+    let dir = open_dir_with(DirOpenTier::NtCreateFile, tmp.path(), 0, 0).unwrap();
+    assert!(dir.file_attr().unwrap().file_type().is_dir());
+    assert!(fs::metadata(tmp.path()).unwrap().is_dir());
+    assert!(fs::symlink_metadata(tmp.path()).unwrap().is_dir());
+}
+
+#[test]
+fn open_dir_without_nt_create_file() {
+    use crate::process::Command;
+
+    if !compat::is_windows_nt() {
+        return;
+    }
+    let output = Command::new(crate::env::current_exe().unwrap())
+        .args(["fs::tests::open_dir_blocked_child", "--test-threads=1"])
+        .env("RUST_TEST_COMPAT_BLOCK", "NtCreateFile")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains(" 1 passed"), "{stdout}");
+}
+
+#[test]
+fn open_dir_with_backup_semantics() {
+    if !compat::is_windows_nt() {
+        return;
+    }
+    let tmp = tmpdir();
+    let dir = open_dir_with(DirOpenTier::BackupSemantics, tmp.path(), 0, 0).unwrap();
+    assert!(dir.file_attr().unwrap().file_type().is_dir());
+}
+
+#[test]
+fn open_dir_relative_to_parent() {
+    if !compat::is_windows_nt() || c::NtCreateFile::option().is_none() {
+        return;
+    }
+    let tmp = tmpdir();
+    fs::create_dir(tmp.join("dir")).unwrap();
+    let parent = open_dir(tmp.path(), c::FILE_LIST_DIRECTORY, 0).unwrap();
+    let name: Vec<u16> = "dir".encode_utf16().collect();
+    let dir = open_link_no_reparse(&parent, &name, c::FILE_READ_ATTRIBUTES | c::SYNCHRONIZE);
+    assert!(dir.unwrap().file_attr().unwrap().file_type().is_dir());
+}
+
+#[test]
+fn open_dir_files_only() {
+    let tmp = tmpdir();
+    let err = open_dir_with(DirOpenTier::FilesOnly, tmp.path(), 0, 0).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    // Which is what 9x/ME read the metadata by path for.
+    assert!(metadata_by_path(tmp.path()).unwrap().file_type().is_dir());
+
+    let file = tmp.join("file");
+    fs::write(&file, b"data").unwrap();
+    let opened = open_dir_with(DirOpenTier::FilesOnly, &file, 0, 0).unwrap();
+    assert_eq!(opened.file_attr().unwrap().size(), 4);
+}
+
+#[test]
+fn identity_only_from_handles() {
+    let tmp = tmpdir();
+    let path = tmp.join("file");
+    fs::write(&path, b"data").unwrap();
+    let other = tmp.join("other");
+    fs::write(&other, b"data").unwrap();
+
+    let by_handle = fs::File::open(&path).unwrap().metadata().unwrap();
+    let by_handle = by_handle.as_inner();
+    let entry = fs::read_dir(tmp.path()).unwrap().map(|e| e.unwrap()).find(|e| e.path() == path);
+    let by_find = entry.unwrap().metadata().unwrap();
+    let by_path = metadata_by_path(&path).unwrap();
+
+    for attr in [by_find.as_inner(), &by_path] {
+        assert_eq!(attr.size(), by_handle.size());
+        assert_eq!(attr.modified_u64(), by_handle.modified_u64());
+        assert_eq!(attr.volume_serial_number(), None);
+        assert_eq!(attr.number_of_links(), None);
+        assert_eq!(attr.file_index(), None);
+    }
+
+    assert!(by_handle.volume_serial_number().is_some());
+    assert_eq!(by_handle.number_of_links(), Some(1));
+    if compat::is_windows_nt() {
+        let again = fs::metadata(&path).unwrap();
+        let other = fs::metadata(&other).unwrap();
+        assert!(by_handle.file_index().is_some());
+        assert_eq!(again.as_inner().file_index(), by_handle.file_index());
+        assert_ne!(other.as_inner().file_index(), by_handle.file_index());
+    } else {
+        assert_eq!(by_handle.file_index(), None);
+    }
+}
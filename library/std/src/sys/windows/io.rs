@@ -1,10 +1,15 @@
 use crate::marker::PhantomData;
 use crate::mem::size_of;
-use crate::os::windows::io::{AsHandle, AsRawHandle, BorrowedHandle};
+use crate::os::windows::io::{AsHandle, AsRawHandle, BorrowedHandle, VtSupport};
+use crate::ptr;
 use crate::slice;
-use crate::sys::c;
+use crate::sync::Mutex;
+use crate::sys::{c, compat};
 use core::ffi::c_void;
 
+#[cfg(test)]
+mod tests;
+
 #[derive(Copy, Clone)]
 #[repr(transparent)]
 pub struct IoSlice<'a> {
@@ -79,6 +84,109 @@ pub fn as_mut_slice(&mut self) -> &mut [u8] {
     }
 }
 
+/// The results of `console_vt_support` for the standard output and error handles, together with
+/// the handle they were determined for, as the standard handles can be changed.
+static STD_VT_SUPPORT: Mutex<[Option<(usize, VtSupport)>; 2]> = Mutex::new([None; 2]);
+
+/// Tries to enable virtual terminal sequences (ANSI escape codes) for the console output `handle`.
+///
+/// The console mode is left untouched if that fails, which is always the case before Windows 10
+/// 1511. On 9x/ME, this doesn't even try. The result is cached for the standard output and error
+/// handles, so asking again is cheap.
+pub fn console_vt_support(handle: BorrowedHandle<'_>) -> VtSupport {
+    let raw = handle.as_raw_handle();
+    let slot = [c::STD_OUTPUT_HANDLE, c::STD_ERROR_HANDLE]
+        .iter()
+        .position(|&std_handle| !raw.is_null() && unsafe { c::GetStdHandle(std_handle) } == raw);
+    let Some(slot) = slot else { return unsafe { probe_vt_support(raw) } };
+
+    let mut cache = STD_VT_SUPPORT.lock().unwrap_or_else(|e| e.into_inner());
+    match cache[slot] {
+        Some((cached, support)) if cached == raw as usize => support,
+        _ => {
+            let support = unsafe { probe_vt_support(raw) };
+            cache[slot] = Some((raw as usize, support));
+            support
+        }
+    }
+}
+
+unsafe fn probe_vt_support(handle: c::HANDLE) -> VtSupport {
+    let mut mode = 0;
+    if handle.is_null() || c::GetConsoleMode(handle, &mut mode) == 0 {
+        return VtSupport::NotConsole;
+    }
+    // The 9x console has no notion of VT sequences, and rejects unknown mode flags anyway.
+    if !compat::is_windows_nt() {
+        return VtSupport::Unsupported;
+    }
+    if mode & c::ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0 {
+        return VtSupport::Enabled;
+    }
+
+    if c::SetConsoleMode(handle, mode | c::ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0 {
+        VtSupport::Enabled
+    } else {
+        // Older consoles reject the unknown flag, but make sure that we didn't change anything.
+        c::SetConsoleMode(handle, mode);
+        VtSupport::Unsupported
+    }
+}
+
+/// What a handle refers to, as far as reading and writing it is concerned.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HandleKind {
+    Console,
+    Pipe,
+    Disk,
+    /// A character device other than a console, like `NUL`.
+    Char,
+    Unknown,
+}
+
+/// Determines what `handle` refers to.
+pub fn handle_kind(handle: BorrowedHandle<'_>) -> HandleKind {
+    let handle = handle.as_raw_handle();
+    if handle.is_null() {
+        return HandleKind::Unknown;
+    }
+    classify_handle(
+        || {
+            let mut mode = 0;
+            unsafe { c::GetConsoleMode(handle, &mut mode) != 0 }
+        },
+        || unsafe { c::GetFileType(handle) },
+        || {
+            let null = ptr::null_mut();
+            unsafe { c::PeekNamedPipe(handle, ptr::null_mut(), 0, null, null, null) != 0 }
+        },
+    )
+}
+
+/// Classifies a handle by the results of `GetConsoleMode`, `GetFileType` and `PeekNamedPipe`,
+/// which are only asked for as needed.
+///
+/// Only consoles have a console mode, so that one is trusted. Not having one doesn't rule out a
+/// console though, as reading the mode needs read access. `GetFileType` on the other hand can't
+/// be fully trusted on 9x/ME, which report some perfectly good pipes as `FILE_TYPE_UNKNOWN`.
+/// Those can still be peeked into, which nothing but a pipe can.
+fn classify_handle(
+    console_mode: impl FnOnce() -> bool,
+    file_type: impl FnOnce() -> u32,
+    peek: impl FnOnce() -> bool,
+) -> HandleKind {
+    if console_mode() {
+        return HandleKind::Console;
+    }
+    match file_type() {
+        c::FILE_TYPE_DISK => HandleKind::Disk,
+        c::FILE_TYPE_CHAR => HandleKind::Char,
+        c::FILE_TYPE_PIPE => HandleKind::Pipe,
+        _ if peek() => HandleKind::Pipe,
+        _ => HandleKind::Unknown,
+    }
+}
+
 pub fn is_terminal(h: &impl AsHandle) -> bool {
     unsafe { handle_is_console(h.as_handle()) }
 }
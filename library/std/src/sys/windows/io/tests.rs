@@ -0,0 +1,115 @@
+use super::{
+    classify_handle, console_vt_support, handle_kind, probe_vt_support, HandleKind,
+    STD_VT_SUPPORT,
+};
+use crate::fs;
+use crate::os::windows::io::{
+    AsHandle, AsRawHandle, BorrowedHandle, FromRawHandle, OwnedHandle, VtSupport,
+};
+use crate::ptr;
+use crate::sys::{c, to_u16s};
+use crate::sys_common::io::test::tmpdir;
+
+#[test]
+fn vt_support_pipe() {
+    let (read, write) = unsafe {
+        let mut read = ptr::null_mut();
+        let mut write = ptr::null_mut();
+        assert_ne!(c::CreatePipe(&mut read, &mut write, ptr::null_mut(), 0), c::FALSE);
+        (OwnedHandle::from_raw_handle(read), OwnedHandle::from_raw_handle(write))
+    };
+    assert_eq!(console_vt_support(read.as_handle()), VtSupport::NotConsole);
+    assert_eq!(console_vt_support(write.as_handle()), VtSupport::NotConsole);
+}
+
+#[test]
+fn vt_support_cached_for_std_handles() {
+    let stdout = unsafe { c::GetStdHandle(c::STD_OUTPUT_HANDLE) };
+    if stdout.is_null() || stdout == c::INVALID_HANDLE_VALUE {
+        return;
+    }
+    let handle = unsafe { BorrowedHandle::borrow_raw(stdout) };
+
+    let support = console_vt_support(handle);
+    let cached = STD_VT_SUPPORT.lock().unwrap()[0];
+    assert_eq!(cached, Some((stdout as usize, support)));
+    assert_eq!(console_vt_support(handle), support);
+}
+
+#[test]
+fn vt_support_restores_console_mode() {
+    // Only testable when running in a real console.
+    let name = to_u16s("CONOUT$").unwrap();
+    let handle = unsafe {
+        c::CreateFileW(
+            name.as_ptr(),
+            c::GENERIC_READ | c::GENERIC_WRITE,
+            c::FILE_SHARE_READ | c::FILE_SHARE_WRITE,
+            ptr::null(),
+            c::OPEN_EXISTING,
+            0,
+            ptr::null_mut(),
+        )
+    };
+    if handle == c::INVALID_HANDLE_VALUE {
+        return;
+    }
+    let handle = unsafe { OwnedHandle::from_raw_handle(handle) };
+    let raw = handle.as_handle().as_raw_handle();
+
+    let mut before = 0;
+    assert_ne!(unsafe { c::GetConsoleMode(raw, &mut before) }, c::FALSE);
+    let support = unsafe { probe_vt_support(raw) };
+    let mut after = 0;
+    assert_ne!(unsafe { c::GetConsoleMode(raw, &mut after) }, c::FALSE);
+
+    match support {
+        VtSupport::Enabled => {
+            assert_eq!(after, before | c::ENABLE_VIRTUAL_TERMINAL_PROCESSING);
+        }
+        VtSupport::Unsupported => assert_eq!(after, before),
+        VtSupport::NotConsole => panic!("CONOUT$ is not a console"),
+    }
+    unsafe { c::SetConsoleMode(raw, before) };
+}
+
+#[test]
+fn handle_kinds() {
+    let (read, write) = unsafe {
+        let mut read = ptr::null_mut();
+        let mut write = ptr::null_mut();
+        assert_ne!(c::CreatePipe(&mut read, &mut write, ptr::null_mut(), 0), c::FALSE);
+        (OwnedHandle::from_raw_handle(read), OwnedHandle::from_raw_handle(write))
+    };
+    let tmp = tmpdir();
+    let file = fs::File::create(tmp.join("file")).unwrap();
+    let nul = fs::OpenOptions::new().write(true).open("NUL").unwrap();
+
+    let expected = [
+        (read.as_handle(), HandleKind::Pipe),
+        (write.as_handle(), HandleKind::Pipe),
+        (file.as_handle(), HandleKind::Disk),
+        (nul.as_handle(), HandleKind::Char),
+    ];
+    for (handle, kind) in expected {
+        assert_eq!(handle_kind(handle), kind);
+        assert_eq!(handle_kind(handle), kind);
+    }
+
+    // Only testable when running in a real console.
+    if let Ok(console) = fs::OpenOptions::new().read(true).write(true).open("CONOUT$") {
+        assert_eq!(handle_kind(console.as_handle()), HandleKind::Console);
+    }
+}
+
+#[test]
+fn handle_kinds_on_9x() {
+    let never = || -> bool { panic!("asked needlessly") };
+    // A console mode settles it.
+    assert_eq!(classify_handle(|| true, || c::FILE_TYPE_CHAR, never), HandleKind::Console);
+    assert_eq!(classify_handle(|| false, || c::FILE_TYPE_CHAR, never), HandleKind::Char);
+    assert_eq!(classify_handle(|| false, || c::FILE_TYPE_PIPE, never), HandleKind::Pipe);
+    // Pipes that 9x/ME don't know the type of.
+    assert_eq!(classify_handle(|| false, || c::FILE_TYPE_UNKNOWN, || true), HandleKind::Pipe);
+    assert_eq!(classify_handle(|| false, || c::FILE_TYPE_UNKNOWN, || false), HandleKind::Unknown);
+}
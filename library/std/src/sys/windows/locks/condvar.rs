@@ -1,4 +1,4 @@
-use super::compat::{MutexKind, MUTEX_KIND};
+use super::compat::{mutex_kind, MutexKind};
 use crate::cell::UnsafeCell;
 use crate::io;
 use crate::mem::ManuallyDrop;
@@ -8,6 +8,7 @@
 use crate::sys::cvt;
 use crate::sys::locks::Mutex;
 use crate::sys::os;
+use crate::sys::windows::Deadline;
 use crate::sys_common::lazy_box::{LazyBox, LazyInit};
 use crate::time::Duration;
 
@@ -23,7 +24,7 @@ union CondvarImpl {
 impl Drop for CondvarImpl {
     fn drop(&mut self) {
         unsafe {
-            match MUTEX_KIND {
+            match mutex_kind() {
                 MutexKind::SrwLock => {}
                 MutexKind::CriticalSection | MutexKind::Legacy => {
                     cvt(c::CloseHandle(self.event)).unwrap();
@@ -46,7 +47,7 @@ pub const fn new() -> Condvar {
     pub unsafe fn wait(&self, mutex: &Mutex) {
         let inner = self.inner.deref();
 
-        match MUTEX_KIND {
+        match mutex_kind() {
             MutexKind::SrwLock => {
                 let mutex = mutex.inner.deref();
                 let r = c::SleepConditionVariableSRW(
@@ -69,33 +70,38 @@ pub unsafe fn wait(&self, mutex: &Mutex) {
 
     pub unsafe fn wait_timeout(&self, mutex: &Mutex, dur: Duration) -> bool {
         let inner = self.inner.deref();
+        let deadline = Deadline::new(dur);
 
-        match MUTEX_KIND {
+        match mutex_kind() {
             MutexKind::SrwLock => {
                 let mutex = mutex.inner.deref();
-                let r = c::SleepConditionVariableSRW(
-                    inner.srw.get(),
-                    mutex.srwlock.inner.get(),
-                    crate::sys::windows::dur2timeout(dur),
-                    0,
-                );
-                if r == 0 {
+                while let Some(timeout) = deadline.remaining_timeout() {
+                    let r = c::SleepConditionVariableSRW(
+                        inner.srw.get(),
+                        mutex.srwlock.inner.get(),
+                        timeout,
+                        0,
+                    );
+                    if r != 0 {
+                        return true;
+                    }
                     debug_assert_eq!(os::errno() as usize, c::ERROR_TIMEOUT as usize);
-                    false
-                } else {
-                    true
                 }
+                false
             }
             MutexKind::CriticalSection | MutexKind::Legacy => {
                 mutex.unlock();
-                let ret = match c::WaitForSingleObject(
-                    inner.event,
-                    crate::sys::windows::dur2timeout(dur),
-                ) {
-                    c::WAIT_OBJECT_0 => true,
-                    c::WAIT_TIMEOUT => false,
-                    _ => panic!("event wait failed: {}", io::Error::last_os_error()),
-                };
+                let mut ret = false;
+                while let Some(timeout) = deadline.remaining_timeout() {
+                    match c::WaitForSingleObject(inner.event, timeout) {
+                        c::WAIT_OBJECT_0 => {
+                            ret = true;
+                            break;
+                        }
+                        c::WAIT_TIMEOUT => {}
+                        _ => panic!("event wait failed: {}", io::Error::last_os_error()),
+                    }
+                }
                 mutex.lock();
                 ret
             }
@@ -107,7 +113,7 @@ pub fn notify_one(&self) {
         let inner = self.inner.deref();
 
         unsafe {
-            match MUTEX_KIND {
+            match mutex_kind() {
                 MutexKind::SrwLock => c::WakeConditionVariable(inner.srw.get()),
                 MutexKind::CriticalSection | MutexKind::Legacy => {
                     // this currently wakes up all threads, but spurious wakeups are allowed, so
@@ -123,7 +129,7 @@ pub fn notify_all(&self) {
         let inner = self.inner.deref();
 
         unsafe {
-            match MUTEX_KIND {
+            match mutex_kind() {
                 MutexKind::SrwLock => c::WakeAllConditionVariable(inner.srw.get()),
                 MutexKind::CriticalSection | MutexKind::Legacy => {
                     cvt(c::PulseEvent(inner.event)).unwrap();
@@ -136,12 +142,12 @@ pub fn notify_all(&self) {
 impl LazyInit for CondvarImpl {
     fn init() -> Box<Self> {
         Box::new(unsafe {
-            match MUTEX_KIND {
+            match mutex_kind() {
                 MutexKind::SrwLock => CondvarImpl {
                     srw: ManuallyDrop::new(UnsafeCell::new(c::CONDITION_VARIABLE_INIT)),
                 },
                 MutexKind::CriticalSection | MutexKind::Legacy => {
-                    let event = c::CreateEventA(
+                    let event = c::CreateEvent(
                         ptr::null_mut(),
                         c::TRUE, // manual reset event
                         c::FALSE,
@@ -1,6 +1,8 @@
 mod condvar;
 mod mutex;
 mod rwlock;
+#[cfg(test)]
+mod tests;
 pub use condvar::Condvar;
 pub use mutex::{compat, Mutex};
 pub use rwlock::RwLock;
@@ -14,7 +14,7 @@
 //! 3. While CriticalSection is fair and SRWLock is not, the current Rust policy
 //!    is that there are no guarantees of fairness.
 
-use self::compat::{MutexKind, MUTEX_KIND};
+use self::compat::{mutex_kind, MutexKind};
 use crate::cell::UnsafeCell;
 use crate::mem::ManuallyDrop;
 use crate::ops::Deref;
@@ -35,7 +35,7 @@ impl Drop for InnerMutex {
     #[inline]
     fn drop(&mut self) {
         unsafe {
-            match MUTEX_KIND {
+            match mutex_kind() {
                 MutexKind::SrwLock => ManuallyDrop::drop(&mut self.srwlock),
                 MutexKind::CriticalSection => {
                     if self.critical_section.destroy() {
@@ -75,7 +75,7 @@ pub fn lock(&self) {
         let m = self.inner.deref();
 
         unsafe {
-            match MUTEX_KIND {
+            match mutex_kind() {
                 MutexKind::SrwLock => m.srwlock.write(),
                 MutexKind::CriticalSection => {
                     m.critical_section.lock();
@@ -100,7 +100,7 @@ pub fn try_lock(&self) -> bool {
         let m = self.inner.deref();
 
         unsafe {
-            match MUTEX_KIND {
+            match mutex_kind() {
                 MutexKind::SrwLock => m.srwlock.try_write(),
                 MutexKind::CriticalSection => {
                     if !m.critical_section.try_lock() {
@@ -131,7 +131,7 @@ pub unsafe fn unlock(&self) {
         let m = self.inner.deref();
 
         unsafe {
-            match MUTEX_KIND {
+            match mutex_kind() {
                 MutexKind::SrwLock => m.srwlock.write_unlock(),
                 MutexKind::CriticalSection => {
                     *self.held.get() = false;
@@ -158,7 +158,7 @@ unsafe fn flag_locked(&self) -> bool {
 impl LazyInit for InnerMutex {
     fn init() -> Box<Self> {
         unsafe {
-            match MUTEX_KIND {
+            match mutex_kind() {
                 MutexKind::SrwLock => {
                     Box::new(InnerMutex { srwlock: ManuallyDrop::new(srwlock::SrwLock::new()) })
                 }
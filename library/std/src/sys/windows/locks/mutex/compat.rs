@@ -1,6 +1,10 @@
 use crate::sys::c;
+use crate::sys::compat::SrwFunctions;
 
-#[derive(Debug, PartialEq)]
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MutexKind {
     /// Win 7+ (Vista doesn't support the `Try*` APIs)
     SrwLock,
@@ -10,18 +14,28 @@ pub enum MutexKind {
     Legacy,
 }
 
-pub static mut MUTEX_KIND: MutexKind = MutexKind::SrwLock;
+/// The lock implementation picked for this system.
+///
+/// Every lock operation asks for this, so that the first ones pick it if `preload` hasn't yet.
+/// Reading it synchronizes with the loading of the functions it needs.
+#[inline]
+pub fn mutex_kind() -> MutexKind {
+    crate::sys::compat::windows_capabilities().mutex_kind
+}
 
-pub fn init() {
-    let kind = if c::TryAcquireSRWLockExclusive::option().is_some() {
+/// Picks the lock implementation for this system, given the SRW lock functions that were found.
+pub fn choose(srw: SrwFunctions) -> MutexKind {
+    pick(srw, c::TryEnterCriticalSection::option().is_some())
+}
+
+/// Picks the best lock implementation for the functions that were found.
+fn pick(srw: SrwFunctions, try_enter_critical_section: bool) -> MutexKind {
+    // Without the `Try*` functions, `try_lock` couldn't work.
+    if srw.locks && srw.try_locks {
         MutexKind::SrwLock
-    } else if c::TryEnterCriticalSection::option().is_some() {
+    } else if try_enter_critical_section {
         MutexKind::CriticalSection
     } else {
         MutexKind::Legacy
-    };
-
-    unsafe {
-        MUTEX_KIND = kind;
     }
 }
@@ -0,0 +1,17 @@
+use super::{pick, MutexKind};
+use crate::sys::compat::SrwFunctions;
+
+#[test]
+fn srw_locks_need_try_locks() {
+    let seven = SrwFunctions { locks: true, try_locks: true };
+    assert_eq!(pick(seven, true), MutexKind::SrwLock);
+
+    // `try_lock` would always fail on Vista.
+    let vista = SrwFunctions { locks: true, try_locks: false };
+    assert_eq!(pick(vista, true), MutexKind::CriticalSection);
+    assert_eq!(pick(vista, false), MutexKind::Legacy);
+
+    let xp = SrwFunctions { locks: false, try_locks: false };
+    assert_eq!(pick(xp, true), MutexKind::CriticalSection);
+    assert_eq!(pick(xp, false), MutexKind::Legacy);
+}
@@ -14,7 +14,7 @@ unsafe impl Sync for LegacyMutex {}
 impl LegacyMutex {
     #[inline]
     pub unsafe fn new() -> Self {
-        let handle = c::CreateMutexA(ptr::null_mut(), c::FALSE, ptr::null());
+        let handle = c::CreateMutex(ptr::null_mut(), c::FALSE, ptr::null());
 
         if handle.is_null() {
             panic!("failed creating mutex: {}", io::Error::last_os_error());
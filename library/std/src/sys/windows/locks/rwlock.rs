@@ -1,5 +1,5 @@
 use super::{
-    compat::{MutexKind, MUTEX_KIND},
+    compat::{mutex_kind, MutexKind},
     Mutex,
 };
 use crate::ops::Deref;
@@ -18,42 +18,42 @@ pub const fn new() -> RwLock {
     }
     #[inline]
     pub unsafe fn read(&self) {
-        match MUTEX_KIND {
+        match mutex_kind() {
             MutexKind::SrwLock => self.inner.inner.deref().srwlock.read(),
             MutexKind::CriticalSection | MutexKind::Legacy => self.inner.lock(),
         }
     }
     #[inline]
     pub unsafe fn try_read(&self) -> bool {
-        match MUTEX_KIND {
+        match mutex_kind() {
             MutexKind::SrwLock => self.inner.inner.deref().srwlock.try_read(),
             MutexKind::CriticalSection | MutexKind::Legacy => self.inner.try_lock(),
         }
     }
     #[inline]
     pub unsafe fn write(&self) {
-        match MUTEX_KIND {
+        match mutex_kind() {
             MutexKind::SrwLock => self.inner.inner.deref().srwlock.write(),
             MutexKind::CriticalSection | MutexKind::Legacy => self.inner.lock(),
         }
     }
     #[inline]
     pub unsafe fn try_write(&self) -> bool {
-        match MUTEX_KIND {
+        match mutex_kind() {
             MutexKind::SrwLock => self.inner.inner.deref().srwlock.try_write(),
             MutexKind::CriticalSection | MutexKind::Legacy => self.inner.try_lock(),
         }
     }
     #[inline]
     pub unsafe fn read_unlock(&self) {
-        match MUTEX_KIND {
+        match mutex_kind() {
             MutexKind::SrwLock => self.inner.inner.deref().srwlock.read_unlock(),
             MutexKind::CriticalSection | MutexKind::Legacy => self.inner.unlock(),
         }
     }
     #[inline]
     pub unsafe fn write_unlock(&self) {
-        match MUTEX_KIND {
+        match mutex_kind() {
             MutexKind::SrwLock => self.inner.inner.deref().srwlock.write_unlock(),
             MutexKind::CriticalSection | MutexKind::Legacy => self.inner.unlock(),
         }
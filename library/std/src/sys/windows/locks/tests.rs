@@ -0,0 +1,123 @@
+//! Runs std's lock tests again under the lock implementations for older systems. They run in child
+//! processes in which the functions the better implementations need are blocked, so that `preload`
+//! picks the older ones.
+
+use super::compat::{mutex_kind, MutexKind};
+use crate::env;
+use crate::process::Command;
+use crate::sync::atomic::{AtomicBool, Ordering};
+use crate::sync::{Arc, Mutex, RwLock};
+use crate::thread;
+
+/// Set for the child processes, to the `MutexKind` they are expected to use.
+const KIND_VAR: &str = "RUST_TEST_MUTEX_KIND";
+
+/// Checks that a child process got the lock implementation it was started for, and does nothing
+/// otherwise.
+#[test]
+fn forced_kind() {
+    let Ok(kind) = env::var(KIND_VAR) else { return };
+    assert_eq!(format!("{:?}", mutex_kind()), kind);
+}
+
+/// Run along with the lock tests in the child processes.
+#[test]
+fn uncontended_try_locks() {
+    let mutex = Mutex::new(());
+    drop(mutex.try_lock().unwrap());
+    let rwlock = RwLock::new(());
+    drop(rwlock.try_write().unwrap());
+    let (a, b) = (rwlock.try_read().unwrap(), rwlock.try_read());
+    // Readers of a lock that isn't an SRW lock exclude each other.
+    assert_eq!(b.is_ok(), mutex_kind() == MutexKind::SrwLock);
+    drop((a, b));
+}
+
+fn run_lock_tests(kind: MutexKind, blocked: &str) {
+    let output = Command::new(env::current_exe().unwrap())
+        .args([
+            "sync::mutex::",
+            "sync::condvar::",
+            "sync::rwlock::",
+            "locks::tests::forced_kind",
+            "locks::tests::uncontended_try_locks",
+        ])
+        .env("RUST_TEST_COMPAT_BLOCK", blocked)
+        .env(KIND_VAR, format!("{kind:?}"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{kind:?}: {stdout}{stderr}");
+    assert!(!stdout.contains(" 0 passed"), "{kind:?}: {stdout}");
+}
+
+#[test]
+fn lock_tests_on_vista() {
+    // Vista has SRW locks, but not the `TryAcquire*` functions.
+    run_lock_tests(
+        MutexKind::CriticalSection,
+        "TryAcquireSRWLockExclusive,TryAcquireSRWLockShared",
+    );
+}
+
+#[test]
+fn lock_tests_with_critical_sections() {
+    run_lock_tests(MutexKind::CriticalSection, "AcquireSRWLockExclusive");
+}
+
+#[test]
+fn lock_tests_with_legacy_mutexes() {
+    run_lock_tests(MutexKind::Legacy, "AcquireSRWLockExclusive,TryEnterCriticalSection");
+}
+
+/// Set for the child process of `first_locks_race_kind_selection`.
+const RACE_VAR: &str = "RUST_TEST_LOCK_RACE";
+
+/// Run by `first_locks_race_kind_selection`, and does nothing otherwise.
+#[test]
+fn racing_first_locks() {
+    if env::var_os(RACE_VAR).is_none() {
+        return;
+    }
+
+    const THREADS: usize = 16;
+    const ROUNDS: usize = 1000;
+    let mutex = Arc::new(Mutex::new(0));
+    let go = Arc::new(AtomicBool::new(false));
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let (mutex, go) = (mutex.clone(), go.clone());
+            thread::spawn(move || {
+                while !go.load(Ordering::Acquire) {
+                    thread::yield_now();
+                }
+                for _ in 0..ROUNDS {
+                    *mutex.lock().unwrap() += 1;
+                }
+            })
+        })
+        .collect();
+
+    // Like a binary without CRT initialization, the threads' first locks have to pick the kind.
+    // Nothing else locks meanwhile: the harness runs only this test, and waits for it.
+    unsafe { crate::sys::compat::unload_mutex_kind() };
+    go.store(true, Ordering::Release);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(*mutex.lock().unwrap(), THREADS * ROUNDS);
+}
+
+#[test]
+fn first_locks_race_kind_selection() {
+    let output = Command::new(env::current_exe().unwrap())
+        .args(["locks::tests::racing_first_locks", "--test-threads=1"])
+        .env(RACE_VAR, "1")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stdout}{stderr}");
+    assert!(stdout.contains(" 1 passed"), "{stdout}");
+}
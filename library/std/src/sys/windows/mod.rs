@@ -1,5 +1,6 @@
 #![allow(missing_docs, nonstandard_style)]
 
+use crate::cmp;
 use crate::ffi::{OsStr, OsString};
 use crate::io::ErrorKind;
 use crate::mem::MaybeUninit;
@@ -25,6 +26,7 @@
 pub mod net;
 pub mod os;
 pub mod os_str;
+pub mod overlapped;
 pub mod path;
 pub mod pipe;
 pub mod process;
@@ -45,6 +47,45 @@
 }
 
 mod api;
+#[cfg(test)]
+mod tests;
+
+#[cfg(test)]
+pub mod test {
+    use super::c;
+    use crate::env;
+    use crate::process::Command;
+
+    /// The number of handles the process has open, or `None` before XP SP1.
+    pub fn handle_count() -> Option<u32> {
+        let get_process_handle_count = c::GetProcessHandleCount::option()?;
+        let mut count = 0;
+        let ok = unsafe { get_process_handle_count(c::GetCurrentProcess(), &mut count) };
+        (ok != 0).then_some(count)
+    }
+
+    const ALONE_VAR: &str = "RUST_TEST_ALONE";
+
+    /// Runs `body` in a child process that runs no other test, for tests that look at state of
+    /// the whole process, like its handle count, which tests running in parallel would disturb.
+    ///
+    /// `name` is the path of the calling test, e.g. `process::tests::spawn`, which the child is
+    /// told to run again. It then runs `body` instead of starting another child.
+    pub fn run_alone(name: &str, body: impl FnOnce()) {
+        if env::var_os(ALONE_VAR).is_some_and(|alone| alone == name) {
+            return body();
+        }
+        let output = Command::new(env::current_exe().unwrap())
+            .args([name, "--test-threads=1"])
+            .env(ALONE_VAR, name)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{stdout}{stderr}");
+        assert!(stdout.contains(" 1 passed"), "{stdout}");
+    }
+}
 
 /// Map a Result<T, WinError> to io::Result<T>.
 trait IoResult<T> {
@@ -93,6 +134,8 @@ pub fn decode_error_kind(errno: i32) -> ErrorKind {
         c::ERROR_NO_DATA => return BrokenPipe,
         c::ERROR_INVALID_NAME | c::ERROR_BAD_PATHNAME => return InvalidFilename,
         c::ERROR_INVALID_PARAMETER => return InvalidInput,
+        // Set by the ANSI fallbacks for strings that the code page can't represent.
+        c::ERROR_NO_UNICODE_TRANSLATION => return InvalidInput,
         c::ERROR_NOT_ENOUGH_MEMORY | c::ERROR_OUTOFMEMORY => return OutOfMemory,
         c::ERROR_SEM_TIMEOUT
         | c::WAIT_TIMEOUT
@@ -113,12 +156,18 @@ pub fn decode_error_kind(errno: i32) -> ErrorKind {
         c::ERROR_CALL_NOT_IMPLEMENTED => return Unsupported,
         c::ERROR_HOST_UNREACHABLE => return HostUnreachable,
         c::ERROR_NETWORK_UNREACHABLE => return NetworkUnreachable,
+        // What the Winsock errors below become when they come out of overlapped socket I/O,
+        // which reports Win32 errors.
+        c::ERROR_CONNECTION_REFUSED => return ConnectionRefused,
+        c::ERROR_CONNECTION_ABORTED => return ConnectionAborted,
+        c::ERROR_NETNAME_DELETED => return ConnectionReset,
         c::ERROR_DIRECTORY => return NotADirectory,
         c::ERROR_DIRECTORY_NOT_SUPPORTED => return IsADirectory,
         c::ERROR_DIR_NOT_EMPTY => return DirectoryNotEmpty,
         c::ERROR_WRITE_PROTECT => return ReadOnlyFilesystem,
         c::ERROR_DISK_FULL | c::ERROR_HANDLE_DISK_FULL => return StorageFull,
         c::ERROR_SEEK_ON_DEVICE => return NotSeekable,
+        c::ERROR_NEGATIVE_SEEK => return InvalidInput,
         c::ERROR_DISK_QUOTA_EXCEEDED => return FilesystemQuotaExceeded,
         c::ERROR_FILE_TOO_LARGE => return FileTooLarge,
         c::ERROR_BUSY => return ResourceBusy,
@@ -136,13 +185,34 @@ pub fn decode_error_kind(errno: i32) -> ErrorKind {
         c::WSAECONNABORTED => ConnectionAborted,
         c::WSAECONNREFUSED => ConnectionRefused,
         c::WSAECONNRESET => ConnectionReset,
+        // The connection was dropped by the network, which is a reset as far as the socket is
+        // concerned. Winsock 1.1 stacks report this where later ones report `WSAECONNRESET`.
+        c::WSAENETRESET => ConnectionReset,
+        // Winsock 1.1 also fails a `connect` that is still in progress with this, where later
+        // versions fail with `WSAEALREADY`. It stays `InvalidInput`, as it mostly means just that,
+        // and a retry loop treating it as `WouldBlock` would never end.
         c::WSAEINVAL => InvalidInput,
         c::WSAENOTCONN => NotConnected,
         c::WSAEWOULDBLOCK => WouldBlock,
+        // A non-blocking `connect` that is still in progress, so trying again later is right.
+        c::WSAEALREADY => WouldBlock,
+        // A blocking call that was cancelled by `WSACancelBlockingCall` (Winsock 1.1 only), which
+        // is what `EINTR` is to a Unix system call.
+        c::WSAEINTR => Interrupted,
+        // Sending on a socket that was shut down for sending.
+        c::WSAESHUTDOWN => BrokenPipe,
         c::WSAETIMEDOUT => TimedOut,
         c::WSAEHOSTUNREACH => HostUnreachable,
+        // Reported instead of `WSAEHOSTUNREACH` by some stacks.
+        c::WSAEHOSTDOWN => HostUnreachable,
         c::WSAENETDOWN => NetworkDown,
         c::WSAENETUNREACH => NetworkUnreachable,
+        // Winsock ran out of buffer space. Like running out of memory, it goes away once
+        // other sockets give some back.
+        c::WSAENOBUFS => OutOfMemory,
+        // Too many processes are using Winsock at once, which 9x/ME limit. Another attempt may
+        // succeed once some of them are done.
+        c::WSAEPROCLIM => ResourceBusy,
 
         _ => Uncategorized,
     }
@@ -322,6 +392,41 @@ pub fn dur2timeout(dur: Duration) -> c::DWORD {
         .unwrap_or(c::INFINITE)
 }
 
+/// The remaining time of a kernel wait that may have to be retried.
+///
+/// Wait loops look like `while let Some(ms) = deadline.remaining_timeout() { ... }`, so that
+/// spurious wakeups don't restart the full timeout. Unlike with `dur2timeout`, a timeout of more
+/// than `u32::MAX` milliseconds (about 49.7 days) is split into several waits instead of turning
+/// into `INFINITE`.
+///
+/// The deadline is kept in `GetTickCount64` milliseconds, which is what the kernel measures wait
+/// timeouts in as well.
+pub struct Deadline {
+    /// `None` if the deadline can't be represented, which we treat as never expiring.
+    deadline: Option<u64>,
+}
+
+impl Deadline {
+    pub fn new(dur: Duration) -> Deadline {
+        // Round up to whole milliseconds, like `dur2timeout`.
+        let ms = u64::try_from((dur.as_nanos() + 999_999) / 1_000_000).ok();
+        let now = unsafe { c::GetTickCount64() };
+        Deadline { deadline: ms.and_then(|ms| now.checked_add(ms)) }
+    }
+
+    /// Returns the timeout for the next wait, or `None` if the deadline has passed.
+    ///
+    /// The timeout is never `INFINITE`.
+    pub fn remaining_timeout(&self) -> Option<c::DWORD> {
+        let Some(deadline) = self.deadline else { return Some(c::INFINITE - 1) };
+        let now = unsafe { c::GetTickCount64() };
+        match deadline.checked_sub(now) {
+            None | Some(0) => None,
+            Some(remaining) => Some(cmp::min(remaining, (c::INFINITE - 1) as u64) as c::DWORD),
+        }
+    }
+}
+
 /// Use `__fastfail` to abort the process
 ///
 /// This is the same implementation as in libpanic_abort's `__rust_start_panic`. See
@@ -1,36 +1,50 @@
 #![unstable(issue = "none", feature = "windows_net")]
 
+#[cfg(test)]
+use crate::cell::Cell;
 use crate::cmp;
 use crate::io::{self, BorrowedBuf, BorrowedCursor, IoSlice, IoSliceMut, Read};
 use crate::mem;
-use crate::net::{Shutdown, SocketAddr};
+use crate::net::{Ipv6Addr, Shutdown, SocketAddr};
 use crate::os::windows::io::{
-    AsRawSocket, AsSocket, BorrowedSocket, FromRawSocket, IntoRawSocket, OwnedSocket, RawSocket,
+    AsRawSocket, AsSocket, BorrowedHandle, BorrowedSocket, FromRawSocket, IntoRawHandle,
+    IntoRawSocket, OwnedSocket, RawSocket,
 };
 use crate::ptr;
-use crate::sync::OnceLock;
+use crate::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+use crate::sync::{Mutex, OnceLock, PoisonError};
 use crate::sys;
 use crate::sys::c;
 use crate::sys_common::net;
 use crate::sys_common::{AsInner, FromInner, IntoInner};
 use crate::time::Duration;
 
-use core::ffi::{c_int, c_long, c_ulong, c_ushort};
+use core::ffi::{c_char, c_int, c_long, c_ulong, c_ushort};
+
+use super::{api, IoResult};
 
 pub type wrlen_t = i32;
 
+#[cfg(test)]
+mod tests;
+
 pub mod netc {
     pub use crate::sys::c::ADDRESS_FAMILY as sa_family_t;
     pub use crate::sys::c::ADDRINFOA as addrinfo;
     pub use crate::sys::c::SOCKADDR as sockaddr;
     pub use crate::sys::c::SOCKADDR_STORAGE_LH as sockaddr_storage;
     pub use crate::sys::c::*;
+    // Takes precedence over the glob import above.
+    pub use super::getaddrinfo;
 }
 
-pub struct Socket(OwnedSocket);
+pub struct Socket(OwnedSocket, SelectTimeouts);
 
 static WSA_CLEANUP: OnceLock<unsafe extern "system" fn() -> i32> = OnceLock::new();
 
+/// The Winsock version negotiated by `init`, in the format of `WSADATA::wVersion`.
+static WINSOCK_VERSION: AtomicU16 = AtomicU16::new(0);
+
 /// Checks whether the Windows socket interface has been started already, and
 /// if not, starts it.
 pub fn init() {
@@ -41,6 +55,7 @@ pub fn init() {
             &mut data,
         );
         assert_eq!(ret, 0);
+        WINSOCK_VERSION.store(data.wVersion, Ordering::Relaxed);
 
         // Only register `WSACleanup` if `WSAStartup` is actually ever called.
         // Workaround to prevent linking to `WS2_32.dll` when no network functionality is used.
@@ -60,7 +75,7 @@ pub fn cleanup() {
 
 /// Returns the last error from the Windows socket interface.
 fn last_error() -> io::Error {
-    io::Error::from_raw_os_error(unsafe { c::WSAGetLastError() })
+    io::Error::from_raw_os_error(api::wsa_get_last_error().code as i32)
 }
 
 #[doc(hidden)]
@@ -82,12 +97,52 @@ fn is_minus_one(&self) -> bool {
 /// and if so, returns the last error from the Windows socket interface. This
 /// function must be called before another call to the socket API is made.
 pub fn cvt<T: IsMinusOne>(t: T) -> io::Result<T> {
-    if t.is_minus_one() { Err(last_error()) } else { Ok(t) }
+    api::cvt_wsa(t).io_result()
 }
 
 /// A variant of `cvt` for `getaddrinfo` which return 0 for a success.
+///
+/// The error is taken from the return value, as the `wspiapi` fallback doesn't set the last error.
 pub fn cvt_gai(err: c_int) -> io::Result<()> {
-    if err == 0 { Ok(()) } else { Err(last_error()) }
+    match err {
+        0 => Ok(()),
+        // No name resolution provider is installed, which stripped-down 9x/ME systems may lack.
+        c::WSASERVICE_NOT_FOUND => Err(io::const_io_error!(
+            io::ErrorKind::Unsupported,
+            "no name resolution service is installed, only IP addresses can be used",
+        )),
+        _ => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static FORCE_NO_RESOLVER: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f` as if there was no name resolution provider, on the current thread.
+#[cfg(test)]
+fn without_resolver<R>(f: impl FnOnce() -> R) -> R {
+    let forced = FORCE_NO_RESOLVER.with(|force| force.replace(true));
+    let result = f();
+    FORCE_NO_RESOLVER.with(|force| force.set(forced));
+    result
+}
+
+/// `getaddrinfo` from whichever of `ws2_32`, `wship6` and the `wspiapi` emulation is there.
+///
+/// Addresses given literally never get here: they are parsed by `ToSocketAddrs` first.
+pub unsafe fn getaddrinfo(
+    node: *const c_char,
+    service: *const c_char,
+    hints: *const c::ADDRINFOA,
+    res: *mut *mut c::ADDRINFOA,
+) -> c_int {
+    #[cfg(test)]
+    if FORCE_NO_RESOLVER.with(Cell::get) {
+        return c::WSASERVICE_NOT_FOUND;
+    }
+    c::getaddrinfo(node, service, hints, res)
 }
 
 /// Just to provide the same interface as sys/unix/net.rs
@@ -99,6 +154,324 @@ pub fn cvt_r<T, F>(mut f: F) -> io::Result<T>
     cvt(f())
 }
 
+#[cfg(test)]
+thread_local! {
+    static FORCE_NO_IPV6: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f` as on systems without an IPv6 stack, for tests.
+#[cfg(test)]
+fn without_ipv6<R>(f: impl FnOnce() -> R) -> R {
+    let forced = FORCE_NO_IPV6.with(|force| force.replace(true));
+    let result = f();
+    FORCE_NO_IPV6.with(|force| force.set(forced));
+    result
+}
+
+/// Whether the IPv6 stack is installed, checked once by creating an IPv6 socket.
+///
+/// 9x/ME have no IPv6 support at all, and on XP it is an optional component. Without it, IPv6
+/// socket options fail with `WSAEAFNOSUPPORT` or `WSAENOPROTOOPT`, no matter the arguments.
+pub fn ipv6_available() -> bool {
+    #[cfg(test)]
+    if FORCE_NO_IPV6.with(Cell::get) {
+        return false;
+    }
+
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| unsafe {
+        init();
+        let socket = c::WSASocketA(c::AF_INET6, c::SOCK_DGRAM, 0, ptr::null_mut(), 0, 0);
+        if socket == c::INVALID_SOCKET {
+            return false;
+        }
+        c::closesocket(socket);
+        true
+    })
+}
+
+fn ipv6_unsupported() -> io::Error {
+    io::const_io_error!(io::ErrorKind::Unsupported, "IPv6 is not installed on this system")
+}
+
+/// Maps the error that IPv6 socket options fail with if IPv6 is unusable to `ipv6_unsupported`.
+///
+/// Without the IPv6 stack they would also fail with `WSAENOPROTOOPT`, but `ipv6_available` rules
+/// that out beforehand. With the stack, that error means that the option doesn't apply to the
+/// socket, e.g. because it is an IPv4 one, so it is passed through.
+fn map_ipv6_error(err: io::Error) -> io::Error {
+    match err.raw_os_error() {
+        Some(c::WSAEAFNOSUPPORT) => ipv6_unsupported(),
+        _ => err,
+    }
+}
+
+/// `setsockopt` for an `IPPROTO_IPV6` option, failing with `Unsupported` without an IPv6 stack.
+pub fn setsockopt_v6<T>(sock: &Socket, option_name: c_int, option_value: T) -> io::Result<()> {
+    if !ipv6_available() {
+        return Err(ipv6_unsupported());
+    }
+    net::setsockopt(sock, c::IPPROTO_IPV6, option_name, option_value).map_err(map_ipv6_error)
+}
+
+/// `getsockopt` for an `IPPROTO_IPV6` option, failing with `Unsupported` without an IPv6 stack.
+pub fn getsockopt_v6<T: Copy>(sock: &Socket, option_name: c_int) -> io::Result<T> {
+    if !ipv6_available() {
+        return Err(ipv6_unsupported());
+    }
+    net::getsockopt(sock, c::IPPROTO_IPV6, option_name).map_err(map_ipv6_error)
+}
+
+#[cfg(test)]
+thread_local! {
+    static FORCE_NO_V6ONLY: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f` as on systems where `IPV6_V6ONLY` doesn't exist, for tests.
+#[cfg(test)]
+fn without_v6only<R>(f: impl FnOnce() -> R) -> R {
+    let forced = FORCE_NO_V6ONLY.with(|force| force.replace(true));
+    let result = f();
+    FORCE_NO_V6ONLY.with(|force| force.set(forced));
+    result
+}
+
+/// Runs `option`, which gets or sets `IPV6_V6ONLY`, and tells whether it failed because the
+/// system doesn't have that option.
+///
+/// `IPV6_V6ONLY` only exists since Vista. XP has it fail with `WSAENOPROTOOPT`, just like it
+/// fails for IPv4 sockets, so those are told apart by the address family of the socket.
+fn v6only_option<T>(
+    sock: &Socket,
+    option: impl FnOnce() -> io::Result<T>,
+) -> Option<io::Result<T>> {
+    #[cfg(test)]
+    if FORCE_NO_V6ONLY.with(Cell::get) && is_ipv6(sock) {
+        return None;
+    }
+    match option() {
+        Err(err) if err.raw_os_error() == Some(c::WSAENOPROTOOPT) && is_ipv6(sock) => None,
+        result => Some(result),
+    }
+}
+
+fn is_ipv6(sock: &Socket) -> bool {
+    let mut storage: c::SOCKADDR_STORAGE_LH = unsafe { mem::zeroed() };
+    let mut len = mem::size_of_val(&storage) as c_int;
+    let result =
+        unsafe { c::getsockname(sock.as_raw(), &mut storage as *mut _ as *mut _, &mut len) };
+    result == 0 && storage.ss_family as c_int == c::AF_INET6
+}
+
+/// Whether IPv6 sockets only take IPv6 connections on systems without `IPV6_V6ONLY`, where that
+/// can't be changed, checked once with a probe socket.
+fn fixed_only_v6() -> bool {
+    static ONLY_V6: OnceLock<bool> = OnceLock::new();
+    *ONLY_V6.get_or_init(|| {
+        // A socket that only takes IPv6 can't be bound to an IPv4-mapped address. The probe is
+        // closed again when it goes out of scope.
+        let addr = SocketAddr::from((Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0x7f00, 1), 0));
+        let Ok(probe) = Socket::new(&addr, c::SOCK_STREAM) else { return true };
+        let (addr, len) = addr.into_inner();
+        unsafe { c::bind(probe.as_raw(), addr.as_ptr(), len) != 0 }
+    })
+}
+
+/// Sets `IPV6_V6ONLY`. Where the option doesn't exist, this only succeeds if the socket already
+/// behaves as asked.
+pub fn set_only_v6(sock: &Socket, only_v6: bool) -> io::Result<()> {
+    let set = || setsockopt_v6(sock, c::IPV6_V6ONLY, only_v6 as c_int);
+    match v6only_option(sock, set) {
+        Some(result) => result,
+        None if only_v6 == fixed_only_v6() => Ok(()),
+        None => Err(io::const_io_error!(
+            io::ErrorKind::Unsupported,
+            "IPV6_V6ONLY can't be changed on this system",
+        )),
+    }
+}
+
+/// Gets `IPV6_V6ONLY`, or what the socket behaves like where the option doesn't exist.
+pub fn only_v6(sock: &Socket) -> io::Result<bool> {
+    let get = || getsockopt_v6::<c_int>(sock, c::IPV6_V6ONLY);
+    match v6only_option(sock, get) {
+        Some(result) => result.map(|raw| raw != 0),
+        None => Ok(fixed_only_v6()),
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static FORCE_HANDLE_DUPLICATION: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f` with sockets duplicated with `DuplicateHandle` even with Winsock 2, for tests.
+#[cfg(test)]
+fn with_handle_duplication<R>(f: impl FnOnce() -> R) -> R {
+    let forced = FORCE_HANDLE_DUPLICATION.with(|force| force.replace(true));
+    let result = f();
+    FORCE_HANDLE_DUPLICATION.with(|force| force.set(forced));
+    result
+}
+
+/// Sockets created by `duplicate_handle`, which have to be closed with `CloseHandle`.
+static DUPLICATED_HANDLES: Mutex<Vec<c::SOCKET>> = Mutex::new(Vec::new());
+
+/// Set once `DUPLICATED_HANDLES` may be non-empty, so closing other sockets doesn't have to lock
+/// it until then.
+static HAS_DUPLICATED_HANDLES: AtomicBool = AtomicBool::new(false);
+
+/// Whether sockets have to be duplicated with `duplicate_handle`, as `WSADuplicateSocket` is part
+/// of Winsock 2 and doesn't work when only Winsock 1.1 could be negotiated.
+pub(crate) fn needs_handle_duplication() -> bool {
+    #[cfg(test)]
+    if FORCE_HANDLE_DUPLICATION.with(Cell::get) {
+        return true;
+    }
+
+    init();
+    // The major version is in the low-order byte.
+    (WINSOCK_VERSION.load(Ordering::Relaxed) & 0xff) < 2
+}
+
+/// Duplicates `socket` with `DuplicateHandle`, for systems without `WSADuplicateSocket`.
+///
+/// The duplicate is only valid in this process, which is all `try_clone` needs. Unlike a socket
+/// from `WSADuplicateSocket`, it must be closed with `CloseHandle`, so it is remembered until
+/// `close_socket` or `release_socket` is called for it.
+pub(crate) fn duplicate_handle(socket: BorrowedSocket<'_>) -> io::Result<OwnedSocket> {
+    let handle = ptr::invalid_mut(socket.as_raw_socket() as usize);
+    let handle = unsafe { BorrowedHandle::borrow_raw(handle) };
+    let handle = handle.duplicate(0, false, c::DUPLICATE_SAME_ACCESS)?.into_raw_handle();
+
+    let mut handles = DUPLICATED_HANDLES.lock().unwrap_or_else(PoisonError::into_inner);
+    handles.push(handle as c::SOCKET);
+    HAS_DUPLICATED_HANDLES.store(true, Ordering::Relaxed);
+    unsafe { Ok(OwnedSocket::from_raw_socket(handle as RawSocket)) }
+}
+
+/// Forgets that `socket` was created by `duplicate_handle`, as it is handed out as a raw socket.
+///
+/// Whoever takes it over closes it like any other socket, and once they do, its value can be reused
+/// by a new socket that must not be mistaken for the duplicate.
+pub(crate) fn release_socket(socket: c::SOCKET) {
+    if HAS_DUPLICATED_HANDLES.load(Ordering::Relaxed) {
+        let mut handles = DUPLICATED_HANDLES.lock().unwrap_or_else(PoisonError::into_inner);
+        handles.retain(|&handle| handle != socket);
+    }
+}
+
+/// Closes `socket` with `CloseHandle` if it was created by `duplicate_handle`, and with
+/// `closesocket` otherwise.
+pub(crate) unsafe fn close_socket(socket: c::SOCKET) {
+    if HAS_DUPLICATED_HANDLES.load(Ordering::Relaxed) {
+        let mut handles = DUPLICATED_HANDLES.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(i) = handles.iter().position(|&handle| handle == socket) {
+            handles.swap_remove(i);
+            // Close while still holding the lock, so the handle value can't be reused by a new
+            // socket that would then be mistaken for the duplicate.
+            c::CloseHandle(ptr::invalid_mut(socket));
+            return;
+        }
+    }
+    c::closesocket(socket);
+}
+
+#[cfg(test)]
+thread_local! {
+    static FORCE_SELECT_TIMEOUTS: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f` with timeouts waited out with `select` even with Winsock 2, for tests.
+#[cfg(test)]
+fn with_select_timeouts<R>(f: impl FnOnce() -> R) -> R {
+    let forced = FORCE_SELECT_TIMEOUTS.with(|force| force.replace(true));
+    let result = f();
+    FORCE_SELECT_TIMEOUTS.with(|force| force.set(forced));
+    result
+}
+
+/// The timeouts of a socket that are waited out with `select`, in milliseconds or 0 for none,
+/// and whether it is non-blocking, in which case they don't apply.
+#[derive(Default)]
+struct SelectTimeouts {
+    /// Set once a timeout or non-blocking mode is set while `select_timeouts` holds. From then on,
+    /// the socket keeps its timeouts here.
+    enabled: AtomicBool,
+    read: AtomicU32,
+    write: AtomicU32,
+    nonblocking: AtomicBool,
+}
+
+impl SelectTimeouts {
+    fn timeout(&self, kind: c_int) -> &AtomicU32 {
+        match kind {
+            c::SO_RCVTIMEO => &self.read,
+            _ => &self.write,
+        }
+    }
+}
+
+// Duplicates share the options of the underlying socket, so they start out with the same
+// timeouts, though later changes to either aren't seen by the other.
+impl Clone for SelectTimeouts {
+    fn clone(&self) -> SelectTimeouts {
+        SelectTimeouts {
+            enabled: AtomicBool::new(self.enabled.load(Ordering::Relaxed)),
+            read: AtomicU32::new(self.read.load(Ordering::Relaxed)),
+            write: AtomicU32::new(self.write.load(Ordering::Relaxed)),
+            nonblocking: AtomicBool::new(self.nonblocking.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Whether read and write timeouts are waited out with `select`, as Winsock 1.1 doesn't reliably
+/// honor `SO_RCVTIMEO` and `SO_SNDTIMEO`.
+///
+/// This only decides how new timeouts are set. Sockets that have their timeouts in
+/// `SelectTimeouts` already keep them there.
+fn select_timeouts() -> bool {
+    #[cfg(test)]
+    if FORCE_SELECT_TIMEOUTS.with(Cell::get) {
+        return true;
+    }
+
+    init();
+    // The major version is in the low-order byte.
+    (WINSOCK_VERSION.load(Ordering::Relaxed) & 0xff) < 2
+}
+
+/// `send`, after waiting out the write timeout if that is done with `select`.
+pub fn send(sock: &Socket, buf: &[u8]) -> io::Result<usize> {
+    sock.wait_for_timeout(c::SO_SNDTIMEO)?;
+    let len = cmp::min(buf.len(), <wrlen_t>::MAX as usize) as wrlen_t;
+    let ret = cvt(unsafe { c::send(sock.as_raw(), buf.as_ptr() as *const _, len, 0) })?;
+    Ok(ret as usize)
+}
+
+/// `sendto`, after waiting out the write timeout if that is done with `select`.
+pub fn send_to(sock: &Socket, buf: &[u8], dst: &SocketAddr) -> io::Result<usize> {
+    sock.wait_for_timeout(c::SO_SNDTIMEO)?;
+    let len = cmp::min(buf.len(), <wrlen_t>::MAX as usize) as wrlen_t;
+    let (dst, dstlen) = dst.into_inner();
+    let ret = cvt(unsafe {
+        c::sendto(sock.as_raw(), buf.as_ptr() as *const _, len, 0, dst.as_ptr(), dstlen)
+    })?;
+    Ok(ret as usize)
+}
+
+/// A set of just `socket`, for `select`.
+fn fd_set(socket: c::SOCKET) -> c::fd_set {
+    let mut fds = unsafe { mem::zeroed::<c::fd_set>() };
+    fds.fd_count = 1;
+    fds.fd_array[0] = socket;
+    fds
+}
+
+fn timeval(ms: c::DWORD) -> c::timeval {
+    c::timeval { tv_sec: (ms / 1000) as c_long, tv_usec: (ms % 1000 * 1000) as c_long }
+}
+
 impl Socket {
     pub fn new(addr: &SocketAddr, ty: c_int) -> io::Result<Socket> {
         let family = match *addr {
@@ -119,10 +492,10 @@ pub fn new(addr: &SocketAddr, ty: c_int) -> io::Result<Socket> {
         if socket != c::INVALID_SOCKET {
             unsafe { Ok(Self::from_raw(socket)) }
         } else {
-            let error = unsafe { c::WSAGetLastError() };
+            let error = api::wsa_get_last_error();
 
-            if error != c::WSAEPROTOTYPE && error != c::WSAEINVAL {
-                return Err(io::Error::from_raw_os_error(error));
+            if error.code != c::WSAEPROTOTYPE as u32 && error.code != c::WSAEINVAL as u32 {
+                return Err(error).io_result();
             }
 
             let socket =
@@ -160,44 +533,37 @@ pub fn connect_timeout(&self, addr: &SocketAddr, timeout: Duration) -> io::Resul
                     ));
                 }
 
-                let mut timeout = c::timeval {
-                    tv_sec: cmp::min(timeout.as_secs(), c_long::MAX as u64) as c_long,
-                    tv_usec: timeout.subsec_micros() as c_long,
-                };
-
-                if timeout.tv_sec == 0 && timeout.tv_usec == 0 {
-                    timeout.tv_usec = 1;
-                }
-
-                let fds = {
-                    let mut fds = unsafe { mem::zeroed::<c::fd_set>() };
-                    fds.fd_count = 1;
-                    fds.fd_array[0] = self.as_raw();
-                    fds
-                };
-
-                let mut writefds = fds;
-                let mut errorfds = fds;
-
-                let count = {
+                let fds = fd_set(self.as_raw());
+
+                // `select` may wake up before the timeout is over without the socket being
+                // ready, so each call only gets what is left of it. Once the deadline has passed,
+                // the socket is still checked once without waiting, so that a connection that
+                // completed in the meantime isn't reported as timed out.
+                let deadline = sys::Deadline::new(timeout);
+                loop {
+                    let remaining = deadline.remaining_timeout();
+                    let timeout = timeval(remaining.unwrap_or(0));
+                    let mut writefds = fds;
+                    let mut errorfds = fds;
                     let result = unsafe {
                         c::select(1, ptr::null_mut(), &mut writefds, &mut errorfds, &timeout)
                     };
-                    cvt(result)?
-                };
-
-                match count {
-                    0 => Err(io::const_io_error!(io::ErrorKind::TimedOut, "connection timed out")),
-                    _ => {
-                        if writefds.fd_count != 1 {
-                            if let Some(e) = self.take_error()? {
-                                return Err(e);
-                            }
+                    if cvt(result)? == 0 {
+                        if remaining.is_none() {
+                            break;
                         }
+                        continue;
+                    }
 
-                        Ok(())
+                    // A failed connection is reported in the except set. 9x/ME may leave
+                    // `SO_ERROR` unset for a refused one, so that is what it's taken to be then.
+                    if errorfds.fd_count == 1 || writefds.fd_count != 1 {
+                        let refused = || io::Error::from_raw_os_error(c::WSAECONNREFUSED);
+                        return Err(self.take_error()?.unwrap_or_else(refused));
                     }
+                    return Ok(());
                 }
+                Err(io::const_io_error!(io::ErrorKind::TimedOut, "connection timed out"))
             }
             _ => result,
         }
@@ -213,25 +579,22 @@ pub fn accept(&self, storage: *mut c::SOCKADDR, len: *mut c_int) -> io::Result<S
     }
 
     pub fn duplicate(&self) -> io::Result<Socket> {
-        Ok(Self(self.0.try_clone()?))
+        Ok(Self(self.0.try_clone()?, self.1.clone()))
     }
 
     fn recv_with_flags(&self, mut buf: BorrowedCursor<'_>, flags: c_int) -> io::Result<()> {
         // On unix when a socket is shut down all further reads return 0, so we
         // do the same on windows to map a shut down socket to returning EOF.
+        self.wait_for_timeout(c::SO_RCVTIMEO)?;
         let length = cmp::min(buf.capacity(), i32::MAX as usize) as i32;
         let result =
             unsafe { c::recv(self.as_raw(), buf.as_mut().as_mut_ptr() as *mut _, length, flags) };
 
         match result {
             c::SOCKET_ERROR => {
-                let error = unsafe { c::WSAGetLastError() };
+                let error = api::wsa_get_last_error();
 
-                if error == c::WSAESHUTDOWN {
-                    Ok(())
-                } else {
-                    Err(io::Error::from_raw_os_error(error))
-                }
+                if error.code == c::WSAESHUTDOWN as u32 { Ok(()) } else { Err(error).io_result() }
             }
             _ => {
                 unsafe { buf.advance(result as usize) };
@@ -253,6 +616,7 @@ pub fn read_buf(&self, buf: BorrowedCursor<'_>) -> io::Result<()> {
     pub fn read_vectored(&self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
         // On unix when a socket is shut down all further reads return 0, so we
         // do the same on windows to map a shut down socket to returning EOF.
+        self.wait_for_timeout(c::SO_RCVTIMEO)?;
         let length = cmp::min(bufs.len(), c::DWORD::MAX as usize) as c::DWORD;
         let mut nread = 0;
         let mut flags = 0;
@@ -271,13 +635,9 @@ pub fn read_vectored(&self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
         match result {
             0 => Ok(nread as usize),
             _ => {
-                let error = unsafe { c::WSAGetLastError() };
+                let error = api::wsa_get_last_error();
 
-                if error == c::WSAESHUTDOWN {
-                    Ok(0)
-                } else {
-                    Err(io::Error::from_raw_os_error(error))
-                }
+                if error.code == c::WSAESHUTDOWN as u32 { Ok(0) } else { Err(error).io_result() }
             }
         }
     }
@@ -298,6 +658,7 @@ fn recv_from_with_flags(
         buf: &mut [u8],
         flags: c_int,
     ) -> io::Result<(usize, SocketAddr)> {
+        self.wait_for_timeout(c::SO_RCVTIMEO)?;
         let mut storage = unsafe { mem::zeroed::<c::SOCKADDR_STORAGE_LH>() };
         let mut addrlen = mem::size_of_val(&storage) as c::socklen_t;
         let length = cmp::min(buf.len(), <wrlen_t>::MAX as usize) as wrlen_t;
@@ -317,12 +678,12 @@ fn recv_from_with_flags(
 
         match result {
             c::SOCKET_ERROR => {
-                let error = unsafe { c::WSAGetLastError() };
+                let error = api::wsa_get_last_error();
 
-                if error == c::WSAESHUTDOWN {
+                if error.code == c::WSAESHUTDOWN as u32 {
                     Ok((0, net::sockaddr_to_addr(&storage, addrlen as usize)?))
                 } else {
-                    Err(io::Error::from_raw_os_error(error))
+                    Err(error).io_result()
                 }
             }
             _ => Ok((result as usize, net::sockaddr_to_addr(&storage, addrlen as usize)?)),
@@ -338,6 +699,7 @@ pub fn peek_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
     }
 
     pub fn write_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.wait_for_timeout(c::SO_SNDTIMEO)?;
         let length = cmp::min(bufs.len(), c::DWORD::MAX as usize) as c::DWORD;
         let mut nwritten = 0;
         let result = unsafe {
@@ -373,11 +735,20 @@ pub fn set_timeout(&self, dur: Option<Duration>, kind: c_int) -> io::Result<()>
             }
             None => 0,
         };
+        if self.uses_select_timeouts() {
+            self.1.timeout(kind).store(timeout, Ordering::Relaxed);
+            self.1.enabled.store(true, Ordering::Relaxed);
+            return Ok(());
+        }
         net::setsockopt(self, c::SOL_SOCKET, kind, timeout)
     }
 
     pub fn timeout(&self, kind: c_int) -> io::Result<Option<Duration>> {
-        let raw: c::DWORD = net::getsockopt(self, c::SOL_SOCKET, kind)?;
+        let raw: c::DWORD = if self.uses_select_timeouts() {
+            self.1.timeout(kind).load(Ordering::Relaxed)
+        } else {
+            net::getsockopt(self, c::SOL_SOCKET, kind)?
+        };
         if raw == 0 {
             Ok(None)
         } else {
@@ -401,7 +772,51 @@ pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
         let mut nonblocking = nonblocking as c_ulong;
         let result =
             unsafe { c::ioctlsocket(self.as_raw(), c::FIONBIO as c_int, &mut nonblocking) };
-        cvt(result).map(drop)
+        cvt(result)?;
+        // Timeouts waited out with `select` must not make non-blocking calls wait.
+        if self.uses_select_timeouts() {
+            self.1.nonblocking.store(nonblocking != 0, Ordering::Relaxed);
+            self.1.enabled.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Whether the timeouts of this socket are waited out with `select`.
+    fn uses_select_timeouts(&self) -> bool {
+        self.1.enabled.load(Ordering::Relaxed) || select_timeouts()
+    }
+
+    /// Waits until the socket is ready for the kind of call that the timeout `kind` is for, if
+    /// that timeout is waited out with `select`. Fails with `WSAETIMEDOUT` like the call would
+    /// with `SO_RCVTIMEO` or `SO_SNDTIMEO`.
+    ///
+    /// Readiness includes errors and a closed connection, which the call itself then reports.
+    fn wait_for_timeout(&self, kind: c_int) -> io::Result<()> {
+        if !self.1.enabled.load(Ordering::Relaxed) || self.1.nonblocking.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let timeout = match self.1.timeout(kind).load(Ordering::Relaxed) {
+            0 => return Ok(()),
+            ms => Duration::from_millis(ms as u64),
+        };
+
+        let fds = fd_set(self.as_raw());
+        // Like in `connect_timeout`, each call only gets what is left of the timeout.
+        let deadline = sys::Deadline::new(timeout);
+        while let Some(ms) = deadline.remaining_timeout() {
+            let timeout = timeval(ms);
+            let mut readyfds = fds;
+            let mut errorfds = fds;
+            let (readfds, writefds) = match kind {
+                c::SO_RCVTIMEO => (ptr::addr_of_mut!(readyfds), ptr::null_mut()),
+                _ => (ptr::null_mut(), ptr::addr_of_mut!(readyfds)),
+            };
+            let result = unsafe { c::select(1, readfds, writefds, &mut errorfds, &timeout) };
+            if cvt(result)? != 0 {
+                return Ok(());
+            }
+        }
+        Err(io::Error::from_raw_os_error(c::WSAETIMEDOUT))
     }
 
     pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
@@ -462,7 +877,7 @@ fn as_inner(&self) -> &OwnedSocket {
 
 impl FromInner<OwnedSocket> for Socket {
     fn from_inner(sock: OwnedSocket) -> Socket {
-        Socket(sock)
+        Socket(sock, SelectTimeouts::default())
     }
 }
 
@@ -492,6 +907,6 @@ fn into_raw_socket(self) -> RawSocket {
 
 impl FromRawSocket for Socket {
     unsafe fn from_raw_socket(raw_socket: RawSocket) -> Self {
-        Self(FromRawSocket::from_raw_socket(raw_socket))
+        Self(FromRawSocket::from_raw_socket(raw_socket), SelectTimeouts::default())
     }
 }
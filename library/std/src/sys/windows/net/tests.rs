@@ -0,0 +1,302 @@
+use super::{
+    api, cvt_gai, fixed_only_v6, ipv6_available, last_error, map_ipv6_error,
+    with_handle_duplication, with_select_timeouts, without_ipv6, without_resolver, without_v6only,
+    DUPLICATED_HANDLES, IoResult,
+};
+use crate::io::{Error, ErrorKind, Read, Write};
+use crate::net::{Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use crate::os::windows::io::{AsRawSocket, FromRawSocket, IntoRawSocket, OwnedSocket};
+use crate::sys::compat::is_windows_nt;
+use crate::sys::{c, to_u16s};
+use crate::sys_common::io::test::tmpdir;
+use crate::sys_common::AsInner;
+use crate::time::{Duration, Instant};
+
+#[test]
+fn ipv6_options_without_stack() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let (join, leave, set_loop, get_loop) = without_ipv6(|| {
+        assert!(!ipv6_available());
+        let group = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x123);
+        let join = socket.join_multicast_v6(&group, 0);
+        let leave = socket.leave_multicast_v6(&group, 0);
+        (join, leave, socket.set_multicast_loop_v6(true), socket.multicast_loop_v6())
+    });
+
+    assert_eq!(join.unwrap_err().kind(), ErrorKind::Unsupported);
+    assert_eq!(leave.unwrap_err().kind(), ErrorKind::Unsupported);
+    assert_eq!(set_loop.unwrap_err().kind(), ErrorKind::Unsupported);
+    assert_eq!(get_loop.unwrap_err().kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn ipv6_errors_mapped() {
+    let err = map_ipv6_error(Error::from_raw_os_error(c::WSAEAFNOSUPPORT));
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert_eq!(err.raw_os_error(), None);
+
+    // With an IPv6 stack, these are about the arguments or the socket, not the platform.
+    for code in [c::WSAENOPROTOOPT, c::WSAEINVAL] {
+        let err = map_ipv6_error(Error::from_raw_os_error(code));
+        assert_eq!(err.raw_os_error(), Some(code));
+    }
+}
+
+/// Clones `stream` with `DuplicateHandle`, as on systems with only Winsock 1.1.
+fn clone_by_handle(stream: &TcpStream) -> TcpStream {
+    with_handle_duplication(|| stream.try_clone()).unwrap()
+}
+
+fn is_duplicated_handle(stream: &TcpStream) -> bool {
+    DUPLICATED_HANDLES.lock().unwrap().contains(&(stream.as_raw_socket() as c::SOCKET))
+}
+
+fn assert_sends(from: &mut TcpStream, to: &mut TcpStream, data: &[u8]) {
+    from.write_all(data).unwrap();
+    let mut buf = vec![0; data.len()];
+    to.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn try_clone_by_handle() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+
+    let mut clone = clone_by_handle(&client);
+    assert!(is_duplicated_handle(&clone));
+    assert!(!is_duplicated_handle(&client));
+    assert_sends(&mut clone, &mut server, b"from the clone");
+    assert_sends(&mut server, &mut clone, b"to the clone");
+
+    // Closing the clone leaves the original open, and forgets about the duplicated handle.
+    let raw = clone.as_raw_socket();
+    drop(clone);
+    assert!(!DUPLICATED_HANDLES.lock().unwrap().contains(&(raw as c::SOCKET)));
+    assert_sends(&mut client, &mut server, b"from the original");
+    assert_sends(&mut server, &mut client, b"to the original");
+
+    // And closing the original leaves a clone open.
+    let mut clone = clone_by_handle(&client);
+    drop(client);
+    assert_sends(&mut clone, &mut server, b"from the clone");
+    assert_sends(&mut server, &mut clone, b"to the clone");
+
+    drop(clone);
+    let mut buf = [0; 1];
+    assert_eq!(server.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn handle_duplicates_released() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+
+    // Converting to an `OwnedSocket` keeps track of the duplicate.
+    let clone = OwnedSocket::from(clone_by_handle(&client));
+    assert!(DUPLICATED_HANDLES.lock().unwrap().contains(&(clone.as_raw_socket() as c::SOCKET)));
+    let mut clone = TcpStream::from(clone);
+    assert!(is_duplicated_handle(&clone));
+    assert_sends(&mut clone, &mut server, b"converted");
+
+    // Handing it out as a raw socket doesn't.
+    let raw = clone.into_raw_socket();
+    assert!(!DUPLICATED_HANDLES.lock().unwrap().contains(&(raw as c::SOCKET)));
+    unsafe { c::CloseHandle(crate::ptr::invalid_mut(raw as usize)) };
+}
+
+#[test]
+fn connect_timeout_to_listener() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut client = TcpStream::connect_timeout(&addr, Duration::from_secs(10)).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+    assert_sends(&mut client, &mut server, b"connected");
+}
+
+#[test]
+fn connect_timeout_refused() {
+    // Nothing listens on the port once the listener is gone.
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let err = TcpStream::connect_timeout(&addr, Duration::from_secs(10)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionRefused, "{err}");
+}
+
+#[test]
+fn connect_timeout_unroutable() {
+    // Connections to this address are never answered, if it is routed anywhere at all.
+    let addr: SocketAddr = "10.255.255.1:80".parse().unwrap();
+    let timeout = Duration::from_millis(200);
+    let start = Instant::now();
+    match TcpStream::connect_timeout(&addr, timeout) {
+        Err(err) if err.kind() == ErrorKind::TimedOut => {
+            // The deadline is kept on the 10-16ms system timer tick.
+            let elapsed = start.elapsed();
+            assert!(elapsed + Duration::from_millis(16) >= timeout, "gave up after {elapsed:?}");
+            assert!(elapsed < timeout + Duration::from_secs(1), "gave up after {elapsed:?}");
+        }
+        // Without a route there, the connection fails right away instead.
+        Err(_) => {}
+        Ok(_) => panic!("connected to {addr}"),
+    }
+}
+
+/// Connects a client to a server that never sends anything, with the client's timeouts waited
+/// out with `select`, as on systems with only Winsock 1.1.
+fn stalled_with_select_timeouts(timeout: Duration) -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    with_select_timeouts(|| client.set_read_timeout(Some(timeout))).unwrap();
+    (client, server)
+}
+
+#[test]
+fn select_read_timeout() {
+    let timeout = Duration::from_millis(200);
+    let (mut client, mut server) = stalled_with_select_timeouts(timeout);
+    assert_eq!(client.read_timeout().unwrap(), Some(timeout));
+
+    let start = Instant::now();
+    let err = client.read(&mut [0; 1]).unwrap_err();
+    let elapsed = start.elapsed();
+    assert_eq!(err.kind(), ErrorKind::TimedOut, "{err}");
+    // The deadline is kept on the 10-16ms system timer tick.
+    assert!(elapsed + Duration::from_millis(16) >= timeout, "gave up after {elapsed:?}");
+    assert!(elapsed < timeout + Duration::from_secs(1), "gave up after {elapsed:?}");
+
+    // Data that arrives in time is read as usual.
+    assert_sends(&mut server, &mut client, b"in time");
+    drop(server);
+    assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn select_timeouts_skipped_when_nonblocking() {
+    let (mut client, mut server) = stalled_with_select_timeouts(Duration::from_secs(10));
+    client.set_nonblocking(true).unwrap();
+    let start = Instant::now();
+    let err = client.read(&mut [0; 1]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock, "{err}");
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    client.set_nonblocking(false).unwrap();
+    client.set_read_timeout(None).unwrap();
+    assert_eq!(client.read_timeout().unwrap(), None);
+    assert_sends(&mut server, &mut client, b"blocking again");
+}
+
+#[test]
+fn select_timeouts_kept_by_socket() {
+    let timeout = Duration::from_millis(200);
+    let (client, _server) = stalled_with_select_timeouts(timeout);
+
+    // A clone starts out with the timeouts of the original.
+    let mut clone = client.try_clone().unwrap();
+    assert_eq!(clone.read_timeout().unwrap(), Some(timeout));
+    let err = clone.read(&mut [0; 1]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut, "{err}");
+
+    // The timeouts go with the socket that holds them, not with the raw handle.
+    let raw = client.into_raw_socket();
+    let client = unsafe { TcpStream::from_raw_socket(raw) };
+    assert_eq!(client.read_timeout().unwrap(), None);
+}
+
+#[test]
+fn only_v6_without_option() {
+    if !ipv6_available() {
+        return;
+    }
+
+    let (listener, only_v6, set_same, set_other) = without_v6only(|| {
+        let listener = TcpListener::bind("[::1]:0");
+        let inner = listener.as_ref().ok().map(AsInner::as_inner);
+        let only_v6 = inner.map(|listener| listener.only_v6());
+        let set_same = inner.map(|listener| listener.set_only_v6(fixed_only_v6()));
+        let set_other = inner.map(|listener| listener.set_only_v6(!fixed_only_v6()));
+        (listener, only_v6, set_same, set_other)
+    });
+
+    // Binding doesn't depend on the option, and the socket reports what the probe found.
+    listener.unwrap();
+    assert_eq!(only_v6.unwrap().unwrap(), fixed_only_v6());
+    set_same.unwrap().unwrap();
+    assert_eq!(set_other.unwrap().unwrap_err().kind(), ErrorKind::Unsupported);
+
+    // IPv4 sockets still fail, as the option doesn't apply to them.
+    let v4 = TcpListener::bind("127.0.0.1:0").unwrap();
+    assert!(without_v6only(|| v4.as_inner().only_v6()).is_err());
+}
+
+#[test]
+fn fixed_only_v6_matches_default() {
+    if !ipv6_available() {
+        return;
+    }
+    // Where the option exists, the probe finds its default, which is on for Windows.
+    let listener = TcpListener::bind("[::1]:0").unwrap();
+    assert_eq!(listener.as_inner().only_v6().unwrap(), fixed_only_v6());
+}
+
+fn resolve(addr: impl ToSocketAddrs) -> Vec<SocketAddr> {
+    addr.to_socket_addrs().unwrap().collect()
+}
+
+#[test]
+fn literals_without_resolver() {
+    without_resolver(|| {
+        let v4: SocketAddr = "127.0.0.1:80".parse().unwrap();
+        let v6: SocketAddr = "[::1]:443".parse().unwrap();
+        assert_eq!(resolve("127.0.0.1:80"), [v4]);
+        assert_eq!(resolve(("127.0.0.1", 80)), [v4]);
+        assert_eq!(resolve("[::1]:443"), [v6]);
+        assert_eq!(resolve(("::1", 443)), [v6]);
+
+        let err = "localhost:80".to_socket_addrs().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported, "{err}");
+        let err = ("localhost", 80).to_socket_addrs().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported, "{err}");
+    });
+}
+
+#[test]
+fn resolver_errors_from_return_value() {
+    assert!(cvt_gai(0).is_ok());
+    let err = cvt_gai(c::WSAHOST_NOT_FOUND).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(c::WSAHOST_NOT_FOUND));
+    let err = cvt_gai(c::WSASERVICE_NOT_FOUND).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn socket_errors_read_from_winsock() {
+    super::init();
+    let missing = to_u16s(tmpdir().join("missing")).unwrap();
+    // Fail a socket call, then a file call, and read both errors only afterwards.
+    unsafe {
+        assert_eq!(c::closesocket(c::INVALID_SOCKET), c::SOCKET_ERROR);
+        assert_eq!(c::GetFileAttributesW(missing.as_ptr()), c::INVALID_FILE_ATTRIBUTES);
+    }
+
+    let error = api::get_last_error();
+    assert_eq!(error.code, c::ERROR_FILE_NOT_FOUND);
+    assert_eq!(error.domain, api::ErrorDomain::Win32);
+    // 9x/ME keeps the socket error apart from the thread's error. On NT, Winsock shares the
+    // thread's error, so the file call overwrote it.
+    let socket = if is_windows_nt() { c::ERROR_FILE_NOT_FOUND as i32 } else { c::WSAENOTSOCK };
+    assert_eq!(last_error().raw_os_error(), Some(socket));
+}
+
+#[test]
+fn cvt_wsa_captures_socket_error() {
+    super::init();
+    let error = api::cvt_wsa(unsafe { c::closesocket(c::INVALID_SOCKET) }).unwrap_err();
+    let code = c::WSAENOTSOCK as u32;
+    assert_eq!(error, api::WinError { code, domain: api::ErrorDomain::Winsock });
+    assert_eq!(Err::<(), _>(error).io_result().unwrap_err().raw_os_error(), Some(c::WSAENOTSOCK));
+    assert_eq!(api::cvt_wsa(0).unwrap(), 0);
+}
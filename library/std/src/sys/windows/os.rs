@@ -7,6 +7,7 @@
 
 use crate::os::windows::prelude::*;
 
+use crate::borrow::Cow;
 use crate::error::Error as StdError;
 use crate::ffi::{OsStr, OsString};
 use crate::fmt;
@@ -17,7 +18,7 @@
 use crate::slice;
 use crate::sys::{c, cvt};
 
-use super::{api, to_u16s};
+use super::{api, compat, to_u16s};
 
 pub fn errno() -> i32 {
     api::get_last_error().code as i32
@@ -162,7 +163,13 @@ fn next(&mut self) -> Option<(OsString, OsString)> {
 impl Drop for Env {
     fn drop(&mut self) {
         unsafe {
-            c::FreeEnvironmentStringsW(self.base);
+            // Without the real `GetEnvironmentStringsW`, the block was converted from the ANSI
+            // environment, which has to be freed differently.
+            if c::GetEnvironmentStringsW::available() {
+                c::FreeEnvironmentStringsW(self.base);
+            } else {
+                c::ansi::FreeEnvironmentStringsW(self.base);
+            }
         }
     }
 }
@@ -271,12 +278,59 @@ fn description(&self) -> &str {
 }
 
 pub fn current_exe() -> io::Result<PathBuf> {
+    module_file_name(|buf, sz| unsafe { c::GetModuleFileNameW(ptr::null_mut(), buf, sz) })
+}
+
+/// Gets the path of a module from `get`, which behaves like `GetModuleFileNameW`.
+///
+/// On 9x/ME without unicows, that is the ANSI fallback, which converts the path lossily: the file
+/// exists, so a path with a few replacement characters beats no path at all.
+fn module_file_name(mut get: impl FnMut(c::LPWSTR, c::DWORD) -> c::DWORD) -> io::Result<PathBuf> {
     super::fill_utf16_buf(
-        |buf, sz| unsafe { c::GetModuleFileNameW(ptr::null_mut(), buf, sz) },
-        super::os2path,
+        |buf, sz| match get(buf, sz) {
+            // A truncated path returns the buffer size, but XP and older don't set
+            // `ERROR_INSUFFICIENT_BUFFER`. Set it ourselves so that the buffer is grown.
+            n if n == sz => {
+                unsafe { c::SetLastError(c::ERROR_INSUFFICIENT_BUFFER) };
+                n
+            }
+            n => n,
+        },
+        |path| super::os2path(&strip_verbatim_prefix(path)),
     )
 }
 
+/// Removes the `\\?\` prefix that NT keeps if a program was started through a verbatim path,
+/// as long as the path means the same without it. 9x/ME know nothing about verbatim paths, so this
+/// way the path has the same form everywhere, and can be passed to APIs without long path support.
+fn strip_verbatim_prefix(path: &[u16]) -> Cow<'_, [u16]> {
+    const SEP: u16 = b'\\' as u16;
+    const COLON: u16 = b':' as u16;
+    const DOT: u16 = b'.' as u16;
+    const SPACE: u16 = b' ' as u16;
+    const VERBATIM_PREFIX: &[u16] = &[SEP, SEP, b'?' as u16, SEP];
+    const UNC_PREFIX: &[u16] = &[b'U' as u16, b'N' as u16, b'C' as u16, SEP];
+
+    let Some(rest) = path.strip_prefix(VERBATIM_PREFIX) else { return Cow::Borrowed(path) };
+    let stripped = match rest {
+        // \\?\C:\ => C:\
+        [_, COLON, SEP, ..] => Cow::Borrowed(rest),
+        // \\?\UNC\server\share => \\server\share
+        _ if rest.starts_with(UNC_PREFIX) => {
+            let mut unc = vec![SEP, SEP];
+            unc.extend_from_slice(&rest[UNC_PREFIX.len()..]);
+            Cow::Owned(unc)
+        }
+        _ => return Cow::Borrowed(path),
+    };
+    // Without the prefix, the path is normalized: trailing dots and spaces are removed from its
+    // components, and it may not be longer than `MAX_PATH`.
+    let normalized = stripped
+        .split(|&w| w == SEP)
+        .all(|component| !matches!(component.last(), Some(&(DOT | SPACE))));
+    if normalized && stripped.len() < c::MAX_PATH as usize { stripped } else { Cow::Borrowed(path) }
+}
+
 pub fn getcwd() -> io::Result<PathBuf> {
     super::fill_utf16_buf(|buf, sz| unsafe { c::GetCurrentDirectoryW(sz, buf) }, super::os2path)
 }
@@ -314,9 +368,40 @@ pub fn temp_dir() -> PathBuf {
     super::fill_utf16_buf(|buf, sz| unsafe { c::GetTempPath2W(sz, buf) }, super::os2path).unwrap()
 }
 
+#[cfg(all(test, not(target_vendor = "uwp")))]
+thread_local! {
+    static FORCE_NO_TOKEN_API: crate::cell::Cell<bool> = crate::cell::Cell::new(false);
+}
+
+/// Runs `f` with the token API treated as missing, like on 9x/ME, for tests.
+#[cfg(all(test, not(target_vendor = "uwp")))]
+fn without_token_api<R>(f: impl FnOnce() -> R) -> R {
+    let forced = FORCE_NO_TOKEN_API.with(|force| force.replace(true));
+    let result = f();
+    FORCE_NO_TOKEN_API.with(|force| force.set(forced));
+    result
+}
+
+/// Whether we can get a token for the current process and look up its profile directory.
+///
+/// 9x/ME have no notion of access tokens. Their `advapi32` still exports `OpenProcessToken`, but
+/// only as a stub that fails with `ERROR_CALL_NOT_IMPLEMENTED`.
+#[cfg(not(target_vendor = "uwp"))]
+fn token_api_available() -> bool {
+    #[cfg(test)]
+    if FORCE_NO_TOKEN_API.with(|force| force.get()) {
+        return false;
+    }
+
+    compat::is_windows_nt()
+        && c::OpenProcessToken::available()
+        && c::GetUserProfileDirectoryW::option().is_some()
+}
+
 #[cfg(not(target_vendor = "uwp"))]
 fn home_dir_crt() -> Option<PathBuf> {
-    if c::OpenProcessToken::option().is_none() || c::GetUserProfileDirectoryW::option().is_none() {
+    // Without tokens there is no per-user profile to ask for, so all we have is the environment.
+    if !token_api_available() {
         return None;
     }
 
@@ -1,4 +1,8 @@
+use crate::ffi::OsString;
 use crate::io::Error;
+use crate::os::windows::ffi::OsStrExt;
+use crate::path::Path;
+use crate::slice;
 use crate::sys::c;
 
 // tests `error_string` above
@@ -11,3 +15,111 @@ fn ntstatus_error() {
             .contains("FormatMessageW() returned error")
     );
 }
+
+#[test]
+#[cfg(not(target_vendor = "uwp"))]
+fn home_dir_without_token_api() {
+    use crate::env;
+    use crate::path::PathBuf;
+
+    let (crt, home) = super::without_token_api(|| (super::home_dir_crt(), super::home_dir()));
+
+    assert_eq!(crt, None);
+    let from_env = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).map(PathBuf::from);
+    assert_eq!(home, from_env);
+}
+
+/// Stores `path` into the buffer like `GetModuleFileNameW` does, truncating it without a null
+/// terminator if the buffer is too small, and setting the last error like XP does if `xp`.
+unsafe fn fake_module_file_name(path: &[u16], buf: *mut u16, sz: u32, xp: bool) -> u32 {
+    let buf = slice::from_raw_parts_mut(buf, sz as usize);
+    if path.len() < buf.len() {
+        buf[..path.len()].copy_from_slice(path);
+        buf[path.len()] = 0;
+        path.len() as u32
+    } else {
+        buf.copy_from_slice(&path[..buf.len()]);
+        c::SetLastError(if xp { 0 } else { c::ERROR_INSUFFICIENT_BUFFER });
+        sz
+    }
+}
+
+#[test]
+fn current_exe_retries_truncated_paths() {
+    // Longer than the initial buffer, so it has to grow at least once.
+    let long: Vec<u16> =
+        OsString::from(format!(r"\\?\C:{}\exe.exe", r"\dir".repeat(300))).encode_wide().collect();
+    for xp in [false, true] {
+        let mut calls = 0;
+        let path = super::module_file_name(|buf, sz| {
+            calls += 1;
+            unsafe { fake_module_file_name(&long, buf, sz, xp) }
+        })
+        .unwrap();
+        assert!(calls > 1, "xp: {xp}");
+        // Too long to be used without the prefix, so it is kept.
+        assert_eq!(path.as_os_str().encode_wide().collect::<Vec<_>>(), long, "xp: {xp}");
+    }
+}
+
+#[test]
+fn current_exe_ansi_fallback_truncates() {
+    let ansi = b"C:\\dir\\exe.exe";
+    let mut buf = [0u16; 32];
+    unsafe {
+        let n = c::ansi::module_file_name_to_wide(ansi, buf.as_mut_ptr(), 4);
+        assert_eq!((n, c::GetLastError()), (4, c::ERROR_INSUFFICIENT_BUFFER));
+        assert_eq!(buf[..4], *OsString::from("C:\\d").encode_wide().collect::<Vec<_>>());
+
+        let n = c::ansi::module_file_name_to_wide(ansi, buf.as_mut_ptr(), buf.len() as u32);
+        assert_eq!((n, c::GetLastError()), (ansi.len() as u32, 0));
+        assert_eq!(buf[ansi.len()], 0);
+    }
+
+    let path = super::module_file_name(|buf, sz| unsafe {
+        c::ansi::module_file_name_to_wide(ansi, buf, sz)
+    })
+    .unwrap();
+    assert_eq!(path, Path::new(r"C:\dir\exe.exe"));
+}
+
+#[test]
+fn current_exe_ansi_fallback_is_lossy() {
+    // Not valid in every code page. Whatever the conversion makes of it, the path is returned.
+    let ansi = b"C:\\dir\\\xff\xfe\x81.exe";
+    let path = super::module_file_name(|buf, sz| unsafe {
+        c::ansi::module_file_name_to_wide(ansi, buf, sz)
+    })
+    .unwrap();
+    assert!(path.starts_with(r"C:\dir"), "{path:?}");
+}
+
+#[test]
+fn current_exe_strips_verbatim_prefix() {
+    fn chk(module: &str, expected: &str) {
+        let module: Vec<u16> = OsString::from(module).encode_wide().collect();
+        let path = super::module_file_name(|buf, sz| unsafe {
+            fake_module_file_name(&module, buf, sz, false)
+        })
+        .unwrap();
+        assert_eq!(path, Path::new(expected));
+    }
+
+    chk(r"C:\dir\exe.exe", r"C:\dir\exe.exe");
+    chk(r"\\?\C:\dir\exe.exe", r"C:\dir\exe.exe");
+    chk(r"\\?\UNC\server\share\exe.exe", r"\\server\share\exe.exe");
+    // These mean something else without the prefix.
+    chk(r"\\?\C:\dir.\exe.exe", r"\\?\C:\dir.\exe.exe");
+    chk(r"\\?\C:\dir \exe.exe", r"\\?\C:\dir \exe.exe");
+    chk(r"\\?\GLOBALROOT\Device\exe.exe", r"\\?\GLOBALROOT\Device\exe.exe");
+}
+
+#[test]
+fn current_exe_matches_module() {
+    let exe = crate::env::current_exe().unwrap();
+    assert!(exe.is_absolute(), "{exe:?}");
+    assert!(exe.exists(), "{exe:?}");
+    // Whichever way it was found, the path doesn't keep a prefix it doesn't need.
+    let wide: Vec<u16> = exe.as_os_str().encode_wide().collect();
+    assert_eq!(*super::strip_verbatim_prefix(&wide), *wide);
+}
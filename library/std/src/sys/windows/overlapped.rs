@@ -0,0 +1,257 @@
+//! Reads and writes using alertable I/O, with an optional timeout.
+//!
+//! On systems that support asynchronous I/O, these use `ReadFileEx` and `WriteFileEx` with a
+//! completion routine, and wait for it in an alertable `SleepEx`. Otherwise (9x/ME, NT 3.x), they
+//! degrade to plain synchronous `ReadFile` and `WriteFile` calls.
+
+use crate::cmp;
+use crate::io;
+use crate::mem::{self, MaybeUninit};
+use crate::os::windows::io::{AsRawHandle, BorrowedHandle};
+use crate::ptr;
+use crate::sys::pipe::emulation::polled_read;
+use crate::sys::{c, compat, cvt, Deadline};
+use crate::time::Duration;
+
+#[cfg(test)]
+mod tests;
+
+// Abstracts over `ReadFileEx` and `WriteFileEx`
+type AlertableIoFn = unsafe extern "system" fn(
+    BorrowedHandle<'_>,
+    c::LPVOID,
+    c::DWORD,
+    c::LPOVERLAPPED,
+    c::LPOVERLAPPED_COMPLETION_ROUTINE,
+) -> c::BOOL;
+
+/// Reads from `handle` at `offset`, or at the current position if `offset` is `None`.
+///
+/// If the read doesn't complete within `timeout`, it is cancelled and an error of kind
+/// `TimedOut` is returned.
+///
+/// Without asynchronous I/O, timeouts are only supported for pipes, by waiting for data to
+/// become available before reading. Reads from other handles block until they complete.
+///
+/// With asynchronous I/O, `handle` should have been opened for overlapped I/O. `offset` is
+/// ignored for handles that don't have a file position, like pipes. The position of files is only
+/// moved past the data if `offset` is `None`.
+pub fn overlapped_read(
+    handle: BorrowedHandle<'_>,
+    buf: &mut [MaybeUninit<u8>],
+    offset: Option<u64>,
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    // The length is clamped at u32::MAX.
+    let len = cmp::min(buf.len(), c::DWORD::MAX as usize) as c::DWORD;
+
+    unsafe {
+        if !compat::supports_async_io() {
+            return synchronous_read(handle, buf.as_mut_ptr().cast(), len, offset, timeout);
+        }
+
+        match alertable_io(handle, c::ReadFileEx, buf.as_mut_ptr().cast(), len, offset, timeout) {
+            // Unlike synchronous reads, overlapped reads at the end of a file fail.
+            Err(e) if e.raw_os_error() == Some(c::ERROR_HANDLE_EOF as i32) => Ok(0),
+            result => result,
+        }
+    }
+}
+
+/// Writes to `handle` at `offset`, or at the current position if `offset` is `None`.
+///
+/// If the write doesn't complete within `timeout`, it is cancelled and an error of kind
+/// `TimedOut` is returned.
+///
+/// Without asynchronous I/O, writes can't time out and block until they complete.
+///
+/// With asynchronous I/O, `handle` should have been opened for overlapped I/O. `offset` is
+/// ignored for handles that don't have a file position, like pipes. The position of files is only
+/// moved past the data if `offset` is `None`.
+pub fn overlapped_write(
+    handle: BorrowedHandle<'_>,
+    buf: &[u8],
+    offset: Option<u64>,
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    // The length is clamped at u32::MAX.
+    let len = cmp::min(buf.len(), c::DWORD::MAX as usize) as c::DWORD;
+
+    unsafe {
+        if !compat::supports_async_io() {
+            return synchronous_write(handle, buf.as_ptr(), len, offset);
+        }
+
+        alertable_io(handle, c::WriteFileEx, buf.as_ptr() as c::LPVOID, len, offset, timeout)
+    }
+}
+
+/// The fallback of `overlapped_read` for systems without asynchronous I/O.
+unsafe fn synchronous_read(
+    handle: BorrowedHandle<'_>,
+    buf: *mut u8,
+    len: c::DWORD,
+    offset: Option<u64>,
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    if let Some(offset) = offset {
+        seek(handle, offset)?;
+    }
+    polled_read(handle, buf, len, timeout)
+}
+
+/// The fallback of `overlapped_write` for systems without asynchronous I/O.
+unsafe fn synchronous_write(
+    handle: BorrowedHandle<'_>,
+    buf: *const u8,
+    len: c::DWORD,
+    offset: Option<u64>,
+) -> io::Result<usize> {
+    if let Some(offset) = offset {
+        seek(handle, offset)?;
+    }
+
+    let mut bytes_written = 0;
+    cvt(c::WriteFile(handle.as_raw_handle(), buf, len, &mut bytes_written, ptr::null_mut()))?;
+    Ok(bytes_written as usize)
+}
+
+unsafe fn seek(handle: BorrowedHandle<'_>, offset: u64) -> io::Result<()> {
+    cvt(c::SetFilePointerEx(handle.as_raw_handle(), offset as i64, ptr::null_mut(), c::FILE_BEGIN))
+        .map(drop)
+}
+
+/// The file position of `handle`, or `None` if it isn't a file.
+unsafe fn current_position(handle: BorrowedHandle<'_>) -> Option<u64> {
+    // Seeking in anything but a file isn't an error, but gives meaningless results.
+    if c::GetFileType(handle.as_raw_handle()) != c::FILE_TYPE_DISK {
+        return None;
+    }
+    let mut position = 0;
+    let moved = c::SetFilePointerEx(handle.as_raw_handle(), 0, &mut position, c::FILE_CURRENT);
+    (moved != c::FALSE).then_some(position as u64)
+}
+
+fn timed_out() -> io::Error {
+    io::const_io_error!(io::ErrorKind::TimedOut, "I/O operation timed out")
+}
+
+/// Performs a read or write using [`ReadFileEx`] or [`WriteFileEx`] and waits for it to complete
+/// using [Asynchronous Procedure Call] (APC).
+///
+/// # Safety
+///
+/// `buf` must be a pointer to a buffer that's valid for reads or writes
+/// up to `len` bytes. The `AlertableIoFn` must be either `ReadFileEx` or `WriteFileEx`
+///
+/// [`ReadFileEx`]: https://docs.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-readfileex
+/// [`WriteFileEx`]: https://docs.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-writefileex
+/// [Asynchronous Procedure Call]: https://docs.microsoft.com/en-us/windows/win32/sync/asynchronous-procedure-calls
+unsafe fn alertable_io(
+    handle: BorrowedHandle<'_>,
+    io: AlertableIoFn,
+    buf: c::LPVOID,
+    len: c::DWORD,
+    offset: Option<u64>,
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    // Use "alertable I/O" to synchronize the I/O.
+    // This has four steps.
+    //
+    // STEP 1: Start the asynchronous I/O operation.
+    //         This simply calls either `ReadFileEx` or `WriteFileEx`,
+    //         giving it a pointer to the buffer and callback function.
+    //
+    // STEP 2: Enter an alertable state.
+    //         The callback set in step 1 will not be called until the thread
+    //         enters an "alertable" state. This can be done using `SleepEx`.
+    //         If the timeout expires first, we cancel the operation and keep
+    //         waiting, as the callback will still be called.
+    //
+    // STEP 3: The callback
+    //         Once the I/O is complete and the thread is in an alertable state,
+    //         the callback will be run on the same thread as the call to
+    //         `ReadFileEx` or `WriteFileEx` done in step 1.
+    //         In the callback we simply set the result of the async operation.
+    //
+    // STEP 4: Return the result.
+    //         At this point we'll have a result from the callback function
+    //         and can simply return it. Note that we must not return earlier,
+    //         while the I/O is still in progress.
+
+    // The result that will be set from the asynchronous callback.
+    let mut async_result: Option<AsyncResult> = None;
+    struct AsyncResult {
+        error: u32,
+        transferred: u32,
+    }
+
+    // STEP 3: The callback.
+    unsafe extern "system" fn callback(
+        dwErrorCode: u32,
+        dwNumberOfBytesTransferred: u32,
+        lpOverlapped: *mut c::OVERLAPPED,
+    ) {
+        // Set `async_result` using a pointer smuggled through `hEvent`.
+        let result = AsyncResult { error: dwErrorCode, transferred: dwNumberOfBytesTransferred };
+        *(*lpOverlapped).hEvent.cast::<Option<AsyncResult>>() = Some(result);
+    }
+
+    // Overlapped I/O always goes to the offset it's given, and doesn't move the file position. So
+    // continue at the position, and move it past the data once the I/O completes, like
+    // synchronous I/O does.
+    let position = if offset.is_none() { current_position(handle) } else { None };
+
+    // STEP 1: Start the I/O operation.
+    let mut overlapped: c::OVERLAPPED = mem::zeroed();
+    let offset = offset.or(position).unwrap_or(0);
+    overlapped.Anonymous.Anonymous.Offset = offset as u32;
+    overlapped.Anonymous.Anonymous.OffsetHigh = (offset >> 32) as u32;
+    // `hEvent` is unused by `ReadFileEx` and `WriteFileEx`.
+    // Therefore the documentation suggests using it to smuggle a pointer to the callback.
+    overlapped.hEvent = &mut async_result as *mut _ as *mut _;
+
+    // If successful, `callback` will be called once it completes.
+    let result = io(handle, buf, len, &mut overlapped, Some(callback));
+    if result == c::FALSE {
+        // We can return here because the call failed.
+        // After this we must not return until the I/O completes.
+        return Err(io::Error::last_os_error());
+    }
+
+    let deadline = timeout.map(Deadline::new);
+    let mut cancelled = false;
+    let result = loop {
+        // STEP 2: Enter an alertable state.
+        // The second parameter of `SleepEx` is used to make this sleep alertable.
+        let ms = match &deadline {
+            Some(deadline) if !cancelled => match deadline.remaining_timeout() {
+                Some(ms) => ms,
+                None => {
+                    // `CancelIo` only fails for invalid handles, in which case there is nothing
+                    // to wait for. The callback is run with `ERROR_OPERATION_ABORTED`, unless
+                    // the I/O managed to complete in the meantime.
+                    c::CancelIo(handle.as_raw_handle());
+                    cancelled = true;
+                    c::INFINITE
+                }
+            },
+            _ => c::INFINITE,
+        };
+        c::SleepEx(ms, c::TRUE);
+        if let Some(result) = async_result {
+            break result;
+        }
+    };
+    // STEP 4: Return the result.
+    match result.error {
+        c::ERROR_SUCCESS => {
+            if let Some(position) = position {
+                seek(handle, position + u64::from(result.transferred))?;
+            }
+            Ok(result.transferred as usize)
+        }
+        c::ERROR_OPERATION_ABORTED if cancelled => Err(timed_out()),
+        error => Err(io::Error::from_raw_os_error(error as _)),
+    }
+}
@@ -0,0 +1,131 @@
+use super::{overlapped_read, overlapped_write, synchronous_read, synchronous_write};
+use crate::fs::{self, OpenOptions};
+use crate::io::ErrorKind;
+use crate::mem::MaybeUninit;
+use crate::os::windows::fs::OpenOptionsExt;
+use crate::os::windows::io::{AsHandle, BorrowedHandle, FromRawHandle, OwnedHandle};
+use crate::ptr;
+use crate::sys::{c, compat, pipe};
+use crate::sys_common::io::test::tmpdir;
+use crate::time::{Duration, Instant};
+
+fn read(
+    handle: BorrowedHandle<'_>,
+    len: usize,
+    offset: Option<u64>,
+    timeout: Option<Duration>,
+    synchronous: bool,
+) -> crate::io::Result<Vec<u8>> {
+    let mut buf = vec![MaybeUninit::<u8>::uninit(); len];
+    let n = if synchronous {
+        unsafe { synchronous_read(handle, buf.as_mut_ptr().cast(), len as u32, offset, timeout)? }
+    } else {
+        overlapped_read(handle, &mut buf, offset, timeout)?
+    };
+    Ok(buf[..n].iter().map(|b| unsafe { b.assume_init() }).collect())
+}
+
+fn write(handle: BorrowedHandle<'_>, buf: &[u8], offset: Option<u64>, synchronous: bool) -> usize {
+    if synchronous {
+        unsafe { synchronous_write(handle, buf.as_ptr(), buf.len() as u32, offset).unwrap() }
+    } else {
+        overlapped_write(handle, buf, offset, None).unwrap()
+    }
+}
+
+/// Returns the (read, write) ends of an anonymous pipe without overlapped I/O.
+fn synchronous_pipe() -> (OwnedHandle, OwnedHandle) {
+    unsafe {
+        let mut read = ptr::null_mut();
+        let mut write = ptr::null_mut();
+        assert_ne!(c::CreatePipe(&mut read, &mut write, ptr::null_mut(), 0), c::FALSE);
+        (OwnedHandle::from_raw_handle(read), OwnedHandle::from_raw_handle(write))
+    }
+}
+
+fn pipe_read_times_out(reader: BorrowedHandle<'_>, writer: BorrowedHandle<'_>, synchronous: bool) {
+    let start = Instant::now();
+    let err = read(reader, 16, None, Some(Duration::from_millis(50)), synchronous).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    // The cancelled read must not have consumed anything. Our end of the pipe is the only one
+    // opened for overlapped I/O, so always write synchronously.
+    assert_eq!(write(writer, b"hello", None, true), 5);
+    let data = read(reader, 16, None, Some(Duration::from_secs(10)), synchronous).unwrap();
+    assert_eq!(data, b"hello");
+}
+
+#[test]
+fn overlapped_pipe_read_times_out() {
+    if !compat::supports_async_io() {
+        return;
+    }
+    let pipes = pipe::anon_pipe(true, false).unwrap();
+    let reader = pipes.ours.handle().as_handle();
+    let writer = pipes.theirs.handle().as_handle();
+    pipe_read_times_out(reader, writer, false);
+}
+
+#[test]
+fn synchronous_pipe_read_times_out() {
+    let (reader, writer) = synchronous_pipe();
+    pipe_read_times_out(reader.as_handle(), writer.as_handle(), true);
+}
+
+fn file_positioned_io(file: BorrowedHandle<'_>, synchronous: bool) {
+    assert_eq!(write(file, b"hello", Some(0), synchronous), 5);
+    assert_eq!(write(file, b"world", Some(10), synchronous), 5);
+    assert_eq!(read(file, 5, Some(10), None, synchronous).unwrap(), b"world");
+    assert_eq!(read(file, 3, Some(1), None, synchronous).unwrap(), b"ell");
+    // Reading at the end of the file isn't an error.
+    assert_eq!(read(file, 5, Some(15), Some(Duration::from_secs(10)), synchronous).unwrap(), b"");
+}
+
+/// Without an offset, I/O continues at the file position.
+fn file_sequential_io(file: BorrowedHandle<'_>, synchronous: bool) {
+    assert_eq!(write(file, b"hello", None, synchronous), 5);
+    assert_eq!(write(file, b" world", None, synchronous), 6);
+    assert_eq!(read(file, 16, Some(0), None, synchronous).unwrap(), b"hello world");
+}
+
+#[test]
+fn overlapped_file_io() {
+    if !compat::supports_async_io() {
+        return;
+    }
+    let tmp = tmpdir();
+    let path = tmp.join("file");
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .custom_flags(c::FILE_FLAG_OVERLAPPED)
+        .open(&path)
+        .unwrap();
+    file_positioned_io(file.as_handle(), false);
+    drop(file);
+    assert_eq!(&fs::read(&path).unwrap()[10..], b"world");
+
+    let path = tmp.join("sequential");
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .custom_flags(c::FILE_FLAG_OVERLAPPED)
+        .open(&path)
+        .unwrap();
+    file_sequential_io(file.as_handle(), false);
+}
+
+#[test]
+fn synchronous_file_io() {
+    let tmp = tmpdir();
+    let path = tmp.join("file");
+    let file = OpenOptions::new().read(true).write(true).create(true).open(&path).unwrap();
+    file_positioned_io(file.as_handle(), true);
+
+    let path = tmp.join("sequential");
+    let file = OpenOptions::new().read(true).write(true).create(true).open(&path).unwrap();
+    file_sequential_io(file.as_handle(), true);
+}
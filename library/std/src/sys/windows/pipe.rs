@@ -1,7 +1,7 @@
 use crate::os::windows::prelude::*;
 
 use crate::ffi::OsStr;
-use crate::io::{self, BorrowedCursor, IoSlice, IoSliceMut, Read};
+use crate::io::{self, BorrowedBuf, BorrowedCursor, IoSlice, IoSliceMut, Read};
 use crate::mem;
 use crate::path::Path;
 use crate::ptr;
@@ -12,8 +12,12 @@
 use crate::sys::fs::{File, OpenOptions};
 use crate::sys::handle::Handle;
 use crate::sys::hashmap_random_keys;
+use crate::sys::overlapped::{overlapped_read, overlapped_write};
+use crate::sys::windows::{api, IoResult};
 use crate::sys_common::{FromInner, IntoInner};
 
+pub mod emulation;
+
 ////////////////////////////////////////////////////////////////////////////////
 // Anonymous pipes
 ////////////////////////////////////////////////////////////////////////////////
@@ -64,8 +68,8 @@ pub fn anon_pipe(ours_readable: bool, their_handle_inheritable: bool) -> io::Res
 
     // Since Windows 9X/ME does not support creating named pipes (only connecting to remote pipes
     // created on NT), we'll have to make do with anonymous pipes, without overlapped I/O. In
-    // particular, this means that we'll have to do reading from two threads in the case where both
-    // stdout and stderr being piped (see `read2`).
+    // particular, this means that we'll have to poll the pipes in the case where both stdout and
+    // stderr are being piped (see `read2`).
 
     // 9X/ME *does* have a kernel32 export entry for `CreateNamedPipe`, so an availability check
     // would not work. We're just gonna check the bit that's only set on non-unicode Windows
@@ -178,20 +182,19 @@ pub fn anon_pipe(ours_readable: bool, their_handle_inheritable: bool) -> io::Res
             // testing strategy
             // For more info, see https://github.com/rust-lang/rust/pull/37677.
             if handle == c::INVALID_HANDLE_VALUE {
-                let err = io::Error::last_os_error();
-                let raw_os_err = err.raw_os_error();
+                let err = api::get_last_error();
                 if tries < 10 {
-                    if raw_os_err == Some(c::ERROR_ACCESS_DENIED as i32) {
+                    if err.code == c::ERROR_ACCESS_DENIED {
                         continue;
                     } else if reject_remote_clients_flag != 0
-                        && raw_os_err == Some(c::ERROR_INVALID_PARAMETER as i32)
+                        && err.code == c::ERROR_INVALID_PARAMETER
                     {
                         reject_remote_clients_flag = 0;
                         tries -= 1;
                         continue;
                     }
                 }
-                return Err(err);
+                return Err(err).io_result();
             }
             ours = Handle::from_raw_handle(handle);
             break;
@@ -276,15 +279,6 @@ fn random_number() -> usize {
     }
 }
 
-// Abstracts over `ReadFileEx` and `WriteFileEx`
-type AlertableIoFn = unsafe extern "system" fn(
-    BorrowedHandle<'_>,
-    c::LPVOID,
-    c::DWORD,
-    c::LPOVERLAPPED,
-    c::LPOVERLAPPED_COMPLETION_ROUTINE,
-) -> c::BOOL;
-
 impl AnonPipe {
     pub fn handle(&self) -> &Handle {
         &self.inner
@@ -297,34 +291,13 @@ fn duplicate(&self) -> io::Result<Self> {
     }
 
     pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
-        if !crate::sys::compat::supports_async_io() {
-            return self.inner.read(buf);
-        }
-
-        let result = unsafe {
-            let len = crate::cmp::min(buf.len(), c::DWORD::MAX as usize) as c::DWORD;
-            self.alertable_io_internal(c::ReadFileEx, buf.as_mut_ptr() as _, len)
-        };
-
-        match result {
-            // The special treatment of BrokenPipe is to deal with Windows
-            // pipe semantics, which yields this error when *reading* from
-            // a pipe after the other end has closed; we interpret that as
-            // EOF on the pipe.
-            Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(0),
-            _ => result,
-        }
+        let mut buf = BorrowedBuf::from(buf);
+        self.read_buf(buf.unfilled())?;
+        Ok(buf.len())
     }
 
     pub fn read_buf(&self, mut buf: BorrowedCursor<'_>) -> io::Result<()> {
-        if !crate::sys::compat::supports_async_io() {
-            return self.inner.read_buf(buf);
-        }
-
-        let result = unsafe {
-            let len = crate::cmp::min(buf.capacity(), c::DWORD::MAX as usize) as c::DWORD;
-            self.alertable_io_internal(c::ReadFileEx, buf.as_mut().as_mut_ptr() as _, len)
-        };
+        let result = overlapped_read(self.inner.as_handle(), unsafe { buf.as_mut() }, None, None);
 
         match result {
             // The special treatment of BrokenPipe is to deal with Windows
@@ -356,14 +329,7 @@ pub fn read_to_end(&self, buf: &mut Vec<u8>) -> io::Result<usize> {
     }
 
     pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
-        if !crate::sys::compat::supports_async_io() {
-            return self.inner.write(buf);
-        }
-
-        unsafe {
-            let len = crate::cmp::min(buf.len(), c::DWORD::MAX as usize) as c::DWORD;
-            self.alertable_io_internal(c::WriteFileEx, buf.as_ptr() as _, len)
-        }
+        overlapped_write(self.inner.as_handle(), buf, None, None)
     }
 
     pub fn write_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
@@ -374,100 +340,6 @@ pub fn write_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
     pub fn is_write_vectored(&self) -> bool {
         self.inner.is_write_vectored()
     }
-
-    /// Synchronizes asynchronous reads or writes using our anonymous pipe.
-    ///
-    /// This is a wrapper around [`ReadFileEx`] or [`WriteFileEx`] that uses
-    /// [Asynchronous Procedure Call] (APC) to synchronize reads or writes.
-    ///
-    /// Note: This should not be used for handles we don't create.
-    ///
-    /// # Safety
-    ///
-    /// `buf` must be a pointer to a buffer that's valid for reads or writes
-    /// up to `len` bytes. The `AlertableIoFn` must be either `ReadFileEx` or `WriteFileEx`
-    ///
-    /// [`ReadFileEx`]: https://docs.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-readfileex
-    /// [`WriteFileEx`]: https://docs.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-writefileex
-    /// [Asynchronous Procedure Call]: https://docs.microsoft.com/en-us/windows/win32/sync/asynchronous-procedure-calls
-    unsafe fn alertable_io_internal(
-        &self,
-        io: AlertableIoFn,
-        buf: c::LPVOID,
-        len: c::DWORD,
-    ) -> io::Result<usize> {
-        // Use "alertable I/O" to synchronize the pipe I/O.
-        // This has four steps.
-        //
-        // STEP 1: Start the asynchronous I/O operation.
-        //         This simply calls either `ReadFileEx` or `WriteFileEx`,
-        //         giving it a pointer to the buffer and callback function.
-        //
-        // STEP 2: Enter an alertable state.
-        //         The callback set in step 1 will not be called until the thread
-        //         enters an "alertable" state. This can be done using `SleepEx`.
-        //
-        // STEP 3: The callback
-        //         Once the I/O is complete and the thread is in an alertable state,
-        //         the callback will be run on the same thread as the call to
-        //         `ReadFileEx` or `WriteFileEx` done in step 1.
-        //         In the callback we simply set the result of the async operation.
-        //
-        // STEP 4: Return the result.
-        //         At this point we'll have a result from the callback function
-        //         and can simply return it. Note that we must not return earlier,
-        //         while the I/O is still in progress.
-
-        // The result that will be set from the asynchronous callback.
-        let mut async_result: Option<AsyncResult> = None;
-        struct AsyncResult {
-            error: u32,
-            transferred: u32,
-        }
-
-        // STEP 3: The callback.
-        unsafe extern "system" fn callback(
-            dwErrorCode: u32,
-            dwNumberOfBytesTransferred: u32,
-            lpOverlapped: *mut c::OVERLAPPED,
-        ) {
-            // Set `async_result` using a pointer smuggled through `hEvent`.
-            let result =
-                AsyncResult { error: dwErrorCode, transferred: dwNumberOfBytesTransferred };
-            *(*lpOverlapped).hEvent.cast::<Option<AsyncResult>>() = Some(result);
-        }
-
-        // STEP 1: Start the I/O operation.
-        let mut overlapped: c::OVERLAPPED = crate::mem::zeroed();
-        // `hEvent` is unused by `ReadFileEx` and `WriteFileEx`.
-        // Therefore the documentation suggests using it to smuggle a pointer to the callback.
-        overlapped.hEvent = &mut async_result as *mut _ as *mut _;
-
-        // Asynchronous read of the pipe.
-        // If successful, `callback` will be called once it completes.
-        let result = io(self.inner.as_handle(), buf, len, &mut overlapped, Some(callback));
-        if result == c::FALSE {
-            // We can return here because the call failed.
-            // After this we must not return until the I/O completes.
-            return Err(io::Error::last_os_error());
-        }
-
-        // Wait indefinitely for the result.
-        let result = loop {
-            // STEP 2: Enter an alertable state.
-            // The second parameter of `SleepEx` is used to make this sleep alertable.
-            c::SleepEx(c::INFINITE, c::TRUE);
-            if let Some(result) = async_result {
-                break result;
-            }
-        };
-        // STEP 4: Return the result.
-        // `async_result` is always `Some` at this point
-        match result.error {
-            c::ERROR_SUCCESS => Ok(result.transferred as usize),
-            error => Err(io::Error::from_raw_os_error(error as _)),
-        }
-    }
 }
 
 pub fn read2(p1: AnonPipe, v1: &mut Vec<u8>, p2: AnonPipe, v2: &mut Vec<u8>) -> io::Result<()> {
@@ -475,22 +347,9 @@ pub fn read2(p1: AnonPipe, v1: &mut Vec<u8>, p2: AnonPipe, v2: &mut Vec<u8>) ->
     let p2 = p2.into_handle();
 
     if !crate::sys::compat::supports_async_io() {
-        use crate::io::Read;
-
-        // Since we are using anonymous pipes (= without overlapped I/O support) here, we can't do
-        // async waiting on both stdout and stderr at the same time on one thread, so we have to
-        // spawn an additional thread to do the waiting for the second pipe.
-
-        // See https://github.com/rust-lang/rust/pull/31618, where this was removed initially.
-        let second_pipe = crate::thread::spawn(move || {
-            let mut ret = Vec::new();
-            (&p2).read_to_end(&mut ret).map(|_| ret)
-        });
-
-        (&p1).read_to_end(v1)?;
-        *v2 = second_pipe.join().unwrap()?;
-
-        return Ok(());
+        // Since we are using anonymous pipes (= without overlapped I/O support) here, we can't
+        // wait on both stdout and stderr at the same time, so we poll them in turns instead.
+        return emulation::polled_read2(p1.as_handle(), v1, p2.as_handle(), v2);
     }
 
     let mut p1 = AsyncPipe::new(p1, v1)?;
@@ -516,7 +375,7 @@ pub fn read2(p1: AnonPipe, v1: &mut Vec<u8>, p2: AnonPipe, v2: &mut Vec<u8>) ->
                 return p1.finish();
             }
         } else {
-            return Err(io::Error::last_os_error());
+            return Err(api::get_last_error()).io_result();
         }
     }
 }
@@ -0,0 +1,382 @@
+//! Pipe reads and writes with a timeout, and the polling that emulates them on systems without
+//! overlapped I/O.
+//!
+//! On systems that support asynchronous I/O, the pipe end must have been opened for overlapped
+//! I/O, like the `ours` end returned by `anon_pipe`. The operation is started with an `OVERLAPPED`
+//! carrying a manual-reset event, which is then waited on before the result is collected with
+//! `GetOverlappedResult`.
+//!
+//! On 9x/ME, pipes are anonymous and synchronous. Reads instead poll `PeekNamedPipe`, backing off
+//! exponentially up to `MAX_POLL_INTERVAL`, and then only read what is already buffered, so that
+//! they never block past the timeout. Writes can't be emulated this way and block until they
+//! complete.
+//!
+//! `read2` polls the same way, so that neither pipe fills up (and stalls the child) while the
+//! other one is being read. If both stay empty for long, polling only costs wakeups, and the
+//! pipes are handed to blocking reads on two threads instead. Either way, the data is appended
+//! to the caller's buffers just like the overlapped reads do.
+
+use crate::cmp;
+use crate::io;
+use crate::mem;
+use crate::os::windows::io::{AsRawHandle, BorrowedHandle};
+use crate::ptr;
+use crate::sys::handle::Handle;
+use crate::sys::io::{handle_kind, HandleKind};
+use crate::sys::windows::{api, IoResult};
+use crate::sys::{c, compat, cvt, Deadline};
+use crate::thread;
+use crate::time::Duration;
+
+#[cfg(test)]
+mod tests;
+
+/// The longest the emulation sleeps between two polls of a pipe.
+const MAX_POLL_INTERVAL: c::DWORD = 10;
+
+/// How many polls in a row may find both pipes empty before `polled_read2` switches to blocking
+/// reads on two threads. At `MAX_POLL_INTERVAL`, that's about a second.
+const STARVED_POLLS: u32 = 100;
+
+/// Reads from the pipe `handle`, returning `Ok(0)` once the other end is closed.
+///
+/// If no data arrives within `timeout`, an error of kind `TimedOut` is returned. Bytes that
+/// arrive while the read is being cancelled are returned instead of being lost.
+#[allow(dead_code)] // Nothing in std reads or writes pipes with a timeout yet.
+pub fn pipe_read_timeout(
+    handle: BorrowedHandle<'_>,
+    buf: &mut [u8],
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    let result = if compat::supports_async_io() {
+        unsafe { event_read(handle, buf, timeout) }
+    } else {
+        unsafe { polled_read(handle, buf.as_mut_ptr(), clamp_len(buf.len()), timeout) }
+    };
+    match result {
+        // Reading from a pipe whose other end has been closed fails with `ERROR_BROKEN_PIPE`,
+        // which we interpret as EOF.
+        Err(e) if e.raw_os_error() == Some(c::ERROR_BROKEN_PIPE as i32) => Ok(0),
+        result => result,
+    }
+}
+
+/// Writes to the pipe `handle`.
+///
+/// If nothing can be written within `timeout`, an error of kind `TimedOut` is returned. If only
+/// part of `buf` was written when the timeout expired, the number of bytes written is returned.
+///
+/// Without asynchronous I/O, writes can't time out and block until they complete.
+#[allow(dead_code)] // Nothing in std reads or writes pipes with a timeout yet.
+pub fn pipe_write_timeout(
+    handle: BorrowedHandle<'_>,
+    buf: &[u8],
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    unsafe {
+        if compat::supports_async_io() {
+            event_write(handle, buf, timeout)
+        } else {
+            let mut written = 0;
+            let len = clamp_len(buf.len());
+            let result = c::WriteFile(
+                handle.as_raw_handle(),
+                buf.as_ptr(),
+                len,
+                &mut written,
+                ptr::null_mut(),
+            );
+            if result == c::FALSE {
+                return Err(api::get_last_error()).io_result();
+            }
+            Ok(written as usize)
+        }
+    }
+}
+
+/// Reads both pipes to the end, for `read2` on systems without overlapped I/O.
+///
+/// The pipes are polled in turns on the current thread, backing off while neither of them has
+/// data. After `STARVED_POLLS` empty polls in a row, the pipes that are still open are read with
+/// blocking reads instead, see `threaded_read2`.
+pub fn polled_read2(
+    p1: BorrowedHandle<'_>,
+    v1: &mut Vec<u8>,
+    p2: BorrowedHandle<'_>,
+    v2: &mut Vec<u8>,
+) -> io::Result<()> {
+    let mut pipes = [(p1, v1, true), (p2, v2, true)];
+    let mut backoff = Backoff::new();
+    let mut empty_polls = 0;
+    while pipes.iter().any(|(_, _, open)| *open) {
+        let mut progress = false;
+        for (pipe, dst, open) in pipes.iter_mut().filter(|(_, _, open)| *open) {
+            match unsafe { try_read_to_end(*pipe, dst)? } {
+                None => {}
+                Some(0) => *open = false,
+                Some(_) => progress = true,
+            }
+        }
+        if progress {
+            backoff.reset();
+            empty_polls = 0;
+        } else if empty_polls < STARVED_POLLS {
+            backoff.sleep(None);
+            empty_polls += 1;
+        } else {
+            let [(p1, v1, open1), (p2, v2, open2)] = pipes;
+            return match (open1, open2) {
+                (true, true) => threaded_read2(p1, v1, p2, v2),
+                (true, false) => blocking_read_to_end(p1, v1),
+                (false, _) => blocking_read_to_end(p2, v2),
+            };
+        }
+    }
+    Ok(())
+}
+
+/// Reads both pipes to the end with blocking reads, the second one on another thread.
+fn threaded_read2(
+    p1: BorrowedHandle<'_>,
+    v1: &mut Vec<u8>,
+    p2: BorrowedHandle<'_>,
+    v2: &mut Vec<u8>,
+) -> io::Result<()> {
+    thread::scope(|s| {
+        let other = thread::Builder::new().spawn_scoped(s, move || blocking_read_to_end(p2, v2))?;
+        let result = blocking_read_to_end(p1, v1);
+        // Only a panic could make joining fail, and there is nothing to panic in there.
+        let other = other.join().unwrap();
+        result.and(other)
+    })
+}
+
+/// Appends everything read from the pipe `handle` to `dst` until the other end is closed.
+fn blocking_read_to_end(handle: BorrowedHandle<'_>, dst: &mut Vec<u8>) -> io::Result<()> {
+    loop {
+        let read = unsafe {
+            // Grows `dst` the same way the overlapped reads of `read2` do.
+            let buf = super::slice_to_end(dst);
+            polled_read(handle, buf.as_mut_ptr(), clamp_len(buf.len()), None)
+        };
+        match read {
+            Ok(0) => return Ok(()),
+            Ok(read) => unsafe { dst.set_len(dst.len() + read) },
+            Err(e) if e.raw_os_error() == Some(c::ERROR_BROKEN_PIPE as i32) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Exponential backoff for polling, starting at 1 ms and capped at `MAX_POLL_INTERVAL`.
+struct Backoff {
+    interval: c::DWORD,
+}
+
+impl Backoff {
+    const fn new() -> Backoff {
+        Backoff { interval: 1 }
+    }
+
+    fn reset(&mut self) {
+        self.interval = 1;
+    }
+
+    /// Sleeps for the current interval, but not past `deadline`, and doubles the interval.
+    ///
+    /// Returns `false` without sleeping if the deadline has passed.
+    fn sleep(&mut self, deadline: Option<&Deadline>) -> bool {
+        let ms = match deadline.map(Deadline::remaining_timeout) {
+            Some(None) => return false,
+            Some(Some(remaining)) => cmp::min(remaining, self.interval),
+            None => self.interval,
+        };
+        unsafe { c::Sleep(ms) };
+        self.interval = cmp::min(self.interval * 2, MAX_POLL_INTERVAL);
+        true
+    }
+}
+
+fn clamp_len(len: usize) -> c::DWORD {
+    cmp::min(len, c::DWORD::MAX as usize) as c::DWORD
+}
+
+fn timed_out() -> io::Error {
+    io::const_io_error!(io::ErrorKind::TimedOut, "pipe operation timed out")
+}
+
+/// Returns the number of bytes buffered in the pipe `handle`, or `None` if peeking fails, e.g.
+/// because the other end was closed.
+unsafe fn peek_available(handle: BorrowedHandle<'_>) -> Option<c::DWORD> {
+    let mut available = 0;
+    let result = c::PeekNamedPipe(
+        handle.as_raw_handle(),
+        ptr::null_mut(),
+        0,
+        ptr::null_mut(),
+        &mut available,
+        ptr::null_mut(),
+    );
+    (result != c::FALSE).then_some(available)
+}
+
+/// Reads from `handle` synchronously, first polling until the pipe has data if there's a
+/// timeout.
+///
+/// The read is limited to the buffered bytes, so it returns right away. If peeking fails, the
+/// read is done anyway and reports the error, or EOF if the other end was closed. Handles that
+/// aren't pipes are read from without waiting.
+pub(crate) unsafe fn polled_read(
+    handle: BorrowedHandle<'_>,
+    buf: *mut u8,
+    mut len: c::DWORD,
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    let timeout = timeout.filter(|_| handle_kind(handle) == HandleKind::Pipe);
+    if let Some(timeout) = timeout {
+        let deadline = Deadline::new(timeout);
+        let mut backoff = Backoff::new();
+        loop {
+            match peek_available(handle) {
+                Some(0) => {}
+                Some(available) => {
+                    len = cmp::min(len, available);
+                    break;
+                }
+                None => break,
+            }
+            if !backoff.sleep(Some(&deadline)) {
+                return Err(timed_out());
+            }
+        }
+    }
+
+    let mut read = 0;
+    cvt(c::ReadFile(handle.as_raw_handle(), buf, len, &mut read, ptr::null_mut()))?;
+    Ok(read as usize)
+}
+
+/// Appends the bytes buffered in the pipe `handle` to `dst` without blocking.
+///
+/// Returns `None` if there is no data yet, and `Some(0)` at EOF.
+unsafe fn try_read_to_end(
+    handle: BorrowedHandle<'_>,
+    dst: &mut Vec<u8>,
+) -> io::Result<Option<usize>> {
+    let len = match peek_available(handle) {
+        Some(0) => return Ok(None),
+        Some(available) => available as usize,
+        // Let the read report why peeking failed.
+        None => 1,
+    };
+    dst.reserve(len);
+    let spare = dst.spare_capacity_mut();
+    match polled_read(handle, spare.as_mut_ptr().cast(), clamp_len(len), None) {
+        Ok(read) => {
+            dst.set_len(dst.len() + read);
+            Ok(Some(read))
+        }
+        Err(e) if e.raw_os_error() == Some(c::ERROR_BROKEN_PIPE as i32) => Ok(Some(0)),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) unsafe fn event_read(
+    handle: BorrowedHandle<'_>,
+    buf: &mut [u8],
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    let len = clamp_len(buf.len());
+    event_io(handle, timeout, |overlapped, transferred| {
+        c::ReadFile(handle.as_raw_handle(), buf.as_mut_ptr(), len, transferred, overlapped)
+    })
+}
+
+pub(crate) unsafe fn event_write(
+    handle: BorrowedHandle<'_>,
+    buf: &[u8],
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    let len = clamp_len(buf.len());
+    event_io(handle, timeout, |overlapped, transferred| {
+        c::WriteFile(handle.as_raw_handle(), buf.as_ptr(), len, transferred, overlapped)
+    })
+}
+
+/// Starts an overlapped operation with `start` and waits for its manual-reset event.
+///
+/// If the timeout expires first, the operation is cancelled. As it may have completed in the
+/// meantime, `GetOverlappedResult` decides whether the result is a timeout or the bytes that
+/// were transferred after all.
+///
+/// # Safety
+///
+/// `start` must start a read or write on `handle` with the given `OVERLAPPED`, using buffers that
+/// stay valid until this function returns.
+unsafe fn event_io(
+    handle: BorrowedHandle<'_>,
+    timeout: Option<Duration>,
+    start: impl FnOnce(*mut c::OVERLAPPED, *mut c::DWORD) -> c::BOOL,
+) -> io::Result<usize> {
+    let event = Handle::new_event(true, false)?;
+    let mut overlapped: c::OVERLAPPED = mem::zeroed();
+    overlapped.hEvent = event.as_raw_handle();
+
+    let mut transferred = 0;
+    if start(&mut overlapped, &mut transferred) != c::FALSE {
+        return Ok(transferred as usize);
+    }
+    let error = api::get_last_error();
+    if error.code != c::ERROR_IO_PENDING {
+        return Err(error).io_result();
+    }
+    // From here on, the operation is in flight and we must not return until it completes.
+
+    let deadline = timeout.map(Deadline::new);
+    let mut cancelled = false;
+    loop {
+        let ms = match &deadline {
+            None => c::INFINITE,
+            Some(deadline) => match deadline.remaining_timeout() {
+                Some(ms) => ms,
+                None => {
+                    // `CancelIo` only fails for invalid handles, in which case there is nothing
+                    // to wait for.
+                    c::CancelIo(handle.as_raw_handle());
+                    cancelled = true;
+                    break;
+                }
+            },
+        };
+        match c::WaitForSingleObject(event.as_raw_handle(), ms) {
+            c::WAIT_OBJECT_0 => break,
+            c::WAIT_TIMEOUT => {}
+            _ => {
+                // Waiting on our own event can't really fail, but the operation must still be
+                // finished before the buffers go away.
+                let error = api::get_last_error();
+                c::CancelIo(handle.as_raw_handle());
+                c::GetOverlappedResult(
+                    handle.as_raw_handle(),
+                    &overlapped,
+                    &mut transferred,
+                    c::TRUE,
+                );
+                return Err(error).io_result();
+            }
+        }
+    }
+
+    let result =
+        c::GetOverlappedResult(handle.as_raw_handle(), &overlapped, &mut transferred, c::TRUE);
+    if result != c::FALSE {
+        return Ok(transferred as usize);
+    }
+    let error = api::get_last_error();
+    match error.code {
+        // A cancelled write may have been partially done, which the caller needs to know.
+        c::ERROR_OPERATION_ABORTED if cancelled && transferred > 0 => Ok(transferred as usize),
+        c::ERROR_OPERATION_ABORTED if cancelled => Err(timed_out()),
+        _ => Err(error).io_result(),
+    }
+}
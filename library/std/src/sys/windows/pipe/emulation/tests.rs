@@ -0,0 +1,199 @@
+use super::{
+    event_read, event_write, polled_read, polled_read2, threaded_read2, Backoff, MAX_POLL_INTERVAL,
+};
+use crate::fs;
+use crate::io::{ErrorKind, Result};
+use crate::os::windows::io::{AsHandle, AsRawHandle, BorrowedHandle, FromRawHandle, OwnedHandle};
+use crate::os::windows::process::CommandExt;
+use crate::process::Command;
+use crate::ptr;
+use crate::sys::{c, compat, pipe};
+use crate::sys_common::io::test::tmpdir;
+use crate::sys_common::IntoInner;
+use crate::thread;
+use crate::time::{Duration, Instant};
+
+/// How much later than requested a timeout may expire.
+const TOLERANCE: Duration = Duration::from_secs(1);
+
+/// Returns the (read, write) ends of an anonymous pipe without overlapped I/O, like on 9x/ME.
+fn synchronous_pipe() -> (OwnedHandle, OwnedHandle) {
+    unsafe {
+        let mut read = ptr::null_mut();
+        let mut write = ptr::null_mut();
+        assert_ne!(c::CreatePipe(&mut read, &mut write, ptr::null_mut(), 0), c::FALSE);
+        (OwnedHandle::from_raw_handle(read), OwnedHandle::from_raw_handle(write))
+    }
+}
+
+fn write_all(handle: BorrowedHandle<'_>, mut buf: &[u8]) {
+    while !buf.is_empty() {
+        let mut written = 0;
+        let result = unsafe {
+            c::WriteFile(
+                handle.as_raw_handle(),
+                buf.as_ptr(),
+                buf.len() as u32,
+                &mut written,
+                ptr::null_mut(),
+            )
+        };
+        assert_ne!(result, c::FALSE);
+        buf = &buf[written as usize..];
+    }
+}
+
+/// Checks that a read times out while a slow writer hasn't written anything, and that the data
+/// written afterwards is read in full.
+fn slow_writer(writer: OwnedHandle, read: impl Fn(&mut [u8], Duration) -> Result<usize>) {
+    let slow_writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        write_all(writer.as_handle(), b"hello");
+    });
+
+    let mut buf = [0; 16];
+    let timeout = Duration::from_millis(50);
+    let start = Instant::now();
+    let err = read(&mut buf, timeout).unwrap_err();
+    let elapsed = start.elapsed();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert!(elapsed >= timeout, "timed out early after {elapsed:?}");
+    assert!(elapsed < timeout + TOLERANCE, "timed out late after {elapsed:?}");
+
+    // Nothing written after the timeout is lost.
+    let mut data = Vec::new();
+    while data.len() < 5 {
+        let n = read(&mut buf, Duration::from_secs(10)).unwrap();
+        assert_ne!(n, 0);
+        data.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(data, b"hello");
+    slow_writer.join().unwrap();
+
+    // The writer is gone, so the next read fails with a broken pipe, which is EOF.
+    let err = read(&mut buf, Duration::from_secs(10)).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(c::ERROR_BROKEN_PIPE as i32));
+}
+
+#[test]
+fn event_read_slow_writer() {
+    if !compat::supports_async_io() {
+        return;
+    }
+    let pipe::Pipes { ours, theirs } = pipe::anon_pipe(true, false).unwrap();
+    let reader = ours.handle().as_handle();
+    let writer = theirs.into_handle().into_inner();
+    slow_writer(writer, |buf, timeout| unsafe { event_read(reader, buf, Some(timeout)) });
+}
+
+#[test]
+fn polled_read_slow_writer() {
+    let (reader, writer) = synchronous_pipe();
+    let reader = reader.as_handle();
+    slow_writer(writer, |buf, timeout| unsafe {
+        polled_read(reader, buf.as_mut_ptr(), buf.len() as u32, Some(timeout))
+    });
+}
+
+#[test]
+fn event_write_times_out() {
+    if !compat::supports_async_io() {
+        return;
+    }
+    let pipe::Pipes { ours, theirs: _reader } = pipe::anon_pipe(false, false).unwrap();
+    let writer = ours.handle().as_handle();
+
+    // Nobody reads, so the write stalls once the pipe buffer is full.
+    let buf = vec![0; 1024 * 1024];
+    let timeout = Duration::from_millis(50);
+    let start = Instant::now();
+    let result = unsafe { event_write(writer, &buf, Some(timeout)) };
+    let elapsed = start.elapsed();
+    match result {
+        Ok(n) => assert!(n > 0 && n < buf.len()),
+        Err(e) => assert_eq!(e.kind(), ErrorKind::TimedOut),
+    }
+    assert!(elapsed >= timeout, "timed out early after {elapsed:?}");
+    assert!(elapsed < timeout + TOLERANCE, "timed out late after {elapsed:?}");
+}
+
+#[test]
+fn polled_read2_slow_writers() {
+    let (r1, w1) = synchronous_pipe();
+    let (r2, w2) = synchronous_pipe();
+    let writer = |handle: OwnedHandle, chunk: &'static [u8]| {
+        thread::spawn(move || {
+            for _ in 0..10 {
+                write_all(handle.as_handle(), chunk);
+                thread::sleep(Duration::from_millis(5));
+            }
+        })
+    };
+    let w1 = writer(w1, b"out");
+    let w2 = writer(w2, b"err");
+
+    let (mut v1, mut v2) = (Vec::new(), Vec::new());
+    polled_read2(r1.as_handle(), &mut v1, r2.as_handle(), &mut v2).unwrap();
+    w1.join().unwrap();
+    w2.join().unwrap();
+    assert_eq!(v1, b"out".repeat(10));
+    assert_eq!(v2, b"err".repeat(10));
+}
+
+#[test]
+fn threaded_read2_slow_writers() {
+    let (r1, w1) = synchronous_pipe();
+    let (r2, w2) = synchronous_pipe();
+    let writer = |handle: OwnedHandle, chunk: &'static [u8]| {
+        thread::spawn(move || {
+            for _ in 0..10 {
+                write_all(handle.as_handle(), chunk);
+                thread::sleep(Duration::from_millis(5));
+            }
+        })
+    };
+    let w1 = writer(w1, b"out");
+    let w2 = writer(w2, b"err");
+
+    let (mut v1, mut v2) = (Vec::new(), Vec::new());
+    threaded_read2(r1.as_handle(), &mut v1, r2.as_handle(), &mut v2).unwrap();
+    w1.join().unwrap();
+    w2.join().unwrap();
+    assert_eq!(v1, b"out".repeat(10));
+    assert_eq!(v2, b"err".repeat(10));
+}
+
+#[test]
+fn output_drains_both_streams_with_sync_io() {
+    // More than fits into either pipe, written to both streams in turns, so reading only one
+    // of them at a time would leave the child stuck writing to the other.
+    let dir = tmpdir();
+    let chunk: Vec<u8> = (0..512 * 1024).map(|i| (i % 251) as u8).collect();
+    fs::write(dir.join("chunk"), &chunk).unwrap();
+
+    let output = compat::with_sync_io(|| {
+        Command::new("cmd")
+            .current_dir(dir.path())
+            .raw_arg("/C for /L %i in (1,1,4) do @(type chunk 1>&2 & type chunk)")
+            .output()
+            .unwrap()
+    });
+    assert!(output.status.success());
+    assert_eq!(output.stdout.len(), chunk.len() * 4);
+    assert_eq!(output.stderr.len(), chunk.len() * 4);
+    assert!(output.stdout.chunks(chunk.len()).all(|c| c == chunk));
+    assert!(output.stderr.chunks(chunk.len()).all(|c| c == chunk));
+}
+
+#[test]
+fn backoff_is_capped() {
+    let mut backoff = Backoff::new();
+    let mut intervals = Vec::new();
+    for _ in 0..6 {
+        intervals.push(backoff.interval);
+        assert!(backoff.sleep(None));
+    }
+    assert_eq!(intervals, [1, 2, 4, 8, MAX_POLL_INTERVAL, MAX_POLL_INTERVAL]);
+    backoff.reset();
+    assert_eq!(backoff.interval, 1);
+}
@@ -72,12 +72,11 @@ fn new<T: Into<OsString>>(key: T) -> Self {
 // [2] https://docs.microsoft.com/en-us/dotnet/standard/base-types/best-practices-strings#stringtoupper-and-stringtolower
 // [3] https://docs.microsoft.com/en-us/dotnet/api/system.stringcomparison?view=net-5.0#System_StringComparison_Ordinal
 // [4] https://docs.microsoft.com/en-us/windows/win32/api/stringapiset/nf-stringapiset-comparestringordinal
+//
+// Before Vista, `CompareStringOrdinal` falls back to a comparison that case-folds with Rust's
+// uppercase mapping instead, which agrees with Windows' for all but a few characters.
 impl Ord for EnvKey {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        if !c::CompareStringOrdinal::option().is_some() {
-            return self.os_string.cmp(&other.os_string);
-        }
-
         unsafe {
             let result = c::CompareStringOrdinal(
                 self.utf16.as_ptr(),
@@ -117,23 +116,15 @@ fn partial_cmp(&self, other: &str) -> Option<cmp::Ordering> {
 }
 impl PartialEq<str> for EnvKey {
     fn eq(&self, other: &str) -> bool {
-        if self.os_string.len() != other.len() {
-            false
-        } else {
-            self.cmp(&EnvKey::new(other)) == cmp::Ordering::Equal
-        }
+        // Case-folding keeps the number of UTF-16 code units, but not the number of bytes.
+        *self == EnvKey::new(other)
     }
 }
 
 // Environment variable keys should preserve their original case even though
 // they are compared using a caseless string mapping.
 impl From<OsString> for EnvKey {
-    fn from(mut k: OsString) -> Self {
-        if !c::CompareStringOrdinal::option().is_some() {
-            k.make_ascii_uppercase();
-            return EnvKey { utf16: Vec::new(), os_string: k };
-        }
-
+    fn from(k: OsString) -> Self {
         EnvKey { utf16: k.encode_wide().collect(), os_string: k }
     }
 }
@@ -281,21 +272,16 @@ pub fn spawn(
             None
         };
         let program = resolve_exe(&self.program, || env::var_os("PATH"), child_paths)?;
-        // Case insensitive "ends_with" of UTF-16 encoded ".bat" or ".cmd"
-
-        let end = program.len().checked_sub(5).and_then(|i| program.get(i..));
-        let is_batch_file = if compat::is_windows_nt() {
-            matches!(
-                end,
-                Some([46, 98 | 66, 97 | 65, 116 | 84, 0] | [46, 99 | 67, 109 | 77, 100 | 68, 0])
-            )
-        } else {
-            matches!(end, Some([46, 98 | 66, 97 | 65, 116 | 84, 0]))
-        };
-        let (program, mut cmd_str) = if is_batch_file {
+        let interpreter = args::BatInterpreter::current();
+        let (program, mut cmd_str) = if interpreter.runs(&program) {
             (
-                command_prompt()?,
-                args::make_bat_command_line(&program, &self.args, self.force_quotes_enabled)?,
+                command_prompt(interpreter)?,
+                args::make_bat_command_line(
+                    interpreter,
+                    &program,
+                    &self.args,
+                    self.force_quotes_enabled,
+                )?,
             )
         } else {
             let cmd_str = make_command_line(&self.program, &self.args, self.force_quotes_enabled)?;
@@ -305,7 +291,8 @@ pub fn spawn(
 
         // stolen from the libuv code.
         let mut flags = self.flags;
-        if compat::is_windows_nt() {
+        let ansi = ansi_spawn();
+        if !ansi {
             // a unicode environment is not supported on 9x/ME
             flags |= c::CREATE_UNICODE_ENVIRONMENT;
         }
@@ -313,8 +300,8 @@ pub fn spawn(
             flags |= c::DETACHED_PROCESS | c::CREATE_NEW_PROCESS_GROUP;
         }
 
-        let (envp, _data) = make_envp(maybe_env)?;
-        let (dirp, _data) = make_dirp(self.cwd.as_ref())?;
+        let (envp, env_block) = make_envp(maybe_env)?;
+        let (dirp, dir) = make_dirp(self.cwd.as_ref())?;
         let mut pi = zeroed_process_information();
 
         // Prepare all stdio handles to be inherited by the child. This
@@ -326,6 +313,10 @@ pub fn spawn(
         //
         // For more information, msdn also has an article about this race:
         // https://support.microsoft.com/kb/315939
+        //
+        // This is also the only way to hand specific handles to a child on 9x/ME, which can't
+        // change the inheritance flag of an existing handle. The duplicates are closed when they
+        // are dropped at the end of this function, whether the child was created or not.
         static CREATE_PROCESS_LOCK: Mutex<()> = Mutex::new(());
 
         let _guard = CREATE_PROCESS_LOCK.lock();
@@ -359,11 +350,15 @@ pub fn spawn(
         let mut proc_thread_attribute_list;
         let mut si_ex;
 
+        // Attribute lists are only used for attributes set through `raw_attribute`. Handle
+        // inheritance doesn't need them: std never creates inheritable handles other than the
+        // stdio duplicates above, which the lock keeps other spawns from picking up. Any handle
+        // made inheritable outside of std is inherited by every child, with or without them.
         if !self.proc_thread_attributes.is_empty() {
-            if c::InitializeProcThreadAttributeList::option().is_none() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Setting proc_thread_attributes is not supported on this version of Windows",
+            if ansi || !attribute_lists_available() {
+                return Err(io::const_io_error!(
+                    io::ErrorKind::Unsupported,
+                    "setting proc_thread_attributes is not supported on this version of Windows",
                 ));
             }
 
@@ -383,19 +378,26 @@ pub fn spawn(
         }
 
         unsafe {
-            cvt(c::CreateProcessW(
-                program.as_ptr(),
-                cmd_str.as_mut_ptr(),
-                ptr::null_mut(),
-                ptr::null_mut(),
-                c::TRUE,
-                flags,
-                envp,
-                dirp,
-                si_ptr,
-                &mut pi,
-            ))
-        }?;
+            if !ansi {
+                cvt(c::CreateProcessW(
+                    program.as_ptr(),
+                    cmd_str.as_mut_ptr(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    c::TRUE,
+                    flags,
+                    envp,
+                    dirp,
+                    si_ptr,
+                    &mut pi,
+                ))?;
+            } else {
+                // Process thread attributes aren't supported on 9x/ME, so this is always `si`.
+                let env_block = (!envp.is_null()).then_some(&env_block[..]);
+                let dir = (!dirp.is_null()).then_some(&dir[..]);
+                create_process_ansi(&program, &cmd_str, flags, env_block, dir, &si, &mut pi)?;
+            }
+        }
 
         unsafe {
             Ok((
@@ -697,30 +699,40 @@ pub fn main_thread_handle(&self) -> BorrowedHandle<'_> {
     }
 
     pub fn wait(&mut self) -> io::Result<ExitStatus> {
-        unsafe {
-            let res = c::WaitForSingleObject(self.handle.as_raw_handle(), c::INFINITE);
-            if res != c::WAIT_OBJECT_0 {
-                return Err(Error::last_os_error());
+        loop {
+            // Waits without a timeout only end when the process exits.
+            if let Some(status) = self.wait_timeout(c::INFINITE)? {
+                return Ok(status);
             }
-            let mut status = 0;
-            cvt(c::GetExitCodeProcess(self.handle.as_raw_handle(), &mut status))?;
-            Ok(ExitStatus(status))
         }
     }
 
     pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
-        unsafe {
-            match c::WaitForSingleObject(self.handle.as_raw_handle(), 0) {
-                c::WAIT_OBJECT_0 => {}
-                c::WAIT_TIMEOUT => {
-                    return Ok(None);
-                }
-                _ => return Err(io::Error::last_os_error()),
+        self.wait_timeout(0)
+    }
+
+    /// Waits up to `ms` milliseconds for the process to exit, and returns its exit status if it
+    /// did.
+    ///
+    /// Only the wait tells whether the process has exited. `GetExitCodeProcess` reports
+    /// `STILL_ACTIVE` (259) for running processes, but a process may just as well have exited
+    /// with that code. On 9x/ME, the exit code is only ever read once the wait said so.
+    fn wait_timeout(&self, ms: c::DWORD) -> io::Result<Option<ExitStatus>> {
+        match unsafe { c::WaitForSingleObject(self.handle.as_raw_handle(), ms) } {
+            c::WAIT_OBJECT_0 => {}
+            c::WAIT_TIMEOUT => return Ok(None),
+            c::WAIT_FAILED => return Err(Error::last_os_error()),
+            // Not possible for a process handle, and there's no last error to report for it.
+            _ => {
+                return Err(io::const_io_error!(
+                    ErrorKind::Uncategorized,
+                    "unexpected result waiting for the process",
+                ));
             }
-            let mut status = 0;
-            cvt(c::GetExitCodeProcess(self.handle.as_raw_handle(), &mut status))?;
-            Ok(Some(ExitStatus(status)))
         }
+        let mut status = 0;
+        cvt(unsafe { c::GetExitCodeProcess(self.handle.as_raw_handle(), &mut status) })?;
+        Ok(Some(ExitStatus(status)))
     }
 
     pub fn handle(&self) -> &Handle {
@@ -832,6 +844,73 @@ fn zeroed_startupinfo() -> c::STARTUPINFOW {
     }
 }
 
+/// Spawns the process with `CreateProcessA`, as 9x/ME don't have `CreateProcessW`.
+///
+/// The strings are the same ones `CreateProcessW` gets, null terminated, and are converted to the
+/// active ANSI code page. Everything in the startup info is copied over except for the strings,
+/// which `spawn` never sets.
+unsafe fn create_process_ansi(
+    program: &[u16],
+    cmd_str: &[u16],
+    flags: u32,
+    env_block: Option<&[u16]>,
+    dir: Option<&[u16]>,
+    si: &c::STARTUPINFOW,
+    pi: &mut c::PROCESS_INFORMATION,
+) -> io::Result<()> {
+    let program = to_ansi(program)?;
+    let mut cmd_str = to_ansi(cmd_str)?;
+    let env_block = env_block.map(to_ansi).transpose()?;
+    let dir = dir.map(to_ansi).transpose()?;
+    let si = c::STARTUPINFOA {
+        cb: mem::size_of::<c::STARTUPINFOA>() as c::DWORD,
+        lpReserved: ptr::null_mut(),
+        lpDesktop: ptr::null_mut(),
+        lpTitle: ptr::null_mut(),
+        dwX: si.dwX,
+        dwY: si.dwY,
+        dwXSize: si.dwXSize,
+        dwYSize: si.dwYSize,
+        dwXCountChars: si.dwXCountChars,
+        dwYCountChars: si.dwYCountChars,
+        dwFillAttribute: si.dwFillAttribute,
+        dwFlags: si.dwFlags,
+        wShowWindow: si.wShowWindow,
+        cbReserved2: si.cbReserved2,
+        lpReserved2: si.lpReserved2,
+        hStdInput: si.hStdInput,
+        hStdOutput: si.hStdOutput,
+        hStdError: si.hStdError,
+    };
+    cvt(c::CreateProcessA(
+        program.as_ptr(),
+        cmd_str.as_mut_ptr(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+        c::TRUE,
+        flags,
+        env_block.as_ref().map_or(ptr::null(), |block| block.as_ptr().cast()),
+        dir.as_ref().map_or(ptr::null(), |dir| dir.as_ptr()),
+        &si,
+        pi,
+    ))?;
+    Ok(())
+}
+
+/// Converts a string for `CreateProcessA`, keeping any nulls.
+///
+/// Characters without a representation in the active code page would reach the child as `?`, so
+/// they are rejected instead.
+fn to_ansi(wide: &[u16]) -> io::Result<Vec<u8>> {
+    c::ansi::wide_to_ansi(wide).map_err(|code| match code {
+        c::ERROR_NO_UNICODE_TRANSLATION => io::const_io_error!(
+            ErrorKind::InvalidInput,
+            "string contains characters that can't be represented in the ANSI code page",
+        ),
+        code => io::Error::from_raw_os_error(code as i32),
+    })
+}
+
 fn zeroed_process_information() -> c::PROCESS_INFORMATION {
     c::PROCESS_INFORMATION {
         hProcess: ptr::null_mut(),
@@ -863,22 +942,32 @@ fn make_command_line(argv0: &OsStr, args: &[Arg], force_quotes: bool) -> io::Res
     Ok(cmd)
 }
 
-// Get `cmd.exe` for use with bat scripts, encoded as a UTF-16 string.
-fn command_prompt() -> io::Result<Vec<u16>> {
-    if compat::is_windows_nt() {
-        let mut system: Vec<u16> = super::fill_utf16_buf(
-            |buf, size| unsafe { c::GetSystemDirectoryW(buf, size) },
-            |buf| buf.into(),
-        )?;
-        system.extend("\\cmd.exe".encode_utf16().chain([0]));
-        Ok(system)
-    } else {
-        let mut system: Vec<u16> = super::fill_utf16_buf(
-            |buf, size| unsafe { c::GetWindowsDirectoryW(buf, size) },
-            |buf| buf.into(),
-        )?;
-        system.extend("\\command.com".encode_utf16().chain([0]));
-        Ok(system)
+// Get the command interpreter for use with bat scripts, encoded as a UTF-16 string.
+//
+// On NT this is always the `cmd.exe` in the system directory, rather than whatever `%COMSPEC%`
+// points to. 9x has no such hardening to keep, and `command.com` doesn't live in the same place
+// on every installation, so there `%COMSPEC%` is used if it's set.
+fn command_prompt(interpreter: args::BatInterpreter) -> io::Result<Vec<u16>> {
+    match interpreter {
+        args::BatInterpreter::Cmd => {
+            let mut system: Vec<u16> = super::fill_utf16_buf(
+                |buf, size| unsafe { c::GetSystemDirectoryW(buf, size) },
+                |buf| buf.into(),
+            )?;
+            system.extend("\\cmd.exe".encode_utf16().chain([0]));
+            Ok(system)
+        }
+        args::BatInterpreter::CommandCom => {
+            if let Some(comspec) = env::var_os("COMSPEC").filter(|s| !s.is_empty()) {
+                return super::to_u16s(comspec);
+            }
+            let mut system: Vec<u16> = super::fill_utf16_buf(
+                |buf, size| unsafe { c::GetWindowsDirectoryW(buf, size) },
+                |buf| buf.into(),
+            )?;
+            system.extend("\\command.com".encode_utf16().chain([0]));
+            Ok(system)
+        }
     }
 }
 
@@ -896,12 +985,8 @@ fn make_envp(maybe_env: Option<BTreeMap<EnvKey, OsString>>) -> io::Result<(*mut
         }
 
         for (k, v) in env {
-            if !c::CompareStringOrdinal::option().is_some() {
-                blk.extend(ensure_no_nuls(k.os_string)?.encode_wide());
-            } else {
-                ensure_no_nuls(k.os_string)?;
-                blk.extend(k.utf16);
-            }
+            ensure_no_nuls(k.os_string)?;
+            blk.extend(k.utf16);
             blk.push('=' as u16);
             blk.extend(ensure_no_nuls(v)?.encode_wide());
             blk.push(0);
@@ -939,6 +1024,54 @@ struct ProcThreadAttributeValue {
     size: usize,
 }
 
+/// Whether children are created through `CreateProcessA`, as on 9x/ME.
+fn ansi_spawn() -> bool {
+    #[cfg(test)]
+    if FORCE_ANSI_SPAWN.with(crate::cell::Cell::get) {
+        return true;
+    }
+    !compat::is_windows_nt()
+}
+
+#[cfg(test)]
+thread_local! {
+    static FORCE_ANSI_SPAWN: crate::cell::Cell<bool> = crate::cell::Cell::new(false);
+}
+
+/// Runs `f` with children created like on 9x/ME on the current thread, so that spawning there
+/// can be tested on NT.
+#[cfg(test)]
+fn with_ansi_spawn<R>(f: impl FnOnce() -> R) -> R {
+    let forced = FORCE_ANSI_SPAWN.with(|force| force.replace(true));
+    let result = f();
+    FORCE_ANSI_SPAWN.with(|force| force.set(forced));
+    result
+}
+
+/// Whether `InitializeProcThreadAttributeList` and friends exist, which they do from Vista on.
+fn attribute_lists_available() -> bool {
+    #[cfg(test)]
+    if FORCE_NO_ATTRIBUTE_LISTS.with(crate::cell::Cell::get) {
+        return false;
+    }
+    c::InitializeProcThreadAttributeList::option().is_some()
+}
+
+#[cfg(test)]
+thread_local! {
+    static FORCE_NO_ATTRIBUTE_LISTS: crate::cell::Cell<bool> = crate::cell::Cell::new(false);
+}
+
+/// Runs `f` as if proc-thread attribute lists were missing on the current thread, so that
+/// spawning on XP and earlier can be tested on newer systems.
+#[cfg(test)]
+fn with_no_attribute_lists<R>(f: impl FnOnce() -> R) -> R {
+    let forced = FORCE_NO_ATTRIBUTE_LISTS.with(|force| force.replace(true));
+    let result = f();
+    FORCE_NO_ATTRIBUTE_LISTS.with(|force| force.set(forced));
+    result
+}
+
 fn make_proc_thread_attribute_list(
     attributes: &BTreeMap<usize, ProcThreadAttributeValue>,
 ) -> io::Result<ProcThreadAttributeList> {
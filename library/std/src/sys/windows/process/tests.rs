@@ -1,8 +1,14 @@
 use super::make_command_line;
-use super::Arg;
+use super::{make_envp, to_ansi, with_ansi_spawn, with_no_attribute_lists, Arg, EnvKey};
+use crate::collections::BTreeMap;
 use crate::env;
 use crate::ffi::{OsStr, OsString};
-use crate::process::Command;
+use crate::io::{ErrorKind, Read, Write};
+use crate::os::windows::process::CommandExt;
+use crate::process::{Command, Stdio};
+use crate::sys::c;
+use crate::sys::test::{handle_count, run_alone};
+use crate::sys_common::process::CommandEnv;
 
 #[test]
 fn test_raw_args() {
@@ -220,3 +226,205 @@ fn windows_exe_resolver() {
         );
     }
 }
+
+/// A character that no ANSI code page can represent.
+const NOT_ANSI: &str = "\u{1F980}";
+
+/// With UTF-8 as the ANSI code page, every character can be represented.
+fn ansi_is_utf8() -> bool {
+    unsafe { c::GetACP() == c::CP_UTF8 }
+}
+
+#[test]
+fn ansi_env_block() {
+    if ansi_is_utf8() {
+        return;
+    }
+    let env = |value: &str| {
+        let env = BTreeMap::from([
+            (EnvKey::from(OsStr::new("A")), OsString::from("1")),
+            (EnvKey::from(OsStr::new("B")), OsString::from(value)),
+        ]);
+        make_envp(Some(env)).unwrap().1
+    };
+
+    assert_eq!(to_ansi(&env("two")).unwrap(), b"A=1\0B=two\0\0");
+    let err = to_ansi(&env(&format!("two{NOT_ANSI}"))).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn ansi_command_line() {
+    if ansi_is_utf8() {
+        return;
+    }
+    let command_line = |arg: &str| {
+        let mut cmd =
+            make_command_line(OsStr::new("prog"), &[Arg::Regular(arg.into())], false).unwrap();
+        cmd.push(0);
+        cmd
+    };
+
+    // The quoting is the same as for `CreateProcessW`.
+    assert_eq!(to_ansi(&command_line("a \"b\"")).unwrap(), b"\"prog\" \"a \\\"b\\\"\"\0");
+    let err = to_ansi(&command_line(NOT_ANSI)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn exit_code_still_active() {
+    // Waits for a line on stdin, and then exits with the code `STILL_ACTIVE` stands for.
+    let mut child = Command::new("cmd")
+        .args(["/c", "set /p line= & exit 259"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(child.try_wait().unwrap().is_none());
+
+    child.stdin.take().unwrap().write_all(b"done\r\n").unwrap();
+    let status = child.wait().unwrap();
+    assert_eq!(status.code(), Some(259));
+    // Which doesn't make the child look like it's running again.
+    assert_eq!(child.try_wait().unwrap().and_then(|status| status.code()), Some(259));
+}
+
+#[test]
+fn spawn_without_attribute_lists() {
+    // Nothing but raw attributes needs attribute lists, so stdio still reaches the child.
+    let mut child = with_no_attribute_lists(|| {
+        Command::new("cmd").args(["/c", "echo hello"]).stdout(Stdio::piped()).spawn()
+    })
+    .unwrap();
+    let mut out = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut out).unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(out.trim(), "hello");
+
+    // Raw attributes can't be emulated, so they fail before a child is created.
+    const PROC_THREAD_ATTRIBUTE_PARENT_PROCESS: usize = 0x00020000;
+    let mut cmd = Command::new("cmd");
+    cmd.args(["/c", "exit 0"]);
+    unsafe {
+        cmd.raw_attribute(PROC_THREAD_ATTRIBUTE_PARENT_PROCESS, c::GetCurrentProcess() as isize)
+    };
+    let err = with_no_attribute_lists(|| cmd.spawn()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+}
+
+/// Pipes `input` through a child that copies its stdin to its stdout, spawned like on 9x/ME.
+fn echo_through_ansi_child(input: &str) -> String {
+    let mut child = with_ansi_spawn(|| {
+        Command::new("findstr")
+            .arg("^")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+    })
+    .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let mut out = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut out).unwrap();
+    assert!(child.wait().unwrap().success());
+    out
+}
+
+#[test]
+fn ansi_spawn_stdio() {
+    assert_eq!(echo_through_ansi_child("one\r\ntwo\r\n"), "one\r\ntwo\r\n");
+}
+
+#[test]
+fn ansi_spawn_closes_duplicates() {
+    run_alone("process::tests::ansi_spawn_closes_duplicates", || {
+        // The first spawn may set up handles that are kept for later ones.
+        echo_through_ansi_child("x\r\n");
+
+        // Once the child is gone, the inheritable duplicates it was handed are all closed again.
+        let Some(before) = handle_count() else { return };
+        for _ in 0..50 {
+            echo_through_ansi_child("x\r\n");
+        }
+        let after = handle_count().unwrap();
+        // A leak would leave 150 duplicates behind.
+        assert!(after < before + 8, "{before} handles before, {after} after");
+    });
+}
+
+#[test]
+fn ansi_spawn_failure_closes_duplicates() {
+    if ansi_is_utf8() {
+        return;
+    }
+    // Only fails once the stdio duplicates exist, when converting the command line.
+    let spawn = || {
+        let err = with_ansi_spawn(|| {
+            Command::new("findstr")
+                .arg(NOT_ANSI)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::inherit())
+                .spawn()
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    };
+    run_alone("process::tests::ansi_spawn_failure_closes_duplicates", || {
+        spawn();
+        let Some(before) = handle_count() else { return };
+        for _ in 0..50 {
+            spawn();
+        }
+        let after = handle_count().unwrap();
+        assert!(after < before + 8, "{before} handles before, {after} after");
+    });
+}
+
+/// Builds the environment block for a cleared environment with `vars` set, one after the other.
+fn env_block(vars: &[(&str, &str)]) -> String {
+    let mut env = CommandEnv::default();
+    env.clear();
+    for (key, value) in vars {
+        env.set(key.as_ref(), value.as_ref());
+    }
+    let block = make_envp(env.capture_if_changed()).unwrap().1;
+    String::from_utf16(&block).unwrap()
+}
+
+#[test]
+fn env_block_merges_case_colliding_keys() {
+    // The first spelling of a key is kept, with the last value.
+    assert_eq!(env_block(&[("Path", "a"), ("PATH", "b"), ("path", "c")]), "Path=c\0\0");
+    assert_eq!(env_block(&[("Äpfel", "1"), ("äPFEL", "2")]), "Äpfel=2\0\0");
+    // These only look alike.
+    assert_eq!(env_block(&[("ß", "1"), ("SS", "2")]), "SS=2\0ß=1\0\0");
+}
+
+#[test]
+fn env_block_sorted_ignoring_case() {
+    // Sorted by the uppercase code units, so `_` (0x5F) comes after all letters, and `Ä` (0xC4)
+    // after all ASCII.
+    let block = env_block(&[("b", "1"), ("Ä", "2"), ("_x", "3"), ("a", "4"), ("Z", "5")]);
+    assert_eq!(block, "a=4\0b=1\0Z=5\0_x=3\0Ä=2\0\0");
+}
+
+#[test]
+fn ordinal_fallback_matches_host() {
+    if !c::CompareStringOrdinal::available() {
+        return;
+    }
+    let strings = "a A ab aB b _ Path PATH ä Ä ö ß SS ss I i İ ı ſ σ Σ д Д ǆ Ǆ \u{1F980} Ａ ａ";
+    for a in strings.split(' ').chain([""]) {
+        for b in strings.split(' ').chain([""]) {
+            let a16: Vec<u16> = a.encode_utf16().collect();
+            let b16: Vec<u16> = b.encode_utf16().collect();
+            let (p1, n1, p2, n2) = (a16.as_ptr(), a16.len() as i32, b16.as_ptr(), b16.len() as i32);
+            for ignore_case in [c::FALSE, c::TRUE] {
+                let real = unsafe { c::CompareStringOrdinal(p1, n1, p2, n2, ignore_case) };
+                let fallback =
+                    unsafe { c::CompareStringOrdinal::fallback(p1, n1, p2, n2, ignore_case) };
+                assert_eq!(fallback, real, "comparing {a:?} to {b:?}, ignore case: {ignore_case}");
+            }
+        }
+    }
+}
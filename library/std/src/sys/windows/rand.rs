@@ -1,64 +1,458 @@
-use crate::mem;
+use crate::ffi::c_void;
+use crate::io;
+use crate::mem::{self, MaybeUninit};
 use crate::ptr;
-use crate::sys::c;
+use crate::slice;
+use crate::sync::atomic::{AtomicI32, AtomicPtr, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use crate::sync::OnceLock;
+use crate::sys::{c, compat};
 
-pub fn hashmap_random_keys() -> (u64, u64) {
-    let mut v = (0, 0);
-
-    if c::BCryptGenRandom::available() {
-        let ret = unsafe {
-            c::BCryptGenRandom(
-                ptr::null_mut(),
-                &mut v as *mut _ as *mut u8,
-                mem::size_of_val(&v) as c::ULONG,
-                c::BCRYPT_USE_SYSTEM_PREFERRED_RNG,
-            )
+#[cfg(test)]
+mod tests;
+
+/// The ways of generating random data, from the most to the least preferred.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+enum Backend {
+    /// `BCryptGenRandom` (Vista+).
+    BCrypt = 1,
+    /// `ProcessPrng` (8+), which doesn't need advapi32 and is allowed on UWP.
+    ProcessPrng,
+    /// `RtlGenRandom` (XP+).
+    RtlGenRandom,
+    /// `CryptGenRandom` (NT 4, 95 OSR2+).
+    CryptApi,
+    /// None of the others is left, so `fill_bytes` fails and `hashmap_random_keys` has to make do
+    /// with `fallback_keys`.
+    Weak,
+}
+
+/// Which backend is used, and what happened to `BCryptGenRandom`.
+struct Rng {
+    /// The backend in use, or 0 before the first call.
+    backend: AtomicU8,
+    /// The status `BCryptGenRandom` failed with when it was last demoted, or `STATUS_SUCCESS`.
+    bcrypt_failure: AtomicI32,
+    /// How many chunks a less preferred backend filled since `BCryptGenRandom` was demoted.
+    since_demotion: AtomicUsize,
+}
+
+impl Rng {
+    const fn new() -> Rng {
+        Rng {
+            backend: AtomicU8::new(0),
+            bcrypt_failure: AtomicI32::new(c::STATUS_SUCCESS),
+            since_demotion: AtomicUsize::new(0),
+        }
+    }
+}
+
+static RNG: Rng = Rng::new();
+
+/// The most bytes requested from a backend at once.
+const MAX_CHUNK: usize = 256;
+
+/// How often a backend is called for a chunk before it is considered broken.
+const MAX_ATTEMPTS: usize = 3;
+
+/// After how many chunks filled by another backend `BCryptGenRandom` is tried once more, in case
+/// it only failed because of a transient loader issue.
+const BCRYPT_REPROBE_AFTER: usize = 1024;
+
+impl Backend {
+    const ALL: [Backend; 5] = [
+        Backend::BCrypt,
+        Backend::ProcessPrng,
+        Backend::RtlGenRandom,
+        Backend::CryptApi,
+        Backend::Weak,
+    ];
+
+    fn from_u8(backend: u8) -> Option<Backend> {
+        Backend::ALL.into_iter().find(|b| *b as u8 == backend)
+    }
+
+    fn is_available(self) -> bool {
+        match self {
+            Backend::BCrypt => c::BCryptGenRandom::available(),
+            Backend::ProcessPrng => c::ProcessPrng::option().is_some(),
+            // We can't use RtlGenRandom or CryptGenRandom with UWP.
+            Backend::RtlGenRandom => {
+                cfg!(not(target_vendor = "uwp")) && c::SystemFunction036::available()
+            }
+            Backend::CryptApi => {
+                cfg!(not(target_vendor = "uwp"))
+                    && c::CryptAcquireContextA::available()
+                    && c::CryptGenRandom::available()
+            }
+            Backend::Weak => true,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Backend::BCrypt => "BCryptGenRandom",
+            Backend::ProcessPrng => "ProcessPrng",
+            Backend::RtlGenRandom => "RtlGenRandom",
+            Backend::CryptApi => "CryptGenRandom",
+            Backend::Weak => "weak fallback",
+        }
+    }
+
+    /// Fills `buf` with random data, returning the status the backend failed with. Backends that
+    /// only report success or failure fail with `STATUS_UNSUCCESSFUL`.
+    fn fill(self, buf: &mut [MaybeUninit<u8>]) -> Result<(), c::NTSTATUS> {
+        let len = buf.len() as u32;
+        let buf = buf.as_mut_ptr().cast::<u8>();
+        let succeeded = unsafe {
+            match self {
+                Backend::BCrypt => {
+                    let (algorithm, flags) = bcrypt_algorithm();
+                    let status = c::BCryptGenRandom(algorithm, buf, len, flags);
+                    return if c::nt_success(status) { Ok(()) } else { Err(status) };
+                }
+                // RtlGenRandom is also needed where BCryptGenRandom fails to load the SysWOW64
+                // variant of the bcryptprimitives.dll library from code that lives in bcrypt.dll.
+                // See <https://bugzilla.mozilla.org/show_bug.cgi?id=1788004#c9>
+                Backend::ProcessPrng => match c::ProcessPrng::option() {
+                    Some(process_prng) => process_prng(buf, len as usize) != c::FALSE,
+                    None => false,
+                },
+                Backend::RtlGenRandom => c::RtlGenRandom(buf.cast(), len) != 0,
+                Backend::CryptApi => crypt_gen_random(buf, len),
+                Backend::Weak => false,
+            }
         };
-        if c::nt_success(ret) { v } else { fallback_rng() }
-    } else if c::SystemFunction036::available() {
-        fallback_rng()
-    } else {
-        true_fallback_rng()
+        if succeeded { Ok(()) } else { Err(c::STATUS_UNSUCCESSFUL) }
     }
 }
 
-/// Generate random numbers using the fallback RNG function (RtlGenRandom)
+/// Returns the name of the backend in use, for diagnostics.
 ///
-/// This is necessary because of a failure to load the SysWOW64 variant of the
-/// bcryptprimitives.dll library from code that lives in bcrypt.dll
-/// See <https://bugzilla.mozilla.org/show_bug.cgi?id=1788004#c9>
-#[cfg(not(target_vendor = "uwp"))]
-#[inline(never)]
-fn fallback_rng() -> (u64, u64) {
-    use crate::ffi::c_void;
-    use crate::io;
+/// This doesn't resolve the backend, so it's "unresolved" until random data was requested first.
+pub fn backend_name() -> &'static str {
+    Backend::from_u8(RNG.backend.load(Ordering::Relaxed)).map_or("unresolved", Backend::name)
+}
 
-    let mut v = (0, 0);
-    let ret = unsafe {
-        c::RtlGenRandom(&mut v as *mut _ as *mut c_void, mem::size_of_val(&v) as c::ULONG)
-    };
+/// Returns the status `BCryptGenRandom` failed with when it was last replaced by another backend,
+/// for diagnostics.
+pub fn bcrypt_failure() -> Option<c::NTSTATUS> {
+    let status = RNG.bcrypt_failure.load(Ordering::Relaxed);
+    if status == c::STATUS_SUCCESS { None } else { Some(status) }
+}
 
-    if ret != 0 { v } else { panic!("fallback RNG broken: {}", io::Error::last_os_error()) }
+pub fn hashmap_random_keys() -> (u64, u64) {
+    let mut keys = (0u64, 0u64);
+    let len = mem::size_of_val(&keys);
+    // Viewing initialized memory as `MaybeUninit` is fine.
+    let buf = unsafe { slice::from_raw_parts_mut(ptr::addr_of_mut!(keys).cast(), len) };
+    match fill_bytes(buf) {
+        Ok(()) => keys,
+        // The weak fallback isn't an option for store apps.
+        Err(e) if cfg!(target_vendor = "uwp") => panic!("no RNG available on UWP: {e}"),
+        // Builds that would rather abort than have guessable hash map keys.
+        Err(e) if cfg!(feature = "strict_rng") => panic!("no secure RNG available: {e}"),
+        Err(_) => fallback_keys(),
+    }
 }
 
-/// We can't use RtlGenRandom with UWP, so there is no fallback
-#[cfg(target_vendor = "uwp")]
-#[inline(never)]
-fn fallback_rng() -> (u64, u64) {
-    panic!("fallback RNG broken: RtlGenRandom() not supported on UWP");
+/// The final tier, used when none of the secure backends works.
+///
+/// The weak entropy is gathered only once, as a per-process key. Every call then hashes the next
+/// value of a counter with that key, so the keys can't be predicted from earlier ones without
+/// knowing the key and don't repeat within the process.
+fn fallback_keys() -> (u64, u64) {
+    static KEY: OnceLock<(u64, u64)> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    keyed_counter(*KEY.get_or_init(true_fallback_rng), &COUNTER)
+}
+
+fn keyed_counter(key: (u64, u64), counter: &AtomicU64) -> (u64, u64) {
+    mix(key, &[counter.fetch_add(1, Ordering::Relaxed)])
+}
+
+/// Fills `buf` with cryptographically secure random data.
+///
+/// Fails if no secure backend is available or all of them failed, as `true_fallback_rng` isn't
+/// good enough for anything but hash map keys.
+pub fn fill_bytes(buf: &mut [MaybeUninit<u8>]) -> io::Result<()> {
+    fill_from(&RNG, Backend::is_available, Backend::fill, buf)
+}
+
+/// Fills `buf` in chunks of up to `MAX_CHUNK` bytes with the backend stored in `rng`, which is
+/// resolved to the first available one on first use.
+///
+/// Failures are retried up to `MAX_ATTEMPTS` times per chunk, as `BCryptGenRandom` can fail
+/// transiently. After that, the next available backend is stored instead, so a broken provider
+/// isn't tried over and over again.
+///
+/// `BCryptGenRandom` is known to fail under SysWOW64 when bcrypt.dll can't load
+/// bcryptprimitives.dll, which may only be temporary. So its status is recorded when it's
+/// demoted, and it's probed once more after `BCRYPT_REPROBE_AFTER` chunks, taking over again if
+/// it works by then.
+fn fill_from(
+    rng: &Rng,
+    is_available: impl Fn(Backend) -> bool,
+    mut fill: impl FnMut(Backend, &mut [MaybeUninit<u8>]) -> Result<(), c::NTSTATUS>,
+    buf: &mut [MaybeUninit<u8>],
+) -> io::Result<()> {
+    let backend = &rng.backend;
+    let mut current = backend.load(Ordering::Relaxed);
+    for chunk in buf.chunks_mut(MAX_CHUNK) {
+        loop {
+            let selected = match Backend::from_u8(current) {
+                Some(Backend::Weak) => {
+                    return Err(io::const_io_error!(
+                        io::ErrorKind::Unsupported,
+                        "no secure random number generator is available",
+                    ));
+                }
+                Some(selected) => selected,
+                None => {
+                    current = replace_backend(backend, current, next_available(0, &is_available));
+                    continue;
+                }
+            };
+            let mut status = c::STATUS_SUCCESS;
+            let filled = (0..MAX_ATTEMPTS).any(|_| match fill(selected, chunk) {
+                Ok(()) => true,
+                Err(failure) => {
+                    status = failure;
+                    false
+                }
+            });
+            if filled {
+                if selected != Backend::BCrypt && should_reprobe_bcrypt(rng) {
+                    match fill(Backend::BCrypt, chunk) {
+                        Ok(()) => current = replace_backend(backend, current, Backend::BCrypt),
+                        // It stays demoted for good.
+                        Err(status) => rng.bcrypt_failure.store(status, Ordering::Relaxed),
+                    }
+                }
+                break;
+            }
+
+            if selected == Backend::BCrypt {
+                record_bcrypt_failure(rng, status);
+            }
+            // Demote the backend, unless another thread has done so already.
+            let next = next_available(selected as u8 + 1, &is_available);
+            current = replace_backend(backend, current, next);
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether `BCryptGenRandom` was demoted and exactly `BCRYPT_REPROBE_AFTER` chunks have
+/// been filled since, counting this one.
+fn should_reprobe_bcrypt(rng: &Rng) -> bool {
+    rng.bcrypt_failure.load(Ordering::Relaxed) != c::STATUS_SUCCESS
+        && rng.since_demotion.fetch_add(1, Ordering::Relaxed) + 1 == BCRYPT_REPROBE_AFTER
+}
+
+/// Records why `BCryptGenRandom` is being demoted and restarts the count towards the next probe.
+#[cold]
+fn record_bcrypt_failure(rng: &Rng, status: c::NTSTATUS) {
+    rng.since_demotion.store(0, Ordering::Relaxed);
+    let previous = rng.bcrypt_failure.swap(status, Ordering::Relaxed);
+    if previous == c::STATUS_SUCCESS && cfg!(debug_assertions) {
+        rtprintpanic!("warning: BCryptGenRandom failed with {status:#x}, using another RNG\n");
+    }
+}
+
+/// Stores `new` in `backend` if it still holds `current`, and returns the backend stored now.
+fn replace_backend(backend: &AtomicU8, current: u8, new: Backend) -> u8 {
+    match backend.compare_exchange(current, new as u8, Ordering::Relaxed, Ordering::Relaxed) {
+        Ok(_) => {
+            // `Weak` is never replaced, so only one thread gets here for each `backend`.
+            if new == Backend::Weak {
+                warn_weak();
+            }
+            new as u8
+        }
+        Err(other) => other,
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// How often `warn_weak` was called on this thread.
+    static WEAK_WARNINGS: crate::cell::Cell<usize> = crate::cell::Cell::new(0);
+}
+
+/// Tells developers that hash map keys are about to become predictable, which is easy to miss on
+/// old systems otherwise.
+#[cold]
+fn warn_weak() {
+    #[cfg(test)]
+    WEAK_WARNINGS.with(|w| w.set(w.get() + 1));
+    if cfg!(debug_assertions) {
+        rtprintpanic!("warning: no secure random number generator is available\n");
+    }
+}
+
+/// Returns the first available backend that is `from` or less preferred.
+#[cold]
+fn next_available(from: u8, is_available: &impl Fn(Backend) -> bool) -> Backend {
+    Backend::ALL
+        .into_iter()
+        .filter(|b| *b as u8 >= from)
+        .find(|b| is_available(*b))
+        .unwrap_or(Backend::Weak)
+}
+
+/// The first build of Windows 10, which accepts the `BCRYPT_RNG_ALG_HANDLE` pseudo-handle.
+const RNG_PSEUDO_HANDLE_BUILD: u32 = 10240;
+
+/// Stored in place of a handle once opening the provider failed.
+const NO_BCRYPT_HANDLE: *mut c_void = ptr::invalid_mut(usize::MAX);
+
+/// Returns the algorithm handle and flags to call `BCryptGenRandom` with.
+///
+/// A null handle with `BCRYPT_USE_SYSTEM_PREFERRED_RNG` makes every call look up the provider
+/// again, so it's only used if opening the RNG provider failed. The opened handle is kept for the
+/// lifetime of the process and deliberately never closed, as random data may still be requested
+/// while the process shuts down. If several threads race to open it, all but one handle leak.
+fn bcrypt_algorithm() -> (c::BCRYPT_ALG_HANDLE, c::BCRYPTGENRANDOM_FLAGS) {
+    static ALGORITHM: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+
+    if compat::nt_build_number() >= RNG_PSEUDO_HANDLE_BUILD {
+        return (c::BCRYPT_RNG_ALG_HANDLE, 0);
+    }
+
+    let mut handle = ALGORITHM.load(Ordering::Acquire);
+    if handle.is_null() {
+        handle = open_rng_provider().unwrap_or(NO_BCRYPT_HANDLE);
+        if let Err(other) =
+            ALGORITHM.compare_exchange(ptr::null_mut(), handle, Ordering::AcqRel, Ordering::Acquire)
+        {
+            handle = other;
+        }
+    }
+    if handle == NO_BCRYPT_HANDLE {
+        (ptr::null_mut(), c::BCRYPT_USE_SYSTEM_PREFERRED_RNG)
+    } else {
+        (handle, 0)
+    }
+}
+
+fn open_rng_provider() -> Option<c::BCRYPT_ALG_HANDLE> {
+    // "RNG", i.e. `BCRYPT_RNG_ALGORITHM`.
+    const RNG_ALGORITHM: [u16; 4] = [b'R' as u16, b'N' as u16, b'G' as u16, 0];
+
+    let open = c::BCryptOpenAlgorithmProvider::option()?;
+    let mut handle = ptr::null_mut();
+    let status = unsafe { open(&mut handle, RNG_ALGORITHM.as_ptr(), ptr::null(), 0) };
+    (c::nt_success(status) && !handle.is_null()).then_some(handle)
+}
+
+/// Generates random data with CryptoAPI, acquiring a context on the first call that is then kept
+/// for the lifetime of the process.
+unsafe fn crypt_gen_random(buf: *mut u8, len: u32) -> bool {
+    static PROVIDER: AtomicUsize = AtomicUsize::new(0);
+
+    let mut provider = PROVIDER.load(Ordering::Acquire);
+    if provider == 0 {
+        let acquired = c::CryptAcquireContextA(
+            &mut provider,
+            ptr::null(),
+            ptr::null(),
+            c::PROV_RSA_FULL,
+            c::CRYPT_VERIFYCONTEXT,
+        );
+        if acquired == c::FALSE {
+            return false;
+        }
+        if let Err(other) =
+            PROVIDER.compare_exchange(0, provider, Ordering::AcqRel, Ordering::Acquire)
+        {
+            // Another thread was faster.
+            c::CryptReleaseContext(provider, 0);
+            provider = other;
+        }
+    }
+    c::CryptGenRandom(provider, len, buf) != c::FALSE
 }
 
+/// Gathers the key for `fallback_keys` on systems without any of the secure backends, i.e. NT 3.x
+/// and 95 without CryptoAPI.
+///
+/// None of the sources is hard to guess on its own, so all of them are combined: the tick count,
+/// the performance counter (if there is one), the process and thread ids, the addresses of a
+/// local and a static, the system time, and a per-process counter. The counter makes consecutive
+/// calls differ even within the same tick, and mixing with SipHash rounds spreads every source
+/// bit over the whole result.
 #[inline(never)]
 fn true_fallback_rng() -> (u64, u64) {
-    unsafe {
-        let tickCount = c::GetTickCount();
-        let id = c::GetCurrentThreadId();
-        let mut file_time: c::FILETIME = crate::mem::zeroed();
-        c::GetSystemTimeAsFileTime(&mut file_time as *mut _);
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
 
+    let local = 0u8;
+    let (tick_count, perf_count, file_time, process_id, thread_id) = unsafe {
+        let mut perf_count = 0;
+        // Fails without a high-resolution counter, leaving it at zero.
+        c::QueryPerformanceCounter(&mut perf_count);
+        let mut file_time: c::FILETIME = mem::zeroed();
+        c::GetSystemTimeAsFileTime(&mut file_time);
         (
-            (file_time.dwHighDateTime as u64) << 32 | tickCount as u64,
-            (id as u64) << 32 | file_time.dwLowDateTime as u64,
+            c::GetTickCount(),
+            perf_count,
+            file_time,
+            c::GetCurrentProcessId(),
+            c::GetCurrentThreadId(),
         )
+    };
+
+    mix(
+        (0, 0),
+        &[
+            tick_count as u64,
+            perf_count as u64,
+            (file_time.dwHighDateTime as u64) << 32 | file_time.dwLowDateTime as u64,
+            (process_id as u64) << 32 | thread_id as u64,
+            ptr::addr_of!(local).addr() as u64,
+            ptr::addr_of!(COUNTER).addr() as u64,
+            COUNTER.fetch_add(1, Ordering::Relaxed) as u64,
+        ],
+    )
+}
+
+/// Mixes `sources` into two keys, like SipHash-1-3 with a 128 bit output.
+fn mix((k0, k1): (u64, u64), sources: &[u64]) -> (u64, u64) {
+    // The SipHash initialization constants.
+    let mut v = [
+        k0 ^ 0x736f6d6570736575,
+        k1 ^ 0x646f72616e646f6d,
+        k0 ^ 0x6c7967656e657261,
+        k1 ^ 0x7465646279746573,
+    ];
+    for &m in sources {
+        v[3] ^= m;
+        sip_round(&mut v);
+        v[0] ^= m;
     }
+
+    v[2] ^= 0xee;
+    for _ in 0..3 {
+        sip_round(&mut v);
+    }
+    let k0 = v[0] ^ v[1] ^ v[2] ^ v[3];
+    v[1] ^= 0xdd;
+    for _ in 0..3 {
+        sip_round(&mut v);
+    }
+    (k0, v[0] ^ v[1] ^ v[2] ^ v[3])
+}
+
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13) ^ v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16) ^ v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21) ^ v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17) ^ v[2];
+    v[2] = v[2].rotate_left(32);
 }
@@ -0,0 +1,316 @@
+use super::{
+    backend_name, bcrypt_algorithm, fallback_keys, fill_bytes, fill_from, hashmap_random_keys,
+    keyed_counter, mix, next_available, open_rng_provider, true_fallback_rng, Backend, Rng,
+    BCRYPT_REPROBE_AFTER, MAX_ATTEMPTS, MAX_CHUNK, RNG, WEAK_WARNINGS,
+};
+use crate::collections::BTreeSet;
+use crate::io::{ErrorKind, Result};
+use crate::mem::MaybeUninit;
+use crate::ptr;
+use crate::sync::atomic::{AtomicU64, Ordering};
+use crate::sys::c;
+
+#[test]
+fn true_fallback_rng_differs_within_a_tick() {
+    let keys: BTreeSet<_> = (0..1000).map(|_| true_fallback_rng()).collect();
+    assert_eq!(keys.len(), 1000);
+}
+
+#[test]
+fn mix_diffuses_bits() {
+    let sources = [0x0123_4567_89ab_cdef, 0, u64::MAX, 42, 0x8000_0000, 7, 1];
+    let (k0, k1) = mix((0, 0), &sources);
+    let mut total = 0;
+    for i in 0..sources.len() {
+        for bit in 0..64 {
+            let mut flipped = sources;
+            flipped[i] ^= 1 << bit;
+            let (f0, f1) = mix((0, 0), &flipped);
+            // About half of the 128 output bits should change for every input bit.
+            let changed = (k0 ^ f0).count_ones() + (k1 ^ f1).count_ones();
+            assert!((32..=96).contains(&changed), "source {i} bit {bit}: {changed} bits changed");
+            total += changed;
+        }
+    }
+    let average = total as f64 / (sources.len() * 64) as f64;
+    assert!((60.0..=68.0).contains(&average), "{average} bits changed on average");
+}
+
+/// Runs `fill_from` for `len` bytes with providers that are unavailable if they're in
+/// `unavailable` and fail while `fail` returns `true`. Returns the result, the filled bytes if it
+/// succeeded, and the backends that were called with the length of each chunk.
+fn fill_with(
+    rng: &Rng,
+    len: usize,
+    unavailable: &[Backend],
+    mut fail: impl FnMut(Backend) -> bool,
+) -> (Result<Vec<u8>>, Vec<(Backend, usize)>) {
+    let mut buf = vec![MaybeUninit::uninit(); len];
+    let mut calls = Vec::new();
+    let result = fill_from(
+        rng,
+        |b| !unavailable.contains(&b),
+        |b, chunk| {
+            calls.push((b, chunk.len()));
+            chunk.fill(MaybeUninit::new(b as u8));
+            if fail(b) { Err(c::STATUS_UNSUCCESSFUL) } else { Ok(()) }
+        },
+        &mut buf,
+    );
+    let bytes = result.map(|()| buf.iter().map(|b| unsafe { b.assume_init() }).collect());
+    (bytes, calls)
+}
+
+#[test]
+fn fill_in_chunks() {
+    for len in [0, 1, 4096] {
+        let rng = Rng::new();
+        let (bytes, calls) = fill_with(&rng, len, &[], |_| false);
+        assert_eq!(bytes.unwrap(), vec![Backend::BCrypt as u8; len]);
+        assert_eq!(calls.len(), len.div_ceil(MAX_CHUNK));
+        assert!(calls.iter().all(|&(b, chunk)| b == Backend::BCrypt && chunk <= MAX_CHUNK));
+    }
+}
+
+#[test]
+fn backend_resolved_once() {
+    let rng = Rng::new();
+    let (_, calls) = fill_with(&rng, 16, &[Backend::BCrypt], |_| false);
+    assert_eq!(calls, [(Backend::ProcessPrng, 16)]);
+    assert_eq!(rng.backend.load(Ordering::Relaxed), Backend::ProcessPrng as u8);
+
+    // Availability isn't checked again.
+    let (_, calls) = fill_with(&rng, 16, &Backend::ALL, |_| false);
+    assert_eq!(calls, [(Backend::ProcessPrng, 16)]);
+}
+
+#[test]
+fn transient_failure_retried() {
+    let rng = Rng::new();
+    let mut failures = MAX_ATTEMPTS - 1;
+    let fail = |_| {
+        failures = failures.saturating_sub(1);
+        failures > 0
+    };
+    let (bytes, calls) = fill_with(&rng, 16, &[], fail);
+    assert!(bytes.is_ok());
+    assert_eq!(calls.len(), MAX_ATTEMPTS - 1);
+    assert_eq!(rng.backend.load(Ordering::Relaxed), Backend::BCrypt as u8);
+}
+
+#[test]
+fn failing_backend_demoted() {
+    let rng = Rng::new();
+    let failing = [Backend::BCrypt, Backend::ProcessPrng];
+    let (bytes, calls) = fill_with(&rng, 16, &[], |b| failing.contains(&b));
+    assert_eq!(bytes.unwrap(), [Backend::RtlGenRandom as u8; 16]);
+    assert_eq!(calls.len(), 2 * MAX_ATTEMPTS + 1);
+    assert_eq!(rng.backend.load(Ordering::Relaxed), Backend::RtlGenRandom as u8);
+
+    // The broken providers aren't tried again.
+    let (_, calls) = fill_with(&rng, 16, &[], |_| false);
+    assert_eq!(calls, [(Backend::RtlGenRandom, 16)]);
+}
+
+#[test]
+fn no_secure_backend() {
+    // Unavailable providers aren't called at all.
+    let rng = Rng::new();
+    let (bytes, calls) = fill_with(&rng, 16, &Backend::ALL[..4], |_| false);
+    assert_eq!(bytes.unwrap_err().kind(), ErrorKind::Unsupported);
+    assert!(calls.is_empty());
+
+    // All of them failing ends up in the same place.
+    let rng = Rng::new();
+    let (bytes, calls) = fill_with(&rng, 16, &[], |_| true);
+    assert_eq!(bytes.unwrap_err().kind(), ErrorKind::Unsupported);
+    assert_eq!(calls.len(), 4 * MAX_ATTEMPTS);
+    assert_eq!(rng.backend.load(Ordering::Relaxed), Backend::Weak as u8);
+}
+
+#[test]
+fn fill_bytes_initializes() {
+    for len in [0, 1, 4096] {
+        let mut buf = vec![MaybeUninit::uninit(); len];
+        fill_bytes(&mut buf).unwrap();
+        let bytes: Vec<u8> = buf.iter().map(|b| unsafe { b.assume_init() }).collect();
+        if len == 4096 {
+            assert!(bytes.iter().any(|&b| b != 0));
+        }
+    }
+}
+
+#[test]
+fn hashmap_random_keys_differ() {
+    assert_ne!(hashmap_random_keys(), hashmap_random_keys());
+}
+
+#[test]
+fn process_prng_import() {
+    // Only available from Windows 8 on.
+    let Some(process_prng) = c::ProcessPrng::option() else { return };
+    let mut buf = [0u8; 64];
+    assert_ne!(unsafe { process_prng(buf.as_mut_ptr(), buf.len()) }, c::FALSE);
+    assert!(buf.iter().any(|&b| b != 0));
+}
+
+#[test]
+fn backend_chain_order() {
+    assert_eq!(next_available(0, &|_| true), Backend::BCrypt);
+    assert_eq!(next_available(0, &|b| b != Backend::BCrypt), Backend::ProcessPrng);
+    let without_process_prng = |b| !matches!(b, Backend::BCrypt | Backend::ProcessPrng);
+    assert_eq!(next_available(0, &without_process_prng), Backend::RtlGenRandom);
+    assert_eq!(next_available(Backend::ProcessPrng as u8, &|_| true), Backend::ProcessPrng);
+
+    // The host's backend is the first one it has, as none of them should fail.
+    fill_bytes(&mut [MaybeUninit::uninit(); 16]).unwrap();
+    let selected = Backend::from_u8(RNG.backend.load(Ordering::Relaxed));
+    assert_eq!(selected, Some(next_available(0, &Backend::is_available)));
+}
+
+#[test]
+fn backend_name_of_host() {
+    fill_bytes(&mut [MaybeUninit::uninit(); 16]).unwrap();
+    assert_eq!(backend_name(), next_available(0, &Backend::is_available).name());
+    assert_ne!(backend_name(), Backend::Weak.name());
+}
+
+#[test]
+fn weak_fallback_warns_once() {
+    let warnings = || WEAK_WARNINGS.with(|w| w.get());
+    let rng = Rng::new();
+    let before = warnings();
+    for _ in 0..3 {
+        let (bytes, _) = fill_with(&rng, 16, &Backend::ALL[..4], |_| false);
+        assert!(bytes.is_err());
+    }
+    assert_eq!(warnings(), before + 1);
+
+    // Falling back because all of them failed warns, too.
+    let rng = Rng::new();
+    fill_with(&rng, 16, &[], |_| true).0.unwrap_err();
+    fill_with(&rng, 16, &[], |_| true).0.unwrap_err();
+    assert_eq!(warnings(), before + 2);
+}
+
+#[test]
+fn keyed_counter_differs_between_keys() {
+    // Every process derives its own key, like these two.
+    let (key_a, key_b) = (true_fallback_rng(), true_fallback_rng());
+    assert_ne!(key_a, key_b);
+    let (counter_a, counter_b) = (AtomicU64::new(0), AtomicU64::new(0));
+    for _ in 0..100 {
+        assert_ne!(keyed_counter(key_a, &counter_a), keyed_counter(key_b, &counter_b));
+    }
+}
+
+#[test]
+fn keyed_counter_never_repeats() {
+    let key = true_fallback_rng();
+    let counter = AtomicU64::new(0);
+    let mut keys: Vec<_> = (0..1_000_000).map(|_| keyed_counter(key, &counter)).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    assert_eq!(keys.len(), 1_000_000);
+}
+
+#[test]
+fn fallback_keys_differ() {
+    assert_ne!(fallback_keys(), fallback_keys());
+}
+
+#[test]
+fn bcrypt_handle_forms() {
+    if !c::BCryptGenRandom::available() {
+        return;
+    }
+    let (algorithm, flags) = bcrypt_algorithm();
+    assert!(!algorithm.is_null() || flags == c::BCRYPT_USE_SYSTEM_PREFERRED_RNG);
+    let opened = open_rng_provider().expect("BCryptGenRandom without an RNG provider");
+
+    // Each form should produce distinct, well-spread output.
+    const CALLS: u32 = 1000;
+    let forms = [
+        ("pre-opened", algorithm, flags),
+        ("opened", opened, 0),
+        ("system preferred", ptr::null_mut(), c::BCRYPT_USE_SYSTEM_PREFERRED_RNG),
+    ];
+    for (name, algorithm, flags) in forms {
+        let mut outputs = BTreeSet::new();
+        let mut ones = 0;
+        for _ in 0..CALLS {
+            let mut buf = [0u8; 32];
+            let status =
+                unsafe { c::BCryptGenRandom(algorithm, buf.as_mut_ptr(), buf.len() as u32, flags) };
+            assert!(c::nt_success(status), "{name}: {status:#x}");
+            ones += buf.iter().map(|b| b.count_ones()).sum::<u32>();
+            outputs.insert(buf);
+        }
+        assert_eq!(outputs.len(), CALLS as usize, "{name}: repeated output");
+        let average = ones as f64 / (CALLS * 256) as f64;
+        assert!((0.48..=0.52).contains(&average), "{name}: {average} of the bits are set");
+    }
+}
+
+/// Fills `chunks` chunks with a `BCryptGenRandom` stub that fails with `status` until `bcrypt_fails`
+/// returns `false`, and returns the backends that were called.
+fn fill_with_bcrypt(
+    rng: &Rng,
+    chunks: usize,
+    status: c::NTSTATUS,
+    mut bcrypt_fails: impl FnMut() -> bool,
+) -> Vec<Backend> {
+    let mut calls = Vec::new();
+    let mut buf = vec![MaybeUninit::uninit(); chunks * MAX_CHUNK];
+    let fill = |b: Backend, _: &mut [MaybeUninit<u8>]| {
+        calls.push(b);
+        if b == Backend::BCrypt && bcrypt_fails() { Err(status) } else { Ok(()) }
+    };
+    fill_from(rng, |_| true, fill, &mut buf).unwrap();
+    calls
+}
+
+#[test]
+fn bcrypt_demotion_recorded() {
+    let rng = Rng::new();
+    let calls = fill_with_bcrypt(&rng, 1, c::STATUS_NOT_IMPLEMENTED, || true);
+    let mut expected = vec![Backend::BCrypt; MAX_ATTEMPTS];
+    expected.push(Backend::ProcessPrng);
+    assert_eq!(calls, expected);
+    assert_eq!(rng.bcrypt_failure.load(Ordering::Relaxed), c::STATUS_NOT_IMPLEMENTED);
+    assert_eq!(rng.backend.load(Ordering::Relaxed), Backend::ProcessPrng as u8);
+
+    // Other backends failing isn't recorded.
+    let rng = Rng::new();
+    fill_with(&rng, 16, &[Backend::BCrypt], |b| b == Backend::ProcessPrng).0.unwrap();
+    assert_eq!(rng.bcrypt_failure.load(Ordering::Relaxed), c::STATUS_SUCCESS);
+}
+
+#[test]
+fn bcrypt_reprobed() {
+    let rng = Rng::new();
+    // Fails until it's demoted.
+    let mut failures = 0;
+    let bcrypt_fails = || {
+        failures += 1;
+        failures <= MAX_ATTEMPTS
+    };
+    let calls =
+        fill_with_bcrypt(&rng, BCRYPT_REPROBE_AFTER, c::STATUS_NOT_IMPLEMENTED, bcrypt_fails);
+    let bcrypt_calls = calls.iter().filter(|&&b| b == Backend::BCrypt).count();
+    assert_eq!(bcrypt_calls, MAX_ATTEMPTS + 1);
+    assert_eq!(calls.last(), Some(&Backend::BCrypt));
+    assert_eq!(rng.backend.load(Ordering::Relaxed), Backend::BCrypt as u8);
+    // The status stays around for diagnostics.
+    assert_eq!(rng.bcrypt_failure.load(Ordering::Relaxed), c::STATUS_NOT_IMPLEMENTED);
+}
+
+#[test]
+fn bcrypt_reprobed_once() {
+    let rng = Rng::new();
+    let calls =
+        fill_with_bcrypt(&rng, 3 * BCRYPT_REPROBE_AFTER, c::STATUS_NOT_IMPLEMENTED, || true);
+    let bcrypt_calls = calls.iter().filter(|&&b| b == Backend::BCrypt).count();
+    assert_eq!(bcrypt_calls, MAX_ATTEMPTS + 1);
+    assert_eq!(rng.backend.load(Ordering::Relaxed), Backend::ProcessPrng as u8);
+}
@@ -1,47 +1,422 @@
 #![cfg_attr(test, allow(dead_code))]
 
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+use crate::arch::asm;
+use crate::ffi::c_void;
+use crate::io::{self, Write};
+use crate::mem;
+use crate::ptr;
+use crate::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, Ordering};
 use crate::sys::c;
 use crate::thread;
 
 use super::api;
 
+#[cfg(test)]
+mod tests;
+
 pub struct Handler;
 
+/// The stack space reserved for handling a stack overflow if the size of the stack isn't known.
+const DEFAULT_GUARANTEE: usize = 0x5000;
+
+/// The least stack space reserved for handling a stack overflow, unless the stack is too small.
+const MIN_GUARANTEE: usize = 0x4000;
+
+/// Set once reserving stack space for the handler failed on some thread, e.g. because of a job
+/// object's memory limit. The handler may not have the room to print much then.
+static GUARANTEE_MISSING: AtomicBool = AtomicBool::new(false);
+
+#[cfg(test)]
+thread_local! {
+    /// The guarantee that `Handler::new` last requested on this thread.
+    static LAST_GUARANTEE: crate::cell::Cell<Option<u32>> = crate::cell::Cell::new(None);
+}
+
+#[cfg(test)]
+thread_local! {
+    static FAIL_GUARANTEE: crate::cell::Cell<bool> = crate::cell::Cell::new(false);
+}
+
+/// Runs `f` with `Handler::new` failing as if `SetThreadStackGuarantee` did, for tests.
+#[cfg(test)]
+fn with_failing_guarantee<R>(f: impl FnOnce() -> R) -> R {
+    let forced = FAIL_GUARANTEE.with(|force| force.replace(true));
+    let result = f();
+    FAIL_GUARANTEE.with(|force| force.set(forced));
+    result
+}
+
 impl Handler {
-    pub unsafe fn new() -> Handler {
+    /// Reserves stack space for the handler on the current thread, sized for a stack of
+    /// `stack_size` bytes, or the default size if that isn't known.
+    ///
+    /// Failing to do so only makes the overflow message less likely to get printed, so it's
+    /// only warned about, once, and callers are free to carry on anyway.
+    pub unsafe fn new(stack_size: Option<usize>) -> io::Result<Handler> {
         if let Some(f) = c::SetThreadStackGuarantee::option() {
-            if f(&mut 0x5000) == 0 && api::get_last_error().code != c::ERROR_CALL_NOT_IMPLEMENTED {
-                panic!("failed to reserve stack space for exception handling");
+            let requested = guarantee_for(stack_size);
+            #[cfg(test)]
+            LAST_GUARANTEE.with(|g| g.set(Some(requested)));
+            #[cfg(test)]
+            let f = if FAIL_GUARANTEE.with(|fail| fail.get()) { failing_guarantee } else { f };
+            let mut guarantee = requested;
+            if f(&mut guarantee) == 0 {
+                let error = api::get_last_error();
+                if error.code != c::ERROR_CALL_NOT_IMPLEMENTED {
+                    let os_error = io::Error::from_raw_os_error(error.code as i32);
+                    let error = io::Error::new(
+                        os_error.kind(),
+                        format!(
+                            "failed to reserve {requested:#x} bytes of stack for exception \
+                             handling: {os_error}"
+                        ),
+                    );
+                    if !GUARANTEE_MISSING.swap(true, Ordering::Relaxed) {
+                        rtprintpanic!("warning: {error}\n");
+                    }
+                    return Err(error);
+                }
             }
         };
 
-        Handler
+        Ok(Handler)
     }
 }
 
+#[cfg(test)]
+unsafe extern "system" fn failing_guarantee(_stacksizeinbytes: *mut u32) -> c::BOOL {
+    c::SetLastError(c::ERROR_COMMITMENT_LIMIT);
+    c::FALSE
+}
+
+/// Returns whether reserving stack space for the handler failed on any thread, for diagnostics.
+pub fn guarantee_missing() -> bool {
+    GUARANTEE_MISSING.load(Ordering::Relaxed)
+}
+
+/// Returns the stack guarantee for a stack of `stack_size` bytes: a 32nd of it, but at least
+/// `MIN_GUARANTEE`, and never more than an 8th of it, so that small stacks keep most of their room.
+fn guarantee_for(stack_size: Option<usize>) -> u32 {
+    let guarantee = match stack_size {
+        Some(size) if size > 0 => (size / 32).max(MIN_GUARANTEE).min(size / 8),
+        _ => DEFAULT_GUARANTEE,
+    };
+    guarantee.try_into().unwrap_or(u32::MAX)
+}
+
 unsafe extern "system" fn vectored_handler(ExceptionInfo: *mut c::EXCEPTION_POINTERS) -> c::LONG {
     unsafe {
         let rec = &(*(*ExceptionInfo).ExceptionRecord);
         let code = rec.ExceptionCode;
 
         if code == c::EXCEPTION_STACK_OVERFLOW {
-            rtprintpanic!(
-                "\nthread '{}' has overflowed its stack\n",
-                thread::current().name().unwrap_or("<unknown>")
-            );
+            on_stack_overflow();
         }
+        // Always let the handlers the host application installed see the exception, too.
         c::EXCEPTION_CONTINUE_SEARCH
     }
 }
 
+/// Deals with a stack overflow on the current thread as `MODE` says, unless it terminates the
+/// process, leaving the exception to the next handler.
+unsafe fn on_stack_overflow() {
+    let mode = OverflowMode::from_u8(MODE.load(Ordering::Relaxed));
+    if mode == OverflowMode::Silent {
+        return;
+    }
+    if let Some(mut out) = crate::sys::stdio::panic_output() {
+        if GUARANTEE_MISSING.load(Ordering::Relaxed) {
+            // Looking up the thread's name might overflow again without the reserved space.
+            let _ = out.write_all(b"\nthread has overflowed its stack\n");
+        } else {
+            report_overflow(&mut out);
+        }
+    }
+    if mode == OverflowMode::ReportAndAbort {
+        c::TerminateProcess(c::GetCurrentProcess(), c::EXCEPTION_STACK_OVERFLOW as u32);
+    }
+    // Without a guard page, the handlers after this one would fault again and take the process
+    // down with some other exit code, so restore it like `_resetstkoflw`. If none of them handles
+    // the overflow, the process exits with the code a parent expects for a stack overflow.
+    reset_guard_page();
+}
+
+/// Runs `f`, which should be all of a thread's work, with an SEH frame that reports stack
+/// overflows if vectored exception handlers aren't available, i.e. on 9x/ME and NT before XP.
+/// Only x86 uses frame-based SEH, so other architectures just call `f`.
+pub unsafe fn with_seh_frame<R>(f: impl FnOnce() -> R) -> R {
+    #[cfg(target_arch = "x86")]
+    if seh::is_needed() {
+        return seh::with_frame(f);
+    }
+    f()
+}
+
+/// Per-thread SEH frames, for systems where `vectored_handler` can't be installed.
+///
+/// A frame is an `EXCEPTION_REGISTRATION_RECORD` on the stack, linked into the list starting at
+/// `fs:[0]`. It's the outermost frame of the thread, and panics never get past the `catch_unwind`
+/// in the thread's main function, so it isn't unwound through. The handler ignores everything but
+/// stack overflows, including the calls made while unwinding, and leaves those to the frames that
+/// the code running inside it registers.
+///
+/// Images linked with `/SAFESEH` only allow the handlers listed in them, which doesn't include
+/// this one. That only matters from XP SP2 on, which has vectored handlers anyway.
+#[cfg(target_arch = "x86")]
+mod seh {
+    use super::on_stack_overflow;
+    use crate::arch::asm;
+    use crate::ffi::c_void;
+    use crate::sys::{c, compat};
+
+    #[cfg(test)]
+    pub static FORCE_SEH_FRAME: crate::sync::atomic::AtomicBool =
+        crate::sync::atomic::AtomicBool::new(false);
+
+    /// `EXCEPTION_UNWINDING | EXCEPTION_EXIT_UNWIND`, set when the handler is called to unwind.
+    const EXCEPTION_UNWIND: u32 = 0x6;
+
+    #[repr(C)]
+    struct Registration {
+        next: *mut Registration,
+        handler: unsafe extern "C" fn(
+            *mut c::EXCEPTION_RECORD,
+            *mut c_void,
+            *mut c_void,
+            *mut c_void,
+        ) -> c::EXCEPTION_DISPOSITION,
+    }
+
+    /// Takes `Registration` off the list again when dropped, even if it's left by a panic.
+    struct Frame<'a>(&'a mut Registration);
+
+    impl Drop for Frame<'_> {
+        fn drop(&mut self) {
+            unsafe {
+                asm!(
+                    "mov dword ptr fs:[0], {next}",
+                    next = in(reg) self.0.next,
+                    options(nostack, preserves_flags),
+                );
+            }
+        }
+    }
+
+    pub fn is_needed() -> bool {
+        #[cfg(test)]
+        if FORCE_SEH_FRAME.load(crate::sync::atomic::Ordering::Relaxed) {
+            return true;
+        }
+        !compat::is_windows_nt() || c::AddVectoredExceptionHandler::option().is_none()
+    }
+
+    pub unsafe fn with_frame<R>(f: impl FnOnce() -> R) -> R {
+        let mut registration = Registration { next: crate::ptr::null_mut(), handler };
+        let record: *mut Registration = &mut registration;
+        asm!(
+            "mov {next}, dword ptr fs:[0]",
+            "mov dword ptr [{record}], {next}",
+            "mov dword ptr fs:[0], {record}",
+            record = in(reg) record,
+            next = out(reg) _,
+            options(nostack, preserves_flags),
+        );
+        let _frame = Frame(&mut *record);
+        f()
+    }
+
+    unsafe extern "C" fn handler(
+        record: *mut c::EXCEPTION_RECORD,
+        _establisher_frame: *mut c_void,
+        _context: *mut c_void,
+        _dispatcher_context: *mut c_void,
+    ) -> c::EXCEPTION_DISPOSITION {
+        let record = &*record;
+        if record.ExceptionFlags & EXCEPTION_UNWIND == 0
+            && record.ExceptionCode == c::EXCEPTION_STACK_OVERFLOW
+        {
+            on_stack_overflow();
+        }
+        c::ExceptionContinueSearch
+    }
+}
+
+/// Writes the stack overflow message for the current thread to `out`.
+///
+/// Threads that don't have a name on the Rust side are named by their description if they have
+/// one, as set by `SetThreadDescription` from foreign code, and by their id otherwise.
+unsafe fn report_overflow(out: &mut impl io::Write) {
+    let thread = thread::current();
+    let mut buf = [0; 256];
+    let _ = match thread.name().or_else(|| thread_description(&mut buf)) {
+        Some(name) => write!(out, "\nthread '{name}' has overflowed its stack\n"),
+        None => write!(out, "\nthread {} has overflowed its stack\n", c::GetCurrentThreadId()),
+    };
+}
+
+/// Returns the description of the current thread, converted to UTF-8 in `buf` and truncated to
+/// fit. This doesn't allocate, so that it's fine to call while handling a stack overflow.
+unsafe fn thread_description(buf: &mut [u8]) -> Option<&str> {
+    let get_thread_description = c::GetThreadDescription::option()?;
+    let mut description = ptr::null_mut();
+    if get_thread_description(c::GetCurrentThread(), &mut description) < 0 {
+        return None;
+    }
+    if description.is_null() {
+        return None;
+    }
+
+    let len = (0..).take_while(|&i| *description.add(i) != 0).count();
+    let wide = crate::slice::from_raw_parts(description, len);
+    let mut written = 0;
+    for ch in char::decode_utf16(wide.iter().copied()) {
+        let ch = ch.unwrap_or(char::REPLACEMENT_CHARACTER);
+        if written + ch.len_utf8() > buf.len() {
+            break;
+        }
+        written += ch.encode_utf8(&mut buf[written..]).len();
+    }
+    c::LocalFree(description.cast());
+
+    // Only whole characters were written.
+    let name = crate::str::from_utf8(&buf[..written]).ok()?;
+    if name.is_empty() { None } else { Some(name) }
+}
+
+/// Returns the `(StackLimit, StackBase)` of the current thread from its TIB.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+unsafe fn stack_bounds() -> Option<(usize, usize)> {
+    let tib: *const usize;
+    #[cfg(target_arch = "x86")]
+    asm!("mov {}, dword ptr fs:[0x18]", out(reg) tib, options(nostack, readonly, preserves_flags));
+    #[cfg(target_arch = "x86_64")]
+    asm!("mov {}, qword ptr gs:[0x30]", out(reg) tib, options(nostack, readonly, preserves_flags));
+    #[cfg(target_arch = "aarch64")]
+    asm!("mov {}, x18", out(reg) tib, options(nomem, nostack, preserves_flags));
+    // `ExceptionList` comes first.
+    Some((*tib.add(2), *tib.add(1)))
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+unsafe fn stack_bounds() -> Option<(usize, usize)> {
+    None
+}
+
+/// Turns the lowest committed page of the current thread's stack back into a guard page, returning
+/// whether that worked.
+///
+/// Nothing is done if the handler isn't running on the stack the TIB describes, e.g. on a stack
+/// that some foreign code switched to by hand, as its layout is unknown then.
+unsafe fn reset_guard_page() -> bool {
+    let local = 0u8;
+    let sp = ptr::addr_of!(local).addr();
+    let Some((limit, base)) = stack_bounds() else { return false };
+    if !(limit..base).contains(&sp) {
+        return false;
+    }
+
+    let mut info: c::MEMORY_BASIC_INFORMATION = mem::zeroed();
+    let size = mem::size_of_val(&info);
+    if c::VirtualQuery(ptr::addr_of!(local).cast(), &mut info, size) == 0 {
+        return false;
+    }
+    // The stack grows down into the reserved part at the bottom of the allocation, so the lowest
+    // committed page is right above it. There has to be some left for the guard page to be useful.
+    if c::VirtualQuery(info.AllocationBase, &mut info, size) == 0 || info.State != c::MEM_RESERVE {
+        return false;
+    }
+    let guard = info.BaseAddress.addr() + info.RegionSize;
+
+    let mut sysinfo: c::SYSTEM_INFO = mem::zeroed();
+    c::GetSystemInfo(&mut sysinfo);
+    let page_size = sysinfo.dwPageSize as usize;
+    // Don't take away the page that the handler is running on.
+    if guard + page_size > sp & !(page_size - 1) {
+        return false;
+    }
+
+    let mut old = 0;
+    let protect = c::PAGE_READWRITE | c::PAGE_GUARD;
+    c::VirtualProtect(ptr::invalid(guard), page_size, protect, &mut old) != 0
+}
+
+/// What the handler does about a stack overflow.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[repr(u8)]
+pub enum OverflowMode {
+    /// Print a message and leave the rest to the handlers after ours, which usually means the
+    /// process exits with `STATUS_STACK_OVERFLOW`.
+    #[default]
+    ReportAndContinueSearch,
+    /// Print a message and terminate the process with `STATUS_STACK_OVERFLOW` right away, so that
+    /// crash reporters see the state of the overflow rather than of whatever ran after it.
+    ReportAndAbort,
+    /// Don't do anything, for hosts that handle stack overflows themselves.
+    Silent,
+}
+
+impl OverflowMode {
+    fn from_u8(mode: u8) -> OverflowMode {
+        match mode {
+            1 => OverflowMode::ReportAndAbort,
+            2 => OverflowMode::Silent,
+            _ => OverflowMode::ReportAndContinueSearch,
+        }
+    }
+}
+
+/// The `OverflowMode` last passed to `set_mode`.
+static MODE: AtomicU8 = AtomicU8::new(OverflowMode::ReportAndContinueSearch as u8);
+
+/// The registration of `vectored_handler`, or null while it isn't installed.
+static REGISTRATION: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+
+/// Sets what the handler does about a stack overflow from now on. The mode is only read once an
+/// overflow is detected, so this can be called before or after `init`.
+pub fn set_mode(mode: OverflowMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
 pub unsafe fn init() {
-    let Some(f) = c::AddVectoredExceptionHandler::option() else {
+    let Some(add) = c::AddVectoredExceptionHandler::option() else {
         return;
     };
 
-    if f(0, Some(vectored_handler)).is_null() {
-        panic!("failed to install exception handler");
+    // Applications embedding std as a DLL may not allow registering handlers, which only costs
+    // the overflow message.
+    if !install(&REGISTRATION, || add(0, Some(vectored_handler))) {
+        rtprintpanic!("warning: failed to install the stack overflow handler\n");
+    }
+    // Set the thread stack guarantee for the main thread, whose stack size isn't known here.
+    let _h = Handler::new(None);
+}
+
+/// Removes the handler installed by `init`, so that it doesn't outlive std when its DLL is
+/// unloaded. Does nothing if it isn't installed.
+pub unsafe fn cleanup() {
+    if let Some(remove) = c::RemoveVectoredExceptionHandler::option() {
+        uninstall(&REGISTRATION, |registration| remove(registration));
+    }
+}
+
+/// Stores the registration that `add` returns in `registration`, unless a handler is installed
+/// already. Returns whether a handler is installed now.
+fn install(registration: &AtomicPtr<c_void>, add: impl FnOnce() -> *mut c_void) -> bool {
+    if !registration.load(Ordering::Acquire).is_null() {
+        return true;
+    }
+    let handle = add();
+    if handle.is_null() {
+        return false;
+    }
+    registration.store(handle, Ordering::Release);
+    true
+}
+
+fn uninstall(registration: &AtomicPtr<c_void>, remove: impl FnOnce(*mut c_void) -> u32) {
+    let handle = registration.swap(ptr::null_mut(), Ordering::AcqRel);
+    if !handle.is_null() {
+        remove(handle);
     }
-    // Set the thread stack guarantee for the main thread.
-    let _h = Handler::new();
 }
@@ -0,0 +1,219 @@
+use super::{
+    guarantee_for, guarantee_missing, init, install, report_overflow, set_mode, thread_description,
+    uninstall, with_failing_guarantee, Handler, OverflowMode, DEFAULT_GUARANTEE, LAST_GUARANTEE,
+    MIN_GUARANTEE,
+};
+use crate::env;
+use crate::hint::black_box;
+use crate::process::Command;
+use crate::ptr;
+use crate::sync::atomic::{AtomicPtr, Ordering};
+use crate::sys::c;
+use crate::thread;
+
+#[test]
+fn guarantee_scales_with_stack() {
+    assert_eq!(guarantee_for(None), DEFAULT_GUARANTEE as u32);
+    assert_eq!(guarantee_for(Some(0)), DEFAULT_GUARANTEE as u32);
+    // Capped at an 8th of small stacks.
+    assert_eq!(guarantee_for(Some(0x10000)), 0x2000);
+    assert_eq!(guarantee_for(Some(0x40000)), MIN_GUARANTEE as u32);
+    assert_eq!(guarantee_for(Some(0x800000)), 0x40000);
+}
+
+#[test]
+fn failed_guarantee_not_fatal() {
+    if c::SetThreadStackGuarantee::option().is_none() {
+        return;
+    }
+    let message = with_failing_guarantee(|| unsafe { Handler::new(Some(0x10000)) })
+        .err()
+        .map(|e| e.to_string())
+        .unwrap();
+    assert!(message.contains("failed to reserve 0x2000 bytes of stack"), "{message}");
+    assert!(guarantee_missing());
+}
+
+#[test]
+fn spawned_threads_request_guarantee() {
+    // Nothing is requested where stack guarantees aren't supported.
+    if c::SetThreadStackGuarantee::option().is_none() {
+        return;
+    }
+    for (stack_size, expected) in [(0x10000, 0x2000), (0x800000, 0x40000)] {
+        let requested = thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn(|| LAST_GUARANTEE.with(|g| g.get()))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(requested, Some(expected), "stack size {stack_size:#x}");
+    }
+}
+
+/// Sets the description of the current thread through the OS only, returning whether that's
+/// supported.
+fn set_description(description: &str) -> bool {
+    let wide: Vec<u16> = description.encode_utf16().chain([0]).collect();
+    unsafe { c::SetThreadDescription(c::GetCurrentThread(), wide.as_ptr()) >= 0 }
+}
+
+fn overflow_message() -> String {
+    let mut out = Vec::new();
+    unsafe { report_overflow(&mut out) };
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn overflow_names_described_thread() {
+    thread::spawn(|| {
+        if !set_description("described by the OS") {
+            return;
+        }
+        assert_eq!(overflow_message(), "\nthread 'described by the OS' has overflowed its stack\n");
+    })
+    .join()
+    .unwrap();
+
+    // The Rust name wins.
+    thread::Builder::new()
+        .name("rust name".into())
+        .spawn(|| {
+            set_description("described by the OS");
+            assert!(overflow_message().contains("thread 'rust name' has"));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn overflow_names_thread_by_id() {
+    thread::spawn(|| {
+        let id = unsafe { c::GetCurrentThreadId() };
+        assert_eq!(overflow_message(), format!("\nthread {id} has overflowed its stack\n"));
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn thread_description_truncated() {
+    thread::spawn(|| {
+        if !set_description("\u{e9}\u{e9}\u{e9}") {
+            return;
+        }
+        // Only whole characters fit.
+        let mut buf = [0; 5];
+        assert_eq!(unsafe { thread_description(&mut buf) }, Some("\u{e9}\u{e9}"));
+        let mut buf = [0; 1];
+        assert_eq!(unsafe { thread_description(&mut buf) }, None);
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn install_once() {
+    let registration = AtomicPtr::new(ptr::null_mut());
+    let handle = ptr::invalid_mut(0x1234);
+    let mut added = 0;
+    assert!(install(&registration, || {
+        added += 1;
+        handle
+    }));
+    assert!(install(&registration, || {
+        added += 1;
+        handle
+    }));
+    assert_eq!(added, 1);
+    assert_eq!(registration.load(Ordering::Relaxed), handle);
+
+    let mut removed = Vec::new();
+    uninstall(&registration, |h| {
+        removed.push(h);
+        1
+    });
+    uninstall(&registration, |h| {
+        removed.push(h);
+        1
+    });
+    assert_eq!(removed, [handle]);
+    assert!(registration.load(Ordering::Relaxed).is_null());
+}
+
+#[test]
+fn install_failure_not_fatal() {
+    let registration = AtomicPtr::new(ptr::null_mut());
+    assert!(!install(&registration, ptr::null_mut));
+    assert!(registration.load(Ordering::Relaxed).is_null());
+
+    // Nothing to remove, and installing can be retried.
+    uninstall(&registration, |_| panic!("removed a handler that wasn't installed"));
+    assert!(install(&registration, || ptr::invalid_mut(1)));
+}
+
+/// Set for the child process that `overflow_child` runs in, to the mode to use.
+const CHILD_MODE_VAR: &str = "RUST_TEST_STACK_OVERFLOW_MODE";
+
+#[allow(unconditional_recursion)]
+fn recurse(depth: u64) -> u64 {
+    let buf = black_box([depth; 128]);
+    recurse(depth + 1) + buf[0]
+}
+
+/// Overflows the stack of a spawned thread when run by `overflow_modes`, and does nothing
+/// otherwise.
+#[test]
+fn overflow_child() {
+    let Ok(mode) = env::var(CHILD_MODE_VAR) else { return };
+    let mode = match &*mode {
+        "continue" => OverflowMode::ReportAndContinueSearch,
+        "abort" => OverflowMode::ReportAndAbort,
+        "silent" => OverflowMode::Silent,
+        #[cfg(target_arch = "x86")]
+        "seh" => {
+            // Leave it to the SEH frame alone.
+            unsafe { super::cleanup() };
+            super::seh::FORCE_SEH_FRAME.store(true, Ordering::Relaxed);
+            OverflowMode::ReportAndContinueSearch
+        }
+        _ => unreachable!(),
+    };
+    set_mode(mode);
+    unsafe { init() };
+    let _ = thread::spawn(|| recurse(0)).join();
+}
+
+#[test]
+fn overflow_modes() {
+    // The handler needs vectored exception handling.
+    if c::AddVectoredExceptionHandler::option().is_none() {
+        return;
+    }
+    for (mode, reported) in [("continue", true), ("abort", true), ("silent", false)] {
+        let output = Command::new(env::current_exe().unwrap())
+            .args(["overflow_child", "--test-threads=1"])
+            .env(CHILD_MODE_VAR, mode)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(stderr.contains("has overflowed its stack"), reported, "{mode}: {stderr}");
+        assert_eq!(output.status.code(), Some(c::EXCEPTION_STACK_OVERFLOW), "{mode}");
+    }
+}
+
+// MSVC images are linked with `/SAFESEH`, which keeps the handler from being called on the
+// systems that tests run on.
+#[cfg(all(target_arch = "x86", not(target_env = "msvc")))]
+#[test]
+fn overflow_seh_frame() {
+    let output = Command::new(env::current_exe().unwrap())
+        .args(["overflow_child", "--test-threads=1"])
+        .env(CHILD_MODE_VAR, "seh")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("has overflowed its stack"), "{stderr}");
+    assert_eq!(output.status.code(), Some(c::EXCEPTION_STACK_OVERFLOW));
+}
@@ -1,11 +1,34 @@
 #![cfg_attr(test, allow(dead_code))]
 
+use crate::io;
+
 pub struct Handler;
 
 impl Handler {
-    pub fn new() -> Handler {
-        Handler
+    pub fn new(_stack_size: Option<usize>) -> io::Result<Handler> {
+        Ok(Handler)
     }
 }
 
+/// Stack overflows aren't handled on UWP, so this is only there to match the other platforms.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OverflowMode {
+    #[default]
+    ReportAndContinueSearch,
+    ReportAndAbort,
+    Silent,
+}
+
+pub fn set_mode(_mode: OverflowMode) {}
+
 pub unsafe fn init() {}
+
+pub unsafe fn cleanup() {}
+
+pub fn guarantee_missing() -> bool {
+    false
+}
+
+pub unsafe fn with_seh_frame<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
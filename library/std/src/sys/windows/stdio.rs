@@ -2,14 +2,15 @@
 
 use crate::cmp;
 use crate::io;
-use crate::mem::MaybeUninit;
-use crate::os::windows::io::{FromRawHandle, IntoRawHandle};
+use crate::mem::{self, MaybeUninit};
+use crate::os::windows::io::{BorrowedHandle, FromRawHandle, IntoRawHandle};
 use crate::ptr;
 use crate::str;
 use crate::sys::c;
 use crate::sys::compat;
 use crate::sys::cvt;
 use crate::sys::handle::Handle;
+use crate::sys::io::{handle_kind, HandleKind};
 use crate::sys::windows::api;
 use core::str::utf8_char_width;
 
@@ -21,16 +22,24 @@
 pub struct Stdin {
     surrogate: u16,
     incomplete_utf8: IncompleteUtf8,
+    kind: CachedKind,
 }
 
 pub struct Stdout {
     incomplete_utf8: IncompleteUtf8,
+    kind: CachedKind,
 }
 
 pub struct Stderr {
     incomplete_utf8: IncompleteUtf8,
+    kind: CachedKind,
 }
 
+// The kind of the standard handle, together with the handle it was determined for. It only needs
+// to be determined again once the handle was changed. Reads and writes are already serialized by
+// the locks of `io::Stdin`, `io::Stdout` and `io::Stderr`, so this doesn't need a lock of its own.
+struct CachedKind(Option<(usize, HandleKind)>);
+
 struct IncompleteUtf8 {
     bytes: [u8; 4],
     len: u8,
@@ -80,33 +89,56 @@ pub fn get_handle(handle_id: c::DWORD) -> io::Result<c::HANDLE> {
     }
 }
 
-fn is_console(handle: c::HANDLE) -> bool {
-    // `GetConsoleMode` will return false (0) if this is a pipe (we don't care about the reported
-    // mode). This will only detect Windows Console, not other terminals connected to a pipe like
-    // MSYS. Which is exactly what we need, as only Windows Console needs a conversion to UTF-16.
-    let mut mode = 0;
-    unsafe { c::GetConsoleMode(handle, &mut mode) != 0 }
+fn is_console(handle: c::HANDLE, cached: &mut CachedKind) -> bool {
+    // This will only detect Windows Console, not other terminals connected to a pipe like MSYS.
+    // Which is exactly what we need, as only Windows Console needs a conversion to UTF-16.
+    let kind = match cached.0 {
+        Some((cached, kind)) if cached == handle as usize => kind,
+        _ => {
+            let kind = handle_kind(unsafe { BorrowedHandle::borrow_raw(handle) });
+            cached.0 = Some((handle as usize, kind));
+            kind
+        }
+    };
+    kind == HandleKind::Console
 }
 
 fn write(
     handle_id: c::DWORD,
     data: &[u8],
     incomplete_utf8: &mut IncompleteUtf8,
+    kind: &mut CachedKind,
 ) -> io::Result<usize> {
     if data.is_empty() {
         return Ok(0);
     }
 
     let handle = get_handle(handle_id)?;
-    if !is_console(handle) {
-        unsafe {
-            let handle = Handle::from_raw_handle(handle);
-            let ret = handle.write(data);
-            handle.into_raw_handle(); // Don't close the handle
-            return ret;
-        }
+    if !is_console(handle, kind) {
+        return write_raw(handle, data);
     }
+    write_console_utf8(data, incomplete_utf8, |utf8| write_valid_utf8_to_console(handle, utf8))
+}
 
+fn write_raw(handle: c::HANDLE, data: &[u8]) -> io::Result<usize> {
+    unsafe {
+        let handle = Handle::from_raw_handle(handle);
+        let ret = handle.write(data);
+        handle.into_raw_handle(); // Don't close the handle
+        ret
+    }
+}
+
+/// Writes UTF-8 `data` meant for the console through `write_valid`, which only ever gets whole
+/// characters.
+///
+/// A character cut off at the end of `data` is kept in `incomplete_utf8` until the next write
+/// completes it, so output split across `write` calls isn't broken up.
+fn write_console_utf8(
+    data: &[u8],
+    incomplete_utf8: &mut IncompleteUtf8,
+    mut write_valid: impl FnMut(&str) -> io::Result<usize>,
+) -> io::Result<usize> {
     if incomplete_utf8.len > 0 {
         assert!(
             incomplete_utf8.len < 4,
@@ -132,7 +164,7 @@ fn write(
         match s {
             Ok(s) => {
                 assert_eq!(char_width, s.len());
-                let written = write_valid_utf8_to_console(handle, s)?;
+                let written = write_valid(s)?;
                 assert_eq!(written, s.len()); // guaranteed by write_valid_utf8_to_console() for single codepoint writes
                 return Ok(1);
             }
@@ -170,7 +202,36 @@ fn write(
         Err(e) => str::from_utf8(&data[..e.valid_up_to()]).unwrap(),
     };
 
-    write_valid_utf8_to_console(handle, utf8)
+    write_valid(utf8)
+}
+
+/// Writes out the character left incomplete by the last write, which nothing will complete
+/// anymore, as a replacement character.
+fn flush(
+    handle_id: c::DWORD,
+    incomplete_utf8: &mut IncompleteUtf8,
+    kind: &mut CachedKind,
+) -> io::Result<()> {
+    if incomplete_utf8.len == 0 {
+        return Ok(());
+    }
+    let handle = get_handle(handle_id)?;
+    if !is_console(handle, kind) {
+        // The handle was changed since. It doesn't need the bytes converted anymore.
+        let len = mem::take(&mut incomplete_utf8.len) as usize;
+        return write_raw(handle, &incomplete_utf8.bytes[..len]).map(drop);
+    }
+    flush_console_utf8(incomplete_utf8, |utf8| write_valid_utf8_to_console(handle, utf8))
+}
+
+fn flush_console_utf8(
+    incomplete_utf8: &mut IncompleteUtf8,
+    write_valid: impl FnOnce(&str) -> io::Result<usize>,
+) -> io::Result<()> {
+    if mem::take(&mut incomplete_utf8.len) == 0 {
+        return Ok(());
+    }
+    write_valid(char::REPLACEMENT_CHARACTER.encode_utf8(&mut [0; 4])).map(drop)
 }
 
 fn write_valid_utf8_to_console(handle: c::HANDLE, utf8: &str) -> io::Result<usize> {
@@ -251,14 +312,14 @@ fn write_u16s(handle: c::HANDLE, data: &[u16]) -> io::Result<usize> {
 
 impl Stdin {
     pub const fn new() -> Stdin {
-        Stdin { surrogate: 0, incomplete_utf8: IncompleteUtf8::new() }
+        Stdin { surrogate: 0, incomplete_utf8: IncompleteUtf8::new(), kind: CachedKind(None) }
     }
 }
 
 impl io::Read for Stdin {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let handle = get_handle(c::STD_INPUT_HANDLE)?;
-        if !is_console(handle) {
+        if !is_console(handle, &mut self.kind) {
             unsafe {
                 let handle = Handle::from_raw_handle(handle);
                 let ret = handle.read(buf);
@@ -429,33 +490,33 @@ pub const fn new() -> IncompleteUtf8 {
 
 impl Stdout {
     pub const fn new() -> Stdout {
-        Stdout { incomplete_utf8: IncompleteUtf8::new() }
+        Stdout { incomplete_utf8: IncompleteUtf8::new(), kind: CachedKind(None) }
     }
 }
 
 impl io::Write for Stdout {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        write(c::STD_OUTPUT_HANDLE, buf, &mut self.incomplete_utf8)
+        write(c::STD_OUTPUT_HANDLE, buf, &mut self.incomplete_utf8, &mut self.kind)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+        flush(c::STD_OUTPUT_HANDLE, &mut self.incomplete_utf8, &mut self.kind)
     }
 }
 
 impl Stderr {
     pub const fn new() -> Stderr {
-        Stderr { incomplete_utf8: IncompleteUtf8::new() }
+        Stderr { incomplete_utf8: IncompleteUtf8::new(), kind: CachedKind(None) }
     }
 }
 
 impl io::Write for Stderr {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        write(c::STD_ERROR_HANDLE, buf, &mut self.incomplete_utf8)
+        write(c::STD_ERROR_HANDLE, buf, &mut self.incomplete_utf8, &mut self.kind)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+        flush(c::STD_ERROR_HANDLE, &mut self.incomplete_utf8, &mut self.kind)
     }
 }
 
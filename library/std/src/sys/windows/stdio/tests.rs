@@ -1,6 +1,54 @@
-use super::utf16_to_utf8;
+use super::{flush_console_utf8, utf16_to_utf8, write_console_utf8, IncompleteUtf8};
 
 #[test]
 fn zero_size_read() {
     assert_eq!(utf16_to_utf8(&[], &mut []).unwrap(), 0);
 }
+
+/// Writes all of `data` like `write_all` would, collecting what the console gets in `console`.
+fn write_all(mut data: &[u8], incomplete_utf8: &mut IncompleteUtf8, console: &mut String) {
+    while !data.is_empty() {
+        let n = write_console_utf8(data, incomplete_utf8, |utf8| {
+            console.push_str(utf8);
+            Ok(utf8.len())
+        })
+        .unwrap();
+        data = &data[n..];
+    }
+}
+
+fn flush(incomplete_utf8: &mut IncompleteUtf8, console: &mut String) {
+    flush_console_utf8(incomplete_utf8, |utf8| {
+        console.push_str(utf8);
+        Ok(utf8.len())
+    })
+    .unwrap();
+}
+
+#[test]
+fn split_characters_are_written_whole() {
+    let text = "a€b𝄞c";
+    // Every way of splitting the text in two, also in the middle of a character.
+    for split in 0..=text.len() {
+        let mut incomplete_utf8 = IncompleteUtf8::new();
+        let mut console = String::new();
+        write_all(&text.as_bytes()[..split], &mut incomplete_utf8, &mut console);
+        write_all(&text.as_bytes()[split..], &mut incomplete_utf8, &mut console);
+        flush(&mut incomplete_utf8, &mut console);
+        assert_eq!(console, text, "split at {split}");
+    }
+}
+
+#[test]
+fn flush_writes_incomplete_character() {
+    let mut incomplete_utf8 = IncompleteUtf8::new();
+    let mut console = String::new();
+    write_all(&"x€".as_bytes()[..3], &mut incomplete_utf8, &mut console);
+    assert_eq!(console, "x");
+
+    // Only once, and nothing is left over for the next write.
+    flush(&mut incomplete_utf8, &mut console);
+    flush(&mut incomplete_utf8, &mut console);
+    write_all(b"y", &mut incomplete_utf8, &mut console);
+    assert_eq!(console, "x\u{FFFD}y");
+}
@@ -0,0 +1,71 @@
+use super::{c, decode_error_kind, dur2timeout, Deadline};
+use crate::io::ErrorKind;
+use crate::thread;
+use crate::time::Duration;
+
+#[test]
+fn dur2timeout_rounds_up() {
+    assert_eq!(dur2timeout(Duration::ZERO), 0);
+    assert_eq!(dur2timeout(Duration::from_nanos(1)), 1);
+    assert_eq!(dur2timeout(Duration::from_micros(1500)), 2);
+    assert_eq!(dur2timeout(Duration::from_millis(c::INFINITE as u64 + 1)), c::INFINITE);
+}
+
+#[test]
+fn deadline_zero() {
+    assert_eq!(Deadline::new(Duration::ZERO).remaining_timeout(), None);
+}
+
+#[test]
+fn deadline_saturates() {
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+    for dur in [50 * DAY, 10_000 * DAY, Duration::MAX] {
+        assert_eq!(Deadline::new(dur).remaining_timeout(), Some(c::INFINITE - 1));
+    }
+    let just_below = Duration::from_millis(c::INFINITE as u64 - 1_000);
+    let timeout = Deadline::new(just_below).remaining_timeout().unwrap();
+    assert!(timeout < c::INFINITE - 1 && timeout > c::INFINITE - 1_001);
+}
+
+#[test]
+fn deadline_shrinks() {
+    let deadline = Deadline::new(Duration::from_millis(100));
+    let mut last = 100;
+    while let Some(timeout) = deadline.remaining_timeout() {
+        assert!(timeout <= last);
+        last = timeout;
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(deadline.remaining_timeout(), None);
+}
+
+#[test]
+fn socket_error_kinds() {
+    use ErrorKind::*;
+    // The same failures have different codes on different Winsock versions, or when they come
+    // from overlapped I/O, but portable code can only check the kind.
+    let kinds = [
+        (c::WSAECONNREFUSED, ConnectionRefused),
+        (c::ERROR_CONNECTION_REFUSED as i32, ConnectionRefused),
+        (c::WSAECONNRESET, ConnectionReset),
+        (c::WSAENETRESET, ConnectionReset),
+        (c::ERROR_NETNAME_DELETED as i32, ConnectionReset),
+        (c::WSAECONNABORTED, ConnectionAborted),
+        (c::ERROR_CONNECTION_ABORTED as i32, ConnectionAborted),
+        (c::WSAEHOSTUNREACH, HostUnreachable),
+        (c::WSAEHOSTDOWN, HostUnreachable),
+        (c::ERROR_HOST_UNREACHABLE as i32, HostUnreachable),
+        (c::WSAENETUNREACH, NetworkUnreachable),
+        (c::ERROR_NETWORK_UNREACHABLE as i32, NetworkUnreachable),
+        (c::WSAEWOULDBLOCK, WouldBlock),
+        (c::WSAEALREADY, WouldBlock),
+        (c::WSAEINVAL, InvalidInput),
+        (c::WSAEINTR, Interrupted),
+        (c::WSAESHUTDOWN, BrokenPipe),
+        (c::WSAENOBUFS, OutOfMemory),
+        (c::WSAEPROCLIM, ResourceBusy),
+    ];
+    for (code, kind) in kinds {
+        assert_eq!(decode_error_kind(code), kind, "error {code}");
+    }
+}
@@ -1,20 +1,29 @@
+#[cfg(test)]
+use crate::cell::Cell;
+use crate::cmp;
 use crate::ffi::CStr;
 use crate::io;
+use crate::mem;
 use crate::num::NonZeroUsize;
 use crate::os::windows::io::AsRawHandle;
 use crate::os::windows::io::HandleOrNull;
 use crate::ptr;
+use crate::sync::atomic::{AtomicU8, Ordering};
 use crate::sys::c;
+use crate::sys::compat;
 use crate::sys::handle::Handle;
 use crate::sys::stack_overflow;
 use crate::sys_common::FromInner;
-use crate::time::Duration;
+use crate::time::{Duration, Instant};
 
-use core::ffi::c_void;
+use core::ffi::{c_char, c_void};
 
 use super::time::WaitableTimer;
 use super::to_u16s;
 
+#[cfg(test)]
+mod tests;
+
 pub const DEFAULT_MIN_STACK_SIZE: usize = 2 * 1024 * 1024;
 
 pub struct Thread {
@@ -24,7 +33,7 @@ pub struct Thread {
 impl Thread {
     // unsafe: see thread::Builder::spawn_unchecked for safety requirements
     pub unsafe fn new(stack: usize, p: Box<dyn FnOnce()>) -> io::Result<Thread> {
-        let p = Box::into_raw(Box::new(p));
+        let p = Box::into_raw(Box::new((stack, p)));
 
         // FIXME On UNIX, we guard against stack sizes that are too small but
         // that's because pthreads enforces that stacks are at least
@@ -55,24 +64,32 @@ pub unsafe fn new(stack: usize, p: Box<dyn FnOnce()>) -> io::Result<Thread> {
 
         extern "system" fn thread_start(main: *mut c_void) -> c::DWORD {
             unsafe {
+                let (stack, main) = *Box::from_raw(main as *mut (usize, Box<dyn FnOnce()>));
                 // Next, set up our stack overflow handler which may get triggered if we run
-                // out of stack.
-                let _handler = stack_overflow::Handler::new();
-                // Finally, let's run some code.
-                Box::from_raw(main as *mut Box<dyn FnOnce()>)();
+                // out of stack. The thread still works without it, so errors are ignored.
+                // A stack size of 0 means the default of the executable, which isn't known.
+                let _handler = stack_overflow::Handler::new((stack != 0).then_some(stack));
+                // Finally, let's run some code, with a frame-based handler on systems without
+                // vectored exception handling.
+                stack_overflow::with_seh_frame(main);
             }
             0
         }
     }
 
+    /// Names the current thread for debuggers and other tools.
+    ///
+    /// Panics and stack overflows are reported with the name that `Thread` keeps on the Rust
+    /// side, so they name the thread whether or not this works.
     pub fn set_name(name: &CStr) {
-        if let Ok(utf8) = name.to_str() {
-            if let Ok(utf16) = to_u16s(utf8) {
-                unsafe {
-                    c::SetThreadDescription(c::GetCurrentThread(), utf16.as_ptr());
-                };
-            };
-        };
+        let described = name
+            .to_str()
+            .ok()
+            .and_then(|utf8| to_u16s(utf8).ok())
+            .is_some_and(|utf16| unsafe { set_description(utf16.as_ptr()) });
+        if !described {
+            name_for_debugger(name);
+        }
     }
 
     pub fn join(self) {
@@ -92,17 +109,7 @@ pub fn yield_now() {
     }
 
     pub fn sleep(dur: Duration) {
-        fn high_precision_sleep(dur: Duration) -> Result<(), ()> {
-            let timer = WaitableTimer::high_resolution()?;
-            timer.set(dur)?;
-            timer.wait()
-        }
-        // Attempt to use high-precision sleep (Windows 10, version 1803+).
-        // On error fallback to the standard `Sleep` function.
-        // Also preserves the zero duration behaviour of `Sleep`.
-        if dur.is_zero() || high_precision_sleep(dur).is_err() {
-            unsafe { c::Sleep(super::dur2timeout(dur)) }
-        }
+        sleep_with(SleepKind::get(), dur)
     }
 
     pub fn handle(&self) -> &Handle {
@@ -114,19 +121,189 @@ pub fn into_handle(self) -> Handle {
     }
 }
 
+#[cfg(test)]
+thread_local! {
+    static FORCE_NO_THREAD_DESCRIPTION: Cell<bool> = Cell::new(false);
+    /// How often `name_for_debugger` was called on this thread.
+    static NAMED_FOR_DEBUGGER: Cell<usize> = Cell::new(0);
+}
+
+/// Runs `f` with threads named like on systems without `SetThreadDescription`, for tests.
+#[cfg(test)]
+fn without_thread_description<R>(f: impl FnOnce() -> R) -> R {
+    let forced = FORCE_NO_THREAD_DESCRIPTION.with(|force| force.replace(true));
+    let result = f();
+    FORCE_NO_THREAD_DESCRIPTION.with(|force| force.set(forced));
+    result
+}
+
+/// Sets the description of the current thread to the null-terminated `description`, returning
+/// whether that worked. It doesn't before Windows 10, version 1607.
+unsafe fn set_description(description: *const u16) -> bool {
+    #[cfg(test)]
+    if FORCE_NO_THREAD_DESCRIPTION.with(Cell::get) {
+        return false;
+    }
+    c::SetThreadDescription(c::GetCurrentThread(), description) >= 0
+}
+
+/// Names the current thread the way it was done before thread descriptions: by raising an
+/// exception that debuggers know to take the name from.
+///
+/// Without a debugger, nobody would see the name, so it isn't raised at all. A debugger that
+/// passes the exception on leaves it to a vectored handler to continue from it, which only
+/// exists since XP. Older systems don't get a name, as the exception would be fatal there.
+fn name_for_debugger(name: &CStr) {
+    #[cfg(test)]
+    NAMED_FOR_DEBUGGER.with(|count| count.set(count.get() + 1));
+
+    #[cfg(not(target_vendor = "uwp"))]
+    unsafe {
+        const MS_VC_EXCEPTION: u32 = 0x406D1388;
+
+        #[repr(C)]
+        struct ThreadNameInfo {
+            kind: u32,
+            name: *const c_char,
+            thread_id: u32,
+            flags: u32,
+        }
+
+        unsafe extern "system" fn continue_from_name(info: *mut c::EXCEPTION_POINTERS) -> i32 {
+            if (*(*info).ExceptionRecord).ExceptionCode as u32 == MS_VC_EXCEPTION {
+                c::EXCEPTION_CONTINUE_EXECUTION
+            } else {
+                c::EXCEPTION_CONTINUE_SEARCH
+            }
+        }
+
+        if !c::IsDebuggerPresent::option().is_some_and(|is_debugged| is_debugged() != 0) {
+            return;
+        }
+        let add = c::AddVectoredExceptionHandler::option();
+        let remove = c::RemoveVectoredExceptionHandler::option();
+        let (Some(add), Some(remove)) = (add, remove) else { return };
+        let handler = add(1, Some(continue_from_name));
+        if handler.is_null() {
+            return;
+        }
+        let info = ThreadNameInfo {
+            kind: 0x1000,
+            name: name.as_ptr(),
+            // The current thread.
+            thread_id: u32::MAX,
+            flags: 0,
+        };
+        let args = mem::size_of::<ThreadNameInfo>() / mem::size_of::<usize>();
+        c::RaiseException(MS_VC_EXCEPTION, 0, args as u32, &info as *const _ as *const usize);
+        remove(handler);
+    }
+}
+
+/// What `sleep` waits on, picked on first use.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+enum SleepKind {
+    /// A high-resolution waitable timer (Windows 10, version 1803+).
+    HighResolution = 1,
+    /// An ordinary waitable timer (NT 4+, 98+), which fires on a tick of the system timer.
+    Timer = 2,
+    /// `Sleep`, which also only wakes up on a tick of the system timer.
+    Sleep = 3,
+}
+
+/// Sleeps up to this long are spun out if the system timer is all there is, as its ticks are
+/// 10-16ms by default. Longer ones would burn too much CPU time for too little precision.
+const SPIN_AT_MOST: Duration = Duration::from_millis(2);
+
+impl SleepKind {
+    fn get() -> SleepKind {
+        // 0 until the kind has been picked.
+        static KIND: AtomicU8 = AtomicU8::new(0);
+
+        match KIND.load(Ordering::Relaxed) {
+            1 => SleepKind::HighResolution,
+            2 => SleepKind::Timer,
+            3 => SleepKind::Sleep,
+            _ => {
+                let kind = if WaitableTimer::high_resolution().is_ok() {
+                    SleepKind::HighResolution
+                } else if WaitableTimer::new().is_ok() {
+                    SleepKind::Timer
+                } else {
+                    SleepKind::Sleep
+                };
+                KIND.store(kind as u8, Ordering::Relaxed);
+                kind
+            }
+        }
+    }
+}
+
+fn sleep_with(kind: SleepKind, dur: Duration) {
+    // Preserves the zero duration behaviour of `Sleep`, which gives up the rest of the time slice.
+    if dur.is_zero() {
+        return unsafe { c::Sleep(0) };
+    }
+    if kind != SleepKind::HighResolution && dur <= SPIN_AT_MOST {
+        return spin(dur);
+    }
+
+    let timer = match kind {
+        SleepKind::HighResolution => WaitableTimer::high_resolution(),
+        SleepKind::Timer => WaitableTimer::new(),
+        SleepKind::Sleep => Err(()),
+    };
+    // Creating the timer can still fail, e.g. if the process runs out of handles.
+    if timer.and_then(|timer| timer.set(dur).and_then(|()| timer.wait())).is_err() {
+        unsafe { c::Sleep(super::dur2timeout(dur)) }
+    }
+}
+
+/// Waits out `dur` without the system timer, letting other threads run in the meantime.
+fn spin(dur: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < dur {
+        unsafe { c::SwitchToThread() };
+    }
+}
+
 pub fn available_parallelism() -> io::Result<NonZeroUsize> {
-    let res = unsafe {
-        let mut sysinfo: c::SYSTEM_INFO = crate::mem::zeroed();
-        c::GetSystemInfo(&mut sysinfo);
-        sysinfo.dwNumberOfProcessors as usize
+    let system = system_processors();
+    let cpus = match process_processors() {
+        // More processors in the mask than there are would be a bug, but not ours to pass on.
+        Some(process) if system != 0 => cmp::min(process, system),
+        Some(process) => process,
+        None => system,
     };
-    match res {
-        0 => Err(io::const_io_error!(
-            io::ErrorKind::NotFound,
-            "The number of hardware threads is not known for the target platform",
-        )),
-        cpus => Ok(unsafe { NonZeroUsize::new_unchecked(cpus) }),
+    // 9x/ME always report one processor, and a count of zero can't be right either.
+    Ok(NonZeroUsize::new(cpus).unwrap_or(NonZeroUsize::MIN))
+}
+
+/// The number of processors of the system, which may be zero if something went wrong.
+fn system_processors() -> usize {
+    let mut sysinfo: c::SYSTEM_INFO = unsafe { mem::zeroed() };
+    // Under WOW64, `GetSystemInfo` reports 32 processors at most.
+    match c::GetNativeSystemInfo::option() {
+        Some(get_native_system_info) => unsafe { get_native_system_info(&mut sysinfo) },
+        None => unsafe { c::GetSystemInfo(&mut sysinfo) },
+    }
+    usize::try_from(sysinfo.dwNumberOfProcessors).unwrap_or(usize::MAX)
+}
+
+/// The number of processors the process may run on, or `None` where that can't be told.
+///
+/// This only counts the processors in the process's processor group, of which there are 64 at
+/// most, so it undercounts on systems with more processors rather than failing.
+fn process_processors() -> Option<usize> {
+    if !compat::is_windows_nt() {
+        return None;
     }
+    let get_process_affinity_mask = c::GetProcessAffinityMask::option()?;
+    let (mut process, mut system) = (0, 0);
+    let ok =
+        unsafe { get_process_affinity_mask(c::GetCurrentProcess(), &mut process, &mut system) };
+    (ok != 0 && process != 0).then(|| process.count_ones() as usize)
 }
 
 #[cfg_attr(test, allow(dead_code))]
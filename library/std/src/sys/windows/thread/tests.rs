@@ -0,0 +1,135 @@
+use super::{
+    available_parallelism, process_processors, sleep_with, system_processors,
+    without_thread_description, SleepKind, Thread, NAMED_FOR_DEBUGGER,
+};
+use crate::cell::Cell;
+use crate::io;
+use crate::mem;
+use crate::panic;
+use crate::sync::{Arc, Mutex};
+use crate::sys::c;
+use crate::thread;
+use crate::time::{Duration, Instant};
+
+/// The kinds this system can sleep with, from the one it picks on.
+fn available_kinds() -> &'static [SleepKind] {
+    use SleepKind::*;
+    match SleepKind::get() {
+        HighResolution => &[HighResolution, Timer, Sleep],
+        Timer => &[Timer, Sleep],
+        Sleep => &[Sleep],
+    }
+}
+
+/// The CPU time the current thread used so far, or `None` on 9x/ME.
+fn cpu_time() -> Option<Duration> {
+    let mut times = [unsafe { mem::zeroed::<c::FILETIME>() }; 4];
+    let [creation, exit, kernel, user] = &mut times;
+    let ok = unsafe { c::GetThreadTimes(c::GetCurrentThread(), creation, exit, kernel, user) };
+    let intervals = |t: &c::FILETIME| ((t.dwHighDateTime as u64) << 32) | t.dwLowDateTime as u64;
+    (ok != 0).then(|| Duration::from_nanos((intervals(kernel) + intervals(user)) * 100))
+}
+
+#[test]
+fn short_sleeps_are_short() {
+    for &kind in available_kinds() {
+        let dur = Duration::from_millis(1);
+        // The best of a few tries, so one badly scheduled wakeup doesn't fail the test.
+        let elapsed = (0..5)
+            .map(|_| {
+                let start = Instant::now();
+                sleep_with(kind, dur);
+                start.elapsed()
+            })
+            .min()
+            .unwrap();
+        // The timers' clock isn't the one `Instant` reads, so allow them to fire a little early.
+        assert!(elapsed + Duration::from_millis(1) >= dur, "{kind:?}: woke up after {elapsed:?}");
+        assert!(elapsed < dur + Duration::from_millis(4), "{kind:?}: overslept by {elapsed:?}");
+    }
+}
+
+#[test]
+fn long_sleeps_dont_spin() {
+    for &kind in available_kinds() {
+        let dur = Duration::from_millis(200);
+        let Some(before) = cpu_time() else { return };
+        let start = Instant::now();
+        sleep_with(kind, dur);
+        let elapsed = start.elapsed();
+        let used = cpu_time().unwrap() - before;
+        // Waits on the system timer may end up to a tick early.
+        assert!(elapsed + Duration::from_millis(16) >= dur, "{kind:?}: woke up after {elapsed:?}");
+        // The CPU time is only accounted in whole ticks of the system timer.
+        assert!(used <= Duration::from_millis(32), "{kind:?}: used {used:?} of CPU time");
+    }
+}
+
+#[test]
+fn zero_sleep_returns() {
+    for &kind in available_kinds() {
+        sleep_with(kind, Duration::ZERO);
+    }
+}
+
+#[test]
+fn parallelism_within_system() {
+    let cpus = available_parallelism().unwrap().get();
+    let system = system_processors();
+    assert!(system >= 1);
+    assert!(cpus <= system, "{cpus} of {system} processors");
+}
+
+#[test]
+fn parallelism_matches_affinity() {
+    let Some(process) = process_processors() else { return };
+    assert_eq!(available_parallelism().unwrap().get(), process);
+
+    // Which is what the affinity mask says as well.
+    let get_process_affinity_mask = c::GetProcessAffinityMask::option().unwrap();
+    let (mut mask, mut system) = (0, 0);
+    let ok = unsafe { get_process_affinity_mask(c::GetCurrentProcess(), &mut mask, &mut system) };
+    assert_ne!(ok, 0);
+    assert_eq!(mask.count_ones() as usize, process);
+    assert_eq!(mask & !system, 0, "process mask {mask:#x} outside of system mask {system:#x}");
+}
+
+/// Panics on the current thread, returning the message that the panic hook printed.
+fn panic_message() -> String {
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let previous = io::set_output_capture(Some(output.clone()));
+    let _ = panic::catch_unwind(|| panic!("on purpose"));
+    io::set_output_capture(previous);
+    let output = output.lock().unwrap();
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+#[test]
+fn names_without_description() {
+    let (named_before, named_after, message) = thread::Builder::new()
+        .name("old api".into())
+        .spawn(|| {
+            let named_before = NAMED_FOR_DEBUGGER.with(Cell::get);
+            without_thread_description(|| Thread::set_name(c"old api"));
+            let named_after = NAMED_FOR_DEBUGGER.with(Cell::get);
+            (named_before, named_after, panic_message())
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(named_after, named_before + 1);
+    assert!(message.starts_with("thread 'old api' panicked at "), "{message}");
+}
+
+#[test]
+fn names_with_description() {
+    let named_for_debugger = thread::Builder::new()
+        .name("new api".into())
+        .spawn(|| NAMED_FOR_DEBUGGER.with(Cell::get))
+        .unwrap()
+        .join()
+        .unwrap();
+    if c::GetThreadDescription::option().is_some() {
+        assert_eq!(named_for_debugger, 0);
+    }
+}
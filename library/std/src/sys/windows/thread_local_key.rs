@@ -276,6 +276,11 @@ unsafe fn register_dtor(key: &'static StaticKey) {
 
 #[allow(dead_code, unused_variables)]
 unsafe extern "system" fn on_tls_callback(h: c::LPVOID, dwReason: c::DWORD, pv: c::LPVOID) {
+    // `pv` is null if the DLL is unloaded rather than the process exiting. The stack overflow
+    // handler mustn't outlive the code it points to then.
+    if dwReason == c::DLL_PROCESS_DETACH && pv.is_null() {
+        crate::sys::stack_overflow::cleanup();
+    }
     if !HAS_DTORS.load(Acquire) {
         return;
     }
@@ -8,68 +8,124 @@
 // thread parker matches the Linux thread parker exactly.
 //
 // However, when the modern API is not available, this implementation falls
-// back to NT Keyed Events, which are similar, but have some important
-// differences. These are available since Windows XP.
+// back to one of the parkers from `sys_common`:
+// - NT Keyed Events (Windows XP through 7), which need no kernel object per
+//   thread, but have rendezvous semantics that need some care. See
+//   `sys_common::thread_parking::keyed_event` for the details.
+// - Otherwise, an auto-reset event created the first time the thread parks.
+// Which one is used is decided at runtime, by which of these functions could
+// be loaded, and remembered by each parker so the parking and unparking sides
+// always agree. Tests can also pick one explicitly.
 //
-// WaitOnAddress first checks the state of the thread parker to make sure it no
-// WakeByAddressSingle calls can be missed between updating the parker state
-// and calling the function.
-//
-// NtWaitForKeyedEvent does not have this option, and unconditionally blocks
-// without checking the parker state first. Instead, NtReleaseKeyedEvent
-// (unlike WakeByAddressSingle) *blocks* until it woke up a thread waiting for
-// it by NtWaitForKeyedEvent. This way, we can be sure no events are missed,
-// but we need to be careful not to block unpark() if park_timeout() was woken
-// up by a timeout instead of unpark().
+// Builds for Windows 8 and later (the `windows_min_version = "8"` cfg, set by
+// `STD_WINDOWS_MIN_VERSION=8`) only run on systems that have WaitOnAddress.
+// They import it like any other function, and don't build the fallbacks at
+// all, so that parking costs the same as in upstream std.
 //
-// Unlike WaitOnAddress, NtWaitForKeyedEvent/NtReleaseKeyedEvent operate on a
-// HANDLE (created with NtCreateKeyedEvent). This means that we can be sure
-// a successfully awoken park() was awoken by unpark() and not a
-// NtReleaseKeyedEvent call from some other code, as these events are not only
-// matched by the key (address of the parker (state)), but also by this HANDLE.
-// We lazily allocate this handle the first time it is needed.
+// A parker lives inline in the `Inner` of its `Thread`, so it isn't dropped
+// (closing its event) before the last `Thread` handle is, and every `unpark`
+// goes through such a handle. An `unpark` racing the thread's exit therefore
+// only ever touches a live parker, and the keyed event rendezvous can't be left
+// half done either: the exiting thread last returned from a park, which always
+// completes it. Nothing but the backends' states needs to track teardown.
 //
-// The fast path (calling park() after unpark() was already called) and the
-// possible states are the same for both implementations. This is used here to
-// make sure the fast path does not even check which API to use, but can return
-// right away, independent of the used API. Only the slow paths (which will
-// actually block/wake a thread) check which API is available and have
-// different implementations.
+// None of the backends wait alertably, so user APCs queued to a parked thread
+// (with QueueUserAPC) neither run nor wake it up.
 //
-// Unfortunately, NT Keyed Events are an undocumented Windows API. However:
-// - This API is relatively simple with obvious behaviour, and there are
-//   several (unofficial) articles documenting the details. [1]
-// - `parking_lot` has been using this API for years (on Windows versions
-//   before Windows 8). [2] Many big projects extensively use parking_lot,
-//   such as servo and the Rust compiler itself.
-// - It is the underlying API used by Windows SRW locks and Windows critical
-//   sections. [3] [4]
-// - The source code of the implementations of Wine, ReactOs, and Windows XP
-//   are available and match the expected behaviour.
-// - The main risk with an undocumented API is that it might change in the
-//   future. But since we only use it for older versions of Windows, that's not
-//   a problem.
-// - Even if these functions do not block or wake as we expect (which is
-//   unlikely, see all previous points), this implementation would still be
-//   memory safe. The NT Keyed Events API is only used to sleep/block in the
-//   right place.
-//
-// [1]: http://www.locklessinc.com/articles/keyed_events/
-// [2]: https://github.com/Amanieu/parking_lot/commit/43abbc964e
-// [3]: https://docs.microsoft.com/en-us/archive/msdn-magazine/2012/november/windows-with-c-the-evolution-of-synchronization-in-windows-and-c
-// [4]: Windows Internals, Part 1, ISBN 9780735671300
+// WaitOnAddress first checks the state of the thread parker to make sure it no
+// WakeByAddressSingle calls can be missed between updating the parker state
+// and calling the function.
+
+// These test the fallbacks, and how one is picked.
+#[cfg(all(test, not(windows_min_version = "8")))]
+mod tests;
 
 use crate::pin::Pin;
-use crate::ptr;
 use crate::sync::atomic::{
-    AtomicI8, AtomicPtr,
+    AtomicI8, AtomicU8,
     Ordering::{Acquire, Relaxed, Release},
 };
-use crate::sys::{c, dur2timeout};
-use crate::time::Duration;
+use crate::sys::{c, Deadline};
+#[cfg(not(windows_min_version = "8"))]
+use crate::sys_common::thread_parking::{event, keyed_event};
+use crate::time::{Duration, Instant};
 
 pub struct Parker {
+    backend: Backend,
+    /// Only used by the `WaitOnAddress` backend.
     state: AtomicI8,
+    #[cfg(not(windows_min_version = "8"))]
+    keyed_event: keyed_event::Parker,
+    #[cfg(not(windows_min_version = "8"))]
+    event: event::Parker,
+}
+
+/// The way a [`Parker`] blocks.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Backend {
+    /// `WaitOnAddress` and `WakeByAddressSingle`, Windows 8+.
+    WaitOnAddress = 1,
+    /// NT Keyed Events, Windows XP+.
+    #[cfg(not(windows_min_version = "8"))]
+    KeyedEvent,
+    /// A lazily created auto-reset event.
+    #[cfg(not(windows_min_version = "8"))]
+    Event,
+}
+
+/// The backend chosen for new parkers, or 0 before the first one was created.
+static SELECTED: AtomicU8 = AtomicU8::new(0);
+
+impl Backend {
+    #[cfg(not(windows_min_version = "8"))]
+    const ALL: [Backend; 3] = [Backend::WaitOnAddress, Backend::KeyedEvent, Backend::Event];
+    #[cfg(windows_min_version = "8")]
+    const ALL: [Backend; 1] = [Backend::WaitOnAddress];
+
+    fn from_u8(backend: u8) -> Option<Backend> {
+        Backend::ALL.into_iter().find(|b| *b as u8 == backend)
+    }
+
+    /// The best backend this system supports. Remembered for diagnostics.
+    fn select() -> Backend {
+        if let Some(backend) = Backend::from_u8(SELECTED.load(Relaxed)) {
+            return backend;
+        }
+        #[cfg(windows_min_version = "8")]
+        let backend = Backend::WaitOnAddress;
+        #[cfg(not(windows_min_version = "8"))]
+        let backend = if c::WaitOnAddress::option().is_some() {
+            Backend::WaitOnAddress
+        } else if keyed_event::Parker::is_available() {
+            Backend::KeyedEvent
+        } else {
+            Backend::Event
+        };
+        SELECTED.store(backend as u8, Relaxed);
+        backend
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::WaitOnAddress => "WaitOnAddress",
+            #[cfg(not(windows_min_version = "8"))]
+            Backend::KeyedEvent => "keyed events",
+            #[cfg(not(windows_min_version = "8"))]
+            Backend::Event => "event",
+        }
+    }
+}
+
+/// Returns the name of the backend thread parking uses, for diagnostics.
+///
+/// This doesn't select the backend, so it's "unresolved" until the first thread was created.
+pub fn backend_name() -> &'static str {
+    if cfg!(all(target_arch = "x86", target_vendor = "rust9x")) {
+        // This target uses the generic parker instead, see `sys_common::thread_parking`.
+        return "mutex and condvar";
+    }
+    Backend::from_u8(SELECTED.load(Relaxed)).map_or("unresolved", Backend::name)
 }
 
 const PARKED: i8 = -1;
@@ -100,39 +156,51 @@ impl Parker {
     /// Construct the Windows parker. The UNIX parker implementation
     /// requires this to happen in-place.
     pub unsafe fn new_in_place(parker: *mut Parker) {
-        parker.write(Self { state: AtomicI8::new(EMPTY) });
+        Self::new_in_place_with(parker, Backend::select());
+    }
+
+    /// Construct a Windows parker that uses the given backend, which must be available.
+    pub(crate) unsafe fn new_in_place_with(parker: *mut Parker, backend: Backend) {
+        parker.write(Self {
+            backend,
+            state: AtomicI8::new(EMPTY),
+            #[cfg(not(windows_min_version = "8"))]
+            keyed_event: keyed_event::Parker::new(),
+            #[cfg(not(windows_min_version = "8"))]
+            event: event::Parker::new(),
+        });
     }
 
     // Assumes this is only called by the thread that owns the Parker,
     // which means that `self.state != PARKED`. This implementation doesn't require `Pin`,
     // but other implementations do.
     pub unsafe fn park(self: Pin<&Self>) {
+        match self.backend {
+            Backend::WaitOnAddress => {}
+            #[cfg(not(windows_min_version = "8"))]
+            Backend::KeyedEvent => return self.keyed_event().park(),
+            #[cfg(not(windows_min_version = "8"))]
+            Backend::Event => return self.event().park(),
+        }
+
         // Change NOTIFIED=>EMPTY or EMPTY=>PARKED, and directly return in the
         // first case.
         if self.state.fetch_sub(1, Acquire) == NOTIFIED {
             return;
         }
 
-        if let Some(wait_on_address) = c::WaitOnAddress::option() {
-            loop {
-                // Wait for something to happen, assuming it's still set to PARKED.
-                wait_on_address(self.ptr(), &PARKED as *const _ as c::LPVOID, 1, c::INFINITE);
-                // Change NOTIFIED=>EMPTY but leave PARKED alone.
-                if self.state.compare_exchange(NOTIFIED, EMPTY, Acquire, Acquire).is_ok() {
-                    // Actually woken up by unpark().
-                    return;
-                } else {
-                    // Spurious wake up. We loop to try again.
-                }
+        loop {
+            // Wait for something to happen, assuming it's still set to PARKED.
+            #[cfg(test)]
+            crate::sys_common::thread_parking::count_blocking_call();
+            c::WaitOnAddress(self.ptr(), &PARKED as *const _ as c::LPVOID, 1, c::INFINITE);
+            // Change NOTIFIED=>EMPTY but leave PARKED alone.
+            if self.state.compare_exchange(NOTIFIED, EMPTY, Acquire, Acquire).is_ok() {
+                // Actually woken up by unpark().
+                return;
+            } else {
+                // Spurious wake up. We loop to try again.
             }
-        } else {
-            // Wait for unpark() to produce this event.
-            c::NtWaitForKeyedEvent(keyed_event_handle(), self.ptr(), 0, ptr::null_mut());
-            // Set the state back to EMPTY (from either PARKED or NOTIFIED).
-            // Note that we don't just write EMPTY, but use swap() to also
-            // include an acquire-ordered read to synchronize with unpark()'s
-            // release-ordered write.
-            self.state.swap(EMPTY, Acquire);
         }
     }
 
@@ -140,58 +208,62 @@ pub unsafe fn park(self: Pin<&Self>) {
     // which means that `self.state != PARKED`. This implementation doesn't require `Pin`,
     // but other implementations do.
     pub unsafe fn park_timeout(self: Pin<&Self>, timeout: Duration) {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.park_deadline(deadline),
+            None => self.park(),
+        }
+    }
+
+    // Assumes this is only called by the thread that owns the Parker,
+    // which means that `self.state != PARKED`. This implementation doesn't require `Pin`,
+    // but other implementations do.
+    pub unsafe fn park_deadline(self: Pin<&Self>, deadline: Instant) {
+        match self.backend {
+            Backend::WaitOnAddress => {}
+            #[cfg(not(windows_min_version = "8"))]
+            Backend::KeyedEvent => return self.keyed_event().park_deadline(deadline),
+            #[cfg(not(windows_min_version = "8"))]
+            Backend::Event => return self.event().park_deadline(deadline),
+        }
+
         // Change NOTIFIED=>EMPTY or EMPTY=>PARKED, and directly return in the
         // first case.
         if self.state.fetch_sub(1, Acquire) == NOTIFIED {
             return;
         }
 
-        if let Some(wait_on_address) = c::WaitOnAddress::option() {
+        // Spurious wake ups must not restart the timeout, so all waits count
+        // towards the same deadline.
+        let deadline = Deadline::new(deadline.saturating_duration_since(Instant::now()));
+        while let Some(ms) = deadline.remaining_timeout() {
             // Wait for something to happen, assuming it's still set to PARKED.
-            wait_on_address(self.ptr(), &PARKED as *const _ as c::LPVOID, 1, dur2timeout(timeout));
-            // Set the state back to EMPTY (from either PARKED or NOTIFIED).
-            // Note that we don't just write EMPTY, but use swap() to also
-            // include an acquire-ordered read to synchronize with unpark()'s
-            // release-ordered write.
-            if self.state.swap(EMPTY, Acquire) == NOTIFIED {
+            #[cfg(test)]
+            crate::sys_common::thread_parking::count_blocking_call();
+            c::WaitOnAddress(self.ptr(), &PARKED as *const _ as c::LPVOID, 1, ms);
+            // Change NOTIFIED=>EMPTY but leave PARKED alone.
+            if self.state.compare_exchange(NOTIFIED, EMPTY, Acquire, Acquire).is_ok() {
                 // Actually woken up by unpark().
-            } else {
-                // Timeout or spurious wake up.
-                // We return either way, because we can't easily tell if it was the
-                // timeout or not.
-            }
-        } else {
-            // Need to wait for unpark() using NtWaitForKeyedEvent.
-            let handle = keyed_event_handle();
-
-            // NtWaitForKeyedEvent uses a unit of 100ns, and uses negative
-            // values to indicate a relative time on the monotonic clock.
-            // This is documented here for the underlying KeWaitForSingleObject function:
-            // https://docs.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitforsingleobject
-            let mut timeout = match i64::try_from((timeout.as_nanos() + 99) / 100) {
-                Ok(t) => -t,
-                Err(_) => i64::MIN,
-            };
-
-            // Wait for unpark() to produce this event.
-            let unparked =
-                c::NtWaitForKeyedEvent(handle, self.ptr(), 0, &mut timeout) == c::STATUS_SUCCESS;
-
-            // Set the state back to EMPTY (from either PARKED or NOTIFIED).
-            let prev_state = self.state.swap(EMPTY, Acquire);
-
-            if !unparked && prev_state == NOTIFIED {
-                // We were awoken by a timeout, not by unpark(), but the state
-                // was set to NOTIFIED, which means we *just* missed an
-                // unpark(), which is now blocked on us to wait for it.
-                // Wait for it to consume the event and unblock that thread.
-                c::NtWaitForKeyedEvent(handle, self.ptr(), 0, ptr::null_mut());
+                return;
             }
         }
+
+        // Timed out. Set the state back to EMPTY (from either PARKED or NOTIFIED).
+        // Note that we don't just write EMPTY, but use swap() to also
+        // include an acquire-ordered read to synchronize with unpark()'s
+        // release-ordered write.
+        self.state.swap(EMPTY, Acquire);
     }
 
     // This implementation doesn't require `Pin`, but other implementations do.
     pub fn unpark(self: Pin<&Self>) {
+        match self.backend {
+            Backend::WaitOnAddress => {}
+            #[cfg(not(windows_min_version = "8"))]
+            Backend::KeyedEvent => return self.keyed_event().unpark(),
+            #[cfg(not(windows_min_version = "8"))]
+            Backend::Event => return self.event().unpark(),
+        }
+
         // Change PARKED=>NOTIFIED, EMPTY=>NOTIFIED, or NOTIFIED=>NOTIFIED, and
         // wake the thread in the first case.
         //
@@ -199,57 +271,23 @@ pub fn unpark(self: Pin<&Self>) {
         // purpose, to make sure every unpark() has a release-acquire ordering
         // with park().
         if self.state.swap(NOTIFIED, Release) == PARKED {
-            unsafe {
-                if let Some(wake_by_address_single) = c::WakeByAddressSingle::option() {
-                    wake_by_address_single(self.ptr());
-                } else {
-                    // If we run NtReleaseKeyedEvent before the waiting thread runs
-                    // NtWaitForKeyedEvent, this (shortly) blocks until we can wake it up.
-                    // If the waiting thread wakes up before we run NtReleaseKeyedEvent
-                    // (e.g. due to a timeout), this blocks until we do wake up a thread.
-                    // To prevent this thread from blocking indefinitely in that case,
-                    // park_impl() will, after seeing the state set to NOTIFIED after
-                    // waking up, call NtWaitForKeyedEvent again to unblock us.
-                    c::NtReleaseKeyedEvent(keyed_event_handle(), self.ptr(), 0, ptr::null_mut());
-                }
-            }
+            unsafe { c::WakeByAddressSingle(self.ptr()) };
         }
     }
 
-    fn ptr(&self) -> c::LPVOID {
-        &self.state as *const _ as c::LPVOID
+    #[cfg(not(windows_min_version = "8"))]
+    fn keyed_event(self: Pin<&Self>) -> Pin<&keyed_event::Parker> {
+        // SAFETY: the keyed event parker is structurally pinned.
+        unsafe { self.map_unchecked(|p| &p.keyed_event) }
     }
-}
 
-fn keyed_event_handle() -> c::HANDLE {
-    const INVALID: c::HANDLE = ptr::invalid_mut(!0);
-    static HANDLE: AtomicPtr<crate::ffi::c_void> = AtomicPtr::new(INVALID);
-    match HANDLE.load(Relaxed) {
-        INVALID => {
-            let mut handle = c::INVALID_HANDLE_VALUE;
-            unsafe {
-                match c::NtCreateKeyedEvent(
-                    &mut handle,
-                    c::GENERIC_READ | c::GENERIC_WRITE,
-                    ptr::null_mut(),
-                    0,
-                ) {
-                    c::STATUS_SUCCESS => {}
-                    r => panic!("Unable to create keyed event handle: error {r}"),
-                }
-            }
-            match HANDLE.compare_exchange(INVALID, handle, Relaxed, Relaxed) {
-                Ok(_) => handle,
-                Err(h) => {
-                    // Lost the race to another thread initializing HANDLE before we did.
-                    // Closing our handle and using theirs instead.
-                    unsafe {
-                        c::CloseHandle(handle);
-                    }
-                    h
-                }
-            }
-        }
-        handle => handle,
+    #[cfg(not(windows_min_version = "8"))]
+    fn event(self: Pin<&Self>) -> Pin<&event::Parker> {
+        // SAFETY: the event parker is structurally pinned.
+        unsafe { self.map_unchecked(|p| &p.event) }
+    }
+
+    fn ptr(&self) -> c::LPVOID {
+        &self.state as *const _ as c::LPVOID
     }
 }
@@ -0,0 +1,280 @@
+//! These run against the fallback parkers directly, so they are tested no matter which one the
+//! Windows parker picks on the host.
+
+use crate::pin::Pin;
+use crate::sync::atomic::{
+    AtomicBool, AtomicUsize,
+    Ordering::{Acquire, Relaxed, Release},
+};
+use crate::sync::Arc;
+use crate::sys::c;
+use crate::sys::test::{handle_count, run_alone};
+use crate::sys_common::thread_parking::{event, keyed_event};
+use crate::thread;
+use crate::time::{Duration, Instant};
+
+// GetTickCount64 only advances every 10-16ms, so a deadline may expire up to one tick early.
+const TICK: Duration = Duration::from_millis(16);
+
+#[test]
+fn event_unpark_before_park() {
+    let parker = event::Parker::new();
+    let parker = Pin::new(&parker);
+    for _ in 0..10 {
+        parker.unpark();
+        unsafe { parker.park() };
+    }
+}
+
+#[test]
+fn event_unparks_coalesce() {
+    let parker = event::Parker::new();
+    let parker = Pin::new(&parker);
+    parker.unpark();
+    parker.unpark();
+    parker.unpark();
+    unsafe { parker.park() };
+
+    // Only one token was left behind.
+    let timeout = Duration::from_millis(50);
+    let start = Instant::now();
+    unsafe { parker.park_timeout(timeout) };
+    assert!(start.elapsed() + TICK >= timeout);
+}
+
+#[test]
+fn event_park_timeout_accuracy() {
+    let parker = event::Parker::new();
+    let parker = Pin::new(&parker);
+    for ms in [1, 20, 100] {
+        let timeout = Duration::from_millis(ms);
+        let start = Instant::now();
+        unsafe { parker.park_timeout(timeout) };
+        let elapsed = start.elapsed();
+        assert!(elapsed + TICK >= timeout, "woke up after {elapsed:?}, wanted {timeout:?}");
+        assert!(elapsed < timeout + Duration::from_secs(1), "overslept by {elapsed:?}");
+    }
+}
+
+#[test]
+fn event_unpark_other_thread() {
+    let parker = Arc::new(event::Parker::new());
+    let unparker = parker.clone();
+    let _guard = thread::spawn(move || {
+        for _ in 0..10 {
+            thread::sleep(Duration::from_millis(10));
+            Pin::new(&*unparker).unpark();
+        }
+    });
+    for _ in 0..10 {
+        unsafe { Pin::new(&*parker).park_timeout(Duration::from_secs(60)) };
+    }
+}
+
+#[test]
+fn keyed_event_ping_pong() {
+    if !keyed_event::Parker::is_available() {
+        return;
+    }
+
+    // Each thread parks until the other has handed it the turn.
+    const ROUNDS: usize = 10_000;
+    let parkers = Arc::new([keyed_event::Parker::new(), keyed_event::Parker::new()]);
+    let turn = Arc::new(AtomicUsize::new(0));
+
+    let ping = |me: usize, parkers: Arc<[keyed_event::Parker; 2]>, turn: Arc<AtomicUsize>| {
+        for round in 0..ROUNDS {
+            while turn.load(Acquire) != round * 2 + me {
+                unsafe { Pin::new(&parkers[me]).park() };
+            }
+            turn.store(round * 2 + me + 1, Release);
+            Pin::new(&parkers[1 - me]).unpark();
+        }
+    };
+    let other = {
+        let (parkers, turn) = (parkers.clone(), turn.clone());
+        thread::spawn(move || ping(1, parkers, turn))
+    };
+    ping(0, parkers, turn.clone());
+    other.join().unwrap();
+    assert_eq!(turn.load(Relaxed), ROUNDS * 2);
+}
+
+#[test]
+fn keyed_event_timeout_races_unpark() {
+    if !keyed_event::Parker::is_available() {
+        return;
+    }
+
+    // Short timeouts make the parker wake up by itself right as it gets unparked, which must not
+    // leave `unpark` blocked on a rendezvous that never happens.
+    const ROUNDS: usize = 10_000;
+    let parker = Arc::new(keyed_event::Parker::new());
+    let done = Arc::new(AtomicBool::new(false));
+    let unparker = {
+        let (parker, done) = (parker.clone(), done.clone());
+        thread::spawn(move || {
+            while !done.load(Relaxed) {
+                Pin::new(&*parker).unpark();
+            }
+        })
+    };
+    for _ in 0..ROUNDS {
+        unsafe { Pin::new(&*parker).park_timeout(Duration::from_micros(100)) };
+    }
+    done.store(true, Relaxed);
+    unparker.join().unwrap();
+}
+
+#[test]
+fn keyed_event_park_timeout() {
+    if !keyed_event::Parker::is_available() {
+        return;
+    }
+
+    let parker = keyed_event::Parker::new();
+    let parker = Pin::new(&parker);
+    parker.unpark();
+    parker.unpark();
+    unsafe { parker.park_timeout(Duration::from_secs(60)) };
+
+    let timeout = Duration::from_millis(50);
+    let start = Instant::now();
+    unsafe { parker.park_timeout(timeout) };
+    assert!(start.elapsed() + TICK >= timeout);
+}
+
+/// Parks for `timeout` while another thread keeps waking the parker up without leaving a token,
+/// and returns how long that took.
+fn park_through_spurious_wakeups<P: Send + Sync + 'static>(
+    parker: P,
+    park_timeout: unsafe fn(Pin<&P>, Duration),
+    wake_spuriously: fn(Pin<&P>),
+    timeout: Duration,
+) -> Duration {
+    let parker = Arc::pin(parker);
+    let done = Arc::new(AtomicBool::new(false));
+    let waker = {
+        let (parker, done) = (parker.clone(), done.clone());
+        thread::spawn(move || {
+            while !done.load(Relaxed) {
+                wake_spuriously(parker.as_ref());
+                thread::sleep(Duration::from_millis(2));
+            }
+        })
+    };
+    let start = Instant::now();
+    unsafe { park_timeout(parker.as_ref(), timeout) };
+    let elapsed = start.elapsed();
+    done.store(true, Relaxed);
+    waker.join().unwrap();
+    elapsed
+}
+
+#[test]
+fn event_spurious_wakeups_keep_deadline() {
+    let timeout = Duration::from_millis(200);
+    let elapsed = park_through_spurious_wakeups(
+        event::Parker::new(),
+        event::Parker::park_timeout,
+        event::Parker::wake_spuriously,
+        timeout,
+    );
+    assert!(elapsed + TICK >= timeout, "woke up after {elapsed:?}, wanted {timeout:?}");
+    assert!(elapsed < timeout + Duration::from_millis(100), "overslept by {elapsed:?}");
+}
+
+#[test]
+fn keyed_event_spurious_wakeups_keep_deadline() {
+    if !keyed_event::Parker::is_available() {
+        return;
+    }
+
+    let timeout = Duration::from_millis(200);
+    let elapsed = park_through_spurious_wakeups(
+        keyed_event::Parker::new(),
+        keyed_event::Parker::park_timeout,
+        keyed_event::Parker::wake_spuriously,
+        timeout,
+    );
+    assert!(elapsed + TICK >= timeout, "woke up after {elapsed:?}, wanted {timeout:?}");
+    assert!(elapsed < timeout + Duration::from_millis(100), "overslept by {elapsed:?}");
+}
+
+#[test]
+fn backend_matches_host() {
+    let _ = thread::current();
+    let expected = if cfg!(all(target_arch = "x86", target_vendor = "rust9x")) {
+        "mutex and condvar"
+    } else if c::WaitOnAddress::option().is_some() {
+        "WaitOnAddress"
+    } else if keyed_event::Parker::is_available() {
+        "keyed events"
+    } else {
+        "event"
+    };
+    assert_eq!(super::backend_name(), expected);
+}
+
+#[test]
+fn event_handles_closed_with_parker() {
+    run_alone("thread_parking::tests::event_handles_closed_with_parker", || {
+        let Some(before) = handle_count() else { return };
+
+        // Half of the threads park, and so create an event.
+        let threads: Vec<_> = (0..400)
+            .map(|i| {
+                thread::spawn(move || {
+                    let parker = event::Parker::new();
+                    if i % 2 == 0 {
+                        unsafe { Pin::new(&parker).park_timeout(Duration::from_millis(1)) };
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Nothing else runs in this process, so the count is back where it was, give or take
+        // what the first threads set up. A leak would leave 200 events behind.
+        let after = handle_count().unwrap();
+        assert!(after < before + 8, "{before} handles before, {after} after");
+    });
+}
+
+#[test]
+fn event_unpark_races_thread_exit() {
+    run_alone("thread_parking::tests::event_unpark_races_thread_exit", || {
+        let Some(before) = handle_count() else { return };
+
+        // Each thread parks briefly a few times and exits, dropping its share of the parker,
+        // while another thread keeps unparking it. Whichever of the two lets go last closes the
+        // event.
+        for _ in 0..200 {
+            let parker = Arc::new(event::Parker::new());
+            let done = Arc::new(AtomicBool::new(false));
+            let unparker = {
+                let (parker, done) = (parker.clone(), done.clone());
+                thread::spawn(move || {
+                    while !done.load(Acquire) {
+                        Pin::new(&*parker).unpark();
+                    }
+                    Pin::new(&*parker).unpark();
+                })
+            };
+            let parked = thread::spawn(move || {
+                for _ in 0..10 {
+                    unsafe { Pin::new(&*parker).park_timeout(Duration::from_micros(100)) };
+                }
+                done.store(true, Release);
+            });
+            parked.join().unwrap();
+            unparker.join().unwrap();
+        }
+
+        // Like above, a leak would leave one event behind per round.
+        let after = handle_count().unwrap();
+        assert!(after < before + 8, "{before} handles before, {after} after");
+    });
+}
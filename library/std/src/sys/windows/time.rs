@@ -2,6 +2,7 @@
 use crate::fmt;
 use crate::mem;
 use crate::ptr::null;
+use crate::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use crate::sys::c;
 use crate::sys_common::IntoInner;
 use crate::time::Duration;
@@ -9,6 +10,9 @@
 use core::hash::{Hash, Hasher};
 use core::ops::Neg;
 
+#[cfg(test)]
+mod tests;
+
 const NANOS_PER_SEC: u64 = 1_000_000_000;
 const INTERVALS_PER_SEC: u64 = NANOS_PER_SEC / 100;
 
@@ -40,7 +44,16 @@ pub fn now() -> Instant {
         // These relate to seconds by a factor of QueryPerformanceFrequency.
         // In order to keep unit conversions out of normal interval math, we
         // measure in QPC units and immediately convert to nanoseconds.
-        perf_counter::PerformanceCounterInstant::now().into()
+        //
+        // Systems before XP may lack the hardware for a performance counter,
+        // and then only have the tick count.
+        let now = match perf_counter::PerformanceCounterInstant::now() {
+            Some(now) => now.into(),
+            None => Instant { t: Duration::from_millis(unsafe { c::GetTickCount64() }) },
+        };
+        #[cfg(test)]
+        let now = jitter(now);
+        monotonize(now)
     }
 
     pub fn checked_sub_instant(&self, other: &Instant) -> Option<Duration> {
@@ -151,6 +164,51 @@ fn hash<H: Hasher>(&self, state: &mut H) {
     }
 }
 
+/// Makes sure `now` is never before an `Instant` returned earlier, on any thread.
+///
+/// Neither clock guarantees that: the performance counter may be off between processors on buggy
+/// hardware, and the tick count is extended to 64 bits by `GetTickCount64` on systems without it,
+/// which a thread reading it just before another one can see as going back a little.
+fn monotonize(now: Instant) -> Instant {
+    // The latest `Instant` returned, in nanoseconds, which last for 584 years.
+    static LATEST: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = now.t.as_nanos() as u64;
+    let latest = LATEST.fetch_max(nanos, AtomicOrdering::Relaxed);
+    if latest > nanos { Instant { t: Duration::from_nanos(latest) } } else { now }
+}
+
+#[cfg(test)]
+thread_local! {
+    static JITTER: crate::cell::Cell<Option<u64>> = crate::cell::Cell::new(None);
+}
+
+/// Moves `now` back by up to a millisecond on threads inside `with_jittery_clock`, like a clock
+/// that can't be trusted would.
+#[cfg(test)]
+fn jitter(now: Instant) -> Instant {
+    JITTER.with(|jitter| {
+        let Some(mut state) = jitter.get() else { return now };
+        // xorshift64, which is random enough to hit every kind of step.
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        jitter.set(Some(state));
+        let back = Duration::from_nanos(state % 1_000_000);
+        Instant { t: now.t.saturating_sub(back) }
+    })
+}
+
+/// Runs `f` with the clock behind `Instant::now` jumping back and forth on the current thread.
+#[cfg(test)]
+pub fn with_jittery_clock<R>(seed: u64, f: impl FnOnce() -> R) -> R {
+    // xorshift gets stuck at zero.
+    let jittery = JITTER.with(|jitter| jitter.replace(Some(seed | 1)));
+    let result = f();
+    JITTER.with(|jitter| jitter.set(jittery));
+    result
+}
+
 fn checked_dur2intervals(dur: &Duration) -> Option<i64> {
     dur.as_secs()
         .checked_mul(INTERVALS_PER_SEC)?
@@ -175,47 +233,58 @@ pub struct PerformanceCounterInstant {
         ts: c::LARGE_INTEGER,
     }
     impl PerformanceCounterInstant {
-        pub fn now() -> Self {
-            Self { ts: query() }
+        /// Returns `None` if the system has no performance counter.
+        pub fn now() -> Option<Self> {
+            frequency()?;
+            Some(Self { ts: query() })
         }
 
         // Per microsoft docs, the margin of error for cross-thread time comparisons
         // using QueryPerformanceCounter is 1 "tick" -- defined as 1/frequency().
         // Reference: https://docs.microsoft.com/en-us/windows/desktop/SysInfo
         //                   /acquiring-high-resolution-time-stamps
+        //
+        // Instants from the tick count are never compared with such a margin,
+        // as they all come out of `monotonize` anyway.
         pub fn epsilon() -> Duration {
-            let epsilon = NANOS_PER_SEC / (frequency() as u64);
-            Duration::from_nanos(epsilon)
+            match frequency() {
+                Some(frequency) => Duration::from_nanos(NANOS_PER_SEC / frequency),
+                None => Duration::ZERO,
+            }
         }
     }
     impl From<PerformanceCounterInstant> for super::Instant {
         fn from(other: PerformanceCounterInstant) -> Self {
-            let freq = frequency() as u64;
+            // `PerformanceCounterInstant::now` made sure that there is a frequency.
+            let freq = frequency().unwrap();
             let instant_nsec = mul_div_u64(other.ts as u64, NANOS_PER_SEC, freq);
             Self { t: Duration::from_nanos(instant_nsec) }
         }
     }
 
-    fn frequency() -> c::LARGE_INTEGER {
-        // Either the cached result of `QueryPerformanceFrequency` or `0` for
-        // uninitialized. Storing this as a single `AtomicU64` allows us to use
-        // `Relaxed` operations, as we are only interested in the effects on a
-        // single memory location.
+    /// Stored in place of the frequency if the system has no performance counter.
+    const UNAVAILABLE: u64 = u64::MAX;
+
+    fn frequency() -> Option<u64> {
+        // Either the cached result of `QueryPerformanceFrequency`, `UNAVAILABLE`,
+        // or `0` for uninitialized. Storing this as a single `AtomicU64` allows
+        // us to use `Relaxed` operations, as we are only interested in the
+        // effects on a single memory location.
         static FREQUENCY: AtomicU64 = AtomicU64::new(0);
 
         let cached = FREQUENCY.load(Ordering::Relaxed);
         // If a previous thread has filled in this global state, use that.
         if cached != 0 {
-            return cached as c::LARGE_INTEGER;
+            return (cached != UNAVAILABLE).then_some(cached);
         }
-        // ... otherwise learn for ourselves ...
+        // ... otherwise learn for ourselves. Without the hardware for it, this
+        // fails or reports a frequency of zero.
         let mut frequency = 0;
-        unsafe {
-            cvt(c::QueryPerformanceFrequency(&mut frequency)).unwrap();
-        }
+        let ok = unsafe { c::QueryPerformanceFrequency(&mut frequency) } != 0;
+        let frequency = if ok && frequency > 0 { frequency as u64 } else { UNAVAILABLE };
 
-        FREQUENCY.store(frequency as u64, Ordering::Relaxed);
-        frequency
+        FREQUENCY.store(frequency, Ordering::Relaxed);
+        (frequency != UNAVAILABLE).then_some(frequency)
     }
 
     fn query() -> c::LARGE_INTEGER {
@@ -242,6 +311,12 @@ pub fn high_resolution() -> Result<Self, ()> {
         };
         if !handle.is_null() { Ok(Self { handle }) } else { Err(()) }
     }
+    /// Create an ordinary timer, which fires on the first tick of the system timer after it is
+    /// due. Will fail on 95.
+    pub fn new() -> Result<Self, ()> {
+        let handle = unsafe { c::CreateWaitableTimerW(null(), c::FALSE, null()) };
+        if !handle.is_null() { Ok(Self { handle }) } else { Err(()) }
+    }
     pub fn set(&self, duration: Duration) -> Result<(), ()> {
         // Convert the Duration to a format similar to FILETIME.
         // Negative values are relative times whereas positive values are absolute.
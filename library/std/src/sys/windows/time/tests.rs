@@ -0,0 +1,33 @@
+use super::{jitter, with_jittery_clock, Instant};
+use crate::thread;
+use crate::time::Duration;
+
+#[test]
+fn jittery_clock_goes_back() {
+    // Otherwise the test below would pass no matter what.
+    let now = Instant { t: Duration::from_secs(1) };
+    let back = with_jittery_clock(1, || (0..100).filter(|_| jitter(now) < now).count());
+    assert!(back > 90, "only went back {back} times out of 100");
+    assert_eq!(jitter(now), now, "jittery outside of with_jittery_clock");
+}
+
+#[test]
+fn instant_never_goes_back() {
+    let threads: Vec<_> = (0..16)
+        .map(|seed| {
+            thread::spawn(move || {
+                with_jittery_clock(seed, || {
+                    let mut last = Instant::now();
+                    for _ in 0..100_000 {
+                        let now = Instant::now();
+                        assert!(now >= last, "went back from {last:?} to {now:?}");
+                        last = now;
+                    }
+                })
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
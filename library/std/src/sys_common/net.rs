@@ -1,7 +1,6 @@
 #[cfg(test)]
 mod tests;
 
-use crate::cmp;
 use crate::fmt;
 use crate::io::{self, BorrowedCursor, ErrorKind, IoSlice, IoSliceMut};
 use crate::mem;
@@ -9,11 +8,11 @@
 use crate::ptr;
 use crate::sys::common::small_c_string::run_with_cstr;
 use crate::sys::net::netc as c;
-use crate::sys::net::{cvt, cvt_gai, cvt_r, init, wrlen_t, Socket};
+use crate::sys::net::{cvt, cvt_gai, cvt_r, init, Socket};
 use crate::sys_common::{AsInner, FromInner, IntoInner};
 use crate::time::Duration;
 
-use crate::ffi::{c_int, c_void};
+use crate::ffi::c_int;
 
 cfg_if::cfg_if! {
     if #[cfg(any(
@@ -29,6 +28,57 @@
     }
 }
 
+cfg_if::cfg_if! {
+    if #[cfg(windows)] {
+        use crate::sys::net::{getsockopt_v6, only_v6, send, send_to, set_only_v6, setsockopt_v6};
+    } else {
+        use crate::cmp;
+        use crate::ffi::c_void;
+        use crate::sys::net::wrlen_t;
+
+        fn setsockopt_v6<T>(sock: &Socket, option_name: c_int, option_value: T) -> io::Result<()> {
+            setsockopt(sock, c::IPPROTO_IPV6, option_name, option_value)
+        }
+
+        fn getsockopt_v6<T: Copy>(sock: &Socket, option_name: c_int) -> io::Result<T> {
+            getsockopt(sock, c::IPPROTO_IPV6, option_name)
+        }
+
+        fn set_only_v6(sock: &Socket, only_v6: bool) -> io::Result<()> {
+            setsockopt_v6(sock, c::IPV6_V6ONLY, only_v6 as c_int)
+        }
+
+        fn only_v6(sock: &Socket) -> io::Result<bool> {
+            let raw: c_int = getsockopt_v6(sock, c::IPV6_V6ONLY)?;
+            Ok(raw != 0)
+        }
+
+        fn send(sock: &Socket, buf: &[u8]) -> io::Result<usize> {
+            let len = cmp::min(buf.len(), <wrlen_t>::MAX as usize) as wrlen_t;
+            let ret = cvt(unsafe {
+                c::send(sock.as_raw(), buf.as_ptr() as *const c_void, len, MSG_NOSIGNAL)
+            })?;
+            Ok(ret as usize)
+        }
+
+        fn send_to(sock: &Socket, buf: &[u8], dst: &SocketAddr) -> io::Result<usize> {
+            let len = cmp::min(buf.len(), <wrlen_t>::MAX as usize) as wrlen_t;
+            let (dst, dstlen) = dst.into_inner();
+            let ret = cvt(unsafe {
+                c::sendto(
+                    sock.as_raw(),
+                    buf.as_ptr() as *const c_void,
+                    len,
+                    MSG_NOSIGNAL,
+                    dst.as_ptr(),
+                    dstlen,
+                )
+            })?;
+            Ok(ret as usize)
+        }
+    }
+}
+
 cfg_if::cfg_if! {
     if #[cfg(any(
         target_os = "linux", target_os = "android",
@@ -37,7 +87,7 @@
         target_os = "openbsd", target_os = "netbsd",
         target_os = "haiku", target_os = "nto"))] {
         use libc::MSG_NOSIGNAL;
-    } else {
+    } else if #[cfg(not(windows))] {
         const MSG_NOSIGNAL: c_int = 0x0;
     }
 }
@@ -285,11 +335,7 @@ pub fn is_read_vectored(&self) -> bool {
     }
 
     pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
-        let len = cmp::min(buf.len(), <wrlen_t>::MAX as usize) as wrlen_t;
-        let ret = cvt(unsafe {
-            c::send(self.inner.as_raw(), buf.as_ptr() as *const c_void, len, MSG_NOSIGNAL)
-        })?;
-        Ok(ret as usize)
+        send(&self.inner, buf)
     }
 
     pub fn write_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
@@ -463,12 +509,11 @@ pub fn ttl(&self) -> io::Result<u32> {
     }
 
     pub fn set_only_v6(&self, only_v6: bool) -> io::Result<()> {
-        setsockopt(&self.inner, c::IPPROTO_IPV6, c::IPV6_V6ONLY, only_v6 as c_int)
+        set_only_v6(&self.inner, only_v6)
     }
 
     pub fn only_v6(&self) -> io::Result<bool> {
-        let raw: c_int = getsockopt(&self.inner, c::IPPROTO_IPV6, c::IPV6_V6ONLY)?;
-        Ok(raw != 0)
+        only_v6(&self.inner)
     }
 
     pub fn take_error(&self) -> io::Result<Option<io::Error>> {
@@ -545,19 +590,7 @@ pub fn peek_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
     }
 
     pub fn send_to(&self, buf: &[u8], dst: &SocketAddr) -> io::Result<usize> {
-        let len = cmp::min(buf.len(), <wrlen_t>::MAX as usize) as wrlen_t;
-        let (dst, dstlen) = dst.into_inner();
-        let ret = cvt(unsafe {
-            c::sendto(
-                self.inner.as_raw(),
-                buf.as_ptr() as *const c_void,
-                len,
-                MSG_NOSIGNAL,
-                dst.as_ptr(),
-                dstlen,
-            )
-        })?;
-        Ok(ret as usize)
+        send_to(&self.inner, buf, dst)
     }
 
     pub fn duplicate(&self) -> io::Result<UdpSocket> {
@@ -618,11 +651,11 @@ pub fn multicast_ttl_v4(&self) -> io::Result<u32> {
     }
 
     pub fn set_multicast_loop_v6(&self, multicast_loop_v6: bool) -> io::Result<()> {
-        setsockopt(&self.inner, c::IPPROTO_IPV6, c::IPV6_MULTICAST_LOOP, multicast_loop_v6 as c_int)
+        setsockopt_v6(&self.inner, c::IPV6_MULTICAST_LOOP, multicast_loop_v6 as c_int)
     }
 
     pub fn multicast_loop_v6(&self) -> io::Result<bool> {
-        let raw: c_int = getsockopt(&self.inner, c::IPPROTO_IPV6, c::IPV6_MULTICAST_LOOP)?;
+        let raw: c_int = getsockopt_v6(&self.inner, c::IPV6_MULTICAST_LOOP)?;
         Ok(raw != 0)
     }
 
@@ -639,7 +672,7 @@ pub fn join_multicast_v6(&self, multiaddr: &Ipv6Addr, interface: u32) -> io::Res
             ipv6mr_multiaddr: multiaddr.into_inner(),
             ipv6mr_interface: to_ipv6mr_interface(interface),
         };
-        setsockopt(&self.inner, c::IPPROTO_IPV6, IPV6_ADD_MEMBERSHIP, mreq)
+        setsockopt_v6(&self.inner, IPV6_ADD_MEMBERSHIP, mreq)
     }
 
     pub fn leave_multicast_v4(&self, multiaddr: &Ipv4Addr, interface: &Ipv4Addr) -> io::Result<()> {
@@ -655,7 +688,7 @@ pub fn leave_multicast_v6(&self, multiaddr: &Ipv6Addr, interface: u32) -> io::Re
             ipv6mr_multiaddr: multiaddr.into_inner(),
             ipv6mr_interface: to_ipv6mr_interface(interface),
         };
-        setsockopt(&self.inner, c::IPPROTO_IPV6, IPV6_DROP_MEMBERSHIP, mreq)
+        setsockopt_v6(&self.inner, IPV6_DROP_MEMBERSHIP, mreq)
     }
 
     pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
@@ -684,11 +717,7 @@ pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
     }
 
     pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
-        let len = cmp::min(buf.len(), <wrlen_t>::MAX as usize) as wrlen_t;
-        let ret = cvt(unsafe {
-            c::send(self.inner.as_raw(), buf.as_ptr() as *const c_void, len, MSG_NOSIGNAL)
-        })?;
-        Ok(ret as usize)
+        send(&self.inner, buf)
     }
 
     pub fn connect(&self, addr: io::Result<&SocketAddr>) -> io::Result<()> {
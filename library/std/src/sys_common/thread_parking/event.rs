@@ -0,0 +1,156 @@
+//! Parker implementation based on a Windows auto-reset event.
+//!
+//! This is the fallback for Windows versions without `WaitOnAddress`. The event is only created
+//! the first time the thread actually parks, as most threads never do, and closed when the parker
+//! is dropped.
+//!
+//! The state word works just like the one of the futex parker: `unpark` always leaves a token
+//! behind, and only signals the event if the thread is committed to waiting on it. A signal can
+//! outlive the wait it was meant for (if `park_timeout` timed out right before `unpark` ran), so
+//! waking up from the event alone doesn't mean anything: the state decides whether we return.
+
+use crate::ffi::c_void;
+use crate::io;
+use crate::pin::Pin;
+use crate::ptr;
+use crate::sync::atomic::{
+    AtomicI8, AtomicPtr,
+    Ordering::{AcqRel, Acquire},
+};
+use crate::sys::thread_parking::Backend;
+use crate::sys::{c, Deadline};
+use crate::time::{Duration, Instant};
+
+const PARKED: i8 = -1;
+const EMPTY: i8 = 0;
+const NOTIFIED: i8 = 1;
+
+pub struct Parker {
+    state: AtomicI8,
+    /// Null until the first `park`.
+    event: AtomicPtr<c_void>,
+}
+
+impl Parker {
+    /// Construct the event parker. The UNIX parker implementation
+    /// requires this to happen in-place.
+    pub unsafe fn new_in_place(parker: *mut Parker) {
+        parker.write(Self::new());
+    }
+
+    pub const fn new() -> Parker {
+        Parker { state: AtomicI8::new(EMPTY), event: AtomicPtr::new(ptr::null_mut()) }
+    }
+
+    // Assumes this is only called by the thread that owns the Parker,
+    // which means that `self.state != PARKED`.
+    pub unsafe fn park(self: Pin<&Self>) {
+        let Some(event) = self.prepare_park() else { return };
+        loop {
+            #[cfg(test)]
+            super::count_blocking_call();
+            // WaitForSingleObject never waits alertably, so user APCs can't make
+            // it return early. Whatever it returns, only the state decides.
+            c::WaitForSingleObject(event, c::INFINITE);
+            // Change NOTIFIED=>EMPTY but leave PARKED alone.
+            if self.state.compare_exchange(NOTIFIED, EMPTY, Acquire, Acquire).is_ok() {
+                return;
+            }
+            // A stale signal. Go back to sleep.
+        }
+    }
+
+    // Assumes this is only called by the thread that owns the Parker,
+    // which means that `self.state != PARKED`.
+    pub unsafe fn park_timeout(self: Pin<&Self>, timeout: Duration) {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.park_deadline(deadline),
+            None => self.park(),
+        }
+    }
+
+    // Assumes this is only called by the thread that owns the Parker,
+    // which means that `self.state != PARKED`.
+    pub unsafe fn park_deadline(self: Pin<&Self>, deadline: Instant) {
+        let Some(event) = self.prepare_park() else { return };
+        // Stale signals must not restart the timeout, so all waits count towards the same deadline.
+        let deadline = Deadline::new(deadline.saturating_duration_since(Instant::now()));
+        while let Some(ms) = deadline.remaining_timeout() {
+            #[cfg(test)]
+            super::count_blocking_call();
+            c::WaitForSingleObject(event, ms);
+            if self.state.compare_exchange(NOTIFIED, EMPTY, Acquire, Acquire).is_ok() {
+                return;
+            }
+        }
+        // Timed out. Set the state back to EMPTY, also consuming a token that arrived just now.
+        // Its signal is left on the event, which the next `park` will treat as stale.
+        self.state.swap(EMPTY, Acquire);
+    }
+
+    pub fn unpark(self: Pin<&Self>) {
+        // Change PARKED=>NOTIFIED, EMPTY=>NOTIFIED, or NOTIFIED=>NOTIFIED, and
+        // signal the event in the first case.
+        if self.state.swap(NOTIFIED, AcqRel) == PARKED {
+            // The parked thread published the event before committing to PARKED.
+            unsafe { c::SetEvent(self.event.load(Acquire)) };
+        }
+    }
+
+    /// Wakes up the parked thread without leaving a token, like a stale signal would.
+    #[cfg(test)]
+    pub fn wake_spuriously(self: Pin<&Self>) {
+        let event = self.event.load(Acquire);
+        if !event.is_null() {
+            unsafe { c::SetEvent(event) };
+        }
+    }
+
+    /// Consumes a pending token, or creates the event and moves to PARKED.
+    ///
+    /// Returns `None` if there was a token, in which case `park` must return right away.
+    unsafe fn prepare_park(&self) -> Option<c::HANDLE> {
+        if self.state.compare_exchange(NOTIFIED, EMPTY, Acquire, Acquire).is_ok() {
+            return None;
+        }
+        let event = self.event();
+        // Change NOTIFIED=>EMPTY or EMPTY=>PARKED. `unpark` may have come in since we last looked.
+        if self.state.fetch_sub(1, AcqRel) == NOTIFIED { None } else { Some(event) }
+    }
+
+    /// Returns the event, creating it if this is the first park.
+    fn event(&self) -> c::HANDLE {
+        let event = self.event.load(Acquire);
+        if !event.is_null() {
+            return event;
+        }
+        let new = unsafe { c::CreateEventA(ptr::null_mut(), c::FALSE, c::FALSE, ptr::null()) };
+        if new.is_null() {
+            let backend = Backend::Event.name();
+            panic!(
+                "thread parking ({backend}): failed creating event: {}",
+                io::Error::last_os_error()
+            );
+        }
+        // Publish the event before moving to PARKED, so an `unpark` either sees no waiter, or
+        // this handle. Only the owning thread parks, so nothing else should be publishing one,
+        // but closing the loser keeps this from leaking a handle if that ever changes.
+        match self.event.compare_exchange(ptr::null_mut(), new, AcqRel, Acquire) {
+            Ok(_) => new,
+            Err(existing) => {
+                unsafe { c::CloseHandle(new) };
+                existing
+            }
+        }
+    }
+}
+
+impl Drop for Parker {
+    fn drop(&mut self) {
+        // Nothing can be unparking the parker anymore, as that takes a reference to it.
+        let event = *self.event.get_mut();
+        if !event.is_null() {
+            unsafe { c::CloseHandle(event) };
+        }
+    }
+}
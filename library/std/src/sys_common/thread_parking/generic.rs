@@ -1,10 +1,21 @@
 //! Parker implementation based on a Mutex and Condvar.
+//!
+//! Most threads never park, so the mutex and condvar live in a single box that is only allocated
+//! the first time the thread actually has to wait, and freed along with the parker. Until then, a
+//! parker is just two words and owns no kernel objects.
+
+#[cfg(test)]
+mod tests;
 
 use crate::pin::Pin;
 use crate::sync::atomic::AtomicUsize;
 use crate::sync::atomic::Ordering::SeqCst;
 use crate::sync::{Condvar, Mutex};
-use crate::time::Duration;
+use crate::sys_common::lazy_box::{LazyBox, LazyInit};
+use crate::time::{Duration, Instant};
+
+/// Named in panic messages, to help tell which backend a hang or crash came from.
+const NAME: &str = "mutex and condvar";
 
 const EMPTY: usize = 0;
 const PARKED: usize = 1;
@@ -12,19 +23,25 @@
 
 pub struct Parker {
     state: AtomicUsize,
+    waiter: LazyBox<Waiter>,
+}
+
+struct Waiter {
     lock: Mutex<()>,
     cvar: Condvar,
 }
 
+impl LazyInit for Waiter {
+    fn init() -> Box<Self> {
+        Box::new(Waiter { lock: Mutex::new(()), cvar: Condvar::new() })
+    }
+}
+
 impl Parker {
     /// Construct the generic parker. The UNIX parker implementation
     /// requires this to happen in-place.
     pub unsafe fn new_in_place(parker: *mut Parker) {
-        parker.write(Parker {
-            state: AtomicUsize::new(EMPTY),
-            lock: Mutex::new(()),
-            cvar: Condvar::new(),
-        });
+        parker.write(Parker { state: AtomicUsize::new(EMPTY), waiter: LazyBox::new() });
     }
 
     // This implementation doesn't require `unsafe` and `Pin`, but other implementations do.
@@ -36,7 +53,8 @@ pub unsafe fn park(self: Pin<&Self>) {
         }
 
         // Otherwise we need to coordinate going to sleep
-        let mut m = self.lock.lock().unwrap();
+        let waiter = &*self.waiter;
+        let mut m = waiter.lock.lock().unwrap();
         match self.state.compare_exchange(EMPTY, PARKED, SeqCst, SeqCst) {
             Ok(_) => {}
             Err(NOTIFIED) => {
@@ -47,13 +65,18 @@ pub unsafe fn park(self: Pin<&Self>) {
                 // any writes it made before the call to unpark. To do that we must
                 // read from the write it made to `state`.
                 let old = self.state.swap(EMPTY, SeqCst);
-                assert_eq!(old, NOTIFIED, "park state changed unexpectedly");
+                assert_eq!(
+                    old, NOTIFIED,
+                    "thread parking ({NAME}): park state changed unexpectedly"
+                );
                 return;
             } // should consume this notification, so prohibit spurious wakeups in next park.
-            Err(_) => panic!("inconsistent park state"),
+            Err(_) => panic!("thread parking ({NAME}): inconsistent park state"),
         }
         loop {
-            m = self.cvar.wait(m).unwrap();
+            #[cfg(test)]
+            super::count_blocking_call();
+            m = waiter.cvar.wait(m).unwrap();
             match self.state.compare_exchange(NOTIFIED, EMPTY, SeqCst, SeqCst) {
                 Ok(_) => return, // got a notification
                 Err(_) => {}     // spurious wakeup, go back to sleep
@@ -63,36 +86,69 @@ pub unsafe fn park(self: Pin<&Self>) {
 
     // This implementation doesn't require `unsafe` and `Pin`, but other implementations do.
     pub unsafe fn park_timeout(self: Pin<&Self>, dur: Duration) {
+        match Instant::now().checked_add(dur) {
+            Some(deadline) => self.park_deadline(deadline),
+            None => self.park(),
+        }
+    }
+
+    // This implementation doesn't require `unsafe` and `Pin`, but other implementations do.
+    pub unsafe fn park_deadline(self: Pin<&Self>, deadline: Instant) {
         // Like `park` above we have a fast path for an already-notified thread, and
         // afterwards we start coordinating for a sleep.
         // return quickly.
         if self.state.compare_exchange(NOTIFIED, EMPTY, SeqCst, SeqCst).is_ok() {
             return;
         }
-        let m = self.lock.lock().unwrap();
+        let waiter = &*self.waiter;
+        let mut m = waiter.lock.lock().unwrap();
         match self.state.compare_exchange(EMPTY, PARKED, SeqCst, SeqCst) {
             Ok(_) => {}
             Err(NOTIFIED) => {
                 // We must read again here, see `park`.
                 let old = self.state.swap(EMPTY, SeqCst);
-                assert_eq!(old, NOTIFIED, "park state changed unexpectedly");
+                assert_eq!(
+                    old, NOTIFIED,
+                    "thread parking ({NAME}): park state changed unexpectedly"
+                );
                 return;
             } // should consume this notification, so prohibit spurious wakeups in next park.
-            Err(_) => panic!("inconsistent park_timeout state"),
+            Err(_) => panic!("thread parking ({NAME}): inconsistent park_timeout state"),
+        }
+
+        // Wait until notified or the deadline passes. A spurious wakeup must not
+        // restart the timeout, so each wait only gets what is left of it.
+        // This works with `Instant` rather than the Windows `Deadline`, so the
+        // generic parker stays usable anywhere.
+        while let Some(dur) =
+            deadline.checked_duration_since(Instant::now()).filter(|dur| !dur.is_zero())
+        {
+            #[cfg(test)]
+            super::count_blocking_call();
+            m = waiter.cvar.wait_timeout(m, dur).unwrap().0;
+            if self.state.compare_exchange(NOTIFIED, EMPTY, SeqCst, SeqCst).is_ok() {
+                return; // got a notification, hurray!
+            }
         }
 
-        // Wait with a timeout, and if we spuriously wake up or otherwise wake up
-        // from a notification we just want to unconditionally set the state back to
-        // empty, either consuming a notification or un-flagging ourselves as
-        // parked.
-        let (_m, _result) = self.cvar.wait_timeout(m, dur).unwrap();
+        // Timed out, so we want to unconditionally set the state back to empty,
+        // either consuming a notification that just came in or un-flagging
+        // ourselves as parked.
         match self.state.swap(EMPTY, SeqCst) {
-            NOTIFIED => {} // got a notification, hurray!
+            NOTIFIED => {} // got a notification just in time
             PARKED => {}   // no notification, alas
-            n => panic!("inconsistent park_timeout state: {n}"),
+            n => panic!("thread parking ({NAME}): inconsistent park_timeout state: {n}"),
         }
     }
 
+    /// Wakes up the parked thread without leaving a notification, like a spurious wakeup.
+    #[cfg(test)]
+    pub fn wake_spuriously(self: Pin<&Self>) {
+        let waiter = &*self.waiter;
+        drop(waiter.lock.lock().unwrap());
+        waiter.cvar.notify_one()
+    }
+
     // This implementation doesn't require `Pin`, but other implementations do.
     pub fn unpark(self: Pin<&Self>) {
         // To ensure the unparked thread will observe any writes we made
@@ -105,7 +161,7 @@ pub fn unpark(self: Pin<&Self>) {
             EMPTY => return,    // no one was waiting
             NOTIFIED => return, // already unparked
             PARKED => {}        // gotta go wake someone up
-            _ => panic!("inconsistent state in unpark"),
+            _ => panic!("thread parking ({NAME}): inconsistent state in unpark"),
         }
 
         // There is a period between when the parked thread sets `state` to
@@ -119,7 +175,10 @@ pub fn unpark(self: Pin<&Self>) {
         // Releasing `lock` before the call to `notify_one` means that when the
         // parked thread wakes it doesn't get woken only to have to wait for us
         // to release `lock`.
-        drop(self.lock.lock().unwrap());
-        self.cvar.notify_one()
+        //
+        // The parked thread allocated the waiter before setting `PARKED`, so this doesn't.
+        let waiter = &*self.waiter;
+        drop(waiter.lock.lock().unwrap());
+        waiter.cvar.notify_one()
     }
 }
@@ -0,0 +1,71 @@
+use super::Parker;
+use crate::mem::MaybeUninit;
+use crate::pin::Pin;
+use crate::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use crate::sync::Arc;
+#[cfg(all(target_arch = "x86", target_vendor = "rust9x"))]
+use crate::sys::test::{handle_count, run_alone};
+use crate::thread;
+use crate::time::{Duration, Instant};
+
+/// Spawns and joins `n` threads, one after the other, and returns how many more handles the
+/// process has open afterwards.
+#[cfg(all(target_arch = "x86", target_vendor = "rust9x"))]
+fn handles_leaked_by(n: usize, park: bool) -> Option<i64> {
+    let before = handle_count()?;
+    for _ in 0..n {
+        thread::spawn(move || {
+            if park {
+                thread::park_timeout(Duration::from_millis(1));
+            }
+        })
+        .join()
+        .unwrap();
+    }
+    let after = handle_count()?;
+    Some(after as i64 - before as i64)
+}
+
+// `thread::park_timeout` only goes through this parker where it is the one in use.
+#[test]
+#[cfg(all(target_arch = "x86", target_vendor = "rust9x"))]
+fn parked_threads_release_handles() {
+    run_alone("thread_parking::generic::tests::parked_threads_release_handles", || {
+        // Let the process settle any handles it creates once, on the first thread spawn.
+        if handles_leaked_by(1, true).is_none() {
+            return;
+        }
+
+        let idle = handles_leaked_by(1000, false).unwrap();
+        let parked = handles_leaked_by(1000, true).unwrap();
+        assert!(idle < 8, "{idle} handles left behind by idle threads");
+        assert!(parked < 8, "{parked} handles left behind by parked threads");
+    });
+}
+
+#[test]
+fn spurious_wakeups_keep_deadline() {
+    let mut parker = MaybeUninit::<Parker>::uninit();
+    unsafe { Parker::new_in_place(parker.as_mut_ptr()) };
+    let parker = Arc::new(unsafe { parker.assume_init() });
+    let done = Arc::new(AtomicBool::new(false));
+    let waker = {
+        let (parker, done) = (parker.clone(), done.clone());
+        thread::spawn(move || {
+            while !done.load(Relaxed) {
+                Pin::new(&*parker).wake_spuriously();
+                thread::sleep(Duration::from_millis(2));
+            }
+        })
+    };
+
+    let timeout = Duration::from_millis(200);
+    let start = Instant::now();
+    unsafe { Pin::new(&*parker).park_timeout(timeout) };
+    let elapsed = start.elapsed();
+    done.store(true, Relaxed);
+    waker.join().unwrap();
+
+    assert!(elapsed >= timeout, "woke up after {elapsed:?}, wanted {timeout:?}");
+    assert!(elapsed < timeout + Duration::from_millis(100), "overslept by {elapsed:?}");
+}
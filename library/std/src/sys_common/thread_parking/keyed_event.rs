@@ -0,0 +1,252 @@
+//! Parker implementation based on NT Keyed Events.
+//!
+//! This is used on Windows versions that have keyed events but not `WaitOnAddress`, which is
+//! everything from Windows XP through 7. Unlike the event parker, it doesn't need a kernel object
+//! per thread: all waits and releases go to one keyed event, keyed by the address of the parker.
+//! On Vista and later, that's the global keyed event that critical sections use (selected by
+//! passing a null handle). Critical sections key their waits by their own address, which can
+//! never be the address of a parker. XP and Server 2003 don't accept a null handle, so there, a
+//! keyed event is created the first time it's needed, and shared by all parkers.
+//!
+//! NtWaitForKeyedEvent unconditionally blocks without checking the parker state first. Instead,
+//! NtReleaseKeyedEvent *blocks* until it woke up a thread waiting for it by NtWaitForKeyedEvent.
+//! This way, we can be sure no events are missed, but every release must be matched by exactly
+//! one wait. So `unpark` only releases if the state says the thread is committed to waiting, and
+//! if `park_timeout` times out right as `unpark` commits to releasing, it waits once more to
+//! complete the rendezvous, or `unpark` would block forever.
+//!
+//! Unfortunately, NT Keyed Events are an undocumented Windows API. However:
+//! - This API is relatively simple with obvious behaviour, and there are
+//!   several (unofficial) articles documenting the details. [1]
+//! - `parking_lot` has been using this API for years (on Windows versions
+//!   before Windows 8). [2] Many big projects extensively use parking_lot,
+//!   such as servo and the Rust compiler itself.
+//! - It is the underlying API used by Windows SRW locks and Windows critical
+//!   sections. [3] [4]
+//! - The source code of the implementations of Wine, ReactOs, and Windows XP
+//!   are available and match the expected behaviour.
+//! - The main risk with an undocumented API is that it might change in the
+//!   future. But since we only use it for older versions of Windows, that's not
+//!   a problem.
+//! - Even if these functions do not block or wake as we expect (which is
+//!   unlikely, see all previous points), this implementation would still be
+//!   memory safe. The NT Keyed Events API is only used to sleep/block in the
+//!   right place.
+//!
+//! [1]: http://www.locklessinc.com/articles/keyed_events/
+//! [2]: https://github.com/Amanieu/parking_lot/commit/43abbc964e
+//! [3]: https://docs.microsoft.com/en-us/archive/msdn-magazine/2012/november/windows-with-c-the-evolution-of-synchronization-in-windows-and-c
+//! [4]: Windows Internals, Part 1, ISBN 9780735671300
+
+use crate::ffi::c_void;
+use crate::pin::Pin;
+use crate::ptr;
+use crate::sync::atomic::{
+    AtomicI8, AtomicPtr,
+    Ordering::{Acquire, Relaxed, Release},
+};
+use crate::sys::{c, compat, Deadline};
+use crate::time::{Duration, Instant};
+
+const PARKED: i8 = -1;
+const EMPTY: i8 = 0;
+const NOTIFIED: i8 = 1;
+
+/// The first build of Vista, which accepts a null handle for the global keyed event.
+const GLOBAL_KEYED_EVENT_BUILD: u32 = 6000;
+
+/// User APCs queued to the thread must not interrupt a park, so none of the waits are alertable.
+const NOT_ALERTABLE: c::BOOLEAN = 0;
+
+/// The kernel uses the lowest bit of a key for itself and rejects odd keys, so the state (whose
+/// address is the key) must not end up at an odd address.
+#[repr(align(2))]
+pub struct Parker {
+    state: AtomicI8,
+}
+
+impl Parker {
+    /// Construct the keyed event parker. The UNIX parker implementation
+    /// requires this to happen in-place.
+    pub unsafe fn new_in_place(parker: *mut Parker) {
+        parker.write(Self::new());
+    }
+
+    pub const fn new() -> Parker {
+        Parker { state: AtomicI8::new(EMPTY) }
+    }
+
+    /// Whether keyed events can be used on this system.
+    pub fn is_available() -> bool {
+        c::NtWaitForKeyedEvent::available() && c::NtReleaseKeyedEvent::available()
+    }
+
+    // Assumes this is only called by the thread that owns the Parker,
+    // which means that `self.state != PARKED`.
+    pub unsafe fn park(self: Pin<&Self>) {
+        // Change NOTIFIED=>EMPTY or EMPTY=>PARKED, and directly return in the
+        // first case.
+        if self.state.fetch_sub(1, Acquire) == NOTIFIED {
+            return;
+        }
+
+        // Wait for unpark() to produce this event.
+        self.wait_for_release();
+        // Set the state back to EMPTY (from either PARKED or NOTIFIED).
+        // Note that we don't just write EMPTY, but use swap() to also
+        // include an acquire-ordered read to synchronize with unpark()'s
+        // release-ordered write.
+        self.state.swap(EMPTY, Acquire);
+    }
+
+    // Assumes this is only called by the thread that owns the Parker,
+    // which means that `self.state != PARKED`.
+    pub unsafe fn park_timeout(self: Pin<&Self>, timeout: Duration) {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.park_deadline(deadline),
+            None => self.park(),
+        }
+    }
+
+    // Assumes this is only called by the thread that owns the Parker,
+    // which means that `self.state != PARKED`.
+    pub unsafe fn park_deadline(self: Pin<&Self>, deadline: Instant) {
+        // Change NOTIFIED=>EMPTY or EMPTY=>PARKED, and directly return in the
+        // first case.
+        if self.state.fetch_sub(1, Acquire) == NOTIFIED {
+            return;
+        }
+
+        // A release that didn't come from unpark() must not restart the
+        // timeout, so all waits count towards the same deadline.
+        let deadline = Deadline::new(deadline.saturating_duration_since(Instant::now()));
+        while let Some(ms) = deadline.remaining_timeout() {
+            // NtWaitForKeyedEvent uses a unit of 100ns, and uses negative
+            // values to indicate a relative time on the monotonic clock.
+            // This is documented here for the underlying KeWaitForSingleObject function:
+            // https://docs.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitforsingleobject
+            let mut timeout = -(ms as i64 * 10_000);
+
+            // Wait for unpark() to produce this event.
+            #[cfg(test)]
+            super::count_blocking_call();
+            // Anything but a release (like a timeout, or an APC if the wait
+            // were alertable) must not be mistaken for one.
+            let status = c::NtWaitForKeyedEvent(handle(), self.key(), NOT_ALERTABLE, &mut timeout);
+            let released = status == c::STATUS_SUCCESS;
+
+            // Change NOTIFIED=>EMPTY but leave PARKED alone.
+            if self.state.compare_exchange(NOTIFIED, EMPTY, Acquire, Acquire).is_ok() {
+                if !released {
+                    // We were awoken by a timeout, not by unpark(), but the
+                    // state was set to NOTIFIED, which means we *just* missed
+                    // an unpark(), which is now blocked on us to wait for it.
+                    // Wait for it to consume the event and unblock that thread.
+                    self.wait_for_release();
+                }
+                return;
+            }
+        }
+
+        // Timed out. Set the state back to EMPTY (from either PARKED or NOTIFIED).
+        if self.state.swap(EMPTY, Acquire) == NOTIFIED {
+            // Like above: an unpark() came in right as we gave up, and is
+            // blocked on us.
+            self.wait_for_release();
+        }
+    }
+
+    // This implementation doesn't require `Pin`, but other implementations do.
+    pub fn unpark(self: Pin<&Self>) {
+        // Change PARKED=>NOTIFIED, EMPTY=>NOTIFIED, or NOTIFIED=>NOTIFIED, and
+        // release the parked thread in the first case.
+        //
+        // Note that even NOTIFIED=>NOTIFIED results in a write. This is on
+        // purpose, to make sure every unpark() has a release-acquire ordering
+        // with park().
+        if self.state.swap(NOTIFIED, Release) == PARKED {
+            // If we run NtReleaseKeyedEvent before the waiting thread runs
+            // NtWaitForKeyedEvent, this (shortly) blocks until we can wake it up.
+            // If the waiting thread wakes up before we run NtReleaseKeyedEvent
+            // (e.g. due to a timeout), this blocks until we do wake up a thread.
+            // To prevent this thread from blocking indefinitely in that case,
+            // park_timeout() will, after seeing the state set to NOTIFIED after
+            // waking up, call NtWaitForKeyedEvent again to unblock us.
+            unsafe {
+                c::NtReleaseKeyedEvent(handle(), self.key(), NOT_ALERTABLE, ptr::null_mut());
+            }
+        }
+    }
+
+    /// Waits without a timeout until a release arrives.
+    unsafe fn wait_for_release(&self) {
+        // The wait isn't alertable, so it only returns for a release. Should an
+        // APC get through anyway, returning would leave the release (and the
+        // unpark() sending it) hanging, so wait again. Any other failure would
+        // fail every retry too, and can't be recovered from.
+        loop {
+            #[cfg(test)]
+            super::count_blocking_call();
+            let status =
+                c::NtWaitForKeyedEvent(handle(), self.key(), NOT_ALERTABLE, ptr::null_mut());
+            match status {
+                c::STATUS_SUCCESS => return,
+                c::STATUS_ALERTED | c::STATUS_USER_APC => {}
+                _ => rtabort!("thread parking: waiting for a keyed event failed: {status:#x}"),
+            }
+        }
+    }
+
+    /// Wakes up the parked thread without leaving a token, like a release from other code would.
+    ///
+    /// Gives up if no thread is waiting within a few milliseconds, so this can't get stuck
+    /// waiting for a rendezvous like `unpark` would. It must not race with `unpark` though, as
+    /// `park_deadline` can't tell the two releases apart.
+    #[cfg(test)]
+    pub fn wake_spuriously(self: Pin<&Self>) {
+        let mut timeout = -10 * 10_000;
+        unsafe { c::NtReleaseKeyedEvent(handle(), self.key(), NOT_ALERTABLE, &mut timeout) };
+    }
+
+    fn key(&self) -> c::LPVOID {
+        &self.state as *const _ as c::LPVOID
+    }
+}
+
+/// The keyed event all parkers wait on and release.
+fn handle() -> c::HANDLE {
+    const INVALID: c::HANDLE = ptr::invalid_mut(!0);
+    static HANDLE: AtomicPtr<c_void> = AtomicPtr::new(INVALID);
+
+    if compat::nt_build_number() >= GLOBAL_KEYED_EVENT_BUILD {
+        return ptr::null_mut();
+    }
+    match HANDLE.load(Relaxed) {
+        INVALID => {
+            let mut handle = c::INVALID_HANDLE_VALUE;
+            unsafe {
+                match c::NtCreateKeyedEvent(
+                    &mut handle,
+                    c::GENERIC_READ | c::GENERIC_WRITE,
+                    ptr::null_mut(),
+                    0,
+                ) {
+                    c::STATUS_SUCCESS => {}
+                    r => panic!("Unable to create keyed event handle: error {r}"),
+                }
+            }
+            match HANDLE.compare_exchange(INVALID, handle, Relaxed, Relaxed) {
+                Ok(_) => handle,
+                Err(h) => {
+                    // Lost the race to another thread initializing HANDLE before we did.
+                    // Closing our handle and using theirs instead.
+                    unsafe {
+                        c::CloseHandle(handle);
+                    }
+                    h
+                }
+            }
+        }
+        handle => handle,
+    }
+}
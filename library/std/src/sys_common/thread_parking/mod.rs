@@ -1,5 +1,38 @@
 #![allow(unexpected_cfgs)]
 
+// Fallbacks for the Windows parker, selected at runtime. Builds for Windows 8 and
+// later don't need them.
+#[cfg(all(windows, not(windows_min_version = "8")))]
+#[cfg_attr(all(target_arch = "x86", target_vendor = "rust9x"), allow(dead_code))]
+pub(crate) mod event;
+#[cfg(all(windows, not(windows_min_version = "8")))]
+#[cfg_attr(all(target_arch = "x86", target_vendor = "rust9x"), allow(dead_code))]
+pub(crate) mod keyed_event;
+// Only used on rust9x x86 (see below), but built for the conformance tests on
+// all Windows targets.
+#[cfg(all(test, windows, not(all(target_arch = "x86", target_vendor = "rust9x"))))]
+mod generic;
+
+#[cfg(all(test, windows))]
+mod tests;
+
+#[cfg(all(test, windows))]
+thread_local! {
+    static BLOCKING_CALLS: crate::cell::Cell<usize> = crate::cell::Cell::new(0);
+}
+
+/// Called by the parkers right before every blocking call, so tests can check
+/// which paths don't make any.
+#[cfg(all(test, windows))]
+pub(crate) fn count_blocking_call() {
+    BLOCKING_CALLS.with(|calls| calls.set(calls.get() + 1));
+}
+
+#[cfg(all(test, windows))]
+fn blocking_calls() -> usize {
+    BLOCKING_CALLS.with(|calls| calls.get())
+}
+
 cfg_if::cfg_if! {
     if #[cfg(any(
         target_os = "linux",
@@ -20,6 +53,9 @@
     ))] {
         mod id;
         pub use id::Parker;
+    } else if #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))] {
+        mod single_threaded;
+        pub use single_threaded::Parker;
     } else if #[cfg(all(target_arch = "x86", target_os = "windows", target_vendor = "rust9x"))] {
         mod generic;
         pub use generic::Parker;
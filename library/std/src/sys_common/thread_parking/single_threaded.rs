@@ -0,0 +1,46 @@
+//! Parker for targets without threads, like wasm32 without the atomics feature.
+//!
+//! There is no other thread that could ever unpark us, so a `park` without a pending token would
+//! block forever. Rather than hang (or silently return, making every wait loop spin), we abort
+//! with a message that says what went wrong.
+
+#[cfg(test)]
+mod tests;
+
+use crate::pin::Pin;
+use crate::sync::atomic::AtomicBool;
+use crate::sync::atomic::Ordering::Relaxed;
+use crate::time::Duration;
+
+pub struct Parker {
+    notified: AtomicBool,
+}
+
+impl Parker {
+    /// Construct the single-threaded parker. The UNIX parker implementation
+    /// requires this to happen in-place.
+    pub unsafe fn new_in_place(parker: *mut Parker) {
+        parker.write(Parker { notified: AtomicBool::new(false) });
+    }
+
+    // This implementation doesn't require `unsafe` and `Pin`, but other implementations do.
+    pub unsafe fn park(self: Pin<&Self>) {
+        if !self.notified.swap(false, Relaxed) {
+            rtabort!(
+                "cannot block on this target: thread::park was called without a pending unpark"
+            );
+        }
+    }
+
+    // This implementation doesn't require `unsafe` and `Pin`, but other implementations do.
+    pub unsafe fn park_timeout(self: Pin<&Self>, _dur: Duration) {
+        // Without a token, this times out right away. That is allowed, as `park_timeout` may
+        // return early anyway, and waiting out the timeout can't make a token appear.
+        self.notified.store(false, Relaxed);
+    }
+
+    // This implementation doesn't require `Pin`, but other implementations do.
+    pub fn unpark(self: Pin<&Self>) {
+        self.notified.store(true, Relaxed);
+    }
+}
@@ -0,0 +1,44 @@
+use super::Parker;
+use crate::mem::MaybeUninit;
+use crate::pin::Pin;
+use crate::sync::atomic::Ordering::Relaxed;
+use crate::time::Duration;
+
+fn parker() -> Parker {
+    let mut parker = MaybeUninit::uninit();
+    unsafe {
+        Parker::new_in_place(parker.as_mut_ptr());
+        parker.assume_init()
+    }
+}
+
+#[test]
+fn unpark_then_park() {
+    let parker = parker();
+    let parker = Pin::new(&parker);
+    for _ in 0..10 {
+        parker.unpark();
+        unsafe { parker.park() };
+        assert!(!parker.notified.load(Relaxed));
+    }
+}
+
+#[test]
+fn unparks_coalesce() {
+    let parker = parker();
+    let parker = Pin::new(&parker);
+    parker.unpark();
+    parker.unpark();
+    unsafe { parker.park() };
+    assert!(!parker.notified.load(Relaxed));
+}
+
+#[test]
+fn park_timeout_consumes_token() {
+    let parker = parker();
+    let parker = Pin::new(&parker);
+    unsafe { parker.park_timeout(Duration::from_secs(60)) };
+    parker.unpark();
+    unsafe { parker.park_timeout(Duration::from_secs(60)) };
+    assert!(!parker.notified.load(Relaxed));
+}
@@ -0,0 +1,215 @@
+//! Conformance tests that every Windows parking backend has to pass.
+//!
+//! The backends of the Windows parker are forced one by one, and the generic parker is tested
+//! alongside them. Each gets its own module, so a failing test names its backend.
+
+use super::blocking_calls;
+use crate::mem::MaybeUninit;
+use crate::os::windows::compat::capabilities;
+use crate::os::windows::io::AsRawHandle;
+use crate::pin::Pin;
+use crate::sync::atomic::{
+    AtomicBool, AtomicUsize,
+    Ordering::{Acquire, Release},
+};
+use crate::sync::Arc;
+use crate::sys::c;
+use crate::sys::thread_parking::{self as windows, Backend};
+use crate::sys_common::thread_parking::generic;
+#[cfg(not(windows_min_version = "8"))]
+use crate::sys_common::thread_parking::keyed_event;
+use crate::thread;
+use crate::time::{Duration, Instant};
+
+// GetTickCount64 only advances every 10-16ms, so a deadline may expire up to one tick early.
+const TICK: Duration = Duration::from_millis(16);
+
+/// How long a park that has to block is given.
+const BLOCK: Duration = Duration::from_millis(50);
+
+fn windows_parker(backend: Backend) -> windows::Parker {
+    let mut parker = MaybeUninit::uninit();
+    unsafe {
+        windows::Parker::new_in_place_with(parker.as_mut_ptr(), backend);
+        parker.assume_init()
+    }
+}
+
+fn generic_parker() -> generic::Parker {
+    let mut parker = MaybeUninit::uninit();
+    unsafe {
+        generic::Parker::new_in_place(parker.as_mut_ptr());
+        parker.assume_init()
+    }
+}
+
+fn assert_blocks(backend: &str, park: impl FnOnce()) {
+    let start = Instant::now();
+    park();
+    let elapsed = start.elapsed();
+    assert!(elapsed + TICK >= BLOCK, "{backend}: returned after {elapsed:?} instead of blocking");
+}
+
+fn assert_no_blocking_calls(backend: &str, what: &str, park: impl FnOnce()) {
+    let before = blocking_calls();
+    park();
+    let calls = blocking_calls() - before;
+    assert_eq!(calls, 0, "{backend}: {what} made {calls} blocking calls");
+}
+
+/// Set by any APC that gets to run, which none should while a thread is parked.
+static APC_RAN: AtomicBool = AtomicBool::new(false);
+
+fn queue_apc<T>(thread: &thread::JoinHandle<T>) {
+    unsafe extern "system" fn apc(_: usize) {
+        APC_RAN.store(true, Release);
+    }
+    let queued = unsafe { c::QueueUserAPC(Some(apc), thread.as_raw_handle(), 0) };
+    assert_ne!(queued, 0, "QueueUserAPC failed: {}", crate::io::Error::last_os_error());
+}
+
+macro_rules! conformance_tests {
+    ($($(#[$attr:meta])* $backend:ident: $available:expr => $new:expr;)*) => {$(
+        $(#[$attr])*
+        mod $backend {
+            use super::*;
+
+            const NAME: &str = stringify!($backend);
+
+            #[test]
+            fn unpark_before_park_consumes_one() {
+                if !$available {
+                    return;
+                }
+                let parker = $new;
+                let parker = Pin::new(&parker);
+                parker.unpark();
+                unsafe { parker.park() };
+                assert_blocks(NAME, || unsafe { parker.park_timeout(BLOCK) });
+            }
+
+            #[test]
+            fn unparks_coalesce() {
+                if !$available {
+                    return;
+                }
+                let parker = $new;
+                let parker = Pin::new(&parker);
+                parker.unpark();
+                parker.unpark();
+                parker.unpark();
+                unsafe { parker.park_timeout(BLOCK) };
+                assert_blocks(NAME, || unsafe { parker.park_timeout(BLOCK) });
+            }
+
+            #[test]
+            fn token_returns_without_blocking() {
+                if !$available {
+                    return;
+                }
+                let parker = $new;
+                let parker = Pin::new(&parker);
+                parker.unpark();
+                assert_no_blocking_calls(NAME, "park with a token", || unsafe { parker.park() });
+                parker.unpark();
+                assert_no_blocking_calls(NAME, "park_timeout with a token", || unsafe {
+                    parker.park_timeout(Duration::from_secs(60))
+                });
+            }
+
+            #[test]
+            fn park_timeout_zero_never_blocks() {
+                if !$available {
+                    return;
+                }
+                let parker = $new;
+                let parker = Pin::new(&parker);
+                for _ in 0..100 {
+                    assert_no_blocking_calls(NAME, "park_timeout(0)", || unsafe {
+                        parker.park_timeout(Duration::ZERO)
+                    });
+                }
+            }
+
+            #[test]
+            fn user_apcs_dont_unpark() {
+                if !$available {
+                    return;
+                }
+                let parker = Arc::new($new);
+                let returned = Arc::new(AtomicBool::new(false));
+                let parked = {
+                    let (parker, returned) = (parker.clone(), returned.clone());
+                    thread::spawn(move || {
+                        unsafe { Pin::new(&*parker).park() };
+                        returned.store(true, Release);
+                    })
+                };
+                thread::sleep(BLOCK);
+                queue_apc(&parked);
+                thread::sleep(BLOCK);
+                assert!(!returned.load(Acquire), "{NAME}: park returned for a user APC");
+                assert!(!APC_RAN.load(Acquire), "{NAME}: parked in an alertable wait");
+
+                Pin::new(&*parker).unpark();
+                parked.join().unwrap();
+                assert!(returned.load(Acquire));
+            }
+
+            #[test]
+            fn timeout_races_keep_tokens() {
+                if !$available {
+                    return;
+                }
+                const ROUNDS: usize = 1000;
+                let parker = Arc::new($new);
+                let sent = Arc::new(AtomicUsize::new(0));
+                let seen = Arc::new(AtomicUsize::new(0));
+                let unparker = {
+                    let (parker, sent, seen) = (parker.clone(), sent.clone(), seen.clone());
+                    thread::spawn(move || {
+                        for round in 1..=ROUNDS {
+                            sent.store(round, Release);
+                            Pin::new(&*parker).unpark();
+                            while seen.load(Acquire) < round {
+                                thread::yield_now();
+                            }
+                        }
+                    })
+                };
+
+                let parker = Pin::new(&*parker);
+                let mut round = 0;
+                while round < ROUNDS {
+                    // Timeouts about as long as an unpark takes to arrive, so the two race.
+                    let timeout = Duration::from_micros(50 * (round % 4) as u64);
+                    unsafe { parker.park_timeout(timeout) };
+                    let latest = sent.load(Acquire);
+                    if latest > round {
+                        round = latest;
+                        seen.store(round, Release);
+                    }
+                }
+                unparker.join().unwrap();
+
+                // The last token is still there if its park had already timed out.
+                unsafe { parker.park_timeout(Duration::ZERO) };
+                // One more unpark must now leave exactly one token: not losing it means `park`
+                // returns, and not duplicating it means the next park blocks.
+                parker.unpark();
+                unsafe { parker.park() };
+                assert_blocks(NAME, || unsafe { parker.park_timeout(BLOCK) });
+            }
+        }
+    )*};
+}
+
+conformance_tests! {
+    wait_on_address:
+        capabilities().wait_on_address => windows_parker(Backend::WaitOnAddress);
+    #[cfg(not(windows_min_version = "8"))]
+    keyed_events: keyed_event::Parker::is_available() => windows_parker(Backend::KeyedEvent);
+    #[cfg(not(windows_min_version = "8"))]
+    event: true => windows_parker(Backend::Event);
+    mutex_condvar: true => generic_parker();
+}
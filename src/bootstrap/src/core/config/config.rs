@@ -296,6 +296,7 @@ pub struct Config {
 
     // libstd features
     pub backtrace: bool, // support for RUST_BACKTRACE
+    pub windows_minimal_preload: bool,
 
     // misc
     pub low_priority: bool,
@@ -1075,6 +1076,7 @@ struct Rust {
         debuginfo_level_tests: Option<DebuginfoLevel> = "debuginfo-level-tests",
         split_debuginfo: Option<String> = "split-debuginfo",
         backtrace: Option<bool> = "backtrace",
+        windows_minimal_preload: Option<bool> = "windows-minimal-preload",
         incremental: Option<bool> = "incremental",
         parallel_compiler: Option<bool> = "parallel-compiler",
         default_linker: Option<String> = "default-linker",
@@ -1527,6 +1529,7 @@ fn get_table(option: &str) -> Result<TomlConfig, toml::de::Error> {
                 debuginfo_level_tests: debuginfo_level_tests_toml,
                 split_debuginfo,
                 backtrace,
+                windows_minimal_preload,
                 incremental,
                 parallel_compiler,
                 default_linker,
@@ -1611,6 +1614,7 @@ fn get_table(option: &str) -> Result<TomlConfig, toml::de::Error> {
             set(&mut config.jemalloc, jemalloc);
             set(&mut config.test_compare_mode, test_compare_mode);
             set(&mut config.backtrace, backtrace);
+            set(&mut config.windows_minimal_preload, windows_minimal_preload);
             config.description = description;
             set(&mut config.rust_dist_src, dist_src);
             set(&mut config.verbose_tests, verbose_tests);
@@ -87,6 +87,7 @@
     (Some(Mode::Std), "no_rc", None),
     (Some(Mode::Std), "no_sync", None),
     (Some(Mode::Std), "backtrace_in_libstd", None),
+    (Some(Mode::Std), "windows_min_version", Some(&["xp", "vista", "7", "8", "10"])),
     /* Extra values not defined in the built-in targets yet, but used in std */
     (Some(Mode::Std), "target_env", Some(&["libnx"])),
     // (Some(Mode::Std), "target_os", Some(&[])),
@@ -718,6 +719,9 @@ fn std_features(&self, target: TargetSelection) -> String {
         if self.config.backtrace {
             features.push_str(" backtrace");
         }
+        if self.config.windows_minimal_preload {
+            features.push_str(" windows_minimal_preload");
+        }
         if self.config.profiler_enabled(target) {
             features.push_str(" profiler");
         }
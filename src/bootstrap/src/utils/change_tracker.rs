@@ -96,4 +96,9 @@ pub fn find_recent_config_change_ids(current_id: usize) -> Vec<ChangeInfo> {
         severity: ChangeSeverity::Info,
         summary: "Removed rust.run_dsymutil and dist.gpg_password_file config options, as they were unused.",
     },
+    ChangeInfo {
+        change_id: 118704,
+        severity: ChangeSeverity::Info,
+        summary: "New option `rust.windows-minimal-preload` that defers loading Windows API functions std needs from before `main` to their first use.",
+    },
 ];
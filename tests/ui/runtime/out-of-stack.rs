@@ -44,7 +44,14 @@ fn check_status(status: std::process::ExitStatus)
     assert_eq!(status.signal(), Some(libc::SIGABRT));
 }
 
-#[cfg(not(unix))]
+#[cfg(windows)]
+fn check_status(status: std::process::ExitStatus)
+{
+    // STATUS_STACK_OVERFLOW
+    assert_eq!(status.code(), Some(0xC00000FDu32 as i32));
+}
+
+#[cfg(not(any(unix, windows)))]
 fn check_status(status: std::process::ExitStatus)
 {
     assert!(!status.success());